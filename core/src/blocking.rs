@@ -0,0 +1,190 @@
+//! Synchronous facade over [`Sfu`] for applications without an async runtime.
+//!
+//! Every call blocks the current thread on a runtime owned by [`BlockingSfu`],
+//! so these methods must not be called from inside another Tokio runtime.
+
+use anyhow::Result;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::broadcast;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+use crate::{
+    PublisherRequest, PublisherResponse, PublisherStats, PublisherUpdateRequest,
+    PublisherUpdateResponse, SessionInfo, Sfu, SfuEvent, SubscriberRequest, SubscriberResponse,
+    SubscriberStats, SubscriberUpdateRequest, SubscriberUpdateResponse, TrackMetadata,
+};
+
+pub struct BlockingSfu {
+    // Always `Some` until drop, where the SFU is released inside the runtime
+    // because session teardown spawns tasks.
+    sfu: Option<Box<dyn Sfu>>,
+    runtime: Runtime,
+}
+
+impl BlockingSfu {
+    /// Builds the SFU inside a fresh multi-threaded runtime, so constructors
+    /// that spawn tasks work as they would under `#[tokio::main]`.
+    pub fn new<S, F>(make_sfu: F) -> Result<Self>
+    where
+        S: Sfu + 'static,
+        F: FnOnce() -> Result<S>,
+    {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        Self::with_runtime(runtime, make_sfu)
+    }
+
+    pub fn with_runtime<S, F>(runtime: Runtime, make_sfu: F) -> Result<Self>
+    where
+        S: Sfu + 'static,
+        F: FnOnce() -> Result<S>,
+    {
+        let sfu = {
+            let _guard = runtime.enter();
+            make_sfu()?
+        };
+
+        Ok(Self {
+            sfu: Some(Box::new(sfu)),
+            runtime,
+        })
+    }
+
+    /// Handle for spawning work (e.g. draining ICE candidate channels) on the
+    /// runtime that drives the SFU.
+    pub fn handle(&self) -> &Handle {
+        self.runtime.handle()
+    }
+
+    fn sfu(&self) -> &dyn Sfu {
+        self.sfu.as_deref().expect("SFU is only taken on drop")
+    }
+
+    pub fn id(&self) -> &str {
+        self.sfu().id()
+    }
+
+    pub fn add_publisher(&self, req: PublisherRequest) -> Result<PublisherResponse> {
+        self.runtime.block_on(self.sfu().add_publisher(req))
+    }
+
+    pub fn update_publisher(&self, req: PublisherUpdateRequest) -> Result<PublisherUpdateResponse> {
+        self.runtime.block_on(self.sfu().update_publisher(req))
+    }
+
+    pub fn remove_publisher(&self, publisher_id: &str) -> Result<()> {
+        self.runtime
+            .block_on(self.sfu().remove_publisher(publisher_id))
+    }
+
+    pub fn add_publisher_ice(
+        &self,
+        publisher_id: &str,
+        candidate: RTCIceCandidateInit,
+    ) -> Result<()> {
+        self.runtime
+            .block_on(self.sfu().add_publisher_ice(publisher_id, candidate))
+    }
+
+    pub fn add_subscriber(&self, req: SubscriberRequest) -> Result<SubscriberResponse> {
+        self.runtime.block_on(self.sfu().add_subscriber(req))
+    }
+
+    pub fn update_subscriber(
+        &self,
+        req: SubscriberUpdateRequest,
+    ) -> Result<SubscriberUpdateResponse> {
+        self.runtime.block_on(self.sfu().update_subscriber(req))
+    }
+
+    pub fn remove_subscriber(&self, subscriber_id: &str) -> Result<()> {
+        self.runtime
+            .block_on(self.sfu().remove_subscriber(subscriber_id))
+    }
+
+    pub fn set_subscriber_answer(
+        &self,
+        subscriber_id: &str,
+        answer: RTCSessionDescription,
+    ) -> Result<()> {
+        self.runtime
+            .block_on(self.sfu().set_subscriber_answer(subscriber_id, answer))
+    }
+
+    pub fn add_subscriber_ice(
+        &self,
+        subscriber_id: &str,
+        candidate: RTCIceCandidateInit,
+    ) -> Result<()> {
+        self.runtime
+            .block_on(self.sfu().add_subscriber_ice(subscriber_id, candidate))
+    }
+
+    pub fn set_subscriber_track_paused(
+        &self,
+        subscriber_id: &str,
+        track_id: &str,
+        paused: bool,
+    ) -> Result<()> {
+        self.runtime
+            .block_on(
+                self.sfu()
+                    .set_subscriber_track_paused(subscriber_id, track_id, paused),
+            )
+    }
+
+    pub fn set_publisher_track_metadata(
+        &self,
+        publisher_id: &str,
+        tracks: Vec<TrackMetadata>,
+    ) -> Result<()> {
+        self.runtime.block_on(
+            self.sfu()
+                .set_publisher_track_metadata(publisher_id, tracks),
+        )
+    }
+
+    pub fn set_publisher_audio_muted(&self, publisher_id: &str, muted: bool) -> Result<()> {
+        self.runtime
+            .block_on(self.sfu().set_publisher_audio_muted(publisher_id, muted))
+    }
+
+    pub fn get_publisher_stats(&self, publisher_id: &str) -> Result<PublisherStats> {
+        self.runtime
+            .block_on(self.sfu().get_publisher_stats(publisher_id))
+    }
+
+    pub fn get_subscriber_stats(&self, subscriber_id: &str) -> Result<SubscriberStats> {
+        self.runtime
+            .block_on(self.sfu().get_subscriber_stats(subscriber_id))
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.runtime.block_on(self.sfu().list_sessions())
+    }
+
+    pub fn get_session(&self, session_id: &str) -> Result<Option<SessionInfo>> {
+        self.runtime.block_on(self.sfu().get_session(session_id))
+    }
+
+    pub fn get_metrics(&self) -> Result<sfu_proto::SfuMetrics> {
+        self.runtime.block_on(self.sfu().get_metrics())
+    }
+
+    pub fn health_check(&self) -> Result<()> {
+        self.runtime.block_on(self.sfu().health_check())
+    }
+
+    pub fn events(&self) -> broadcast::Receiver<SfuEvent> {
+        self.sfu().events()
+    }
+}
+
+impl Drop for BlockingSfu {
+    fn drop(&mut self) {
+        let _guard = self.runtime.enter();
+        self.sfu.take();
+    }
+}
@@ -1,13 +1,21 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
 pub use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 
+pub mod blocking;
+
+pub use blocking::BlockingSfu;
+
 pub type IceCandidateSender = mpsc::UnboundedSender<RTCIceCandidateInit>;
 
+/// Carries SFU-initiated offers to a subscriber, e.g. when its publisher adds
+/// a track after the subscriber attached.
+pub type RenegotiationSender = mpsc::UnboundedSender<RTCSessionDescription>;
+
 #[async_trait]
 pub trait Sfu: Send + Sync {
     fn id(&self) -> &str;
@@ -36,15 +44,61 @@ pub trait Sfu: Send + Sync {
 
     async fn remove_subscriber(&self, subscriber_id: &str) -> Result<()>;
 
+    /// Completes an offer previously sent through the subscriber's
+    /// [`RenegotiationSender`].
+    async fn set_subscriber_answer(
+        &self,
+        subscriber_id: &str,
+        answer: RTCSessionDescription,
+    ) -> Result<()>;
+
     async fn add_subscriber_ice(
         &self,
         subscriber_id: &str,
         candidate: RTCIceCandidateInit,
     ) -> Result<()>;
 
+    /// Stops or restarts forwarding a single track to a subscriber without
+    /// renegotiating. `track_id` is the publisher's track id or the id the
+    /// subscriber sees.
+    async fn set_subscriber_track_paused(
+        &self,
+        subscriber_id: &str,
+        track_id: &str,
+        paused: bool,
+    ) -> Result<()>;
+
+    /// Replaces the labels the publisher attached to its tracks.
+    async fn set_publisher_track_metadata(
+        &self,
+        publisher_id: &str,
+        tracks: Vec<TrackMetadata>,
+    ) -> Result<()>;
+
+    /// Stops or restarts forwarding the publisher's audio to every
+    /// subscriber, including audio tracks it adds later.
+    async fn set_publisher_audio_muted(&self, publisher_id: &str, muted: bool) -> Result<()>;
+
+    async fn get_publisher_stats(&self, publisher_id: &str) -> Result<PublisherStats>;
+
+    async fn get_subscriber_stats(&self, subscriber_id: &str) -> Result<SubscriberStats>;
+
+    async fn list_sessions(&self) -> Result<Vec<SessionInfo>>;
+
+    async fn get_session(&self, session_id: &str) -> Result<Option<SessionInfo>> {
+        Ok(self
+            .list_sessions()
+            .await?
+            .into_iter()
+            .find(|info| info.id == session_id))
+    }
+
     async fn get_metrics(&self) -> Result<sfu_proto::SfuMetrics>;
 
     async fn health_check(&self) -> Result<()>;
+
+    /// Things the caller should act on that happen outside any request.
+    fn events(&self) -> broadcast::Receiver<SfuEvent>;
 }
 
 pub struct PublisherRequest {
@@ -76,19 +130,170 @@ pub struct SubscriberRequest {
     pub publisher_id: String,
     pub offer: RTCSessionDescription,
     pub ice_candidate_tx: Option<IceCandidateSender>,
+    pub renegotiation_tx: Option<RenegotiationSender>,
+    /// `"screen"` or `"webcam"` to receive only that stream's tracks,
+    /// `"audio"` for only audio tracks; `None` or `"all"` for every track.
+    pub stream_type: Option<String>,
+    /// Subscriptions naming the same group have their playback aligned,
+    /// e.g. a team's webcam and screen watched side by side.
+    pub sync_group: Option<String>,
+    /// Whether the subscriber may write back to the publisher on its
+    /// upstream data channels, e.g. an operator's remote control.
+    pub upstream: bool,
 }
 
 #[derive(Debug)]
 pub struct SubscriberResponse {
     pub answer: RTCSessionDescription,
+    /// Labels of the publisher's data channels relayed to this subscriber.
+    /// Empty unless the offer negotiated SCTP.
+    pub data_channels: Vec<String>,
 }
 
+/// Re-syncs a subscriber's tracks with its publisher and renegotiates.
 #[derive(Debug)]
 pub struct SubscriberUpdateRequest {
     pub subscriber_id: String,
+    /// Offer from the subscriber. When `None` the SFU creates the offer and
+    /// the subscriber's answer goes to [`Sfu::set_subscriber_answer`].
+    pub offer: Option<RTCSessionDescription>,
 }
 
 #[derive(Debug)]
 pub struct SubscriberUpdateResponse {
-    pub success: bool,
+    /// Answer to the subscriber's offer, or the SFU's own offer.
+    pub description: RTCSessionDescription,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PublisherStats {
+    pub publisher_id: String,
+    pub track_count: usize,
+    pub subscriber_count: usize,
+    pub bytes_received: u64,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    pub bitrate_bps: u64,
+    /// Sum of the REMB targets of video tracks that have one, from their
+    /// content profile or their subscribers' feedback.
+    pub target_bitrate_bps: Option<u64>,
+    pub quality_score: f64,
+    pub rtt_ms: Option<i64>,
+    /// Worst packet loss subscribers recently reported for any track.
+    pub downstream_loss_ratio: f64,
+    /// Worst interarrival jitter subscribers recently reported.
+    pub downstream_jitter_ms: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SubscriberStats {
+    pub subscriber_id: String,
+    pub publisher_id: String,
+    pub track_count: usize,
+    pub bytes_sent: u64,
+    pub packets_sent: u64,
+    /// Packets dropped for exceeding `performance.latency_budget_ms`.
+    pub packets_dropped_stale: u64,
+    pub rtt_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SfuEvent {
+    /// One of a session's media tasks panicked and stopped, so the session
+    /// no longer works and should be closed.
+    TaskPanicked {
+        session_id: String,
+        kind: SessionKind,
+        task: String,
+        message: String,
+    },
+    /// Someone started or stopped talking on one of a publisher's audio
+    /// tracks, going by the audio levels the publisher sends.
+    SpeakingChanged {
+        publisher_id: String,
+        track_id: String,
+        speaking: bool,
+    },
+    /// A publisher kept sending more than `max_publisher_bitrate_kbps`
+    /// despite being asked to send less.
+    BitrateCapExceeded {
+        publisher_id: String,
+        bitrate_bps: u64,
+        cap_bps: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    Publisher,
+    Subscriber,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    pub kind: SessionKind,
+    /// The publisher a subscriber is attached to; `None` for publishers.
+    pub publisher_id: Option<String>,
+    pub connection_state: RTCPeerConnectionState,
+    /// Whether the connection goes through a TURN relay; `None` until it has
+    /// connected.
+    pub relayed: Option<bool>,
+    pub tracks: Vec<TrackInfo>,
+    /// `None` until an offer/answer exchange has completed.
+    pub negotiated: Option<NegotiationReport>,
+    /// For subscribers, forwarded tracks whose codec the subscriber didn't
+    /// negotiate.
+    pub codec_mismatches: Vec<String>,
+    /// For subscribers in a sync group, how far apart the group's
+    /// capture-to-SFU times are, including any offset between the publishers'
+    /// clocks. `None` until two of its tracks have been measured.
+    pub sync_skew_ms: Option<u64>,
+}
+
+/// What the last offer/answer exchange on a session settled on, read from
+/// the answer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegotiationReport {
+    pub codecs: Vec<NegotiatedCodec>,
+    pub header_extensions: Vec<NegotiatedExtension>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedCodec {
+    /// `"audio"` or `"video"`.
+    pub kind: String,
+    pub mime_type: String,
+    pub payload_type: u8,
+    pub clock_rate: u32,
+    pub fmtp: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedExtension {
+    pub kind: String,
+    pub id: u16,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    /// For subscribers, the id of the publisher track being forwarded.
+    pub id: String,
+    pub kind: String,
+    pub mime_type: String,
+    pub ssrc: u32,
+    pub metadata: Option<TrackMetadata>,
+}
+
+/// What a publisher says a track carries, for display to viewers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackMetadata {
+    /// The publisher's track id.
+    pub track_id: String,
+    /// `"screen"`, `"webcam"` or `"audio"`.
+    pub label: String,
+    /// Which display a screen track captures, for multi-monitor grabbers.
+    pub display_index: Option<u32>,
+    pub camera_name: Option<String>,
 }
@@ -0,0 +1,162 @@
+//! `run`: publishes every stream in a config file at once, each as its own
+//! grabber, restarting any whose pipeline or connection fails.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::encoder::EncoderSettings;
+use crate::frame_source::{SourceSettings, SourceSpec};
+use crate::{PublishOptions, Stopped};
+
+const RESTART_DELAY_MIN: Duration = Duration::from_secs(1);
+const RESTART_DELAY_MAX: Duration = Duration::from_secs(60);
+/// A stream that ran this long before failing restarts without backoff.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// ```yaml
+/// url: "ws://sfu.example.com:3000/grabber"
+/// credential: "secret"
+/// streams:
+///   - name: "pc-017-screen"
+///     source: "screen:0"
+///     width: 1920
+///     height: 1080
+///   - name: "pc-017-webcam"
+///     source: "webcam:0"
+///     bitrate: 1500
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct DaemonConfig {
+    /// The server's grabber endpoint; each stream connects to `URL/NAME`.
+    pub url: String,
+    pub credential: String,
+    pub streams: Vec<StreamConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamConfig {
+    /// The grabber name the stream publishes as.
+    pub name: String,
+    /// As for `stream --source`.
+    pub source: String,
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default = "default_height")]
+    pub height: u32,
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+    /// Overrides `--bitrate`.
+    pub bitrate: Option<u32>,
+}
+
+fn default_width() -> u32 {
+    1280
+}
+
+fn default_height() -> u32 {
+    720
+}
+
+fn default_fps() -> u32 {
+    30
+}
+
+impl DaemonConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = serde_yaml::from_str(&text)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if config.streams.is_empty() {
+            bail!("{} defines no streams", path.display());
+        }
+        for (index, stream) in config.streams.iter().enumerate() {
+            if config.streams[..index]
+                .iter()
+                .any(|s| s.name == stream.name)
+            {
+                bail!("Stream name '{}' is used twice", stream.name);
+            }
+            stream
+                .source
+                .parse::<SourceSpec>()
+                .with_context(|| format!("Stream '{}'", stream.name))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Runs every stream until the process is stopped.
+pub async fn run(
+    config: DaemonConfig,
+    encoder: EncoderSettings,
+    options: PublishOptions,
+) -> Result<()> {
+    let base_url = config.url.trim_end_matches('/');
+    let streams = config.streams.iter().map(|stream| {
+        let settings = SourceSettings {
+            width: stream.width,
+            height: stream.height,
+            fps: stream.fps,
+            encoder: EncoderSettings {
+                bitrate_kbps: stream.bitrate.unwrap_or(encoder.bitrate_kbps),
+                ..encoder
+            },
+            preview: options.preview,
+        };
+        supervise(
+            stream,
+            format!("{}/{}", base_url, stream.name),
+            &config.credential,
+            settings,
+            options,
+        )
+    });
+    futures::future::join_all(streams).await;
+    Ok(())
+}
+
+/// Publishes `stream`, restarting it with backoff whenever it stops, unless
+/// the server shut it down.
+async fn supervise(
+    stream: &StreamConfig,
+    url: String,
+    credential: &str,
+    settings: SourceSettings,
+    options: PublishOptions,
+) {
+    let source: SourceSpec = stream.source.parse().expect("validated on load");
+    let mut delay = RESTART_DELAY_MIN;
+    loop {
+        info!("Starting stream '{}' from {}", stream.name, source);
+        let started = Instant::now();
+        let result = crate::publish(
+            url.clone(),
+            credential.to_string(),
+            source.clone(),
+            settings,
+            options,
+            false,
+            false,
+        )
+        .await;
+        match result {
+            Ok(Stopped::ShutDown) => {
+                warn!("Stream '{}' was shut down by the server", stream.name);
+                return;
+            }
+            Ok(Stopped::SourceEnded) => warn!("Stream '{}' ended", stream.name),
+            Err(e) => warn!("Stream '{}' failed: {:#}", stream.name, e),
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            delay = RESTART_DELAY_MIN;
+        }
+        info!("Restarting stream '{}' in {:?}", stream.name, delay);
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RESTART_DELAY_MAX);
+    }
+}
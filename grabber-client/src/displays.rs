@@ -0,0 +1,143 @@
+//! Monitor enumeration for `list` and `--display`. A display's index is the
+//! one the platform's screen capture element takes.
+
+use anyhow::Result;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct Display {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Top-left corner on the virtual desktop.
+    pub x: i32,
+    pub y: i32,
+    pub primary: bool,
+}
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}x{} at {},{}",
+            self.name, self.width, self.height, self.x, self.y
+        )?;
+        if self.primary {
+            f.write_str(" (primary)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The monitors of the X server `ximagesrc` captures, from `xrandr`.
+#[cfg(target_os = "linux")]
+pub fn list_displays() -> Result<Vec<Display>> {
+    use anyhow::Context;
+
+    let output = std::process::Command::new("xrandr")
+        .arg("--listmonitors")
+        .output()
+        .context("Failed to run xrandr")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "xrandr failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_xrandr_monitor)
+        .collect())
+}
+
+/// Parses a line like ` 0: +*eDP-1 1920/344x1080/194+0+0  eDP-1`.
+#[cfg(target_os = "linux")]
+fn parse_xrandr_monitor(line: &str) -> Option<Display> {
+    let (_, rest) = line.trim().split_once(": ")?;
+    let mut fields = rest.split_whitespace();
+    let flags = fields.next()?;
+    let geometry = fields.next()?;
+    let name = flags.trim_start_matches(['+', '*']).to_string();
+
+    // WIDTH/MM x HEIGHT/MM +X +Y, with negative offsets written as +-X.
+    let geometry = geometry.replace("+-", "-");
+    let (width, rest) = geometry.split_once('/')?;
+    let (_, rest) = rest.split_once('x')?;
+    let (height, rest) = rest.split_once('/')?;
+    let offsets = &rest[rest.find(['+', '-'])?..];
+    let split = offsets[1..].find(['+', '-'])? + 1;
+    let (x, y) = offsets.split_at(split);
+
+    Some(Display {
+        name,
+        width: width.parse().ok()?,
+        height: height.parse().ok()?,
+        x: x.trim_start_matches('+').parse().ok()?,
+        y: y.trim_start_matches('+').parse().ok()?,
+        primary: flags.contains('*'),
+    })
+}
+
+/// The active displays, in the order `avfvideosrc` indexes them.
+#[cfg(target_os = "macos")]
+pub fn list_displays() -> Result<Vec<Display>> {
+    use core_graphics::display::CGDisplay;
+
+    let ids = CGDisplay::active_displays()
+        .map_err(|e| anyhow::anyhow!("Failed to list displays: CGError {}", e))?;
+    Ok(ids
+        .into_iter()
+        .map(|id| {
+            let display = CGDisplay::new(id);
+            let bounds = display.bounds();
+            Display {
+                name: format!("Display {}", id),
+                width: display.pixels_wide() as u32,
+                height: display.pixels_high() as u32,
+                x: bounds.origin.x as i32,
+                y: bounds.origin.y as i32,
+                primary: display.is_main(),
+            }
+        })
+        .collect())
+}
+
+/// The monitors `d3d11screencapturesrc` can capture, in `monitor-index`
+/// order.
+#[cfg(target_os = "windows")]
+pub fn list_displays() -> Result<Vec<Display>> {
+    use anyhow::Context;
+    use gstreamer as gst;
+    use gstreamer::prelude::*;
+
+    gst::init().context("Failed to initialize GStreamer")?;
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(Some("Source/Monitor"), None);
+    monitor
+        .start()
+        .context("Failed to start the GStreamer device monitor")?;
+    let devices = monitor.devices();
+    monitor.stop();
+
+    Ok(devices
+        .into_iter()
+        .filter_map(|device| {
+            let properties = device.properties()?;
+            let coordinate = |edge: &str| {
+                properties
+                    .get::<i32>(format!("desktop.coordinates.{}", edge).as_str())
+                    .ok()
+            };
+            let (left, top) = (coordinate("left")?, coordinate("top")?);
+            let (right, bottom) = (coordinate("right")?, coordinate("bottom")?);
+            Some(Display {
+                name: device.display_name().to_string(),
+                width: (right - left) as u32,
+                height: (bottom - top) as u32,
+                x: left,
+                y: top,
+                primary: properties.get::<bool>("device.primary").unwrap_or(false),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,538 @@
+use gstreamer as gst;
+use tracing::debug;
+
+use crate::frame_source::SourceSettings;
+
+/// A video encoder, as the GStreamer element that sits between a source's raw
+/// video and the appsink. Pipelines are assembled from a capture part and an
+/// encoder, so runtime controls work the same for every source.
+pub trait Encoder: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The GStreamer element factory, used to check the encoder is installed.
+    fn factory(&self) -> &'static str;
+
+    fn mime_type(&self) -> &'static str {
+        "video/H264"
+    }
+
+    /// The element for gst-launch, named `encoder`, tuned by
+    /// `settings.encoder`. The profile is set through the output caps.
+    fn element(&self, settings: &SourceSettings) -> String;
+
+    /// What follows the encoder up to the appsink: H.264 byte-stream access
+    /// units with the requested profile.
+    fn output(&self, settings: &SourceSettings) -> String {
+        format!(
+            "h264parse config-interval=1 ! \
+             video/x-h264,stream-format=byte-stream,alignment=au,profile={}",
+            settings.encoder.profile.caps_name()
+        )
+    }
+
+    fn bitrate_property_name(&self) -> &'static str {
+        "bitrate"
+    }
+
+    /// Converts to the unit of the element's bitrate property.
+    fn bitrate_property(&self, kbps: u32) -> u32 {
+        kbps
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EncoderStats {
+    pub encoder: &'static str,
+    pub bitrate_kbps: u32,
+    pub frames: u64,
+    pub bytes: u64,
+    pub keyframes_forced: u64,
+}
+
+/// Which encoder to use, from `--encoder` or an `encoder` stdin command.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderKind {
+    /// The installed hardware encoders, best first, falling back to x264.
+    Auto,
+    X264,
+    /// NVIDIA NVENC.
+    Nvenc,
+    /// VA-API through the `va` plugin.
+    Va,
+    /// VA-API through the older `vaapi` plugin.
+    Vaapi,
+    /// Intel Quick Sync.
+    Qsv,
+    /// Windows Media Foundation.
+    Mediafoundation,
+    Openh264,
+    Videotoolbox,
+}
+
+/// The codec grabbers publish, from `--codec`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    Vp8,
+    Vp9,
+    /// Around a third less bitrate than H.264 for the same quality, for
+    /// sites with little upload bandwidth, at a much higher CPU cost.
+    Av1,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum H264Profile {
+    ConstrainedBaseline,
+    Baseline,
+    Main,
+    High,
+}
+
+impl H264Profile {
+    /// The `profile` caps value.
+    pub fn caps_name(self) -> &'static str {
+        match self {
+            Self::ConstrainedBaseline => "constrained-baseline",
+            Self::Baseline => "baseline",
+            Self::Main => "main",
+            Self::High => "high",
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateControl {
+    /// Constant bitrate, the most predictable load on the uplink.
+    Cbr,
+    /// Variable bitrate, capped at the target bitrate where the encoder
+    /// supports it.
+    Vbr,
+}
+
+/// Encoder choice and tuning, from the command line.
+#[derive(Debug, Clone, Copy)]
+pub struct EncoderSettings {
+    pub codec: VideoCodec,
+    /// Only chooses among H.264 encoders.
+    pub kind: EncoderKind,
+    pub bitrate_kbps: u32,
+    /// In frames; `None` for two seconds' worth.
+    pub keyframe_interval: Option<u32>,
+    pub profile: H264Profile,
+    pub rate_control: RateControl,
+}
+
+impl EncoderSettings {
+    pub fn keyframe_interval(&self, fps: u32) -> u32 {
+        self.keyframe_interval.unwrap_or(fps * 2)
+    }
+
+    /// Encoders to try in order. GStreamer must be initialized.
+    pub fn candidates(&self) -> Vec<Box<dyn Encoder>> {
+        match self.codec {
+            VideoCodec::H264 => self.kind.candidates(),
+            VideoCodec::Vp8 => vec![Box::new(Vpx::Vp8)],
+            VideoCodec::Vp9 => vec![Box::new(Vpx::Vp9)],
+            VideoCodec::Av1 => vec![Box::new(SvtAv1), Box::new(Rav1e)],
+        }
+    }
+}
+
+impl EncoderKind {
+    /// Encoders to try in order. GStreamer must be initialized, as `Auto`
+    /// looks up which hardware encoders are installed.
+    pub fn candidates(self) -> Vec<Box<dyn Encoder>> {
+        match self {
+            Self::Auto => {
+                let mut candidates = hardware_encoders();
+                candidates.push(Box::new(X264));
+                candidates
+            }
+            Self::X264 => vec![Box::new(X264)],
+            Self::Nvenc => vec![Box::new(Nvenc)],
+            Self::Va => vec![Box::new(Va)],
+            Self::Vaapi => vec![Box::new(Vaapi)],
+            Self::Qsv => vec![Box::new(Qsv)],
+            Self::Mediafoundation => vec![Box::new(MediaFoundation)],
+            Self::Openh264 => vec![Box::new(OpenH264)],
+            Self::Videotoolbox => vec![Box::new(VideoToolbox)],
+        }
+    }
+}
+
+/// The installed hardware encoders, in order of preference. An installed
+/// element can still fail without the hardware behind it, which is why
+/// every one of them is a candidate.
+fn hardware_encoders() -> Vec<Box<dyn Encoder>> {
+    let all: Vec<Box<dyn Encoder>> = vec![
+        Box::new(Nvenc),
+        Box::new(Va),
+        Box::new(Vaapi),
+        Box::new(Qsv),
+        Box::new(MediaFoundation),
+        Box::new(VideoToolbox),
+    ];
+    let installed: Vec<Box<dyn Encoder>> = all
+        .into_iter()
+        .filter(|encoder| gst::ElementFactory::find(encoder.factory()).is_some())
+        .collect();
+    debug!(
+        "Hardware encoders found: {:?}",
+        installed
+            .iter()
+            .map(|encoder| encoder.name())
+            .collect::<Vec<_>>()
+    );
+    installed
+}
+
+struct X264;
+
+impl Encoder for X264 {
+    fn name(&self) -> &'static str {
+        "x264"
+    }
+
+    fn factory(&self) -> &'static str {
+        "x264enc"
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        // With quality-based rate control, x264 treats `bitrate` as a cap.
+        let pass = match encoder.rate_control {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "qual",
+        };
+        format!(
+            "x264enc name=encoder tune=zerolatency speed-preset=ultrafast pass={} bitrate={} key-int-max={}",
+            pass,
+            encoder.bitrate_kbps,
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+}
+
+struct Nvenc;
+
+impl Encoder for Nvenc {
+    fn name(&self) -> &'static str {
+        "nvenc"
+    }
+
+    fn factory(&self) -> &'static str {
+        "nvh264enc"
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let rc_mode = match encoder.rate_control {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "vbr",
+        };
+        format!(
+            "nvh264enc name=encoder preset=low-latency-hq zerolatency=true rc-mode={} bitrate={} gop-size={}",
+            rc_mode,
+            encoder.bitrate_kbps,
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+}
+
+struct Va;
+
+impl Encoder for Va {
+    fn name(&self) -> &'static str {
+        "va"
+    }
+
+    fn factory(&self) -> &'static str {
+        "vah264enc"
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let rate_control = match encoder.rate_control {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "vbr",
+        };
+        format!(
+            "vah264enc name=encoder rate-control={} bitrate={} key-int-max={}",
+            rate_control,
+            encoder.bitrate_kbps,
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+}
+
+struct Vaapi;
+
+impl Encoder for Vaapi {
+    fn name(&self) -> &'static str {
+        "vaapi"
+    }
+
+    fn factory(&self) -> &'static str {
+        "vaapih264enc"
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let rate_control = match encoder.rate_control {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "vbr",
+        };
+        format!(
+            "vaapih264enc name=encoder rate-control={} bitrate={} keyframe-period={}",
+            rate_control,
+            encoder.bitrate_kbps,
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+}
+
+struct Qsv;
+
+impl Encoder for Qsv {
+    fn name(&self) -> &'static str {
+        "qsv"
+    }
+
+    fn factory(&self) -> &'static str {
+        "qsvh264enc"
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let rate_control = match encoder.rate_control {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "vbr",
+        };
+        format!(
+            "qsvh264enc name=encoder rate-control={} bitrate={} gop-size={}",
+            rate_control,
+            encoder.bitrate_kbps,
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+}
+
+struct MediaFoundation;
+
+impl Encoder for MediaFoundation {
+    fn name(&self) -> &'static str {
+        "mediafoundation"
+    }
+
+    fn factory(&self) -> &'static str {
+        "mfh264enc"
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let rc_mode = match encoder.rate_control {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "qvbr",
+        };
+        format!(
+            "mfh264enc name=encoder low-latency=true rc-mode={} bitrate={} gop-size={}",
+            rc_mode,
+            encoder.bitrate_kbps,
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+}
+
+struct OpenH264;
+
+impl Encoder for OpenH264 {
+    fn name(&self) -> &'static str {
+        "openh264"
+    }
+
+    fn factory(&self) -> &'static str {
+        "openh264enc"
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let rate_control = match encoder.rate_control {
+            RateControl::Cbr => "bitrate",
+            RateControl::Vbr => "quality",
+        };
+        format!(
+            "openh264enc name=encoder rate-control={} bitrate={} gop-size={}",
+            rate_control,
+            self.bitrate_property(encoder.bitrate_kbps),
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+
+    fn bitrate_property(&self, kbps: u32) -> u32 {
+        kbps * 1000
+    }
+}
+
+struct VideoToolbox;
+
+impl Encoder for VideoToolbox {
+    fn name(&self) -> &'static str {
+        "videotoolbox"
+    }
+
+    fn factory(&self) -> &'static str {
+        "vtenc_h264"
+    }
+
+    /// VideoToolbox only targets an average bitrate, so `rate_control` is
+    /// ignored.
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        format!(
+            "vtenc_h264 name=encoder realtime=true allow-frame-reordering=false \
+             max-keyframe-interval={} bitrate={}",
+            encoder.keyframe_interval(settings.fps),
+            encoder.bitrate_kbps
+        )
+    }
+}
+
+/// libvpx, in realtime mode.
+enum Vpx {
+    Vp8,
+    Vp9,
+}
+
+impl Encoder for Vpx {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Vp8 => "vp8",
+            Self::Vp9 => "vp9",
+        }
+    }
+
+    fn factory(&self) -> &'static str {
+        match self {
+            Self::Vp8 => "vp8enc",
+            Self::Vp9 => "vp9enc",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Vp8 => "video/VP8",
+            Self::Vp9 => "video/VP9",
+        }
+    }
+
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let end_usage = match encoder.rate_control {
+            RateControl::Cbr => "cbr",
+            RateControl::Vbr => "vbr",
+        };
+        format!(
+            "{} name=encoder deadline=1 cpu-used=8 end-usage={} target-bitrate={} keyframe-max-dist={}",
+            self.factory(),
+            end_usage,
+            self.bitrate_property(encoder.bitrate_kbps),
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+
+    fn output(&self, _settings: &SourceSettings) -> String {
+        match self {
+            Self::Vp8 => "video/x-vp8".to_string(),
+            Self::Vp9 => "video/x-vp9".to_string(),
+        }
+    }
+
+    fn bitrate_property_name(&self) -> &'static str {
+        "target-bitrate"
+    }
+
+    fn bitrate_property(&self, kbps: u32) -> u32 {
+        kbps * 1000
+    }
+}
+
+/// AV1 temporal units in the low-overhead OBU format, which is what the AV1
+/// payloader splits.
+fn av1_output() -> String {
+    "av1parse ! video/x-av1,stream-format=obu-stream,alignment=tu".to_string()
+}
+
+struct SvtAv1;
+
+impl Encoder for SvtAv1 {
+    fn name(&self) -> &'static str {
+        "svt-av1"
+    }
+
+    fn factory(&self) -> &'static str {
+        "svtav1enc"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "video/AV1"
+    }
+
+    /// SVT-AV1 only holds a constant bitrate with its low-delay prediction
+    /// structure, which realtime streaming wants anyway.
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        let rc = match encoder.rate_control {
+            RateControl::Cbr => 2,
+            RateControl::Vbr => 1,
+        };
+        format!(
+            "svtav1enc name=encoder preset=12 target-bitrate={} intra-period-length={} \
+             parameters-string=\"rc={}:pred-struct=1\"",
+            encoder.bitrate_kbps,
+            encoder.keyframe_interval(settings.fps),
+            rc
+        )
+    }
+
+    fn output(&self, _settings: &SourceSettings) -> String {
+        av1_output()
+    }
+
+    fn bitrate_property_name(&self) -> &'static str {
+        "target-bitrate"
+    }
+}
+
+struct Rav1e;
+
+impl Encoder for Rav1e {
+    fn name(&self) -> &'static str {
+        "rav1e"
+    }
+
+    fn factory(&self) -> &'static str {
+        "rav1enc"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "video/AV1"
+    }
+
+    /// rav1e has no constant bitrate mode, so `rate_control` is ignored.
+    fn element(&self, settings: &SourceSettings) -> String {
+        let encoder = &settings.encoder;
+        format!(
+            "rav1enc name=encoder speed-preset=10 low-latency=true bitrate={} max-key-frame-interval={}",
+            self.bitrate_property(encoder.bitrate_kbps),
+            encoder.keyframe_interval(settings.fps)
+        )
+    }
+
+    fn output(&self, _settings: &SourceSettings) -> String {
+        av1_output()
+    }
+
+    fn bitrate_property(&self, kbps: u32) -> u32 {
+        kbps * 1000
+    }
+}
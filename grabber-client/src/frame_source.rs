@@ -0,0 +1,392 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use grabber_protocol_client::messages::{PipelineStats, TrackMetadata};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+
+use crate::encoder::{EncoderKind, EncoderSettings, EncoderStats};
+use crate::{gstreamer_source, gstreamer_webcam};
+
+/// How often [`run`] measures the pipeline for the server's peer list.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_TEST_PATTERN: &str = "smpte";
+
+/// What a source produces. The publisher negotiates the first source's
+/// codec, so sources switched to later must output the same.
+#[derive(Debug, Clone)]
+pub struct SourceCaps {
+    pub mime_type: &'static str,
+    /// `None` when the source passes through whatever its input sends.
+    pub resolution: Option<(u32, u32)>,
+    pub fps: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SourceControl {
+    Pause,
+    Resume,
+    RequestKeyframe,
+    /// Target bitrate in kbps, applied without restarting the encoder.
+    SetBitrate(u32),
+    /// Output size and framerate, renegotiated within the running pipeline.
+    SetQuality {
+        width: u32,
+        height: u32,
+        fps: u32,
+    },
+}
+
+/// A capture backend feeding encoded frames to the publisher.
+#[async_trait]
+pub trait FrameSource: Send {
+    fn caps(&self) -> SourceCaps;
+
+    /// The next encoded frame; `None` once the source has ended.
+    async fn next_frame(&mut self) -> Result<Option<Vec<u8>>>;
+
+    fn control(&mut self, control: SourceControl) -> Result<()>;
+
+    /// `None` for sources that pass their input through without encoding.
+    fn encoder_stats(&self) -> Option<EncoderStats> {
+        None
+    }
+
+    /// Totals since the source was opened.
+    fn counters(&self) -> PipelineCounters;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineCounters {
+    pub frames: u64,
+    pub bytes: u64,
+    pub dropped_frames: u64,
+    /// Frames queued right now rather than a total.
+    pub queued_frames: u32,
+}
+
+/// Output size and rate requested from sources that encode.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceSettings {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub encoder: EncoderSettings,
+    /// Also shows the captured video in a local window.
+    pub preview: bool,
+}
+
+/// A source as written on the command line or stdin: `webcam[:CAMERA]`,
+/// `screen[:N]`, `file:PATH`, an `rtsp://` URL, `test[:PATTERN]` or `mic`.
+/// A camera is an index or id from `list`, a pattern one of
+/// `videotestsrc`'s. `mic` publishes the default microphone without video.
+#[derive(Debug, Clone)]
+pub enum SourceSpec {
+    Webcam(String),
+    Screen(usize),
+    File(PathBuf),
+    Rtsp(String),
+    Test(String),
+    Microphone,
+}
+
+impl SourceSpec {
+    /// How the server and players see the published track. Video sources
+    /// other than a screen are shown like a camera.
+    pub fn track_metadata(&self) -> TrackMetadata {
+        match self {
+            Self::Microphone => TrackMetadata {
+                label: "audio".to_string(),
+                ..Default::default()
+            },
+            Self::Screen(display) => TrackMetadata {
+                label: "screen".to_string(),
+                display_index: Some(*display as u32),
+                ..Default::default()
+            },
+            Self::Webcam(camera) => TrackMetadata {
+                label: "webcam".to_string(),
+                camera_name: Some(camera.clone()),
+                ..Default::default()
+            },
+            Self::File(_) | Self::Rtsp(_) | Self::Test(_) => TrackMetadata {
+                label: "webcam".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn open(&self, settings: &SourceSettings) -> Result<Box<dyn FrameSource>> {
+        Ok(match self {
+            Self::Webcam(camera) => Box::new(gstreamer_webcam::open(camera, settings)?),
+            Self::Screen(display) => Box::new(gstreamer_source::screen(*display, settings)?),
+            Self::File(path) => Box::new(gstreamer_source::file(path, settings)?),
+            Self::Rtsp(url) => Box::new(gstreamer_source::rtsp(url)?),
+            Self::Test(pattern) => Box::new(gstreamer_source::test_pattern(pattern, settings)?),
+            Self::Microphone => Box::new(gstreamer_source::microphone()?),
+        })
+    }
+}
+
+impl FromStr for SourceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("rtsp://") || s.starts_with("rtsps://") {
+            return Ok(Self::Rtsp(s.to_string()));
+        }
+        let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+        let index = || -> Result<usize> { Ok(if arg.is_empty() { 0 } else { arg.parse()? }) };
+        Ok(match kind {
+            "webcam" if arg.is_empty() => Self::Webcam("0".to_string()),
+            "webcam" => Self::Webcam(arg.to_string()),
+            "screen" => Self::Screen(index()?),
+            "file" if !arg.is_empty() => Self::File(PathBuf::from(arg)),
+            "test" if arg.is_empty() => Self::Test(DEFAULT_TEST_PATTERN.to_string()),
+            "test" => Self::Test(arg.to_string()),
+            "mic" if arg.is_empty() => Self::Microphone,
+            _ => bail!(
+                "Unknown source '{}', expected webcam[:CAMERA], screen[:N], file:PATH, rtsp://..., test[:PATTERN] or mic",
+                s
+            ),
+        })
+    }
+}
+
+impl fmt::Display for SourceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Webcam(camera) => write!(f, "webcam:{}", camera),
+            Self::Screen(display) => write!(f, "screen:{}", display),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Rtsp(url) => f.write_str(url),
+            Self::Test(pattern) if pattern == DEFAULT_TEST_PATTERN => f.write_str("test"),
+            Self::Test(pattern) => write!(f, "test:{}", pattern),
+            Self::Microphone => f.write_str("mic"),
+        }
+    }
+}
+
+/// A line typed on stdin while streaming: a [`SourceSpec`] to switch to,
+/// `pause`, `resume`, `keyframe`, `bitrate KBPS`, `quality WxH@FPS`,
+/// `encoder NAME` or `stats`.
+pub enum Command {
+    Switch(SourceSpec),
+    Control(SourceControl),
+    /// Reopens the current source with another encoder.
+    Encoder(EncoderKind),
+    Stats,
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.split_once(' ') {
+            Some(("bitrate", kbps)) => Self::Control(SourceControl::SetBitrate(
+                kbps.trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid bitrate '{}'", kbps.trim()))?,
+            )),
+            Some(("quality", quality)) => Self::Control(parse_quality(quality.trim())?),
+            Some(("encoder", name)) => Self::Encoder(
+                EncoderKind::from_str(name.trim(), true)
+                    .map_err(|e| anyhow::anyhow!("Unknown encoder: {}", e))?,
+            ),
+            _ => match s {
+                "pause" => Self::Control(SourceControl::Pause),
+                "resume" => Self::Control(SourceControl::Resume),
+                "keyframe" => Self::Control(SourceControl::RequestKeyframe),
+                "stats" => Self::Stats,
+                spec => Self::Switch(spec.parse()?),
+            },
+        })
+    }
+}
+
+/// Parses `WxH@FPS`, e.g. `1280x720@30`.
+fn parse_quality(s: &str) -> Result<SourceControl> {
+    let parsed: Option<(u32, u32, u32)> = s.split_once('@').and_then(|(size, fps)| {
+        let (width, height) = size.split_once('x')?;
+        Some((width.parse().ok()?, height.parse().ok()?, fps.parse().ok()?))
+    });
+    match parsed {
+        Some((width, height, fps)) if width > 0 && height > 0 && fps > 0 => {
+            Ok(SourceControl::SetQuality { width, height, fps })
+        }
+        _ => bail!("Invalid quality '{}', expected WxH@FPS", s),
+    }
+}
+
+/// Reads [`Command`]s from stdin until it closes.
+pub fn forward_stdin_commands(tx: mpsc::UnboundedSender<Command>) {
+    tokio::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.parse() {
+                Ok(command) => {
+                    if tx.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+    });
+}
+
+/// Forwards frames from `source`, opened from `spec`, to `frame_tx` until
+/// the source ends, switching sources or encoders whenever `commands` asks.
+/// Publishes the pipeline's [`PipelineStats`] on `stats_tx`.
+pub async fn run(
+    mut spec: SourceSpec,
+    mut source: Box<dyn FrameSource>,
+    mut settings: SourceSettings,
+    frame_tx: mpsc::UnboundedSender<Vec<u8>>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    stats_tx: watch::Sender<Option<PipelineStats>>,
+) -> Result<()> {
+    log_caps("Capturing", &source.caps());
+    let mut stats = StatsMeter::new(source.counters());
+    let mut stats_tick = tokio::time::interval(STATS_INTERVAL);
+    stats_tick.tick().await;
+    loop {
+        tokio::select! {
+            _ = stats_tick.tick() => {
+                let _ = stats_tx.send(Some(stats.measure(source.counters())));
+            }
+            frame = source.next_frame() => match frame? {
+                Some(frame) => {
+                    if frame_tx.send(frame).is_err() {
+                        return Ok(());
+                    }
+                }
+                None => return Ok(()),
+            },
+            Some(command) = commands.recv() => match command {
+                Command::Control(control) => match source.control(control) {
+                    // Keep the bitrate and quality when switching sources or
+                    // encoders.
+                    Ok(()) => match control {
+                        SourceControl::SetBitrate(kbps) => settings.encoder.bitrate_kbps = kbps,
+                        SourceControl::SetQuality { width, height, fps } => {
+                            settings.width = width;
+                            settings.height = height;
+                            settings.fps = fps;
+                        }
+                        _ => {}
+                    },
+                    Err(e) => warn!("Source did not accept {:?}: {:#}", control, e),
+                },
+                Command::Switch(next_spec) => match next_spec.open(&settings) {
+                    Ok(next) if next.caps().mime_type != source.caps().mime_type => {
+                        warn!(
+                            "Not switching to {}: it outputs {} instead of {}",
+                            next_spec,
+                            next.caps().mime_type,
+                            source.caps().mime_type
+                        );
+                    }
+                    Ok(next) => {
+                        stats.restart(source.counters(), next.counters());
+                        source = next;
+                        spec = next_spec;
+                        log_caps(&format!("Switched to {}", spec), &source.caps());
+                    }
+                    Err(e) => warn!("Failed to open {}: {:#}", next_spec, e),
+                },
+                Command::Encoder(kind) => {
+                    stats.restart(source.counters(), PipelineCounters::default());
+                    // The running pipeline may hold a device the new one needs.
+                    drop(source);
+                    let next_settings = SourceSettings {
+                        encoder: EncoderSettings { kind, ..settings.encoder },
+                        ..settings
+                    };
+                    source = match spec.open(&next_settings) {
+                        Ok(next) => {
+                            settings = next_settings;
+                            next
+                        }
+                        Err(e) => {
+                            warn!("Failed to reopen {} with {:?}: {:#}", spec, kind, e);
+                            spec.open(&settings)?
+                        }
+                    };
+                    log_caps(&format!("Reopened {}", spec), &source.caps());
+                }
+                Command::Stats => match source.encoder_stats() {
+                    Some(stats) => info!(
+                        "Encoder {}: {} frames, {} bytes, {} kbps, {} keyframes forced",
+                        stats.encoder,
+                        stats.frames,
+                        stats.bytes,
+                        stats.bitrate_kbps,
+                        stats.keyframes_forced
+                    ),
+                    None => info!("{} is not re-encoded", spec),
+                },
+            },
+        }
+    }
+}
+
+/// Turns a source's totals into rates between measurements. Dropped frames
+/// keep counting across source switches.
+struct StatsMeter {
+    last: PipelineCounters,
+    at: Instant,
+    dropped_before: u64,
+}
+
+impl StatsMeter {
+    fn new(counters: PipelineCounters) -> Self {
+        Self {
+            last: counters,
+            at: Instant::now(),
+            dropped_before: 0,
+        }
+    }
+
+    fn measure(&mut self, counters: PipelineCounters) -> PipelineStats {
+        let elapsed = self.at.elapsed().as_secs_f64().max(0.001);
+        let frames = counters.frames.saturating_sub(self.last.frames);
+        let bytes = counters.bytes.saturating_sub(self.last.bytes);
+        self.last = counters;
+        self.at = Instant::now();
+        PipelineStats {
+            fps: frames as f64 / elapsed,
+            bitrate_kbps: (bytes as f64 * 8.0 / 1000.0 / elapsed) as u32,
+            dropped_frames: self.dropped_before + counters.dropped_frames,
+            queued_frames: counters.queued_frames,
+        }
+    }
+
+    /// Continues from `next`, a newly opened source, after `previous`.
+    fn restart(&mut self, previous: PipelineCounters, next: PipelineCounters) {
+        self.dropped_before += previous.dropped_frames;
+        self.last = next;
+        self.at = Instant::now();
+    }
+}
+
+fn log_caps(action: &str, caps: &SourceCaps) {
+    match (caps.resolution, caps.fps) {
+        (Some((width, height)), Some(fps)) => {
+            info!(
+                "{}: {} {}x{} @ {} fps",
+                action, caps.mime_type, width, height, fps
+            )
+        }
+        _ => info!("{}: {}", action, caps.mime_type),
+    }
+}
@@ -0,0 +1,613 @@
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::encoder::{Encoder, EncoderStats};
+use crate::frame_source::{
+    FrameSource, PipelineCounters, SourceCaps, SourceControl, SourceSettings,
+};
+
+/// How often the bus watcher checks whether the source was dropped.
+const BUS_POLL_INTERVAL: gst::ClockTime = gst::ClockTime::from_mseconds(200);
+/// How long a new encoder pipeline gets to fail before its first frame. Some
+/// hardware encoders start fine and only fail once they see the input.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+const FIRST_FRAME_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+enum Event {
+    Frame(Vec<u8>),
+    Eos,
+    Error(anyhow::Error),
+}
+
+/// The encoder element of a running pipeline.
+struct ActiveEncoder {
+    encoder: Box<dyn Encoder>,
+    element: gst::Element,
+    bitrate_kbps: u32,
+    keyframes_forced: u64,
+}
+
+/// A GStreamer pipeline ending in an appsink named `sink` that outputs
+/// encoded frames: H.264 byte-stream access units, VP8/VP9 frames, AV1
+/// temporal units or Opus packets.
+pub struct GstSource {
+    pipeline: gst::Pipeline,
+    appsink: gst_app::AppSink,
+    caps: SourceCaps,
+    encoder: Option<ActiveEncoder>,
+    frames: Arc<AtomicU64>,
+    bytes: Arc<AtomicU64>,
+    /// Frames the encoder's queue discarded because the encoder fell behind.
+    dropped: Arc<AtomicU64>,
+    events: mpsc::UnboundedReceiver<Event>,
+    /// Taken from `events` by [`Self::await_first_frame`].
+    pending: Option<Event>,
+}
+
+impl GstSource {
+    fn start(
+        pipeline: gst::Pipeline,
+        caps: SourceCaps,
+        encoder: Option<(Box<dyn Encoder>, u32)>,
+    ) -> Result<Self> {
+        let appsink = pipeline
+            .by_name("sink")
+            .context("Failed to get appsink")?
+            .dynamic_cast::<gst_app::AppSink>()
+            .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
+
+        let encoder = match encoder {
+            Some((encoder, bitrate_kbps)) => Some(ActiveEncoder {
+                element: pipeline
+                    .by_name("encoder")
+                    .context("Failed to get encoder")?,
+                encoder,
+                bitrate_kbps,
+                keyframes_forced: 0,
+            }),
+            None => None,
+        };
+
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let frame_tx = events_tx.clone();
+        let frames = Arc::new(AtomicU64::new(0));
+        let bytes = Arc::new(AtomicU64::new(0));
+        let (frame_count, byte_count) = (Arc::clone(&frames), Arc::clone(&bytes));
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Error)?;
+                    let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+                    let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+
+                    frame_count.fetch_add(1, Ordering::Relaxed);
+                    byte_count.fetch_add(map.size() as u64, Ordering::Relaxed);
+                    if frame_tx
+                        .send(Event::Frame(map.as_slice().to_vec()))
+                        .is_err()
+                    {
+                        return Err(gst::FlowError::Error);
+                    }
+
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+
+        let dropped = Arc::new(AtomicU64::new(0));
+        if let Some(queue) = pipeline.by_name("encode_queue") {
+            let dropped = Arc::clone(&dropped);
+            // The queue is leaky, so every overrun discards a frame.
+            queue.connect("overrun", false, move |_| {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            });
+        }
+
+        let bus = pipeline.bus().context("Pipeline without bus")?;
+        std::thread::spawn(move || {
+            while !events_tx.is_closed() {
+                let Some(msg) = bus.timed_pop_filtered(
+                    BUS_POLL_INTERVAL,
+                    &[gst::MessageType::Eos, gst::MessageType::Error],
+                ) else {
+                    continue;
+                };
+
+                use gst::MessageView;
+
+                let event = match msg.view() {
+                    MessageView::Error(err) => {
+                        let source = err.src().map(|s| s.path_string());
+                        Event::Error(anyhow::anyhow!(
+                            "GStreamer error from {}: {} ({})",
+                            source.as_deref().unwrap_or("unknown"),
+                            err.error(),
+                            err.debug().as_deref().unwrap_or("no debug info")
+                        ))
+                    }
+                    _ => Event::Eos,
+                };
+                let _ = events_tx.send(event);
+                break;
+            }
+        });
+
+        if let Err(e) = pipeline.set_state(gst::State::Playing) {
+            // Another encoder may be tried with the same device.
+            let _ = pipeline.set_state(gst::State::Null);
+            return Err(e).context("Failed to set pipeline to Playing");
+        }
+
+        Ok(Self {
+            pipeline,
+            appsink,
+            caps,
+            encoder,
+            frames,
+            bytes,
+            dropped,
+            events,
+            pending: None,
+        })
+    }
+
+    /// Waits up to `timeout` for the pipeline's first frame or failure, and
+    /// fails with its error if that comes first. A pipeline still waiting
+    /// for its input when the time is up is taken to work.
+    fn await_first_frame(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match self.events.try_recv() {
+                Ok(Event::Error(e)) => return Err(e),
+                Ok(event) => {
+                    self.pending = Some(event);
+                    return Ok(());
+                }
+                Err(mpsc::error::TryRecvError::Empty) => {
+                    std::thread::sleep(FIRST_FRAME_POLL_INTERVAL)
+                }
+                Err(mpsc::error::TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FrameSource for GstSource {
+    fn caps(&self) -> SourceCaps {
+        self.caps.clone()
+    }
+
+    async fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let event = match self.pending.take() {
+            Some(event) => Some(event),
+            None => self.events.recv().await,
+        };
+        match event {
+            Some(Event::Frame(frame)) => Ok(Some(frame)),
+            Some(Event::Error(e)) => Err(e),
+            Some(Event::Eos) | None => Ok(None),
+        }
+    }
+
+    fn control(&mut self, control: SourceControl) -> Result<()> {
+        match control {
+            SourceControl::Pause => {
+                self.pipeline
+                    .set_state(gst::State::Paused)
+                    .context("Failed to pause pipeline")?;
+            }
+            SourceControl::Resume => {
+                self.pipeline
+                    .set_state(gst::State::Playing)
+                    .context("Failed to resume pipeline")?;
+            }
+            SourceControl::RequestKeyframe => {
+                let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+                    .all_headers(true)
+                    .build();
+                let sent = match &mut self.encoder {
+                    Some(active) => {
+                        active.keyframes_forced += 1;
+                        active.element.send_event(event)
+                    }
+                    None => self.appsink.send_event(event),
+                };
+                if !sent {
+                    bail!("The encoder ignored the keyframe request");
+                }
+            }
+            SourceControl::SetBitrate(kbps) => {
+                let Some(active) = &mut self.encoder else {
+                    bail!("This source passes its input through without encoding");
+                };
+                let property = active.encoder.bitrate_property_name();
+                let Some(spec) = active.element.find_property(property) else {
+                    bail!(
+                        "The {} encoder has no bitrate setting",
+                        active.encoder.name()
+                    );
+                };
+                // Some encoders take the bitrate as an int, most as a uint.
+                let bitrate = active.encoder.bitrate_property(kbps);
+                if spec.value_type() == i32::static_type() {
+                    active.element.set_property(property, bitrate as i32);
+                } else {
+                    active.element.set_property(property, bitrate);
+                }
+                active.bitrate_kbps = kbps;
+                info!("Encoder bitrate set to {} kbps", kbps);
+            }
+            SourceControl::SetQuality { width, height, fps } => {
+                let Some(filter) = self.pipeline.by_name("quality") else {
+                    bail!("This source can't change its resolution or framerate");
+                };
+                // The capsfilter asks upstream to renegotiate, and the encoder
+                // restarts with the new size on its next frame.
+                filter.set_property("caps", quality_caps(width, height, fps));
+                self.caps.resolution = Some((width, height));
+                self.caps.fps = Some(fps);
+                info!("Capturing at {}x{} {} fps", width, height, fps);
+            }
+        }
+        Ok(())
+    }
+
+    fn encoder_stats(&self) -> Option<EncoderStats> {
+        self.encoder.as_ref().map(|active| EncoderStats {
+            encoder: active.encoder.name(),
+            bitrate_kbps: active.bitrate_kbps,
+            frames: self.frames.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            keyframes_forced: active.keyframes_forced,
+        })
+    }
+
+    fn counters(&self) -> PipelineCounters {
+        PipelineCounters {
+            frames: self.frames.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            dropped_frames: self.dropped.load(Ordering::Relaxed),
+            queued_frames: self
+                .pipeline
+                .by_name("encode_queue")
+                .map_or(0, |queue| queue.property::<u32>("current-level-buffers")),
+        }
+    }
+}
+
+impl Drop for GstSource {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+fn launch(description: &str) -> Result<gst::Pipeline> {
+    gst::init().context("Failed to initialize GStreamer")?;
+    gst::parse::launch(description)
+        .context("Failed to create GStreamer pipeline")?
+        .dynamic_cast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to Pipeline"))
+}
+
+/// Starts `capture`, a pipeline fragment ending in raw video, encoded with
+/// the encoder `settings` selects. When several encoders are candidates, the
+/// next one is tried if a pipeline can't be built or started, or fails
+/// before its first frame, as happens when a hardware encoder is installed
+/// but the device is missing or can't handle the input.
+pub fn launch_encoded(capture: &str, settings: &SourceSettings) -> Result<GstSource> {
+    gst::init().context("Failed to initialize GStreamer")?;
+    // The preview branch leaks frames rather than hold up encoding when the
+    // window falls behind. So does the encoder's queue, rather than stall
+    // capture; its level and the frames it dropped are reported in the
+    // grabber's pings, so either shows the encoder can't keep up.
+    let queue = "queue name=encode_queue leaky=downstream max-size-buffers=5 \
+                 max-size-bytes=0 max-size-time=0 ! ";
+    let (tee, preview) = if settings.preview {
+        (
+            "tee name=preview ! ",
+            " preview. ! queue leaky=downstream max-size-buffers=1 ! \
+             videoconvert ! autovideosink sync=false",
+        )
+    } else {
+        ("", "")
+    };
+    let mut candidates = settings.encoder.candidates().into_iter().peekable();
+    while let Some(encoder) = candidates.next() {
+        let description = format!(
+            "{} ! {}{}\
+             videoconvert ! \
+             {} ! \
+             {} ! \
+             appsink name=sink sync=false emit-signals=true{}",
+            capture,
+            tee,
+            queue,
+            encoder.element(settings),
+            encoder.output(settings),
+            preview
+        );
+
+        let name = encoder.name();
+        let caps = SourceCaps {
+            mime_type: encoder.mime_type(),
+            resolution: Some((settings.width, settings.height)),
+            fps: Some(settings.fps),
+        };
+        let started = launch(&description)
+            .and_then(|pipeline| {
+                GstSource::start(
+                    pipeline,
+                    caps,
+                    Some((encoder, settings.encoder.bitrate_kbps)),
+                )
+            })
+            .and_then(|mut source| {
+                source.await_first_frame(FIRST_FRAME_TIMEOUT)?;
+                Ok(source)
+            });
+        let error = match started {
+            Ok(source) => {
+                info!("Encoding with {}", name);
+                return Ok(source);
+            }
+            Err(e) => e,
+        };
+
+        let hints = missing_element_hints(&description);
+        let Some(next) = candidates.peek() else {
+            return Err(if hints.is_empty() {
+                error
+            } else {
+                error.context(hints.join("; "))
+            });
+        };
+        warn!("{} encoder pipeline failed: {:#}", name, error);
+        for hint in hints {
+            warn!("{}", hint);
+        }
+        info!("Retrying with the {} encoder", next.name());
+    }
+    bail!("No encoder to try")
+}
+
+/// Scales and rate-converts raw video to `settings`, through a capsfilter
+/// that [`SourceControl::SetQuality`] changes while running. Devices that
+/// support the requested size and rate deliver it without conversion.
+pub fn scaled(settings: &SourceSettings) -> String {
+    format!(
+        "videoscale ! videorate name=rate ! capsfilter name=quality caps=\"{}\"",
+        quality_caps(settings.width, settings.height, settings.fps)
+    )
+}
+
+fn quality_caps(width: u32, height: u32, fps: u32) -> gst::Caps {
+    gst::Caps::builder("video/x-raw")
+        .field("width", width as i32)
+        .field("height", height as i32)
+        .field("framerate", gst::Fraction::new(fps as i32, 1))
+        .build()
+}
+
+/// Captures display `display` as numbered by `list`. On Linux without
+/// `xrandr`, display 0 is the whole X screen.
+pub fn screen(display: usize, settings: &SourceSettings) -> Result<GstSource> {
+    #[cfg(target_os = "macos")]
+    let source = format!("avfvideosrc capture-screen=true device-index={}", display);
+
+    #[cfg(target_os = "linux")]
+    let source = match crate::displays::list_displays()
+        .ok()
+        .and_then(|displays| displays.into_iter().nth(display))
+    {
+        Some(monitor) => format!(
+            "ximagesrc use-damage=false startx={} starty={} endx={} endy={}",
+            monitor.x,
+            monitor.y,
+            monitor.x + monitor.width as i32 - 1,
+            monitor.y + monitor.height as i32 - 1
+        ),
+        None if display == 0 => "ximagesrc use-damage=false".to_string(),
+        None => bail!(
+            "No display {}, run `list --device screen` to see the available ones",
+            display
+        ),
+    };
+
+    #[cfg(target_os = "windows")]
+    let source = format!(
+        "d3d11screencapturesrc monitor-index={} ! d3d11download",
+        display
+    );
+
+    launch_encoded(&format!("{} ! {}", source, scaled(settings)), settings)
+}
+
+/// Plays a video file in real time, re-encoded to `settings`.
+pub fn file(path: &Path, settings: &SourceSettings) -> Result<GstSource> {
+    launch_encoded(
+        &format!(
+            "filesrc location=\"{}\" ! decodebin ! videoconvert ! videorate ! \
+             identity sync=true ! {}",
+            path.display(),
+            scaled(settings)
+        ),
+        settings,
+    )
+}
+
+/// Relays an RTSP camera's H.264 stream as is, without re-encoding.
+pub fn rtsp(url: &str) -> Result<GstSource> {
+    let pipeline = launch(&format!(
+        "rtspsrc location={} latency=200 ! \
+         rtph264depay ! \
+         h264parse config-interval=1 ! \
+         video/x-h264,stream-format=byte-stream,alignment=au ! \
+         appsink name=sink sync=false emit-signals=true",
+        url
+    ))?;
+    GstSource::start(
+        pipeline,
+        SourceCaps {
+            mime_type: "video/H264",
+            resolution: None,
+            fps: None,
+        },
+        None,
+    )
+}
+
+/// A generated test pattern, one of `videotestsrc`'s such as `smpte` or
+/// `ball`, with the running time overlaid so stalls are easy to spot.
+pub fn test_pattern(pattern: &str, settings: &SourceSettings) -> Result<GstSource> {
+    launch_encoded(
+        &format!(
+            "videotestsrc is-live=true pattern={} ! timeoverlay ! {}",
+            pattern,
+            scaled(settings)
+        ),
+        settings,
+    )
+}
+
+/// A 440 Hz tone as 20 ms Opus packets, for publishing audio without a
+/// microphone.
+pub fn test_tone() -> Result<GstSource> {
+    opus("audiotestsrc is-live=true wave=sine freq=440 volume=0.2")
+}
+
+/// The system's default microphone as 20 ms Opus packets.
+pub fn microphone() -> Result<GstSource> {
+    opus("autoaudiosrc")
+}
+
+/// Encodes the raw audio from the `source` element to 20 ms Opus packets.
+fn opus(source: &str) -> Result<GstSource> {
+    let description = format!(
+        "{} ! audioconvert ! audioresample ! \
+         audio/x-raw,rate=48000,channels=2 ! \
+         opusenc frame-size=20 ! \
+         appsink name=sink sync=false emit-signals=true",
+        source
+    );
+    let pipeline = launch(&description).map_err(|e| {
+        let hints = missing_element_hints(&description);
+        if hints.is_empty() {
+            e
+        } else {
+            e.context(hints.join("; "))
+        }
+    })?;
+    GstSource::start(
+        pipeline,
+        SourceCaps {
+            mime_type: "audio/opus",
+            resolution: None,
+            fps: None,
+        },
+        None,
+    )
+}
+
+/// One line per element of `description` that no installed plugin provides,
+/// naming the package to install.
+fn missing_element_hints(description: &str) -> Vec<String> {
+    description
+        .split('!')
+        .filter_map(|segment| segment.split_whitespace().next())
+        // Caps filters such as `video/x-raw,...` are not elements.
+        .filter(|name| !name.contains('/'))
+        .filter(|name| gst::ElementFactory::find(name).is_none())
+        .map(|name| match install_hint(name) {
+            Some(hint) => format!("GStreamer element '{}' is missing, {}", name, hint),
+            None => format!("GStreamer element '{}' is missing", name),
+        })
+        .collect()
+}
+
+fn install_hint(element: &str) -> Option<String> {
+    let plugin_set = match element {
+        "appsink" | "videoconvert" | "videoscale" | "videorate" | "videotestsrc" | "decodebin"
+        | "timeoverlay" | "audiotestsrc" | "audioconvert" | "audioresample" | "opusenc" => "base",
+        "v4l2src" | "ximagesrc" | "rtspsrc" | "rtph264depay" | "vp8enc" | "vp9enc"
+        | "autovideosink" | "autoaudiosrc" => "good",
+        "x264enc" => "ugly",
+        "h264parse"
+        | "openh264enc"
+        | "mfvideosrc"
+        | "avfvideosrc"
+        | "vtenc_h264"
+        | "nvh264enc"
+        | "vah264enc"
+        | "qsvh264enc"
+        | "mfh264enc"
+        | "svtav1enc"
+        | "av1parse"
+        | "d3d11screencapturesrc"
+        | "d3d11download" => "bad",
+        "vaapih264enc" => "vaapi",
+        _ => return None,
+    };
+
+    #[cfg(target_os = "linux")]
+    {
+        let os_release = std::fs::read_to_string("/etc/os-release").unwrap_or_default();
+        let is = |id: &str| {
+            os_release.lines().any(|line| {
+                (line.starts_with("ID=") || line.starts_with("ID_LIKE="))
+                    && line.split('=').nth(1).is_some_and(|ids| {
+                        ids.trim_matches('"').split_whitespace().any(|i| i == id)
+                    })
+            })
+        };
+
+        let package = if is("debian") || is("ubuntu") {
+            match plugin_set {
+                "vaapi" => "gstreamer1.0-vaapi".to_string(),
+                set => format!("gstreamer1.0-plugins-{}", set),
+            }
+        } else if is("fedora") || is("rhel") {
+            match plugin_set {
+                "vaapi" => "gstreamer1-vaapi".to_string(),
+                "bad" => "gstreamer1-plugins-bad-free".to_string(),
+                set => format!("gstreamer1-plugins-{}", set),
+            }
+        } else if is("arch") {
+            match plugin_set {
+                "vaapi" => "gstreamer-vaapi".to_string(),
+                set => format!("gst-plugins-{}", set),
+            }
+        } else {
+            return Some(format!("install the GStreamer '{}' plugins", plugin_set));
+        };
+        Some(format!("install the '{}' package", package))
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        Some(format!(
+            "install GStreamer with `brew install gstreamer`, which includes the '{}' plugins",
+            plugin_set
+        ))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        Some(format!(
+            "install the complete GStreamer runtime, which includes the '{}' plugins",
+            plugin_set
+        ))
+    }
+}
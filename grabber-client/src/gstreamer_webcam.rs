@@ -1,155 +1,226 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use gstreamer as gst;
 use gstreamer::prelude::*;
-use gstreamer_app as gst_app;
-use tokio::sync::mpsc;
-use tracing::warn;
+use std::fmt;
+
+use crate::frame_source::SourceSettings;
+use crate::gstreamer_source::{launch_encoded, scaled, GstSource};
+
+/// A camera found by [`list_cameras`].
+#[derive(Debug, Clone)]
+pub struct Camera {
+    /// Names the same camera across reboots where the platform allows: the
+    /// `/dev/v4l/by-id` link on Linux, the AVFoundation unique id on macOS
+    /// and the Media Foundation device path on Windows.
+    pub id: String,
+    pub name: String,
+    pub modes: Vec<CameraMode>,
+    /// What the capture element is given to open the camera.
+    target: Target,
+}
 
-pub struct GStreamerWebcam {
-    pipeline: gst::Pipeline,
+#[derive(Debug, Clone)]
+enum Target {
+    Path(String),
+    /// `avfvideosrc` only opens cameras by index, which is the order the
+    /// device provider lists them in.
+    Index(usize),
 }
 
-impl GStreamerWebcam {
-    pub fn new(camera_index: usize, width: u32, height: u32, fps: u32) -> Result<Self> {
-        gst::init().context("Failed to initialize GStreamer")?;
-
-        #[cfg(target_os = "macos")]
-        let pipeline_str = format!(
-            "avfvideosrc device-index={} ! \
-             video/x-raw,format=NV12,width={},height={},framerate={}/1 ! \
-             vtenc_h264 realtime=true allow-frame-reordering=false max-keyframe-interval=30 quality=0.7 ! \
-             h264parse config-interval=1 ! \
-             video/x-h264,stream-format=byte-stream,alignment=au ! \
-             appsink name=sink sync=false emit-signals=true",
-            camera_index,
-            width,
-            height,
-            fps,
-        );
-
-        #[cfg(target_os = "linux")]
-        let pipeline_str = format!(
-            "v4l2src device=/dev/video{} ! \
-             video/x-raw,width={},height={},framerate={}/1 ! \
-             videoconvert ! \
-             vaapih264enc bitrate={} keyframe-period={} ! \
-             h264parse ! \
-             video/x-h264,stream-format=byte-stream,alignment=au ! \
-             appsink name=sink sync=false",
-            camera_index,
-            width,
-            height,
-            fps,
-            3000,
-            fps * 2
-        );
-
-        #[cfg(target_os = "windows")]
-        let pipeline_str = format!(
-            "mfvideosrc ! \
-             video/x-raw ! \
-             videoscale ! \
-             video/x-raw,width={},height={},framerate={}/1 ! \
-             videoconvert ! \
-             openh264enc bitrate={} gop-size={} ! \
-             h264parse config-interval=1 ! \
-             video/x-h264,stream-format=byte-stream,alignment=au ! \
-             appsink name=sink sync=false emit-signals=true",
-            width,
-            height,
-            fps,
-            15000000,
-            fps * 2
-        );
-
-        let pipeline = gst::parse::launch(&pipeline_str)
-            .context("Failed to create GStreamer pipeline")?
-            .dynamic_cast::<gst::Pipeline>()
-            .map_err(|_| anyhow::anyhow!("Failed to cast to Pipeline"))?;
-
-        Ok(Self { pipeline })
-    }
+/// A raw format the camera offers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraMode {
+    pub width: i32,
+    pub height: i32,
+    pub framerates: Vec<gst::Fraction>,
+}
 
-    pub async fn start_capture(self, frame_tx: mpsc::UnboundedSender<Vec<u8>>) -> Result<()> {
-        let pipeline = self.pipeline;
-
-        let appsink = pipeline
-            .by_name("sink")
-            .context("Failed to get appsink")?
-            .dynamic_cast::<gst_app::AppSink>()
-            .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
-
-        appsink.set_callbacks(
-            gst_app::AppSinkCallbacks::builder()
-                .new_sample(move |appsink| {
-                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Error)?;
-                    let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
-                    let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
-                    let data = map.as_slice().to_vec();
-
-                    if frame_tx.send(data).is_err() {
-                        return Err(gst::FlowError::Error);
-                    }
+impl fmt::Display for Camera {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)?;
+        for mode in &self.modes {
+            write!(f, "\n      {}", mode)?;
+        }
+        Ok(())
+    }
+}
 
-                    Ok(gst::FlowSuccess::Ok)
-                })
-                .build(),
-        );
-
-        pipeline
-            .set_state(gst::State::Playing)
-            .context("Failed to set pipeline to Playing")?;
-
-        let bus = pipeline.bus().context("Pipeline without bus")?;
-
-        for msg in bus.iter_timed(gst::ClockTime::NONE) {
-            use gst::MessageView;
-
-            match msg.view() {
-                MessageView::Eos(..) => break,
-                MessageView::Error(err) => {
-                    warn!(
-                        "GStreamer error from {:?}: {}",
-                        err.src().map(|s| s.path_string()),
-                        err.error()
-                    );
-                    break;
-                }
-                _ => (),
-            }
+impl fmt::Display for CameraMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        let rates: Vec<String> = self
+            .framerates
+            .iter()
+            .map(|rate| match rate.denom() {
+                1 => rate.numer().to_string(),
+                _ => format!("{:.2}", rate.numer() as f64 / rate.denom() as f64),
+            })
+            .collect();
+        if !rates.is_empty() {
+            write!(f, " @ {} fps", rates.join(", "))?;
         }
+        Ok(())
+    }
+}
 
-        pipeline
-            .set_state(gst::State::Null)
-            .context("Failed to set pipeline to Null")?;
+/// Opens `camera`, an index into [`list_cameras`] or a camera id, encoded
+/// with the encoder `settings` selects. Its resolution and framerate can be
+/// changed while streaming.
+pub fn open(camera: &str, settings: &SourceSettings) -> Result<GstSource> {
+    let source = match find_camera(camera)?.target {
+        Target::Index(index) => format!("avfvideosrc device-index={}", index),
+        Target::Path(path) if cfg!(target_os = "windows") => {
+            format!("mfvideosrc device-path=\"{}\"", path.replace('\\', "\\\\"))
+        }
+        Target::Path(path) => format!("v4l2src device=\"{}\"", path),
+    };
+
+    let raw = if cfg!(target_os = "macos") {
+        "video/x-raw,format=NV12"
+    } else {
+        "video/x-raw"
+    };
+
+    launch_encoded(
+        &format!("{} ! {} ! {}", source, raw, scaled(settings)),
+        settings,
+    )
+}
 
-        Ok(())
+fn find_camera(camera: &str) -> Result<Camera> {
+    let mut cameras = list_cameras()?;
+    let position = match camera.parse::<usize>() {
+        Ok(index) if index < cameras.len() => Some(index),
+        _ => cameras.iter().position(|c| c.id == camera),
+    };
+    match position {
+        Some(position) => Ok(cameras.swap_remove(position)),
+        None if cameras.is_empty() => bail!("No cameras found"),
+        None => bail!(
+            "No camera '{}', run `list --device webcam` to see the available ones",
+            camera
+        ),
     }
 }
 
-pub fn list_cameras() -> Result<Vec<String>> {
+/// The cameras the platform's GStreamer device providers report, in their
+/// order.
+pub fn list_cameras() -> Result<Vec<Camera>> {
     gst::init().context("Failed to initialize GStreamer")?;
 
-    #[cfg(target_os = "macos")]
-    {
-        let mut cameras = Vec::new();
-        for i in 0..10 {
-            let pipeline_str = format!("avfvideosrc device-index={} ! fakesink", i);
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(
+        Some("Video/Source"),
+        Some(&gst::Caps::new_empty_simple("video/x-raw")),
+    );
+    monitor
+        .start()
+        .context("Failed to start the GStreamer device monitor")?;
+    let devices = monitor.devices();
+    monitor.stop();
+
+    let mut cameras: Vec<Camera> = Vec::new();
+    let mut avf_index = 0;
+    for device in devices {
+        let properties = device.properties();
+        let property = |name: &str| {
+            properties
+                .as_ref()
+                .and_then(|p| p.get::<String>(name).ok())
+                .filter(|value| !value.is_empty())
+        };
+
+        let (id, target) = if let Some(unique_id) = property("avf.unique_id") {
+            avf_index += 1;
+            (unique_id, Target::Index(avf_index - 1))
+        } else if let Some(path) = property("device.path").or_else(|| property("api.v4l2.path")) {
+            (stable_path(&path), Target::Path(path))
+        } else {
+            continue;
+        };
+
+        // PipeWire and V4L2 can both report the same camera.
+        if cameras.iter().any(|camera| camera.id == id) {
+            continue;
+        }
+        cameras.push(Camera {
+            id,
+            name: device.display_name().to_string(),
+            modes: device.caps().map(|caps| modes(&caps)).unwrap_or_default(),
+            target,
+        });
+    }
+    Ok(cameras)
+}
+
+/// The `/dev/v4l/by-id` link to a V4L2 device, which unlike `/dev/videoN`
+/// survives replugging. Other paths are returned as they are.
+fn stable_path(path: &str) -> String {
+    let Ok(device) = std::fs::canonicalize(path) else {
+        return path.to_string();
+    };
+    std::fs::read_dir("/dev/v4l/by-id")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|link| std::fs::canonicalize(link).is_ok_and(|target| target == device))
+        .map(|link| link.display().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
 
-            if let Ok(pipeline) = gst::parse::launch(&pipeline_str) {
-                if pipeline.set_state(gst::State::Ready).is_ok() {
-                    cameras.push(format!("Camera {}: AVFoundation device", i));
-                    let _ = pipeline.set_state(gst::State::Null);
+fn modes(caps: &gst::Caps) -> Vec<CameraMode> {
+    let mut modes: Vec<CameraMode> = Vec::new();
+    for structure in caps.iter().filter(|s| s.has_name("video/x-raw")) {
+        let (Ok(width), Ok(height)) = (
+            structure.get::<i32>("width"),
+            structure.get::<i32>("height"),
+        ) else {
+            continue;
+        };
+        let framerates = structure
+            .value("framerate")
+            .map(framerates)
+            .unwrap_or_default();
+
+        // Each pixel format repeats the sizes.
+        match modes
+            .iter_mut()
+            .find(|mode| mode.width == width && mode.height == height)
+        {
+            Some(mode) => {
+                for rate in framerates {
+                    if !mode.framerates.contains(&rate) {
+                        mode.framerates.push(rate);
+                    }
                 }
             }
+            None => modes.push(CameraMode {
+                width,
+                height,
+                framerates,
+            }),
         }
-        Ok(cameras)
     }
 
-    #[cfg(not(target_os = "macos"))]
-    {
-        Ok(vec![
-            "Camera listing not implemented for this platform".to_string()
-        ])
+    for mode in &mut modes {
+        mode.framerates.sort_by(|a, b| b.cmp(a));
+    }
+    modes.sort_by_key(|mode| std::cmp::Reverse((mode.width, mode.height)));
+    modes
+}
+
+/// A fixed rate, a list of them or the top of a range.
+fn framerates(value: &gst::glib::SendValue) -> Vec<gst::Fraction> {
+    if let Ok(rate) = value.get::<gst::Fraction>() {
+        vec![rate]
+    } else if let Ok(list) = value.get::<gst::List>() {
+        list.iter()
+            .filter_map(|rate| rate.get::<gst::Fraction>().ok())
+            .collect()
+    } else if let Ok(range) = value.get::<gst::FractionRange>() {
+        vec![range.max()]
+    } else {
+        Vec::new()
     }
 }
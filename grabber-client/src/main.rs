@@ -1,8 +1,22 @@
+mod daemon;
+mod displays;
+mod encoder;
+mod frame_source;
+mod gstreamer_source;
 mod gstreamer_webcam;
+mod operator_settings;
+mod power;
+mod remote_control;
+mod uplink;
 mod webrtc_publisher;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use encoder::{EncoderKind, EncoderSettings, H264Profile, RateControl, VideoCodec};
+use frame_source::{FrameSource, SourceSettings, SourceSpec};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::info;
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
@@ -11,6 +25,84 @@ use tracing_subscriber::EnvFilter;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    #[command(flatten)]
+    publish: PublishOptions,
+
+    #[command(flatten)]
+    encode: EncodeOptions,
+}
+
+#[derive(Args, Clone, Copy)]
+struct PublishOptions {
+    /// Let viewers send keyboard and mouse input to this machine. The SFU must
+    /// list `remote-control` in `data_channels.upstream_labels`.
+    #[arg(long, global = true)]
+    allow_remote_control: bool,
+
+    /// Show a desktop notification when the SFU reports heavy packet loss on
+    /// this machine's uplink.
+    #[arg(long, global = true)]
+    notify_degraded_uplink: bool,
+
+    /// Stop sending video while the screen is locked, so viewers see that the
+    /// machine is locked instead of a frozen frame.
+    #[arg(long, global = true)]
+    pause_when_locked: bool,
+
+    /// Show the capture in a local window, so the operator can check framing
+    /// and focus. Sources relayed without re-encoding have no preview.
+    #[arg(long, global = true)]
+    preview: bool,
+}
+
+#[derive(Args, Clone, Copy)]
+struct EncodeOptions {
+    /// Codec for sources that capture raw video. The SFU must list it in its
+    /// `codecs` config.
+    #[arg(long, global = true, value_enum, default_value = "h264")]
+    codec: VideoCodec,
+
+    /// H.264 encoder for sources that capture raw video. Can be changed
+    /// while streaming with an `encoder NAME` line on stdin.
+    #[arg(long, global = true, value_enum, default_value = "auto")]
+    encoder: EncoderKind,
+
+    /// Target bitrate in kbps. Can be changed while streaming with a
+    /// `bitrate KBPS` line on stdin.
+    #[arg(long, global = true, default_value = "3000")]
+    bitrate: u32,
+
+    /// Frames between keyframes; two seconds' worth when unset.
+    #[arg(long, global = true)]
+    keyframe_interval: Option<u32>,
+
+    /// H.264 profile. Viewers negotiate constrained baseline, but browsers
+    /// decode the higher profiles too.
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value = "constrained-baseline"
+    )]
+    h264_profile: H264Profile,
+
+    /// How the encoder holds the target bitrate.
+    #[arg(long, global = true, value_enum, default_value = "cbr")]
+    rate_control: RateControl,
+}
+
+impl EncodeOptions {
+    fn settings(self) -> EncoderSettings {
+        EncoderSettings {
+            codec: self.codec,
+            kind: self.encoder,
+            bitrate_kbps: self.bitrate,
+            keyframe_interval: self.keyframe_interval,
+            profile: self.h264_profile,
+            rate_control: self.rate_control,
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -41,8 +133,33 @@ enum Commands {
         #[arg(long, default_value = "test")]
         credential: String,
 
+        /// An index or id from `list --device webcam`.
         #[arg(long, default_value = "0")]
-        camera: usize,
+        camera: String,
+
+        #[arg(long, default_value = "1280")]
+        width: u32,
+
+        #[arg(long, default_value = "720")]
+        height: u32,
+
+        #[arg(short, long, default_value = "30")]
+        fps: u32,
+    },
+
+    /// Publish any source. Typing another source on stdin switches to it;
+    /// `pause`, `resume` and `keyframe` control the current one.
+    Stream {
+        #[arg(short, long, default_value = "ws://localhost:3000/ws/grabber")]
+        url: String,
+
+        #[arg(long, default_value = "test")]
+        credential: String,
+
+        /// `webcam[:CAMERA]`, `screen[:N]`, `file:PATH`, an `rtsp://` URL,
+        /// `test[:PATTERN]` or `mic` for audio only.
+        #[arg(long, default_value = "test")]
+        source: SourceSpec,
 
         #[arg(long, default_value = "1280")]
         width: u32,
@@ -54,6 +171,46 @@ enum Commands {
         fps: u32,
     },
 
+    /// Publish a generated test pattern and tone through the usual
+    /// signalling, to exercise the SFU and players without capture devices
+    /// or soak test them.
+    TestPattern {
+        #[arg(short, long, default_value = "ws://localhost:3000/ws/grabber")]
+        url: String,
+
+        #[arg(long, default_value = "test")]
+        credential: String,
+
+        /// A `videotestsrc` pattern, e.g. `smpte`, `ball` or `snow`.
+        #[arg(long, default_value = frame_source::DEFAULT_TEST_PATTERN)]
+        pattern: String,
+
+        #[arg(long, default_value = "1280")]
+        width: u32,
+
+        #[arg(long, default_value = "720")]
+        height: u32,
+
+        #[arg(short, long, default_value = "30")]
+        fps: u32,
+
+        /// Publish video only.
+        #[arg(long)]
+        no_audio: bool,
+
+        /// Stop after this many seconds instead of running until
+        /// interrupted.
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+
+    /// Publish every stream in a config file at once, restarting any that
+    /// fail. For unattended machines; stdin commands are not read.
+    Run {
+        #[arg(long, default_value = "grabber.yaml")]
+        config: PathBuf,
+    },
+
     Both {
         #[arg(long, default_value = "ws://localhost:3000/ws/grabber")]
         url: String,
@@ -65,7 +222,7 @@ enum Commands {
         display: usize,
 
         #[arg(long, default_value = "0")]
-        camera: usize,
+        camera: String,
 
         #[arg(long, default_value = "1280")]
         width: u32,
@@ -113,7 +270,87 @@ async fn main() -> Result<()> {
             width,
             height,
             fps,
-        } => handle_webcam_gst_capture(url, credential, camera, width, height, fps).await,
+        } => {
+            handle_capture(
+                url,
+                credential,
+                SourceSpec::Webcam(camera),
+                SourceSettings {
+                    width,
+                    height,
+                    fps,
+                    encoder: cli.encode.settings(),
+                    preview: cli.publish.preview,
+                },
+                cli.publish,
+            )
+            .await
+        }
+        Commands::Stream {
+            url,
+            credential,
+            source,
+            width,
+            height,
+            fps,
+        } => {
+            handle_capture(
+                url,
+                credential,
+                source,
+                SourceSettings {
+                    width,
+                    height,
+                    fps,
+                    encoder: cli.encode.settings(),
+                    preview: cli.publish.preview,
+                },
+                cli.publish,
+            )
+            .await
+        }
+        Commands::TestPattern {
+            url,
+            credential,
+            pattern,
+            width,
+            height,
+            fps,
+            no_audio,
+            duration,
+        } => {
+            let publishing = publish(
+                url,
+                credential,
+                SourceSpec::Test(pattern),
+                SourceSettings {
+                    width,
+                    height,
+                    fps,
+                    encoder: cli.encode.settings(),
+                    preview: cli.publish.preview,
+                },
+                cli.publish,
+                true,
+                !no_audio,
+            );
+            match duration {
+                Some(secs) => {
+                    match tokio::time::timeout(Duration::from_secs(secs), publishing).await {
+                        Ok(result) => result.map(|_| ()),
+                        Err(_) => {
+                            info!("Published the test pattern for {}s, stopping", secs);
+                            Ok(())
+                        }
+                    }
+                }
+                None => publishing.await.map(|_| ()),
+            }
+        }
+        Commands::Run { config } => {
+            let config = daemon::DaemonConfig::load(&config)?;
+            daemon::run(config, cli.encode.settings(), cli.publish).await
+        }
         Commands::Both {
             url: _,
             credential: _,
@@ -133,7 +370,15 @@ fn handle_list(device_type: DeviceType) -> Result<()> {
     match device_type {
         DeviceType::Screen | DeviceType::All => {
             println!("\n=== Available Displays ===");
-            println!("  Screen capture is temporarily disabled");
+            match displays::list_displays() {
+                Ok(displays) if displays.is_empty() => println!("  No displays found"),
+                Ok(displays) => {
+                    for (index, display) in displays.iter().enumerate() {
+                        println!("  {}: {}", index, display);
+                    }
+                }
+                Err(e) => eprintln!("Error listing displays: {}", e),
+            }
         }
         _ => {}
     }
@@ -142,9 +387,10 @@ fn handle_list(device_type: DeviceType) -> Result<()> {
         DeviceType::Webcam | DeviceType::All => {
             println!("\n=== Available Cameras ===");
             match gstreamer_webcam::list_cameras() {
+                Ok(cameras) if cameras.is_empty() => println!("  No cameras found"),
                 Ok(cameras) => {
-                    for camera in cameras {
-                        println!("  {}", camera);
+                    for (index, camera) in cameras.iter().enumerate() {
+                        println!("  {}: {}", index, camera);
                     }
                 }
                 Err(e) => eprintln!("Error listing cameras: {}", e),
@@ -157,17 +403,89 @@ fn handle_list(device_type: DeviceType) -> Result<()> {
     Ok(())
 }
 
-async fn handle_webcam_gst_capture(
+async fn handle_capture(
     url: String,
     credential: String,
-    camera_index: usize,
-    width: u32,
-    height: u32,
-    fps: u32,
+    source: SourceSpec,
+    settings: SourceSettings,
+    options: PublishOptions,
 ) -> Result<()> {
-    let capturer = gstreamer_webcam::GStreamerWebcam::new(camera_index, width, height, fps)?;
+    publish(url, credential, source, settings, options, true, false)
+        .await
+        .map(|_| ())
+}
+
+/// Why [`publish`] stopped without an error.
+#[derive(Debug, Clone, Copy)]
+enum Stopped {
+    /// The source's pipeline ended.
+    SourceEnded,
+    /// An admin shut the grabber down on the server.
+    ShutDown,
+}
+
+/// Publishes `source` until its pipeline ends or fails, or the server shuts
+/// the grabber down. An `interactive` publisher reads commands from stdin and
+/// reports panics to the server, which only one publisher per process can do.
+/// With `tone`, a test tone is published alongside.
+async fn publish(
+    url: String,
+    credential: String,
+    source: SourceSpec,
+    settings: SourceSettings,
+    options: PublishOptions,
+    interactive: bool,
+    tone: bool,
+) -> Result<Stopped> {
+    let capturer = source.open(&settings)?;
+    let tone = if tone {
+        Some(gstreamer_source::test_tone()?)
+    } else {
+        None
+    };
+    let (command_tx, commands) = tokio::sync::mpsc::unbounded_channel();
+    if interactive {
+        frame_source::forward_stdin_commands(command_tx.clone());
+    }
     let mut publisher = webrtc_publisher::WebRTCPublisher::new(url, credential);
-    let frame_tx = publisher.connect_and_publish(width, height).await?;
-    capturer.start_capture(frame_tx).await?;
-    Ok(())
+    publisher.operator_settings(command_tx, settings.encoder.bitrate_kbps);
+    let (stats_tx, stats_rx) = tokio::sync::watch::channel(None);
+    publisher.report_pipeline_stats(stats_rx);
+    publisher.allow_remote_control(options.allow_remote_control);
+    publisher.notify_degraded_uplink(options.notify_degraded_uplink);
+    publisher.pause_when_locked(options.pause_when_locked);
+    publisher.publish_audio(tone.is_some());
+    let frame_tx = publisher
+        .connect_and_publish(capturer.caps().mime_type, source.track_metadata())
+        .await?;
+    let tone_task = tone
+        .zip(publisher.audio_frames())
+        .map(|(mut tone, audio_tx)| {
+            tokio::spawn(async move {
+                while let Ok(Some(packet)) = tone.next_frame().await {
+                    if audio_tx.send(packet).is_err() {
+                        break;
+                    }
+                }
+            })
+        });
+    if interactive {
+        publisher.install_panic_reporter();
+    }
+
+    let result = frame_source::run(source, capturer, settings, frame_tx, commands, stats_tx).await;
+    if let Some(tone_task) = tone_task {
+        tone_task.abort();
+    }
+    if let Err(e) = &result {
+        publisher.report_error(&format!("{:#}", e), Some("capture pipeline"));
+    }
+
+    publisher.shutdown().await;
+    result?;
+    Ok(if publisher.shut_down_by_server() {
+        Stopped::ShutDown
+    } else {
+        Stopped::SourceEnded
+    })
 }
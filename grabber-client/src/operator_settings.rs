@@ -0,0 +1,101 @@
+use grabber_protocol_client::messages::{PeerSettings, QualityMessage};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+use crate::frame_source::{Command, SourceControl};
+
+/// The SFU repeats its estimate every second while subscribers report; one
+/// this old means they stopped.
+const ESTIMATE_TTL: Duration = Duration::from_secs(5);
+
+/// Applies the overrides an operator set for this grabber on the server,
+/// which sends them on every connect and whenever they change, and the
+/// bitrate the SFU estimates its subscribers can take.
+pub struct OperatorSettings {
+    commands: mpsc::UnboundedSender<Command>,
+    /// The bitrate from the command line, restored when the cap is lifted.
+    bitrate_kbps: u32,
+    cap_kbps: Option<u32>,
+    /// The SFU's latest REMB and when it arrived.
+    estimate: Option<(u32, Instant)>,
+    applied_kbps: u32,
+}
+
+impl OperatorSettings {
+    pub fn new(commands: mpsc::UnboundedSender<Command>, bitrate_kbps: u32) -> Self {
+        Self {
+            commands,
+            bitrate_kbps,
+            cap_kbps: None,
+            estimate: None,
+            applied_kbps: bitrate_kbps,
+        }
+    }
+
+    pub fn apply(&mut self, settings: &PeerSettings) {
+        self.cap_kbps = settings.max_bitrate_kbps;
+        if let Some(kbps) = self.update() {
+            info!("Operator set the bitrate to {} kbps", kbps);
+        }
+    }
+
+    /// A REMB from the SFU for the published track.
+    pub fn set_estimate(&mut self, bitrate_bps: u64) {
+        let kbps = u32::try_from(bitrate_bps / 1000).unwrap_or(u32::MAX);
+        self.estimate = Some((kbps, Instant::now()));
+        if let Some(kbps) = self.update() {
+            debug!("Subscribers' estimate set the bitrate to {} kbps", kbps);
+        }
+    }
+
+    /// Returns to the configured bitrate once the SFU stops sending
+    /// estimates, e.g. after the last subscriber left.
+    pub fn expire_estimate(&mut self) {
+        if self
+            .estimate
+            .is_some_and(|(_, at)| at.elapsed() > ESTIMATE_TTL)
+        {
+            self.estimate = None;
+            if let Some(kbps) = self.update() {
+                debug!(
+                    "Subscribers' estimate expired, bitrate back to {} kbps",
+                    kbps
+                );
+            }
+        }
+    }
+
+    /// Encodes at the lowest of the configured bitrate, the cap and the
+    /// estimate. Returns the new bitrate if that changed it.
+    fn update(&mut self) -> Option<u32> {
+        let kbps = self
+            .cap_kbps
+            .into_iter()
+            .chain(self.estimate.map(|(kbps, _)| kbps))
+            .fold(self.bitrate_kbps, u32::min);
+        if kbps == self.applied_kbps {
+            return None;
+        }
+        let _ = self
+            .commands
+            .send(Command::Control(SourceControl::SetBitrate(kbps)));
+        self.applied_kbps = kbps;
+        Some(kbps)
+    }
+
+    /// Switches the capture size and framerate, keeping the peer connection.
+    pub fn set_quality(&self, quality: &QualityMessage) {
+        info!(
+            "Operator set the capture to {}x{} at {} fps",
+            quality.width, quality.height, quality.fps
+        );
+        let _ = self
+            .commands
+            .send(Command::Control(SourceControl::SetQuality {
+                width: quality.width,
+                height: quality.height,
+                fps: quality.fps,
+            }));
+    }
+}
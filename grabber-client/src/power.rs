@@ -0,0 +1,144 @@
+//! Screen lock and sleep detection. Every OS reports them differently, and
+//! only some of them push notifications, so the lock state is polled, and a
+//! poll that comes far too late means the machine slept in between.
+
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Wall-clock time between two polls beyond which the machine is taken to
+/// have been asleep.
+const SLEEP_GAP: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    Locked,
+    Unlocked,
+    /// The machine woke from sleep; nothing was captured or sent meanwhile.
+    Woke,
+}
+
+/// Reports the screen locking and unlocking, where the lock state can be
+/// read, and the machine waking from sleep.
+pub fn watch_power() -> mpsc::UnboundedReceiver<PowerEvent> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_poll = SystemTime::now();
+        // `None` until the lock state was read once.
+        let mut locked = None;
+        let mut lock_readable = true;
+        loop {
+            interval.tick().await;
+            let now = SystemTime::now();
+            let slept = now
+                .duration_since(last_poll)
+                .is_ok_and(|gap| gap > SLEEP_GAP);
+            last_poll = now;
+            if slept {
+                info!("Woke from sleep");
+                if tx.send(PowerEvent::Woke).is_err() {
+                    return;
+                }
+            }
+
+            if !lock_readable {
+                continue;
+            }
+            let now_locked = match screen_locked().await {
+                Some(now_locked) => now_locked,
+                None if locked.is_none() => {
+                    warn!("Screen lock state is unavailable on this system");
+                    lock_readable = false;
+                    continue;
+                }
+                // Unknown this time; keep the last state.
+                None => continue,
+            };
+            if locked.unwrap_or(false) != now_locked {
+                info!("Screen {}", if now_locked { "locked" } else { "unlocked" });
+                let event = if now_locked {
+                    PowerEvent::Locked
+                } else {
+                    PowerEvent::Unlocked
+                };
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+            locked = Some(now_locked);
+        }
+    });
+    rx
+}
+
+/// Asks logind for the session's `LockedHint`, which screen lockers set.
+#[cfg(target_os = "linux")]
+async fn screen_locked() -> Option<bool> {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+    let output = tokio::process::Command::new("loginctl")
+        .args(["show-session", &session, "-p", "LockedHint", "--value"])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+async fn screen_locked() -> Option<bool> {
+    use core_foundation::base::{CFType, TCFType};
+    use core_foundation::boolean::CFBoolean;
+    use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+    use core_foundation::string::CFString;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGSessionCopyCurrentDictionary() -> CFDictionaryRef;
+    }
+
+    let session = unsafe { CGSessionCopyCurrentDictionary() };
+    if session.is_null() {
+        return None;
+    }
+    let session: CFDictionary<CFString, CFType> =
+        unsafe { CFDictionary::wrap_under_create_rule(session) };
+    // The key is only present while locked.
+    let locked = session
+        .find(CFString::from_static_string("CGSSessionScreenIsLocked"))
+        .and_then(|value| value.downcast::<CFBoolean>())
+        .is_some_and(bool::from);
+    Some(locked)
+}
+
+/// The input desktop can't be opened while the lock screen owns it.
+#[cfg(windows)]
+async fn screen_locked() -> Option<bool> {
+    use windows_sys::Win32::Foundation::{GetLastError, ERROR_ACCESS_DENIED};
+    use windows_sys::Win32::System::StationsAndDesktops::{
+        CloseDesktop, OpenInputDesktop, DESKTOP_SWITCHDESKTOP,
+    };
+
+    let desktop = unsafe { OpenInputDesktop(0, 0, DESKTOP_SWITCHDESKTOP) };
+    if desktop == 0 {
+        // The lock screen's desktop refuses access; other failures say
+        // nothing about the lock.
+        return (unsafe { GetLastError() } == ERROR_ACCESS_DENIED).then_some(true);
+    }
+    unsafe { CloseDesktop(desktop) };
+    Some(false)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+async fn screen_locked() -> Option<bool> {
+    None
+}
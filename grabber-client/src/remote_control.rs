@@ -0,0 +1,164 @@
+use anyhow::Result;
+use enigo::{Axis, Button, Coordinate, Direction, Enigo, Key, Keyboard, Mouse, Settings};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// The SFU only passes viewer messages back on labels listed in its
+/// `data_channels.upstream_labels`.
+pub const REMOTE_CONTROL_LABEL: &str = "remote-control";
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum InputEvent {
+    MouseMove {
+        x: i32,
+        y: i32,
+    },
+    MouseDown {
+        button: MouseButton,
+    },
+    MouseUp {
+        button: MouseButton,
+    },
+    Scroll {
+        dx: i32,
+        dy: i32,
+    },
+    /// `key` uses browser `KeyboardEvent.key` names, e.g. `Enter` or `a`.
+    KeyDown {
+        key: String,
+    },
+    KeyUp {
+        key: String,
+    },
+    Text {
+        text: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+/// Opens the remote control channel on `pc`. Must run before the offer is
+/// created so it negotiates SCTP.
+pub async fn open(pc: &RTCPeerConnection) -> Result<Arc<RTCDataChannel>> {
+    let channel = pc.create_data_channel(REMOTE_CONTROL_LABEL, None).await?;
+
+    // Enigo isn't Send on every platform, so it lives on its own thread.
+    let (input_tx, input_rx) = std::sync::mpsc::channel::<InputEvent>();
+    std::thread::spawn(move || run_input_thread(input_rx));
+
+    channel.on_message(Box::new(move |msg: DataChannelMessage| {
+        match serde_json::from_slice::<InputEvent>(&msg.data) {
+            Ok(event) => {
+                let _ = input_tx.send(event);
+            }
+            Err(e) => warn!("Ignoring malformed remote control message: {}", e),
+        }
+        Box::pin(async {})
+    }));
+
+    info!(
+        "Remote control enabled on data channel '{}'",
+        REMOTE_CONTROL_LABEL
+    );
+    Ok(channel)
+}
+
+fn run_input_thread(input_rx: std::sync::mpsc::Receiver<InputEvent>) {
+    let mut enigo = match Enigo::new(&Settings::default()) {
+        Ok(enigo) => enigo,
+        Err(e) => {
+            warn!(
+                "Remote control unavailable, failed to open input device: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    while let Ok(event) = input_rx.recv() {
+        if let Err(e) = apply(&mut enigo, event) {
+            warn!("Failed to apply remote input: {}", e);
+        }
+    }
+}
+
+fn apply(enigo: &mut Enigo, event: InputEvent) -> enigo::InputResult<()> {
+    match event {
+        InputEvent::MouseMove { x, y } => enigo.move_mouse(x, y, Coordinate::Abs),
+        InputEvent::MouseDown { button } => enigo.button(button.into(), Direction::Press),
+        InputEvent::MouseUp { button } => enigo.button(button.into(), Direction::Release),
+        InputEvent::Scroll { dx, dy } => {
+            if dx != 0 {
+                enigo.scroll(dx, Axis::Horizontal)?;
+            }
+            if dy != 0 {
+                enigo.scroll(dy, Axis::Vertical)?;
+            }
+            Ok(())
+        }
+        InputEvent::KeyDown { key } => match parse_key(&key) {
+            Some(key) => enigo.key(key, Direction::Press),
+            None => Ok(()),
+        },
+        InputEvent::KeyUp { key } => match parse_key(&key) {
+            Some(key) => enigo.key(key, Direction::Release),
+            None => Ok(()),
+        },
+        InputEvent::Text { text } => enigo.text(&text),
+    }
+}
+
+impl From<MouseButton> for Button {
+    fn from(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => Button::Left,
+            MouseButton::Middle => Button::Middle,
+            MouseButton::Right => Button::Right,
+        }
+    }
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "Enter" => Key::Return,
+        "Escape" => Key::Escape,
+        "Backspace" => Key::Backspace,
+        "Tab" => Key::Tab,
+        "Delete" => Key::Delete,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "ArrowUp" => Key::UpArrow,
+        "ArrowDown" => Key::DownArrow,
+        "ArrowLeft" => Key::LeftArrow,
+        "ArrowRight" => Key::RightArrow,
+        "Shift" => Key::Shift,
+        "Control" => Key::Control,
+        "Alt" => Key::Alt,
+        "Meta" => Key::Meta,
+        " " => Key::Space,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Key::Unicode(c),
+                _ => {
+                    warn!("Unsupported remote key: {}", name);
+                    return None;
+                }
+            }
+        }
+    };
+    Some(key)
+}
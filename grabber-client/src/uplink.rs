@@ -0,0 +1,59 @@
+use grabber_protocol_client::messages::IngestQualityMessage;
+use tracing::{debug, info, warn};
+
+/// Tracks the SFU's `INGEST_QUALITY` reports and warns once per degraded
+/// period rather than on every report.
+pub struct UplinkMonitor {
+    notify: bool,
+    degraded: bool,
+}
+
+impl UplinkMonitor {
+    pub fn new(notify: bool) -> Self {
+        Self {
+            notify,
+            degraded: false,
+        }
+    }
+
+    pub fn report(&mut self, quality: &IngestQualityMessage) {
+        debug!(
+            "Uplink: {} kbps (target {}), {:.1}% loss, {:.1}% loss at viewers",
+            quality.bitrate_bps / 1000,
+            quality
+                .target_bitrate_bps
+                .map_or("none".to_string(), |bps| format!("{} kbps", bps / 1000)),
+            quality.loss_ratio * 100.0,
+            quality.downstream_loss_ratio * 100.0
+        );
+
+        match (self.degraded, quality.degraded) {
+            (false, true) => {
+                warn!(
+                    "Uplink degraded: {:.1}% packet loss at {} kbps",
+                    quality.loss_ratio * 100.0,
+                    quality.bitrate_bps / 1000
+                );
+                if self.notify {
+                    show_notification(quality);
+                }
+            }
+            (true, false) => info!("Uplink recovered"),
+            _ => {}
+        }
+        self.degraded = quality.degraded;
+    }
+}
+
+fn show_notification(quality: &IngestQualityMessage) {
+    let result = notify_rust::Notification::new()
+        .summary("Stream upload degraded")
+        .body(&format!(
+            "{:.0}% of video packets are being lost. Please tell the staff.",
+            quality.loss_ratio * 100.0
+        ))
+        .show();
+    if let Err(e) = result {
+        warn!("Failed to show desktop notification: {}", e);
+    }
+}
@@ -1,57 +1,62 @@
-use anyhow::{Context, Result};
-use futures::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use anyhow::Result;
+use grabber_protocol_client::messages::{GrabberMessage, PipelineStats, TrackMetadata};
+use grabber_protocol_client::publisher::{error_message, publisher_state_message};
+use grabber_protocol_client::{PublisherClient, SignallingSender};
+use rand::Rng;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::frame_source::{Command, SourceControl};
+use crate::operator_settings::OperatorSettings;
+use crate::power::{self, PowerEvent};
+use crate::remote_control;
+use crate::uplink::UplinkMonitor;
 use webrtc::api::interceptor_registry::register_default_interceptors;
 use webrtc::api::media_engine::MediaEngine;
-use webrtc::api::APIBuilder;
-use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
-use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::api::{APIBuilder, API};
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
 use webrtc::media::Sample;
-use webrtc::peer_connection::configuration::RTCConfiguration;
-use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::RTCPeerConnection;
 use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
 use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
 use webrtc::track::track_local::TrackLocal;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GrabberMessage {
-    event: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    grabber_auth: Option<GrabberAuth>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    offer: Option<OfferMessage>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    answer: Option<OfferMessage>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    ice: Option<IceMessage>,
-}
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Floor for the ping interval from INIT_PEER, which may be 0.
+const MIN_PING_INTERVAL: Duration = Duration::from_secs(1);
+const VIDEO_FRAME_DURATION: Duration = Duration::from_micros(33_333);
+const AUDIO_FRAME_DURATION: Duration = Duration::from_millis(20);
+const OPUS: &str = "audio/opus";
 
-#[derive(Debug, Serialize, Deserialize)]
-struct GrabberAuth {
-    credential: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct OfferMessage {
-    #[serde(rename = "type")]
-    type_: String,
-    sdp: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct IceMessage {
-    candidate: RTCIceCandidateInit,
-}
+/// The current connection's signalling sender; empty while reconnecting.
+type SharedSignalling = Arc<Mutex<Option<SignallingSender<GrabberMessage>>>>;
 
 pub struct WebRTCPublisher {
     ws_url: String,
     credential: String,
-    pc: Option<Arc<RTCPeerConnection>>,
-    video_track: Option<Arc<TrackLocalStaticSample>>,
+    signalling: SharedSignalling,
+    supervisor: Option<JoinHandle<()>>,
+    stop: Option<oneshot::Sender<()>>,
+    allow_remote_control: bool,
+    notify_degraded_uplink: bool,
+    pause_when_locked: bool,
+    lock_task: Option<JoinHandle<()>>,
+    /// The source's commands, for keyframes after a pause.
+    commands: Option<mpsc::UnboundedSender<Command>>,
+    operator: Option<OperatorSettings>,
+    pipeline_stats: Option<watch::Receiver<Option<PipelineStats>>>,
+    audio: bool,
+    audio_frames: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    shut_down: Arc<AtomicBool>,
 }
 
 impl WebRTCPublisher {
@@ -59,209 +64,682 @@ impl WebRTCPublisher {
         Self {
             ws_url,
             credential,
-            pc: None,
-            video_track: None,
+            signalling: SharedSignalling::default(),
+            supervisor: None,
+            stop: None,
+            allow_remote_control: false,
+            notify_degraded_uplink: false,
+            pause_when_locked: false,
+            lock_task: None,
+            commands: None,
+            operator: None,
+            pipeline_stats: None,
+            audio: false,
+            audio_frames: None,
+            shut_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Accept keyboard and mouse input from viewers over a data channel.
+    pub fn allow_remote_control(&mut self, allow: bool) {
+        self.allow_remote_control = allow;
+    }
+
+    pub fn notify_degraded_uplink(&mut self, notify: bool) {
+        self.notify_degraded_uplink = notify;
+    }
+
+    /// Stop sending frames while the screen is locked and tell the server why.
+    /// Sending starts again from a keyframe once unlocked or woken from sleep.
+    pub fn pause_when_locked(&mut self, pause: bool) {
+        self.pause_when_locked = pause;
+    }
+
+    /// Apply the server's bitrate cap for this grabber by sending `commands`
+    /// to the source, which encodes at `bitrate_kbps` when uncapped.
+    pub fn operator_settings(
+        &mut self,
+        commands: mpsc::UnboundedSender<Command>,
+        bitrate_kbps: u32,
+    ) {
+        self.commands = Some(commands.clone());
+        self.operator = Some(OperatorSettings::new(commands, bitrate_kbps));
+    }
+
+    /// Also publish an Opus track, fed through [`Self::audio_frames`].
+    pub fn publish_audio(&mut self, publish: bool) {
+        self.audio = publish;
+    }
+
+    /// Where to send Opus packets once published with audio.
+    pub fn audio_frames(&self) -> Option<mpsc::UnboundedSender<Vec<u8>>> {
+        self.audio_frames.clone()
+    }
+
+    /// Include the latest capture pipeline stats in pings.
+    pub fn report_pipeline_stats(&mut self, stats: watch::Receiver<Option<PipelineStats>>) {
+        self.pipeline_stats = Some(stats);
+    }
+
+    pub fn report_error(&self, message: &str, context: Option<&str>) {
+        send(&self.signalling, &error_message(message, context));
+    }
+
+    pub fn install_panic_reporter(&self) {
+        let signalling = Arc::clone(&self.signalling);
+
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if send(
+                &signalling,
+                &error_message(&info.to_string(), Some("panic")),
+            ) {
+                // The process aborts below, so give the writer task a moment to flush.
+                std::thread::sleep(Duration::from_millis(500));
+            }
+            default_hook(info);
+            // Panics unwind so the SFU can contain them in session tasks, but
+            // a grabber task that panicked would leave the stream half
+            // working, so the grabber still exits.
+            std::process::abort();
+        }));
+    }
+
+    /// Whether the server told this grabber to stop. Frames are no longer
+    /// taken once it has, so the source's channel closes.
+    pub fn shut_down_by_server(&self) -> bool {
+        self.shut_down.load(Ordering::Relaxed)
+    }
+
+    pub async fn shutdown(&mut self) {
+        if let Some(lock_task) = self.lock_task.take() {
+            lock_task.abort();
+        }
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(supervisor) = self.supervisor.take() {
+            let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, supervisor).await;
         }
     }
 
+    /// Publishes once, then keeps the stream up: when signalling drops or the
+    /// peer connection fails, it reconnects with backoff and publishes again.
+    /// The returned channel, for frames encoded as `mime_type`, outlives
+    /// reconnects. The track is labelled as described by `metadata`. Opus
+    /// frames are published as the only track, without video.
     pub async fn connect_and_publish(
         &mut self,
-        _width: u32,
-        _height: u32,
+        mime_type: &'static str,
+        metadata: TrackMetadata,
     ) -> Result<mpsc::UnboundedSender<Vec<u8>>> {
+        let audio_only = mime_type == OPUS;
+        let tracks = Tracks {
+            mime_type,
+            metadata,
+            audio: self.audio && !audio_only,
+        };
+        let api = Arc::new(build_api(mime_type, tracks.audio)?);
+        let session = Session::establish(
+            &api,
+            &self.ws_url,
+            &self.credential,
+            None,
+            self.allow_remote_control,
+            &tracks,
+        )
+        .await?;
+
+        let (frame_tx, frame_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let (track_tx, track_rx) = watch::channel(Arc::clone(&session.track));
+        let paused = Arc::new(AtomicBool::new(false));
+        let frames = Arc::new(AtomicU64::new(0));
+        spawn_writer(
+            frame_rx,
+            track_rx,
+            if audio_only {
+                AUDIO_FRAME_DURATION
+            } else {
+                VIDEO_FRAME_DURATION
+            },
+            Arc::clone(&paused),
+            Some(Arc::clone(&frames)),
+        );
+
+        let audio_track_tx = session.audio_track.as_ref().map(|track| {
+            let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+            let (audio_track_tx, audio_track_rx) = watch::channel(Arc::clone(track));
+            spawn_writer(
+                audio_rx,
+                audio_track_rx,
+                AUDIO_FRAME_DURATION,
+                Arc::clone(&paused),
+                None,
+            );
+            self.audio_frames = Some(audio_tx);
+            audio_track_tx
+        });
 
-        let (ws_stream, _) = connect_async(&self.ws_url)
-            .await
-            .context("Failed to connect to WebSocket")?;
-
-        let (mut ws_tx, mut ws_rx) = ws_stream.split();
-
-        let auth_msg = GrabberMessage {
-            event: "AUTH".to_string(),
-            grabber_auth: Some(GrabberAuth {
-                credential: self.credential.clone(),
-            }),
-            offer: None,
-            answer: None,
-            ice: None,
+        *self.signalling.lock().unwrap() = Some(session.client.sender());
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let supervisor = Supervisor {
+            api,
+            ws_url: self.ws_url.clone(),
+            credential: self.credential.clone(),
+            allow_remote_control: self.allow_remote_control,
+            tracks,
+            signalling: Arc::clone(&self.signalling),
+            track_tx,
+            audio_track_tx,
+            paused: Arc::clone(&paused),
+            frames,
+            uplink: UplinkMonitor::new(self.notify_degraded_uplink),
+            operator: self.operator.take(),
+            pipeline_stats: self.pipeline_stats.take(),
+            shut_down: Arc::clone(&self.shut_down),
         };
+        self.supervisor = Some(tokio::spawn(supervisor.run(session, stop_rx)));
+        self.stop = Some(stop_tx);
+
+        if self.pause_when_locked {
+            let mut events = power::watch_power();
+            let signalling = Arc::clone(&self.signalling);
+            let commands = self.commands.clone();
+            self.lock_task = Some(tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    if event != PowerEvent::Woke {
+                        let locked = event == PowerEvent::Locked;
+                        paused.store(locked, Ordering::Relaxed);
+                        send(
+                            &signalling,
+                            &publisher_state_message(locked, locked.then_some("locked")),
+                        );
+                        if locked {
+                            continue;
+                        }
+                    }
+                    // Viewers' decoders missed everything since the pause.
+                    if let Some(commands) = &commands {
+                        let _ = commands.send(Command::Control(SourceControl::RequestKeyframe));
+                    }
+                }
+            }));
+        }
 
-        ws_tx
-            .send(Message::Text(serde_json::to_string(&auth_msg)?))
-            .await
-            .context("Failed to send auth")?;
-
-        while let Some(msg) = ws_rx.next().await {
-            let msg = msg.context("WebSocket error")?;
-            if let Message::Text(text) = msg {
-                let parsed: GrabberMessage = serde_json::from_str(&text)?;
-                if parsed.event == "INIT_PEER" {
-                    break;
+        Ok(frame_tx)
+    }
+}
+
+/// Writes each frame from `frames` to the current track as a sample lasting
+/// `duration`, counting those written in `written`. Frames are dropped while
+/// paused or reconnecting; the writer stops once the supervisor has.
+fn spawn_writer(
+    mut frames: mpsc::UnboundedReceiver<Vec<u8>>,
+    track_rx: watch::Receiver<Arc<TrackLocalStaticSample>>,
+    duration: Duration,
+    paused: Arc<AtomicBool>,
+    written: Option<Arc<AtomicU64>>,
+) {
+    tokio::spawn(async move {
+        while let Some(frame_data) = frames.recv().await {
+            if track_rx.has_changed().is_err() {
+                break;
+            }
+            if paused.load(Ordering::Relaxed) {
+                continue;
+            }
+            let sample = Sample {
+                data: frame_data.into(),
+                duration,
+                ..Default::default()
+            };
+
+            let track = Arc::clone(&track_rx.borrow());
+            if track.write_sample(&sample).await.is_ok() {
+                if let Some(written) = &written {
+                    written.fetch_add(1, Ordering::Relaxed);
                 }
             }
         }
+    });
+}
+
+/// Forwards the bitrate of each REMB the SFU sends for `sender`'s track,
+/// which it lowers to what the track's subscribers can take, until the
+/// connection closes.
+async fn read_estimates(sender: Arc<RTCRtpSender>, estimates: mpsc::UnboundedSender<u64>) {
+    use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+
+    while let Ok((packets, _)) = sender.read_rtcp().await {
+        for packet in packets {
+            if let Some(remb) = packet
+                .as_any()
+                .downcast_ref::<ReceiverEstimatedMaximumBitrate>()
+            {
+                let _ = estimates.send(remb.bitrate as u64);
+            }
+        }
+    }
+}
+
+/// Sends on the current connection; false while there is none.
+fn send(signalling: &SharedSignalling, msg: &GrabberMessage) -> bool {
+    // A panic while the lock is held must not stop the panic reporter.
+    let Ok(signalling) = signalling.lock() else {
+        return false;
+    };
+    signalling
+        .as_ref()
+        .is_some_and(|signalling| signalling.send(msg).is_ok())
+}
 
-        let mut media_engine = MediaEngine::default();
+/// An API whose offers carry only `mime_type`, and Opus with `audio`, so the
+/// SFU can't negotiate another codec. Payload types match the SFU's defaults.
+fn build_api(mime_type: &str, audio: bool) -> Result<API> {
+    let mut media_engine = MediaEngine::default();
 
-        use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecParameters;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecParameters;
 
-        let fmtp = "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f;x-google-max-bitrate=15000;x-google-min-bitrate=1000;x-google-start-bitrate=5000".to_owned();
+    let video = match mime_type {
+        "video/H264" => Some((102, "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f;x-google-max-bitrate=15000;x-google-min-bitrate=1000;x-google-start-bitrate=5000")),
+        "video/VP8" => Some((96, "")),
+        "video/VP9" => Some((98, "profile-id=0")),
+        "video/AV1" => Some((45, "")),
+        OPUS => None,
+        _ => anyhow::bail!("Can't publish {}", mime_type),
+    };
 
+    if let Some((payload_type, fmtp)) = video {
         media_engine.register_codec(
             RTCRtpCodecParameters {
                 capability: RTCRtpCodecCapability {
-                    mime_type: "video/H264".to_owned(),
+                    mime_type: mime_type.to_owned(),
                     clock_rate: 90000,
                     channels: 0,
-                    sdp_fmtp_line: fmtp,
+                    sdp_fmtp_line: fmtp.to_owned(),
                     rtcp_feedback: vec![],
                 },
-                payload_type: 102,
+                payload_type,
                 ..Default::default()
             },
             webrtc::rtp_transceiver::rtp_codec::RTPCodecType::Video,
         )?;
+    }
+
+    if audio || video.is_none() {
+        media_engine.register_codec(
+            RTCRtpCodecParameters {
+                capability: opus_capability(),
+                payload_type: 111,
+                ..Default::default()
+            },
+            webrtc::rtp_transceiver::rtp_codec::RTPCodecType::Audio,
+        )?;
+    }
 
-        let mut registry = webrtc::interceptor::registry::Registry::new();
-        registry = register_default_interceptors(registry, &mut media_engine)?;
+    let mut registry = webrtc::interceptor::registry::Registry::new();
+    registry = register_default_interceptors(registry, &mut media_engine)?;
 
-        let api = APIBuilder::new()
-            .with_media_engine(media_engine)
-            .with_interceptor_registry(registry)
-            .build();
+    Ok(APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build())
+}
 
-        let config = RTCConfiguration {
-            ice_servers: vec![RTCIceServer {
-                urls: vec![],
-                ..Default::default()
-            }],
-            ..Default::default()
-        };
+fn opus_capability() -> RTCRtpCodecCapability {
+    RTCRtpCodecCapability {
+        mime_type: OPUS.to_owned(),
+        clock_rate: 48000,
+        channels: 2,
+        sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
+        rtcp_feedback: vec![],
+    }
+}
 
-        let pc = Arc::new(api.new_peer_connection(config).await?);
+/// What every session publishes: a track encoded as `mime_type`, labelled
+/// by `metadata` whose `track_id` is filled in per session, and Opus with
+/// `audio`.
+struct Tracks {
+    mime_type: &'static str,
+    metadata: TrackMetadata,
+    audio: bool,
+}
 
-        let video_track = Arc::new(TrackLocalStaticSample::new(
-            RTCRtpCodecCapability {
-                mime_type: "video/H264".to_owned(),
-                ..Default::default()
-            },
-            "video".to_owned(),
-            "webcam".to_owned(),
-        ));
+/// One signalling connection and the peer connection published over it.
+struct Session {
+    client: PublisherClient,
+    pc: Arc<RTCPeerConnection>,
+    track: Arc<TrackLocalStaticSample>,
+    audio_track: Option<Arc<TrackLocalStaticSample>>,
+    remote_control: Option<Arc<RTCDataChannel>>,
+    failed: mpsc::UnboundedReceiver<()>,
+    ice_disconnected: mpsc::UnboundedReceiver<()>,
+    /// The bitrates the SFU's REMBs for `track` carry.
+    estimates: mpsc::UnboundedReceiver<u64>,
+}
 
-        pc.add_track(Arc::clone(&video_track) as Arc<dyn TrackLocal + Send + Sync>)
-            .await?;
+impl Session {
+    async fn establish(
+        api: &API,
+        ws_url: &str,
+        credential: &str,
+        resume_token: Option<&str>,
+        allow_remote_control: bool,
+        tracks: &Tracks,
+    ) -> Result<Self> {
+        let mut client = PublisherClient::resume(ws_url, credential, resume_token).await?;
+        let pc_config = &client.init_peer().pc_config;
+        if pc_config.encoded_insertable_streams {
+            warn!("The SFU advertises end-to-end encryption, but this grabber sends unencrypted frames");
+        }
 
-        let ws_tx_clone = Arc::new(tokio::sync::Mutex::new(ws_tx));
-        let ws_tx_for_ice = Arc::clone(&ws_tx_clone);
-
-        pc.on_ice_candidate(Box::new(move |candidate| {
-            let ws_tx = Arc::clone(&ws_tx_for_ice);
-            Box::pin(async move {
-                if let Some(candidate) = candidate {
-                    if let Ok(init) = candidate.to_json() {
-                        let ice_msg = GrabberMessage {
-                            event: "GRABBER_ICE".to_string(),
-                            grabber_auth: None,
-                            offer: None,
-                            answer: None,
-                            ice: Some(IceMessage { candidate: init }),
-                        };
-
-                        if let Ok(json) = serde_json::to_string(&ice_msg) {
-                            let _ = ws_tx.lock().await.send(Message::Text(json)).await;
-                        }
-                    }
-                }
-            })
+        // The server's STUN/TURN servers, e.g. a TURN relay for grabbers
+        // behind a firewall.
+        let pc = Arc::new(
+            api.new_peer_connection(pc_config.to_rtc_configuration())
+                .await?,
+        );
+
+        let (failed_tx, failed) = mpsc::unbounded_channel();
+        pc.on_peer_connection_state_change(Box::new(move |state| {
+            if state == RTCPeerConnectionState::Failed {
+                let _ = failed_tx.send(());
+            }
+            Box::pin(async {})
         }));
 
-        use webrtc::peer_connection::offer_answer_options::RTCOfferOptions;
-        let offer = pc
-            .create_offer(Some(RTCOfferOptions {
-                ..Default::default()
-            }))
+        // Usually the network changed, e.g. Wi-Fi to Ethernet. An ICE restart
+        // can recover before the connection fails and has to be replaced.
+        let (ice_disconnected_tx, ice_disconnected) = mpsc::unbounded_channel();
+        pc.on_ice_connection_state_change(Box::new(move |state| {
+            if state == RTCIceConnectionState::Disconnected {
+                let _ = ice_disconnected_tx.send(());
+            }
+            Box::pin(async {})
+        }));
+
+        // The SFU tells screens from cameras by the stream id.
+        let track = Arc::new(if tracks.mime_type == OPUS {
+            TrackLocalStaticSample::new(
+                opus_capability(),
+                "audio".to_owned(),
+                tracks.metadata.label.clone(),
+            )
+        } else {
+            TrackLocalStaticSample::new(
+                RTCRtpCodecCapability {
+                    mime_type: tracks.mime_type.to_owned(),
+                    ..Default::default()
+                },
+                "video".to_owned(),
+                tracks.metadata.label.clone(),
+            )
+        });
+
+        let sender = pc
+            .add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>)
             .await?;
+        let (estimates_tx, estimates) = mpsc::unbounded_channel();
+        tokio::spawn(read_estimates(sender, estimates_tx));
+
+        let audio_track = if tracks.audio {
+            let audio_track = Arc::new(TrackLocalStaticSample::new(
+                opus_capability(),
+                "audio".to_owned(),
+                tracks.metadata.label.clone(),
+            ));
+            pc.add_track(Arc::clone(&audio_track) as Arc<dyn TrackLocal + Send + Sync>)
+                .await?;
+            Some(audio_track)
+        } else {
+            None
+        };
 
-        pc.set_local_description(offer.clone()).await?;
-
-        let offer_msg = GrabberMessage {
-            event: "OFFER".to_string(),
-            grabber_auth: None,
-            offer: Some(OfferMessage {
-                type_: "offer".to_string(),
-                sdp: offer.sdp,
-            }),
-            answer: None,
-            ice: None,
+        let remote_control = if allow_remote_control {
+            Some(remote_control::open(&pc).await?)
+        } else {
+            None
         };
 
-        ws_tx_clone
-            .lock()
-            .await
-            .send(Message::Text(serde_json::to_string(&offer_msg)?))
-            .await?;
+        client.publish(&pc).await?;
+        let mut metadata = vec![TrackMetadata {
+            track_id: track.id().to_owned(),
+            ..tracks.metadata.clone()
+        }];
+        if let Some(audio_track) = &audio_track {
+            metadata.push(TrackMetadata {
+                track_id: audio_track.id().to_owned(),
+                label: "audio".to_owned(),
+                ..Default::default()
+            });
+        }
+        client.set_track_metadata(metadata)?;
+
+        Ok(Self {
+            client,
+            pc,
+            track,
+            audio_track,
+            remote_control,
+            failed,
+            ice_disconnected,
+            estimates,
+        })
+    }
+
+    /// Handles server events and pings the server until the connection is
+    /// lost or the server shuts the grabber down, and says why. `frames`
+    /// counts frames written to the track.
+    async fn run(
+        &mut self,
+        uplink: &mut UplinkMonitor,
+        operator: &mut Option<OperatorSettings>,
+        pipeline_stats: Option<&watch::Receiver<Option<PipelineStats>>>,
+        frames: &AtomicU64,
+        paused: &AtomicBool,
+    ) -> Ended {
+        let period =
+            Duration::from_millis(self.client.init_peer().ping_interval).max(MIN_PING_INTERVAL);
+        let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        let mut last_frames = frames.load(Ordering::Relaxed);
+        let mut stalled = false;
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    let written = frames.load(Ordering::Relaxed);
+                    let streaming = written != last_frames;
+                    last_frames = written;
+                    if !streaming && !stalled && !paused.load(Ordering::Relaxed) {
+                        warn!("No frames from the capture pipeline in the last {:?}", period);
+                    }
+                    stalled = !streaming;
+                    if let Some(operator) = operator.as_mut() {
+                        operator.expire_estimate();
+                    }
 
-        let mut answer_received = false;
-        while let Some(msg) = ws_rx.next().await {
-            let msg = msg.context("WebSocket error")?;
-            if let Message::Text(text) = msg {
-                let parsed: GrabberMessage = serde_json::from_str(&text)?;
-
-                match parsed.event.as_str() {
-                    "ANSWER" => {
-                        if let Some(answer_data) = parsed.answer {
-                            let answer = RTCSessionDescription::answer(answer_data.sdp)?;
-                            pc.set_remote_description(answer).await?;
-                            answer_received = true;
-                            break;
+                    // Only streams that are sending frames count, so the
+                    // server sees a stalled pipeline.
+                    let connected =
+                        self.pc.connection_state() == RTCPeerConnectionState::Connected;
+                    // Players subscribe to "audio" for just the audio track.
+                    let mut stream_types = Vec::new();
+                    if streaming {
+                        stream_types.push(self.track.stream_id().to_string());
+                        if self.audio_track.is_some() {
+                            stream_types.push("audio".to_string());
                         }
                     }
-                    "SERVER_ICE" => {
-                        if let Some(ice_data) = parsed.ice {
-                            pc.add_ice_candidate(ice_data.candidate).await?;
+                    let pipeline = pipeline_stats.and_then(|stats| stats.borrow().clone());
+                    if let Err(e) = self.client.ping(connected as u32, stream_types, pipeline) {
+                        return Ended::Lost(format!("signalling connection error: {}", e));
+                    }
+                }
+                event = self.client.next_event(&self.pc) => match event {
+                    Ok(Some(msg)) => {
+                        if msg.event == "SHUTDOWN" {
+                            let reason = msg.shutdown.and_then(|shutdown| shutdown.reason);
+                            return Ended::ShutDown(reason);
+                        }
+                        if let Some(quality) = msg.ingest_quality {
+                            uplink.report(&quality);
+                        }
+                        if let (Some(settings), Some(operator)) = (msg.settings, operator.as_mut()) {
+                            operator.apply(&settings);
+                        }
+                        if let (Some(quality), Some(operator)) = (msg.quality, operator.as_ref()) {
+                            operator.set_quality(&quality);
                         }
                     }
-                    "OFFER_FAILED" => {
-                        anyhow::bail!("Server rejected offer: OFFER_FAILED");
+                    Ok(None) => return Ended::Lost("signalling connection closed".to_string()),
+                    Err(e) => return Ended::Lost(format!("signalling connection error: {}", e)),
+                },
+                _ = self.ice_disconnected.recv() => {
+                    info!("ICE disconnected, restarting it");
+                    if let Err(e) = self.client.restart_ice(&self.pc).await {
+                        return Ended::Lost(format!("ICE restart failed: {}", e));
                     }
-                    _ => {}
                 }
+                Some(bitrate_bps) = self.estimates.recv() => {
+                    if let Some(operator) = operator.as_mut() {
+                        operator.set_estimate(bitrate_bps);
+                    }
+                }
+                _ = self.failed.recv() => return Ended::Lost("peer connection failed".to_string()),
             }
         }
+    }
 
-        if !answer_received {
-            anyhow::bail!("Connection closed before receiving answer");
+    async fn close(self) {
+        self.client.close().await;
+        if let Some(channel) = self.remote_control {
+            let _ = channel.close().await;
         }
+        let _ = self.pc.close().await;
+    }
+}
 
-        let (frame_tx, mut frame_rx) = mpsc::unbounded_channel::<Vec<u8>>();
-        let video_track_clone = Arc::clone(&video_track);
-
-        tokio::spawn(async move {
-            let frame_duration = std::time::Duration::from_micros(33_333);
+/// Why a [`Session`] stopped.
+enum Ended {
+    /// The connection was lost, for the given reason, and is re-established.
+    Lost(String),
+    /// An admin shut the grabber down, with their reason if they gave one.
+    ShutDown(Option<String>),
+}
 
-            while let Some(frame_data) = frame_rx.recv().await {
-                let sample = Sample {
-                    data: frame_data.into(),
-                    duration: frame_duration,
-                    ..Default::default()
-                };
+/// Keeps the stream published across dropped connections, until stopped or
+/// shut down by the server.
+struct Supervisor {
+    api: Arc<API>,
+    ws_url: String,
+    credential: String,
+    allow_remote_control: bool,
+    tracks: Tracks,
+    signalling: SharedSignalling,
+    /// Where the frame writer sends samples.
+    track_tx: watch::Sender<Arc<TrackLocalStaticSample>>,
+    audio_track_tx: Option<watch::Sender<Arc<TrackLocalStaticSample>>>,
+    paused: Arc<AtomicBool>,
+    frames: Arc<AtomicU64>,
+    uplink: UplinkMonitor,
+    operator: Option<OperatorSettings>,
+    pipeline_stats: Option<watch::Receiver<Option<PipelineStats>>>,
+    shut_down: Arc<AtomicBool>,
+}
 
-                if video_track_clone.write_sample(&sample).await.is_err() {
-                    break;
+impl Supervisor {
+    async fn run(mut self, mut session: Session, mut stop: oneshot::Receiver<()>) {
+        loop {
+            if let Some(operator) = &mut self.operator {
+                operator.apply(&session.client.init_peer().settings);
+            }
+            let ended = tokio::select! {
+                ended = session.run(
+                    &mut self.uplink,
+                    &mut self.operator,
+                    self.pipeline_stats.as_ref(),
+                    &self.frames,
+                    &self.paused,
+                ) => Some(ended),
+                _ = &mut stop => None,
+            };
+            let reason = match ended {
+                Some(Ended::Lost(reason)) => reason,
+                Some(Ended::ShutDown(reason)) => {
+                    match reason {
+                        Some(reason) => warn!("The server shut this grabber down: {}", reason),
+                        None => warn!("The server shut this grabber down"),
+                    }
+                    self.shut_down.store(true, Ordering::Relaxed);
+                    *self.signalling.lock().unwrap() = None;
+                    session.close().await;
+                    return;
+                }
+                None => {
+                    session.close().await;
+                    return;
                 }
+            };
+
+            warn!("Lost connection to the server ({}), reconnecting", reason);
+            *self.signalling.lock().unwrap() = None;
+            let resume_token = session.client.init_peer().resume_token.clone();
+            session.close().await;
+
+            session = match self.reconnect(resume_token.as_deref(), &mut stop).await {
+                Some(session) => session,
+                None => return,
+            };
+            info!("Reconnected to the server");
+
+            self.track_tx.send_replace(Arc::clone(&session.track));
+            if let (Some(audio_track_tx), Some(audio_track)) =
+                (&self.audio_track_tx, &session.audio_track)
+            {
+                audio_track_tx.send_replace(Arc::clone(audio_track));
             }
-        });
-
-        tokio::spawn(async move {
-            while let Some(msg) = ws_rx.next().await {
-                if let Ok(Message::Text(_text)) = msg {}
+            *self.signalling.lock().unwrap() = Some(session.client.sender());
+            if self.paused.load(Ordering::Relaxed) {
+                send(
+                    &self.signalling,
+                    &publisher_state_message(true, Some("locked")),
+                );
             }
-        });
+        }
+    }
 
-        self.pc = Some(pc);
-        self.video_track = Some(video_track);
+    /// Retries with exponential backoff and jitter until a new session is
+    /// published; `None` if stopped first.
+    async fn reconnect(
+        &self,
+        resume_token: Option<&str>,
+        stop: &mut oneshot::Receiver<()>,
+    ) -> Option<Session> {
+        let mut delay = RECONNECT_DELAY_MIN;
+        loop {
+            // Spread reconnects out so grabbers dropped together don't all
+            // return at once.
+            let jittered = delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+            tokio::select! {
+                _ = tokio::time::sleep(jittered) => {}
+                _ = &mut *stop => return None,
+            }
 
-        Ok(frame_tx)
+            match Session::establish(
+                &self.api,
+                &self.ws_url,
+                &self.credential,
+                resume_token,
+                self.allow_remote_control,
+                &self.tracks,
+            )
+            .await
+            {
+                Ok(session) => return Some(session),
+                Err(e) => warn!("Reconnect failed: {:#}", e),
+            }
+            delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+        }
     }
 }
@@ -0,0 +1,72 @@
+//! Renumbering one publisher packet for every subscriber: a clone per
+//! subscriber against each subscriber's reused [`PacketBuffer`].
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use sfu_local::packet::PacketBuffer;
+use std::hint::black_box;
+use webrtc::rtp::header::{Extension, Header};
+use webrtc::rtp::packet::Packet;
+
+const SUBSCRIBERS: [usize; 3] = [10, 100, 1000];
+
+/// A video packet carrying the extensions browsers typically send.
+fn packet() -> Packet {
+    Packet {
+        header: Header {
+            version: 2,
+            extension: true,
+            extension_profile: 0xBEDE,
+            extensions: vec![
+                Extension {
+                    id: 2,
+                    payload: Bytes::from_static(&[0x12, 0x34, 0x56]),
+                },
+                Extension {
+                    id: 4,
+                    payload: Bytes::from_static(&[0x00, 0x01]),
+                },
+            ],
+            ..Default::default()
+        },
+        payload: Bytes::from(vec![0u8; 1200]),
+    }
+}
+
+fn fan_out(c: &mut Criterion) {
+    let packet = packet();
+    let mut group = c.benchmark_group("fan_out");
+    for subscribers in SUBSCRIBERS {
+        group.bench_with_input(
+            BenchmarkId::new("clone", subscribers),
+            &subscribers,
+            |b, &subscribers| {
+                b.iter(|| {
+                    for i in 0..subscribers {
+                        let mut renumbered = packet.clone();
+                        renumbered.header.sequence_number = i as u16;
+                        renumbered.header.timestamp = i as u32;
+                        black_box(&renumbered);
+                    }
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("buffer", subscribers),
+            &subscribers,
+            |b, &subscribers| {
+                let mut buffers: Vec<_> =
+                    (0..subscribers).map(|_| PacketBuffer::default()).collect();
+                b.iter(|| {
+                    for (i, buffer) in buffers.iter_mut().enumerate() {
+                        black_box(buffer.renumber(&packet, i as u16, i as u32));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fan_out);
+criterion_main!(benches);
@@ -0,0 +1,125 @@
+//! Voice activity from the audio level RTP header extension (RFC 6464),
+//! which publishers put on every audio packet: one byte holding the level
+//! in -dBov, 0 the loudest and 127 silence.
+
+use sfu_core::SfuEvent;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use webrtc::rtp::header::Header;
+
+use crate::tasks::SessionTasks;
+
+pub const AUDIO_LEVEL_URI: &str = "urn:ietf:params:rtp-hdrext:ssrc-audio-level";
+
+/// Louder than -45 dBov counts as speech.
+const SPEAKING_LEVEL: u8 = 45;
+/// Weight of the newest packet in the smoothed level, so a click doesn't
+/// count as speech.
+const SMOOTHING: f32 = 0.2;
+/// How long the level must stay below [`SPEAKING_LEVEL`] before speech ends,
+/// so pauses between words don't.
+const SILENCE_HOLD: Duration = Duration::from_millis(1000);
+
+/// The level in -dBov carried by an audio level extension payload.
+pub fn parse(payload: &[u8]) -> Option<u8> {
+    payload.first().map(|byte| byte & 0x7f)
+}
+
+/// Whether a track is speaking, from the levels of its packets.
+pub struct SpeechDetector {
+    /// Smoothed level in -dBov.
+    level: f32,
+    speaking: bool,
+    last_loud: Option<Instant>,
+}
+
+impl Default for SpeechDetector {
+    fn default() -> Self {
+        Self {
+            level: 127.0,
+            speaking: false,
+            last_loud: None,
+        }
+    }
+}
+
+impl SpeechDetector {
+    /// Feeds one packet's level; returns the new state if it changed.
+    pub fn update(&mut self, level: u8, at: Instant) -> Option<bool> {
+        self.level += (level as f32 - self.level) * SMOOTHING;
+        if self.level < SPEAKING_LEVEL as f32 {
+            self.last_loud = Some(at);
+        }
+        let speaking = self
+            .last_loud
+            .is_some_and(|loud| at.saturating_duration_since(loud) < SILENCE_HOLD);
+
+        (speaking != self.speaking).then(|| {
+            self.speaking = speaking;
+            speaking
+        })
+    }
+
+    pub fn speaking(&self) -> bool {
+        self.speaking
+    }
+}
+
+/// An audio track's voice activity, reported as
+/// [`SfuEvent::SpeakingChanged`]. Shared by the track's readers, so it
+/// carries over when the broadcaster switches source.
+#[derive(Clone)]
+pub(crate) struct AudioLevels {
+    track_id: String,
+    /// The extension id negotiated with the publisher; 0 until known.
+    extension_id: Arc<AtomicU8>,
+    detector: Arc<Mutex<SpeechDetector>>,
+    tasks: SessionTasks,
+}
+
+impl AudioLevels {
+    pub fn new(track_id: String, tasks: SessionTasks) -> Self {
+        Self {
+            track_id,
+            extension_id: Arc::new(AtomicU8::new(0)),
+            detector: Arc::new(Mutex::new(SpeechDetector::default())),
+            tasks,
+        }
+    }
+
+    pub fn set_extension_id(&self, id: Option<u8>) {
+        self.extension_id.store(id.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Feeds the level of a packet received at `at`, if it carries one.
+    pub fn observe(&self, header: &Header, at: Instant) {
+        let id = self.extension_id.load(Ordering::Relaxed);
+        if id == 0 {
+            return;
+        }
+        let Some(level) = header.get_extension(id).as_deref().and_then(parse) else {
+            return;
+        };
+        let changed = self.detector.lock().unwrap().update(level, at);
+        if let Some(speaking) = changed {
+            self.report(speaking);
+        }
+    }
+
+    /// The track ended; ends its speech too.
+    pub fn stop(&self) {
+        let was_speaking = std::mem::take(&mut self.detector.lock().unwrap().speaking);
+        if was_speaking {
+            self.report(false);
+        }
+    }
+
+    fn report(&self, speaking: bool) {
+        self.tasks.emit(SfuEvent::SpeakingChanged {
+            publisher_id: self.tasks.session_id().to_string(),
+            track_id: self.track_id.clone(),
+            speaking,
+        });
+    }
+}
@@ -0,0 +1,92 @@
+//! Enforces `performance.max_publisher_bitrate_kbps`: splits the cap into
+//! REMB targets for a publisher's video tracks and reports a publisher that
+//! keeps sending more regardless, e.g. a grabber with a fixed encoder
+//! bitrate.
+
+use sfu_core::SfuEvent;
+use std::sync::Weak;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::ConfigHandle;
+use crate::session::PublisherSession;
+use crate::tasks::SessionTasks;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Over the cap by more than this share counts as exceeding it, so the
+/// encoder's overshoot while it adapts doesn't.
+const TOLERANCE: f64 = 0.1;
+/// Checks in a row over the cap before the publisher is reported.
+const OVER_CAP_CHECKS: u32 = 5;
+/// Video isn't asked to go below this, however much of the cap audio takes.
+const MIN_VIDEO_BPS: u64 = 100_000;
+
+/// Runs until the session is dropped.
+pub(crate) async fn enforce(
+    session: Weak<PublisherSession>,
+    config: ConfigHandle,
+    tasks: SessionTasks,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut over_cap = 0;
+    loop {
+        interval.tick().await;
+        let Some(session) = session.upgrade() else {
+            break;
+        };
+        let broadcasters = session.get_all_broadcasters();
+        let Some(cap_kbps) = config.current().performance.max_publisher_bitrate_kbps else {
+            for (_, broadcaster) in &broadcasters {
+                broadcaster.set_bitrate_cap(None);
+            }
+            over_cap = 0;
+            continue;
+        };
+        let cap_bps = cap_kbps * 1000;
+
+        let mut bitrate_bps = 0;
+        let mut audio_bps = 0;
+        let mut video = Vec::new();
+        for (_, broadcaster) in broadcasters {
+            let ingest = broadcaster.ingest_stats().bitrate_bps;
+            bitrate_bps += ingest;
+            if broadcaster.kind == "video" {
+                video.push(broadcaster);
+            } else {
+                audio_bps += ingest;
+            }
+        }
+        if !video.is_empty() {
+            let share = cap_bps.saturating_sub(audio_bps) / video.len() as u64;
+            for broadcaster in &video {
+                broadcaster.set_bitrate_cap(Some(share.max(MIN_VIDEO_BPS)));
+            }
+        }
+
+        if bitrate_bps as f64 <= cap_bps as f64 * (1.0 + TOLERANCE) {
+            if over_cap >= OVER_CAP_CHECKS {
+                info!(
+                    "Publisher {} is back under its {} kbps cap",
+                    tasks.session_id(),
+                    cap_kbps
+                );
+            }
+            over_cap = 0;
+            continue;
+        }
+        over_cap += 1;
+        if over_cap == OVER_CAP_CHECKS {
+            warn!(
+                "Publisher {} sends {} kbps, over its {} kbps cap",
+                tasks.session_id(),
+                bitrate_bps / 1000,
+                cap_kbps
+            );
+            tasks.emit(SfuEvent::BitrateCapExceeded {
+                publisher_id: tasks.session_id().to_string(),
+                bitrate_bps,
+                cap_bps,
+            });
+        }
+    }
+}
@@ -1,6 +1,17 @@
+use crate::audio_level::AudioLevels;
+use crate::config::{ContentProfile, PacingConfig, PerformanceConfig};
+use crate::feedback::{DownstreamFeedback, DownstreamSnapshot};
+use crate::munger::RtpMunger;
+use crate::pacer::Pacer;
+use crate::packet::PacketBuffer;
+use crate::stats::{EgressStats, IngestSnapshot, IngestStats, IngestTracker};
+use crate::sync::{SenderClock, SyncGroup, SyncMember};
+use crate::tasks::SessionTasks;
 use dashmap::DashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::{mpsc, RwLock};
 use tokio::{sync::broadcast, task::JoinHandle};
 use tracing::{error, info, trace, warn};
@@ -12,110 +23,280 @@ use webrtc::{
     track::track_local::{track_local_static_rtp::TrackLocalStaticRTP, TrackLocal},
 };
 
+/// How often the content policy advertises REMB and checks the keyframe
+/// interval.
+const POLICY_INTERVAL: Duration = Duration::from_secs(1);
+const FRAME_TICK_CAPACITY: usize = 64;
+/// webrtc's default receive MTU; `read_rtp` allocates a buffer this size for
+/// every packet, so the reader keeps one instead.
+const RECEIVE_MTU: usize = 1460;
+/// Most packets a subscriber in a sync group holds back, about a second of
+/// a 40 Mbps screen; a faster stream is aligned less closely.
+const MAX_HELD_PACKETS: usize = 4096;
+
+/// A packet from the publisher and when it arrived, shared by every
+/// subscriber's forwarder. The payload is `Bytes`, so the copy webrtc makes
+/// per subscriber shares it rather than copying it.
+struct Received {
+    packet: Packet,
+    at: Instant,
+    /// Which source track it came from; bumped on every rebind.
+    source: u32,
+}
+
+/// The last packet of a video frame arrived.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTick {
+    pub rtp_timestamp: u32,
+    pub at: SystemTime,
+}
+
+/// Per-subscriber forwarding state. `task` is `None` while the subscriber has
+/// paused the track.
+struct Forwarder {
+    track: Arc<TrackLocalStaticRTP>,
+    egress: Arc<EgressStats>,
+    /// Outlives the task, so a resumed track continues where it stopped.
+    munger: Arc<Mutex<RtpMunger>>,
+    /// Set when the subscriber's playback is aligned with other tracks.
+    sync: Option<Arc<SyncMember>>,
+    /// The subscriber's, so a panicking forwarder closes the subscriber
+    /// rather than the publisher.
+    tasks: SessionTasks,
+    task: Option<JoinHandle<()>>,
+}
+
 pub struct TrackBroadcaster {
     pub id: String,
+    pub stream_id: String,
     pub kind: String,
     pub mime_type: String,
     pub codec_capability: webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
-    pub ssrc: u32,
-    tx: broadcast::Sender<Arc<Packet>>,
-    read_task: JoinHandle<()>,
-    subscribers: Arc<DashMap<String, JoinHandle<()>>>,
-    peer_connection: Arc<RTCPeerConnection>,
+    ssrc: Arc<AtomicU32>,
+    /// The latest [`Received::source`].
+    source: AtomicU32,
+    tx: broadcast::Sender<Arc<Received>>,
+    /// Only sent to for video.
+    frame_ticks: broadcast::Sender<FrameTick>,
+    read_task: Mutex<JoinHandle<()>>,
+    /// The publisher's capture clock, from its sender reports.
+    sender_clock: Arc<SenderClock>,
+    sender_report_task: Mutex<JoinHandle<()>>,
+    subscribers: Arc<DashMap<String, Forwarder>>,
+    /// Set while an operator has silenced the track; packets are dropped
+    /// for every subscriber.
+    muted: Arc<AtomicBool>,
+    /// Where PLIs and REMBs go; swapped when a reconnecting grabber resumes
+    /// the publisher on a new peer connection.
+    peer_connection: Arc<Mutex<Arc<RTCPeerConnection>>>,
     last_pli_time: Arc<RwLock<Option<Instant>>>,
     pli_request_tx: mpsc::UnboundedSender<()>,
-    pli_task: JoinHandle<()>,
+    /// Only spawned for video.
+    pli_task: Option<JoinHandle<()>>,
+    /// Screen or camera policy; `None` for audio.
+    profile: Arc<Mutex<Option<ContentProfile>>>,
+    policy_task: Option<JoinHandle<()>>,
+    limits: Arc<RembLimits>,
+    ingest_stats: Arc<IngestStats>,
+    /// Paces each subscriber's copy; only set for video.
+    pacing: Option<PacingConfig>,
+    latency_budget: Option<Duration>,
+    /// Only set for audio.
+    audio_levels: Option<AudioLevels>,
+    /// Spawns the broadcaster's tasks, reporting panics against its publisher.
+    tasks: SessionTasks,
 }
 
 impl TrackBroadcaster {
-    pub fn new(
+    pub(crate) fn new(
         source_track: Arc<TrackRemote>,
         peer_connection: Arc<RTCPeerConnection>,
         mime_type: String,
         codec_capability: webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
-        channel_capacity: usize,
+        performance: &PerformanceConfig,
+        profile: Option<ContentProfile>,
+        tasks: SessionTasks,
     ) -> Self {
         let id = source_track.id().to_string();
+        let stream_id = source_track.stream_id().to_string();
         let kind = source_track.kind().to_string();
-        let ssrc = source_track.ssrc();
+        let ssrc = Arc::new(AtomicU32::new(source_track.ssrc()));
 
+        let channel_capacity = performance.channel_capacity(&kind);
+        trace!(
+            "Track {} ({}) buffers {} packets",
+            id,
+            kind,
+            channel_capacity
+        );
         let (tx, _) = broadcast::channel(channel_capacity);
-        let tx_clone = tx.clone();
+        let (frame_ticks, _) = broadcast::channel(FRAME_TICK_CAPACITY);
 
-        let source_id = id.clone();
-
-        let read_task = tokio::spawn(async move {
-            loop {
-                match source_track.read_rtp().await {
-                    Ok((pkt, _)) => {
-                        let _ = tx_clone.send(Arc::new(pkt));
-                    }
-                    Err(webrtc::Error::ErrClosedPipe) | Err(webrtc::Error::ErrConnectionClosed) => {
-                        trace!("Source track {} closed", source_id);
-                        break;
-                    }
-                    Err(e) => {
-                        error!("Error reading from track {}: {}", source_id, e);
-                        break;
-                    }
-                }
-            }
-        });
+        let ingest_stats = Arc::new(IngestStats::default());
+        let audio_levels = (kind == "audio").then(|| AudioLevels::new(id.clone(), tasks.clone()));
+        let sender_clock = Arc::new(SenderClock::new(codec_capability.clock_rate));
+        let sender_report_task =
+            spawn_sender_reports(&tasks, Arc::clone(&source_track), Arc::clone(&sender_clock));
+        let read_task = spawn_reader(
+            &tasks,
+            source_track,
+            0,
+            tx.clone(),
+            (kind == "video").then(|| frame_ticks.clone()),
+            audio_levels.clone(),
+            Arc::clone(&ingest_stats),
+        );
 
+        let peer_connection = Arc::new(Mutex::new(peer_connection));
         let (pli_request_tx, mut pli_request_rx) = mpsc::unbounded_channel::<()>();
         let pc_for_pli = Arc::clone(&peer_connection);
         let pli_track_id = id.clone();
-        let pli_kind = kind.clone();
+        let pli_ssrc = Arc::clone(&ssrc);
+        let profile = Arc::new(Mutex::new(profile));
+        let pli_profile = Arc::clone(&profile);
         let last_pli_time = Arc::new(RwLock::new(None::<Instant>));
         let last_pli_clone = Arc::clone(&last_pli_time);
 
-        let pli_task = tokio::spawn(async move {
-            while pli_request_rx.recv().await.is_some() {
-                if pli_kind != "video" {
-                    continue;
-                }
+        // Audio has no keyframes; requests for it are dropped unsent.
+        let pli_task = (kind == "video").then(|| {
+            tasks.spawn("keyframe request", async move {
+                while pli_request_rx.recv().await.is_some() {
+                    let throttle = pli_profile
+                        .lock()
+                        .unwrap()
+                        .map_or(500, |profile| profile.pli_throttle_ms);
 
-                let now = Instant::now();
-                {
-                    let last_time = last_pli_clone.read().await;
-                    if let Some(last) = *last_time {
-                        if now.duration_since(last) < Duration::from_millis(500) {
-                            trace!("PLI request throttled for track {}", pli_track_id);
-                            continue;
+                    let now = Instant::now();
+                    {
+                        let last_time = last_pli_clone.read().await;
+                        if let Some(last) = *last_time {
+                            if now.duration_since(last) < Duration::from_millis(throttle) {
+                                trace!("PLI request throttled for track {}", pli_track_id);
+                                continue;
+                            }
                         }
                     }
-                }
 
-                *last_pli_clone.write().await = Some(now);
+                    *last_pli_clone.write().await = Some(now);
 
-                use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+                    use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
 
-                let pli = PictureLossIndication {
-                    sender_ssrc: 0,
-                    media_ssrc: ssrc,
-                };
+                    let media_ssrc = pli_ssrc.load(Ordering::Relaxed);
+                    let pli = PictureLossIndication {
+                        sender_ssrc: 0,
+                        media_ssrc,
+                    };
 
-                if let Err(e) = pc_for_pli.write_rtcp(&[Box::new(pli)]).await {
-                    warn!("Failed to send PLI for track {}: {}", pli_track_id, e);
-                } else {
-                    trace!("Sent PLI for track {} (SSRC: {})", pli_track_id, ssrc);
+                    let pc = Arc::clone(&pc_for_pli.lock().unwrap());
+                    if let Err(e) = pc.write_rtcp(&[Box::new(pli)]).await {
+                        warn!("Failed to send PLI for track {}: {}", pli_track_id, e);
+                    } else {
+                        trace!("Sent PLI for track {} (SSRC: {})", pli_track_id, media_ssrc);
+                    }
                 }
-            }
+            })
         });
 
+        let limits = Arc::new(RembLimits::default());
+        let policy_task = (kind == "video").then(|| {
+            spawn_policy(
+                &tasks,
+                id.clone(),
+                Arc::clone(&peer_connection),
+                Arc::clone(&ssrc),
+                Arc::clone(&profile),
+                Arc::clone(&limits),
+                pli_request_tx.clone(),
+            )
+        });
+
+        let pacing = performance.egress_pacing.filter(|_| kind == "video");
+        let latency_budget = performance.latency_budget_ms.map(Duration::from_millis);
+
         Self {
             id,
+            stream_id,
             kind,
             mime_type,
             codec_capability,
             ssrc,
+            source: AtomicU32::new(0),
             tx,
-            read_task,
+            frame_ticks,
+            read_task: Mutex::new(read_task),
+            sender_clock,
+            sender_report_task: Mutex::new(sender_report_task),
             subscribers: Arc::new(DashMap::new()),
+            muted: Arc::new(AtomicBool::new(false)),
             peer_connection,
             last_pli_time,
             pli_request_tx,
             pli_task,
+            profile,
+            policy_task,
+            limits,
+            ingest_stats,
+            pacing,
+            latency_budget,
+            audio_levels,
+            tasks,
+        }
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc.load(Ordering::Relaxed)
+    }
+
+    /// Switches to a new source track, e.g. after the grabber swapped its
+    /// camera or reconnected, while every subscriber stays attached.
+    pub fn rebind(
+        &self,
+        source_track: Arc<TrackRemote>,
+        peer_connection: Arc<RTCPeerConnection>,
+        profile: Option<ContentProfile>,
+    ) {
+        info!(
+            "Broadcaster {} now reading from track {} (SSRC: {})",
+            self.id,
+            source_track.id(),
+            source_track.ssrc()
+        );
+        self.ssrc.store(source_track.ssrc(), Ordering::Relaxed);
+        *self.profile.lock().unwrap() = profile;
+        *self.peer_connection.lock().unwrap() = peer_connection;
+
+        self.sender_clock.reset();
+        let sender_reports = spawn_sender_reports(
+            &self.tasks,
+            Arc::clone(&source_track),
+            Arc::clone(&self.sender_clock),
+        );
+        let previous = std::mem::replace(
+            &mut *self.sender_report_task.lock().unwrap(),
+            sender_reports,
+        );
+        previous.abort();
+
+        let task = spawn_reader(
+            &self.tasks,
+            source_track,
+            self.source.fetch_add(1, Ordering::Relaxed) + 1,
+            self.tx.clone(),
+            (self.kind == "video").then(|| self.frame_ticks.clone()),
+            self.audio_levels.clone(),
+            Arc::clone(&self.ingest_stats),
+        );
+        let previous = std::mem::replace(&mut *self.read_task.lock().unwrap(), task);
+        previous.abort();
+
+        self.request_keyframe_with_retries();
+    }
+
+    /// The id the publisher's connection negotiated for the audio level
+    /// header extension, which speech detection reads.
+    pub(crate) fn set_audio_level_extension(&self, id: Option<u8>) {
+        if let Some(audio_levels) = &self.audio_levels {
+            audio_levels.set_extension_id(id);
         }
     }
 
@@ -130,7 +311,7 @@ impl TrackBroadcaster {
 
         let pli_tx = self.pli_request_tx.clone();
 
-        tokio::spawn(async move {
+        self.tasks.spawn("keyframe retry", async move {
             for i in 0..3 {
                 let _ = pli_tx.send(());
                 trace!("Sent PLI request #{} for new subscriber", i + 1);
@@ -142,21 +323,155 @@ impl TrackBroadcaster {
         });
     }
 
+    /// The REMB target advertised for this track, if it is video and its
+    /// content profile, its subscribers' feedback or the publisher's bitrate
+    /// cap sets one.
+    pub fn target_bitrate_bps(&self) -> Option<u64> {
+        if self.kind != "video" {
+            return None;
+        }
+        let profile = *self.profile.lock().unwrap();
+        self.limits.target(profile)
+    }
+
+    /// A receiver report block from subscriber track `track_id` about this
+    /// track. Reports arriving after the subscriber was removed are ignored.
+    pub fn record_receiver_report(&self, track_id: &str, fraction_lost: u8, jitter: u32) {
+        if !self.subscribers.contains_key(track_id) {
+            return;
+        }
+        let sending_bps = self.ingest_stats.snapshot().bitrate_bps;
+        self.limits
+            .downstream
+            .record_loss(track_id, fraction_lost, jitter, sending_bps);
+    }
+
+    /// A REMB from subscriber track `track_id`.
+    pub fn record_receiver_estimate(&self, track_id: &str, bitrate_bps: u64) {
+        if !self.subscribers.contains_key(track_id) {
+            return;
+        }
+        self.limits.downstream.record_remb(track_id, bitrate_bps);
+    }
+
+    /// The worst loss and jitter subscribers recently reported.
+    pub fn downstream(&self) -> DownstreamSnapshot {
+        self.limits.downstream.snapshot()
+    }
+
+    /// This track's share of `performance.max_publisher_bitrate_kbps`, which
+    /// caps its REMB target; `None` lifts the cap.
+    pub fn set_bitrate_cap(&self, bitrate_bps: Option<u64>) {
+        self.limits
+            .publisher_cap_bps
+            .store(bitrate_bps.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn ingest_stats(&self) -> IngestSnapshot {
+        self.ingest_stats.snapshot()
+    }
+
     pub fn subscriber_count(&self) -> usize {
         self.subscribers.len()
     }
 
-    pub async fn add_subscriber(&self, track: Arc<TrackLocalStaticRTP>) {
+    /// A tick per video frame received from the publisher.
+    pub fn frame_ticks(&self) -> broadcast::Receiver<FrameTick> {
+        self.frame_ticks.subscribe()
+    }
+
+    /// Starts forwarding to `track`. In a `sync_group` its packets are held
+    /// back as needed to play in step with the group's other tracks.
+    pub async fn add_subscriber(
+        &self,
+        track: Arc<TrackLocalStaticRTP>,
+        egress: Arc<EgressStats>,
+        sync_group: Option<&Arc<SyncGroup>>,
+        tasks: SessionTasks,
+    ) {
+        let munger = Arc::new(Mutex::new(RtpMunger::new(self.codec_capability.clock_rate)));
+        let sync = sync_group.map(|group| {
+            Arc::new(group.join(track.id().to_string(), Arc::clone(&self.sender_clock)))
+        });
+        let task = self.spawn_forwarder(
+            &tasks,
+            Arc::clone(&track),
+            Arc::clone(&egress),
+            Arc::clone(&munger),
+            sync.clone(),
+        );
+
+        self.subscribers.insert(
+            track.id().to_string(),
+            Forwarder {
+                track,
+                egress,
+                munger,
+                sync,
+                tasks,
+                task: Some(task),
+            },
+        );
+
+        self.request_keyframe_with_retries();
+    }
+
+    fn spawn_forwarder(
+        &self,
+        tasks: &SessionTasks,
+        track: Arc<TrackLocalStaticRTP>,
+        egress: Arc<EgressStats>,
+        munger: Arc<Mutex<RtpMunger>>,
+        sync: Option<Arc<SyncMember>>,
+    ) -> JoinHandle<()> {
         let mut rx = self.tx.subscribe();
+        let mut held = VecDeque::new();
+        let mut buffer = PacketBuffer::default();
         let track_id = track.id().to_string();
-        let map_key = track_id.clone();
         let pli_tx = self.pli_request_tx.clone();
+        let mut pacer = self.pacing.as_ref().map(Pacer::new);
+        let latency_budget = self.latency_budget;
+        let muted = Arc::clone(&self.muted);
+        let mut stale = 0;
 
-        let join_handle = tokio::spawn(async move {
+        tasks.spawn("forwarder", async move {
             loop {
-                match rx.recv().await {
-                    Ok(pkt) => {
-                        if let Err(e) = track.write_rtp(&pkt).await {
+                match next_packet(&mut rx, &mut held, sync.as_deref()).await {
+                    Ok(received) => {
+                        if muted.load(Ordering::Relaxed) {
+                            continue;
+                        }
+                        let pkt = &received.packet;
+                        if let Some(pacer) = &mut pacer {
+                            pacer.wait(pkt.payload.len()).await;
+                        }
+                        // Holding packets back for sync doesn't make them stale.
+                        let sync_delay = sync.as_ref().map_or(Duration::ZERO, |sync| sync.delay());
+                        let latency = received.at.elapsed().saturating_sub(sync_delay);
+                        if latency_budget.is_some_and(|budget| latency > budget) {
+                            stale += 1;
+                            continue;
+                        }
+                        if stale > 0 {
+                            // The decoder lost its reference frames.
+                            trace!(
+                                "Subscriber {} dropped {} stale packets - requesting keyframe",
+                                track_id,
+                                stale
+                            );
+                            egress.record_stale(stale);
+                            stale = 0;
+                            let _ = pli_tx.send(());
+                        }
+                        let mapped =
+                            munger
+                                .lock()
+                                .unwrap()
+                                .map(&pkt.header, received.source, received.at);
+                        let Some((seq, ts)) = mapped else {
+                            continue;
+                        };
+                        if let Err(e) = write_renumbered(&track, &mut buffer, pkt, seq, ts).await {
                             if e == webrtc::Error::ErrClosedPipe
                                 || e == webrtc::Error::ErrConnectionClosed
                             {
@@ -166,6 +481,7 @@ impl TrackBroadcaster {
                             }
                             break;
                         }
+                        egress.record(pkt.payload.len());
                     }
                     Err(broadcast::error::RecvError::Lagged(skipped)) => {
                         warn!(
@@ -182,16 +498,68 @@ impl TrackBroadcaster {
                     }
                 }
             }
-        });
+        })
+    }
+
+    /// Stops or restarts forwarding to every subscriber. Subscribers stay
+    /// attached and continue numbering from their last packet.
+    pub fn set_muted(&self, muted: bool) {
+        if !muted {
+            for forwarder in self.subscribers.iter() {
+                forwarder.munger.lock().unwrap().resync();
+            }
+        }
+        if self.muted.swap(muted, Ordering::Relaxed) != muted {
+            trace!(
+                "Broadcaster {} {}",
+                self.id,
+                if muted { "muted" } else { "unmuted" }
+            );
+        }
+    }
 
-        self.subscribers.insert(map_key, join_handle);
+    /// Stops forwarding to one subscriber track while keeping it attached.
+    /// Returns `false` if the track is unknown.
+    pub fn pause_subscriber(&self, track_id: &str) -> bool {
+        let Some(mut forwarder) = self.subscribers.get_mut(track_id) else {
+            return false;
+        };
+        if let Some(task) = forwarder.task.take() {
+            task.abort();
+            trace!("Paused subscriber {} on broadcaster {}", track_id, self.id);
+        }
+        true
+    }
 
-        self.request_keyframe_with_retries();
+    /// Restarts forwarding after [`Self::pause_subscriber`], beginning at a
+    /// fresh keyframe. Returns `false` if the track is unknown.
+    pub fn resume_subscriber(&self, track_id: &str) -> bool {
+        let Some(mut forwarder) = self.subscribers.get_mut(track_id) else {
+            return false;
+        };
+        if forwarder.task.is_none() {
+            forwarder.munger.lock().unwrap().resync();
+            let task = self.spawn_forwarder(
+                &forwarder.tasks,
+                Arc::clone(&forwarder.track),
+                Arc::clone(&forwarder.egress),
+                Arc::clone(&forwarder.munger),
+                forwarder.sync.clone(),
+            );
+            forwarder.task = Some(task);
+            drop(forwarder);
+            self.request_keyframe_with_retries();
+            trace!("Resumed subscriber {} on broadcaster {}", track_id, self.id);
+        }
+        true
     }
 
     pub async fn remove_subscriber(&self, track_id: &str) {
-        if let Some((_, handle)) = self.subscribers.remove(track_id) {
-            handle.abort();
+        self.limits.downstream.remove(track_id);
+        if let Some((_, forwarder)) = self.subscribers.remove(track_id) {
+            if let Some(task) = forwarder.task {
+                task.abort();
+            }
             trace!(
                 "Removed subscriber {} from broadcaster {}",
                 track_id,
@@ -201,13 +569,211 @@ impl TrackBroadcaster {
     }
 }
 
+/// The next packet for a subscriber. In a sync group, packets wait in `held`
+/// until they are due, while newer ones keep being received; past
+/// [`MAX_HELD_PACKETS`] the oldest goes out early.
+async fn next_packet(
+    rx: &mut broadcast::Receiver<Arc<Received>>,
+    held: &mut VecDeque<Arc<Received>>,
+    sync: Option<&SyncMember>,
+) -> Result<Arc<Received>, broadcast::error::RecvError> {
+    let Some(sync) = sync else {
+        return rx.recv().await;
+    };
+    loop {
+        let due = held.front().map(|received| received.at + sync.delay());
+        match due {
+            Some(due) if due <= Instant::now() || held.len() >= MAX_HELD_PACKETS => {
+                return Ok(held.pop_front().expect("checked above"));
+            }
+            Some(due) => tokio::select! {
+                _ = tokio::time::sleep_until(due.into()) => {}
+                received = rx.recv() => hold(held, received?, sync),
+            },
+            None => {
+                let received = rx.recv().await?;
+                hold(held, received, sync);
+            }
+        }
+    }
+}
+
+fn hold(held: &mut VecDeque<Arc<Received>>, received: Arc<Received>, sync: &SyncMember) {
+    let arrived = SystemTime::now() - received.at.elapsed();
+    sync.observe(received.packet.header.timestamp, arrived);
+    held.push_back(received);
+}
+
+/// Writes `pkt` as sequence number `seq` at timestamp `ts`, copying it into
+/// `buffer` only if that changes it.
+async fn write_renumbered(
+    track: &TrackLocalStaticRTP,
+    buffer: &mut PacketBuffer,
+    pkt: &Packet,
+    seq: u16,
+    ts: u32,
+) -> webrtc::error::Result<usize> {
+    if (seq, ts) == (pkt.header.sequence_number, pkt.header.timestamp) {
+        return track.write_rtp(pkt).await;
+    }
+    track.write_rtp(buffer.renumber(pkt, seq, ts)).await
+}
+
+fn spawn_reader(
+    tasks: &SessionTasks,
+    source_track: Arc<TrackRemote>,
+    source: u32,
+    tx: broadcast::Sender<Arc<Received>>,
+    frame_ticks: Option<broadcast::Sender<FrameTick>>,
+    audio_levels: Option<AudioLevels>,
+    ingest_stats: Arc<IngestStats>,
+) -> JoinHandle<()> {
+    let source_id = source_track.id().to_string();
+    let mut ingest_tracker = IngestTracker::new(ingest_stats);
+
+    tasks.spawn("reader", async move {
+        let mut buf = vec![0u8; RECEIVE_MTU];
+        loop {
+            match source_track.read(&mut buf).await {
+                Ok((pkt, _)) => {
+                    let at = Instant::now();
+                    ingest_tracker.record(pkt.header.sequence_number, pkt.payload.len());
+                    if let Some(audio_levels) = &audio_levels {
+                        audio_levels.observe(&pkt.header, at);
+                    }
+                    // Video packetizers set the marker bit on a frame's last packet.
+                    if let Some(frame_ticks) = frame_ticks.as_ref().filter(|_| pkt.header.marker) {
+                        let _ = frame_ticks.send(FrameTick {
+                            rtp_timestamp: pkt.header.timestamp,
+                            at: SystemTime::now(),
+                        });
+                    }
+                    let _ = tx.send(Arc::new(Received {
+                        packet: pkt,
+                        at,
+                        source,
+                    }));
+                }
+                Err(webrtc::Error::ErrClosedPipe) | Err(webrtc::Error::ErrConnectionClosed) => {
+                    trace!("Source track {} closed", source_id);
+                    break;
+                }
+                Err(e) => {
+                    error!("Error reading from track {}: {}", source_id, e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Feeds the publisher's sender reports for `source_track` to `clock`.
+fn spawn_sender_reports(
+    tasks: &SessionTasks,
+    source_track: Arc<TrackRemote>,
+    clock: Arc<SenderClock>,
+) -> JoinHandle<()> {
+    use webrtc::rtcp::sender_report::SenderReport;
+
+    tasks.spawn("sender reports", async move {
+        while let Ok((packets, _)) = source_track.read_rtcp().await {
+            let ssrc = source_track.ssrc();
+            for packet in packets {
+                if let Some(report) = packet.as_any().downcast_ref::<SenderReport>() {
+                    if report.ssrc == ssrc {
+                        clock.record_sender_report(report.ntp_time, report.rtp_time);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// What lowers a video track's REMB target below its content profile's.
+#[derive(Default)]
+struct RembLimits {
+    /// What subscribers report receiving.
+    downstream: DownstreamFeedback,
+    /// The track's share of the publisher's bitrate cap; 0 when uncapped.
+    publisher_cap_bps: AtomicU64,
+}
+
+impl RembLimits {
+    /// The content profile's REMB target, lowered to what subscribers can
+    /// take and to the publisher's cap.
+    fn target(&self, profile: Option<ContentProfile>) -> Option<u64> {
+        let configured = profile
+            .and_then(|profile| profile.remb_bitrate_kbps)
+            .map(|kbps| kbps * 1000);
+        let cap = Some(self.publisher_cap_bps.load(Ordering::Relaxed)).filter(|cap| *cap > 0);
+        configured
+            .into_iter()
+            .chain(self.downstream.snapshot().estimate_bps)
+            .chain(cap)
+            .min()
+    }
+}
+
+/// Applies the track's content profile: advertises its REMB target and
+/// requests periodic keyframes.
+fn spawn_policy(
+    tasks: &SessionTasks,
+    track_id: String,
+    peer_connection: Arc<Mutex<Arc<RTCPeerConnection>>>,
+    ssrc: Arc<AtomicU32>,
+    profile: Arc<Mutex<Option<ContentProfile>>>,
+    limits: Arc<RembLimits>,
+    pli_request_tx: mpsc::UnboundedSender<()>,
+) -> JoinHandle<()> {
+    use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+
+    tasks.spawn("content policy", async move {
+        let mut interval = tokio::time::interval(POLICY_INTERVAL);
+        let mut last_keyframe = Instant::now();
+
+        loop {
+            interval.tick().await;
+            let Some(current) = *profile.lock().unwrap() else {
+                continue;
+            };
+
+            if let Some(bps) = limits.target(Some(current)) {
+                let remb = ReceiverEstimatedMaximumBitrate {
+                    sender_ssrc: 0,
+                    bitrate: bps as f32,
+                    ssrcs: vec![ssrc.load(Ordering::Relaxed)],
+                };
+                let pc = Arc::clone(&peer_connection.lock().unwrap());
+                if let Err(e) = pc.write_rtcp(&[Box::new(remb)]).await {
+                    trace!("Failed to send REMB for track {}: {}", track_id, e);
+                }
+            }
+
+            if let Some(interval_ms) = current.keyframe_interval_ms {
+                if last_keyframe.elapsed() >= Duration::from_millis(interval_ms) {
+                    last_keyframe = Instant::now();
+                    let _ = pli_request_tx.send(());
+                }
+            }
+        }
+    })
+}
+
 impl Drop for TrackBroadcaster {
     fn drop(&mut self) {
-        self.read_task.abort();
-        self.pli_task.abort();
+        self.read_task.get_mut().unwrap().abort();
+        self.sender_report_task.get_mut().unwrap().abort();
+        for task in self.pli_task.iter().chain(&self.policy_task) {
+            task.abort();
+        }
+        if let Some(audio_levels) = &self.audio_levels {
+            audio_levels.stop();
+        }
 
         for entry in self.subscribers.iter() {
-            entry.value().abort();
+            if let Some(task) = &entry.value().task {
+                task.abort();
+            }
         }
     }
 }
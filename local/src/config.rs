@@ -1,21 +1,65 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::Deserialize;
 use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-#[derive(Debug, Deserialize, Clone)]
+use crate::migration::{self, MigrationReport};
+
+/// What loading a config file found besides its settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadReport {
+    /// Set when the file was written for an older schema version.
+    pub migration: Option<MigrationReport>,
+    /// Dotted paths of keys no setting reads, e.g. misspelt ones, which
+    /// would otherwise leave the setting at its default without a word.
+    pub unknown_keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct SfuConfig {
+    /// The schema version; older files are migrated on load.
+    #[serde(default = "default_version")]
+    pub version: u32,
     pub server: ServerConfig,
     pub ice_servers: Vec<String>,
+    #[serde(default)]
+    pub client_ice_servers: ClientIceServersConfig,
     pub codecs: CodecsConfig,
     #[serde(default = "default_performance")]
     pub performance: PerformanceConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub groups: Vec<GroupConfig>,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub webrtc: WebRtcConfig,
+    #[serde(default)]
+    pub content_profiles: ContentProfilesConfig,
+    #[serde(default)]
+    pub data_channels: DataChannelsConfig,
+    #[serde(default)]
+    pub e2ee: E2eeConfig,
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    pub seating: Option<SeatingConfig>,
+    pub cluster: Option<ClusterConfig>,
+    pub contest_clock: Option<ContestClockConfig>,
+}
+
+fn default_version() -> u32 {
+    migration::CURRENT_VERSION
 }
 
 fn default_performance() -> PerformanceConfig {
     PerformanceConfig::default()
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct PerformanceConfig {
     #[serde(default = "default_broadcast_capacity")]
     pub broadcast_channel_capacity: usize,
@@ -25,6 +69,51 @@ pub struct PerformanceConfig {
 
     #[serde(default = "default_max_subscribers_per_publisher")]
     pub max_subscribers_per_publisher: usize,
+
+    /// Per-kind overrides of `broadcast_channel_capacity`, in packets.
+    pub audio_channel_capacity: Option<usize>,
+    pub video_channel_capacity: Option<usize>,
+
+    /// Size channels to hold this much media instead of a fixed packet count.
+    /// Video uses `expected_video_bitrate_kbps`; explicit per-kind capacities win.
+    pub channel_buffer_ms: Option<u64>,
+
+    #[serde(default = "default_expected_video_bitrate_kbps")]
+    pub expected_video_bitrate_kbps: u64,
+
+    /// Subscriber peer connections kept ready for viewer surges; 0 disables.
+    #[serde(default)]
+    pub subscriber_pool_size: usize,
+
+    /// Smooths each subscriber's video, e.g. the burst of a keyframe, for
+    /// access points with shallow buffers. Unset forwards packets as they
+    /// arrive. Applies to tracks published after a reload.
+    pub egress_pacing: Option<PacingConfig>,
+
+    /// Packets that waited longer than this since arriving from the
+    /// publisher, e.g. behind a stalled subscriber, are dropped instead of
+    /// forwarded, and video resumes at the next keyframe. Unset never drops.
+    pub latency_budget_ms: Option<u64>,
+
+    /// Most a publisher may send, across its tracks. Its video tracks share
+    /// what audio leaves of it as their REMB target, and a publisher that
+    /// keeps sending more anyway is reported. Unset leaves publishers
+    /// uncapped.
+    pub max_publisher_bitrate_kbps: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct PacingConfig {
+    /// Must stay above the tracks' bitrate, or packets queue up until the
+    /// subscriber lags and skips to a keyframe.
+    pub rate_kbps: u64,
+    /// Bytes that may go out back to back before pacing starts.
+    #[serde(default = "default_pacing_burst_bytes")]
+    pub burst_bytes: u64,
+}
+
+fn default_pacing_burst_bytes() -> u64 {
+    16 * 1024
 }
 
 fn default_broadcast_capacity() -> usize {
@@ -36,6 +125,37 @@ fn default_max_publishers() -> usize {
 fn default_max_subscribers_per_publisher() -> usize {
     100
 }
+fn default_expected_video_bitrate_kbps() -> u64 {
+    2500
+}
+
+// Opus is sent in 20 ms frames.
+const AUDIO_PACKETS_PER_SEC: u64 = 50;
+const TYPICAL_VIDEO_PAYLOAD_BYTES: u64 = 1100;
+const MIN_CHANNEL_CAPACITY: usize = 64;
+
+impl PerformanceConfig {
+    pub fn channel_capacity(&self, kind: &str) -> usize {
+        let explicit = match kind {
+            "audio" => self.audio_channel_capacity,
+            "video" => self.video_channel_capacity,
+            _ => None,
+        };
+        if let Some(capacity) = explicit {
+            return capacity;
+        }
+
+        let Some(buffer_ms) = self.channel_buffer_ms else {
+            return self.broadcast_channel_capacity;
+        };
+
+        let packets_per_sec = match kind {
+            "audio" => AUDIO_PACKETS_PER_SEC,
+            _ => (self.expected_video_bitrate_kbps * 1000 / 8 / TYPICAL_VIDEO_PAYLOAD_BYTES).max(1),
+        };
+        ((packets_per_sec * buffer_ms / 1000) as usize).max(MIN_CHANNEL_CAPACITY)
+    }
+}
 
 impl Default for PerformanceConfig {
     fn default() -> Self {
@@ -43,23 +163,584 @@ impl Default for PerformanceConfig {
             broadcast_channel_capacity: default_broadcast_capacity(),
             max_publishers: default_max_publishers(),
             max_subscribers_per_publisher: default_max_subscribers_per_publisher(),
+            audio_channel_capacity: None,
+            video_channel_capacity: None,
+            channel_buffer_ms: None,
+            expected_video_bitrate_kbps: default_expected_video_bitrate_kbps(),
+            subscriber_pool_size: 0,
+            egress_pacing: None,
+            latency_budget_ms: None,
+            max_publisher_bitrate_kbps: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct ClientIceServersConfig {
+    pub grabber: Option<Vec<IceServerConfig>>,
+    pub player: Option<Vec<IceServerConfig>>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct IceServerConfig {
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub player_credentials: Vec<String>,
+    #[serde(default)]
+    pub grabber_credentials: Vec<String>,
+    #[serde(default)]
+    pub acl: Vec<AclEntry>,
+    /// Bearer token for `/api/admin`; the admin API is disabled when unset.
+    pub admin_token: Option<String>,
+    /// Player sessions authenticated with these credentials must be renewed
+    /// before `max_duration_secs` elapses.
+    #[serde(default)]
+    pub session_limits: Vec<SessionLimit>,
+    /// Player credentials whose subscriptions may write back on the
+    /// publisher's `data_channels.upstream_labels`; no one can when empty.
+    #[serde(default)]
+    pub operator_credentials: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn validate_credentials(&self, creds: &str) -> bool {
+        credential_allowed(&self.player_credentials, creds)
+    }
+
+    pub fn validate_grabber_credentials(&self, creds: &str) -> bool {
+        credential_allowed(&self.grabber_credentials, creds)
+    }
+
+    pub fn is_peer_allowed(&self, credential: &str, peer_name: &str) -> bool {
+        if self.acl.is_empty() {
+            return true;
         }
+
+        self.acl
+            .iter()
+            .filter(|entry| entry.credential == credential)
+            .any(|entry| entry.peers.iter().any(|p| name_matches(p, peer_name)))
+    }
+
+    pub fn is_operator(&self, credential: &str) -> bool {
+        self.operator_credentials.iter().any(|c| c == credential)
     }
+
+    pub fn max_session_duration(&self, credential: &str) -> Option<Duration> {
+        self.session_limits
+            .iter()
+            .find(|limit| limit.credential == credential)
+            .map(|limit| Duration::from_secs(limit.max_duration_secs))
+    }
+}
+
+fn credential_allowed(allowed: &[String], creds: &str) -> bool {
+    // An empty list keeps the endpoint open, matching the previous behaviour.
+    allowed.is_empty() || allowed.iter().any(|c| c == creds)
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// A competition hosted alongside others on the same server, reached under
+/// `/t/<name>/`. Its peers, credentials and limits are invisible to other
+/// tenants and to the default namespace.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct TenantConfig {
+    pub name: String,
+    /// `admin_token` is ignored here; the admin API sees every tenant.
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub limits: TenantLimits,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct TenantLimits {
+    pub max_grabbers: Option<usize>,
+    pub max_players: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct SessionLimit {
+    pub credential: String,
+    pub max_duration_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct AclEntry {
+    pub credential: String,
+    pub peers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GroupConfig {
+    pub name: String,
+    pub peers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct TelemetryConfig {
+    /// OTLP gRPC collector endpoint, e.g. `http://localhost:4317`. Export is disabled when unset.
+    pub otlp_endpoint: Option<String>,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// `EnvFilter` directives used when `RUST_LOG` is not set.
+    pub log_level: Option<String>,
+}
+
+fn default_service_name() -> String {
+    "webrtc-grabber-sfu".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct WebhookConfig {
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(default = "default_webhook_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_webhook_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    #[serde(default = "default_webhook_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_webhook_max_retries() -> u32 {
+    3
+}
+fn default_webhook_retry_backoff_ms() -> u64 {
+    1000
+}
+fn default_webhook_timeout_ms() -> u64 {
+    5000
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            max_retries: default_webhook_max_retries(),
+            retry_backoff_ms: default_webhook_retry_backoff_ms(),
+            timeout_ms: default_webhook_timeout_ms(),
+        }
+    }
+}
+
+/// A contest seating map giving grabbers display names and team labels. It
+/// is a JSON object keyed by the name grabbers connect with, usually their
+/// machine's hostname (`tenant/name` for tenants' grabbers), whose values
+/// have optional `name` and `team` fields. Exactly one of `file` and `url`
+/// should be set.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct SeatingConfig {
+    pub file: Option<String>,
+    pub url: Option<String>,
+    /// How often the map is re-read.
+    #[serde(default = "default_seating_refresh_secs")]
+    pub refresh_secs: u64,
+}
+
+fn default_seating_refresh_secs() -> u64 {
+    60
+}
+
+/// The signalling servers of a multi-node deployment, so clients can ask
+/// any of them which one to connect to.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ClusterConfig {
+    /// How clients reach this server, e.g. `https://sfu-eu.example.com`.
+    pub self_url: String,
+    pub region: Option<String>,
+    /// The other servers; each polls their `/api/health` for load.
+    #[serde(default)]
+    pub nodes: Vec<ClusterNode>,
+    #[serde(default = "default_cluster_poll_secs")]
+    pub poll_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ClusterNode {
+    pub url: String,
+    pub region: Option<String>,
+}
+
+fn default_cluster_poll_secs() -> u64 {
+    10
+}
+
+/// Sends subscribers the contest time of each video frame; see
+/// [`crate::contest_clock`].
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct ContestClockConfig {
+    /// When the contest started, in milliseconds since the Unix epoch.
+    pub start_unix_ms: i64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: default_service_name(),
+            log_level: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct WebRtcConfig {
+    /// Restricts ICE host candidates to this UDP port range.
+    pub port_range: Option<PortRange>,
+    /// Serves every peer connection from this single UDP port instead.
+    pub udp_mux_port: Option<u16>,
+    /// Public addresses advertised instead of local host addresses (1:1 NAT).
+    #[serde(default)]
+    pub nat_1to1_ips: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct PortRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct DataChannelsConfig {
+    /// Publisher data channels whose subscriber messages are passed back to
+    /// the publisher, e.g. `remote-control`. Only subscribers authenticated
+    /// with one of `auth.operator_credentials` can write to the grabber.
+    #[serde(default)]
+    pub upstream_labels: Vec<String>,
+}
+
+impl DataChannelsConfig {
+    pub fn allows_upstream(&self, label: &str) -> bool {
+        self.upstream_labels.iter().any(|allowed| allowed == label)
+    }
+}
+
+/// End-to-end encryption of media between grabbers and players, e.g. with
+/// SFrame in insertable streams. The SFU relays RTP packets without parsing
+/// their payloads, so encrypted frames pass through untouched either way;
+/// this only tells clients that they may encrypt.
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct E2eeConfig {
+    /// Sets `encodedInsertableStreams` in the peer connection config sent
+    /// to grabbers and players.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// What a grabber's video track shows, taken from its stream id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    Screen,
+    Camera,
+}
+
+impl ContentKind {
+    /// `screen` and `desktop` streams are screen captures; anything else
+    /// (`camera`, `webcam`, ...) is treated as a camera.
+    pub fn from_stream_id(stream_id: &str) -> Self {
+        let stream_id = stream_id.to_ascii_lowercase();
+        if stream_id.contains("screen") || stream_id.contains("desktop") {
+            Self::Screen
+        } else {
+            Self::Camera
+        }
+    }
+}
+
+/// The tracks a subscriber asked for with its `stream_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFilter {
+    /// Tracks of screen or camera streams, audio included.
+    Content(ContentKind),
+    /// Only audio tracks, e.g. from a commentary microphone.
+    Audio,
+}
+
+impl StreamFilter {
+    /// Parses a subscriber's `stream_type`; `None` means every stream.
+    pub fn from_stream_type(stream_type: &str) -> Option<Option<Self>> {
+        match stream_type {
+            "all" => Some(None),
+            "screen" => Some(Some(Self::Content(ContentKind::Screen))),
+            "webcam" | "camera" => Some(Some(Self::Content(ContentKind::Camera))),
+            "audio" => Some(Some(Self::Audio)),
+            _ => None,
+        }
+    }
+
+    /// Whether a track of `kind` (`"audio"` or `"video"`) in `stream_id`
+    /// passes the filter.
+    pub fn matches(self, kind: &str, stream_id: &str) -> bool {
+        match self {
+            Self::Content(content) => ContentKind::from_stream_id(stream_id) == content,
+            Self::Audio => kind == "audio",
+        }
+    }
+}
+
+/// Per-content SFU policy for video tracks. Screens favour resolution and
+/// tolerate long GOPs; cameras favour frame rate and recover quickly.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ContentProfilesConfig {
+    #[serde(default = "default_screen_profile")]
+    pub screen: ContentProfile,
+    #[serde(default = "default_camera_profile")]
+    pub camera: ContentProfile,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct ContentProfile {
+    /// Bitrate advertised to the grabber via REMB; unset leaves it to the
+    /// grabber's own estimate.
+    pub remb_bitrate_kbps: Option<u64>,
+    /// Minimum gap between keyframe requests sent to the grabber.
+    #[serde(default = "default_pli_throttle_ms")]
+    pub pli_throttle_ms: u64,
+    /// Request a keyframe this often even when no subscriber asked for one.
+    pub keyframe_interval_ms: Option<u64>,
+}
+
+fn default_pli_throttle_ms() -> u64 {
+    500
+}
+fn default_screen_profile() -> ContentProfile {
+    ContentProfile {
+        remb_bitrate_kbps: None,
+        pli_throttle_ms: 2000,
+        keyframe_interval_ms: None,
+    }
+}
+fn default_camera_profile() -> ContentProfile {
+    ContentProfile {
+        remb_bitrate_kbps: None,
+        pli_throttle_ms: default_pli_throttle_ms(),
+        keyframe_interval_ms: None,
+    }
+}
+
+impl ContentProfilesConfig {
+    pub fn profile(&self, kind: ContentKind) -> ContentProfile {
+        match kind {
+            ContentKind::Screen => self.screen,
+            ContentKind::Camera => self.camera,
+        }
+    }
+}
+
+impl Default for ContentProfilesConfig {
+    fn default() -> Self {
+        Self {
+            screen: default_screen_profile(),
+            camera: default_camera_profile(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct ServerConfig {
     pub bind_address: String,
     pub enable_metrics: bool,
+    #[serde(default = "default_subscribe_auto_retry")]
+    pub subscribe_auto_retry: bool,
+    #[serde(default = "default_subscribe_retry_after_ms")]
+    pub subscribe_retry_after_ms: u64,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub peer_liveness: PeerLivenessConfig,
+    #[serde(default)]
+    pub negotiation: NegotiationConfig,
+    /// Proxies whose `X-Forwarded-For` entries are skipped when looking for
+    /// the client address of a connection on a Unix socket listener. The
+    /// proxy listening on the socket itself is always trusted.
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+    /// Where operator overrides for peers are saved so they survive a
+    /// restart; unset keeps them in memory only.
+    pub peer_settings_file: Option<String>,
+}
+
+fn default_subscribe_auto_retry() -> bool {
+    true
+}
+
+fn default_subscribe_retry_after_ms() -> u64 {
+    2000
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_rate_limit_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: f64,
+    #[serde(default = "default_request_burst")]
+    pub request_burst: u32,
+    #[serde(default = "default_ws_messages_per_second")]
+    pub ws_messages_per_second: f64,
+    #[serde(default = "default_ws_message_burst")]
+    pub ws_message_burst: u32,
+}
+
+// Off by default: a whole venue behind one NAT shares a single IP.
+fn default_rate_limit_enabled() -> bool {
+    false
+}
+fn default_requests_per_second() -> f64 {
+    10.0
+}
+fn default_request_burst() -> u32 {
+    40
+}
+fn default_ws_messages_per_second() -> f64 {
+    50.0
+}
+fn default_ws_message_burst() -> u32 {
+    200
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_rate_limit_enabled(),
+            requests_per_second: default_requests_per_second(),
+            request_burst: default_request_burst(),
+            ws_messages_per_second: default_ws_messages_per_second(),
+            ws_message_burst: default_ws_message_burst(),
+        }
+    }
+}
+
+/// With `sweep_stale`, grabbers that send nothing are marked offline after
+/// `offline_after_missed` ping intervals and dropped, along with their
+/// publisher, after `remove_after_missed`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct PeerLivenessConfig {
+    #[serde(default = "default_ping_interval_ms")]
+    pub ping_interval_ms: u64,
+    #[serde(default = "default_offline_after_missed")]
+    pub offline_after_missed: u32,
+    #[serde(default = "default_remove_after_missed")]
+    pub remove_after_missed: u32,
+    /// Off by default: a grabber whose socket closes is handled at once, and
+    /// one on a slow link can go quiet for a while without having left.
+    #[serde(default)]
+    pub sweep_stale: bool,
+    /// How long a disconnected grabber's publisher is kept so viewers stay
+    /// attached if it reconnects with the resume token from its previous
+    /// connection. 0 removes it at once.
+    #[serde(default = "default_reconnect_grace_ms")]
+    pub reconnect_grace_ms: u64,
+    /// Signalling sockets that send nothing, not even a pong to the WebSocket
+    /// pings the server sends a few times within it, for this long are
+    /// closed. 0 disables the check and the pings.
+    #[serde(default = "default_socket_timeout_ms")]
+    pub socket_timeout_ms: u64,
+}
+
+fn default_ping_interval_ms() -> u64 {
+    5000
+}
+fn default_offline_after_missed() -> u32 {
+    3
+}
+fn default_remove_after_missed() -> u32 {
+    12
+}
+fn default_reconnect_grace_ms() -> u64 {
+    10_000
+}
+fn default_socket_timeout_ms() -> u64 {
+    30_000
+}
+
+impl PeerLivenessConfig {
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_millis(self.ping_interval_ms)
+    }
+
+    pub fn offline_after(&self) -> Duration {
+        self.ping_interval() * self.offline_after_missed
+    }
+
+    pub fn remove_after(&self) -> Duration {
+        self.ping_interval() * self.remove_after_missed
+    }
+
+    pub fn reconnect_grace(&self) -> Duration {
+        Duration::from_millis(self.reconnect_grace_ms)
+    }
+
+    pub fn socket_timeout(&self) -> Option<Duration> {
+        (self.socket_timeout_ms > 0).then(|| Duration::from_millis(self.socket_timeout_ms))
+    }
+}
+
+impl Default for PeerLivenessConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval_ms: default_ping_interval_ms(),
+            offline_after_missed: default_offline_after_missed(),
+            remove_after_missed: default_remove_after_missed(),
+            sweep_stale: false,
+            reconnect_grace_ms: default_reconnect_grace_ms(),
+            socket_timeout_ms: default_socket_timeout_ms(),
+        }
+    }
+}
+
+/// Bounds on answering an offer, so a stalled WebRTC stack fails the request
+/// instead of leaving the client waiting. `step_timeout_ms` applies to each
+/// step such as applying the offer, creating the answer, attaching one of a
+/// subscriber's tracks or opening one of its relay channels, `timeout_ms`
+/// to the whole request.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct NegotiationConfig {
+    #[serde(default = "default_negotiation_step_timeout_ms")]
+    pub step_timeout_ms: u64,
+    #[serde(default = "default_negotiation_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_negotiation_step_timeout_ms() -> u64 {
+    5000
+}
+fn default_negotiation_timeout_ms() -> u64 {
+    15_000
+}
+
+impl NegotiationConfig {
+    pub fn step_timeout(&self) -> Duration {
+        Duration::from_millis(self.step_timeout_ms)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+impl Default for NegotiationConfig {
+    fn default() -> Self {
+        Self {
+            step_timeout_ms: default_negotiation_step_timeout_ms(),
+            timeout_ms: default_negotiation_timeout_ms(),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct CodecsConfig {
     pub audio: Vec<CodecItem>,
     pub video: Vec<CodecItem>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct CodecItem {
     pub mime: String,
     pub payload_type: u8,
@@ -68,16 +749,237 @@ pub struct CodecItem {
     pub sdp_fmtp: Option<String>,
 }
 
+fn merge_yaml(base: &mut serde_yaml::Value, overrides: serde_yaml::Value) {
+    match (base, overrides) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
 impl SfuConfig {
     pub fn load(path: &str) -> Result<Self> {
+        Self::load_profile(path, None)
+    }
+
+    /// Loads `path` with the overrides under `profiles.<profile>` merged over
+    /// the shared settings. Mappings merge key by key; any other value,
+    /// lists included, replaces the shared one.
+    pub fn load_profile(path: &str, profile: Option<&str>) -> Result<Self> {
+        Ok(Self::load_reported(path, profile)?.0)
+    }
+
+    /// Like [`Self::load_profile`], also reporting how a file written for an
+    /// older schema version was upgraded and which settings were ignored.
+    pub fn load_reported(path: &str, profile: Option<&str>) -> Result<(Self, LoadReport)> {
         let content = fs::read_to_string(path)
             .with_context(|| format!("Failed to read config file: {}", path))?;
-        let config: SfuConfig =
+        let mut value: serde_yaml::Value =
             serde_yaml::from_str(&content).context("Failed to parse YAML config")?;
-        Ok(config)
+
+        let profiles = value
+            .as_mapping_mut()
+            .and_then(|root| root.remove("profiles"));
+        let version = migration::version(&value)?;
+        let mut migration = migration::migrate(&mut value)?;
+        if let Some(profile) = profile {
+            let Some(overrides) = profiles.as_ref().and_then(|p| p.get(profile)) else {
+                bail!("Config profile '{}' not found in {}", profile, path);
+            };
+            let mut overrides = overrides.clone();
+            let changes = migration::migrate_overrides(version, &mut overrides);
+            if let Some(report) = &mut migration {
+                report.changes.extend(
+                    changes
+                        .into_iter()
+                        .map(|change| format!("profiles.{}: {}", profile, change)),
+                );
+            }
+            merge_yaml(&mut value, overrides);
+        }
+
+        let mut unknown_keys = Vec::new();
+        let config = serde_ignored::deserialize(value, |key| unknown_keys.push(key.to_string()))
+            .context("Failed to parse YAML config")?;
+        Ok((
+            config,
+            LoadReport {
+                migration,
+                unknown_keys,
+            },
+        ))
     }
 
-    pub fn validate_credentials(&self, _creds: &str) -> bool {
-        true // Placeholder
+    /// Names of the settings that differ between `self` and `other`.
+    pub fn changed_sections(&self, other: &SfuConfig) -> Vec<&'static str> {
+        let checks = [
+            (
+                "server.bind_address",
+                self.server.bind_address != other.server.bind_address,
+            ),
+            (
+                "server.enable_metrics",
+                self.server.enable_metrics != other.server.enable_metrics,
+            ),
+            (
+                "server.subscribe_retry",
+                self.server.subscribe_auto_retry != other.server.subscribe_auto_retry
+                    || self.server.subscribe_retry_after_ms
+                        != other.server.subscribe_retry_after_ms,
+            ),
+            (
+                "server.rate_limit",
+                self.server.rate_limit != other.server.rate_limit,
+            ),
+            (
+                "server.peer_liveness",
+                self.server.peer_liveness != other.server.peer_liveness,
+            ),
+            (
+                "server.negotiation",
+                self.server.negotiation != other.server.negotiation,
+            ),
+            (
+                "server.trusted_proxies",
+                self.server.trusted_proxies != other.server.trusted_proxies,
+            ),
+            (
+                "server.peer_settings_file",
+                self.server.peer_settings_file != other.server.peer_settings_file,
+            ),
+            ("ice_servers", self.ice_servers != other.ice_servers),
+            (
+                "client_ice_servers",
+                self.client_ice_servers != other.client_ice_servers,
+            ),
+            ("codecs", self.codecs != other.codecs),
+            ("performance", self.performance != other.performance),
+            ("auth", self.auth != other.auth),
+            ("groups", self.groups != other.groups),
+            (
+                "telemetry.log_level",
+                self.telemetry.log_level != other.telemetry.log_level,
+            ),
+            (
+                "telemetry.otlp",
+                self.telemetry.otlp_endpoint != other.telemetry.otlp_endpoint
+                    || self.telemetry.service_name != other.telemetry.service_name,
+            ),
+            ("webhooks", self.webhooks != other.webhooks),
+            ("webrtc", self.webrtc != other.webrtc),
+            (
+                "content_profiles",
+                self.content_profiles != other.content_profiles,
+            ),
+            ("data_channels", self.data_channels != other.data_channels),
+            ("e2ee", self.e2ee != other.e2ee),
+            ("tenants", self.tenants != other.tenants),
+            ("seating", self.seating != other.seating),
+            ("cluster", self.cluster != other.cluster),
+            ("contest_clock", self.contest_clock != other.contest_clock),
+        ];
+
+        checks
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    pub fn validate_credentials(&self, creds: &str) -> bool {
+        self.auth.validate_credentials(creds)
+    }
+
+    pub fn validate_grabber_credentials(&self, creds: &str) -> bool {
+        self.auth.validate_grabber_credentials(creds)
+    }
+
+    pub fn validate_admin_token(&self, token: &str) -> bool {
+        self.auth
+            .admin_token
+            .as_deref()
+            .is_some_and(|expected| expected == token)
+    }
+
+    pub fn is_peer_allowed(&self, credential: &str, peer_name: &str) -> bool {
+        self.auth.is_peer_allowed(credential, peer_name)
+    }
+
+    pub fn max_session_duration(&self, credential: &str) -> Option<Duration> {
+        self.auth.max_session_duration(credential)
+    }
+
+    pub fn tenant(&self, name: &str) -> Option<&TenantConfig> {
+        self.tenants.iter().find(|t| t.name == name)
+    }
+
+    /// Credentials for `tenant`, or the top-level `auth` for the default
+    /// namespace. `None` when the tenant isn't configured.
+    pub fn auth_for(&self, tenant: Option<&str>) -> Option<&AuthConfig> {
+        match tenant {
+            Some(name) => self.tenant(name).map(|t| &t.auth),
+            None => Some(&self.auth),
+        }
+    }
+
+    pub fn group_for(&self, peer_name: &str) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|g| g.peers.iter().any(|p| name_matches(p, peer_name)))
+            .map(|g| g.name.as_str())
+    }
+}
+
+/// Sections of [`SfuConfig::changed_sections`] that are only read at startup.
+pub const RESTART_REQUIRED: &[&str] = &[
+    "server.bind_address",
+    "codecs",
+    "telemetry.otlp",
+    "webhooks",
+    "webrtc",
+];
+
+/// Shared, swappable configuration. Readers take a snapshot with
+/// [`ConfigHandle::current`], so a reload never changes values mid-request.
+#[derive(Clone)]
+pub struct ConfigHandle(Arc<RwLock<Arc<SfuConfig>>>);
+
+impl ConfigHandle {
+    pub fn new(config: SfuConfig) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn current(&self) -> Arc<SfuConfig> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+
+    pub fn replace(&self, config: SfuConfig) {
+        *self.0.write().unwrap() = Arc::new(config);
+    }
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of characters.
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(remaining) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            if rest.is_empty() {
+                return true;
+            }
+            (0..=remaining.len())
+                .filter(|&i| remaining.is_char_boundary(i))
+                .any(|i| name_matches(rest, &remaining[i..]))
+        }
     }
 }
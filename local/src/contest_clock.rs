@@ -0,0 +1,73 @@
+//! Contest time for each forwarded video frame, for aligning archived
+//! footage when judging disputes. A subscriber that opens a data channel
+//! labelled [`CLOCK_CHANNEL_LABEL`] receives one JSON message per frame with
+//! the frame's RTP timestamp and the contest time it reached the SFU, which
+//! recorders store next to the media.
+
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::broadcast;
+use tracing::trace;
+use webrtc::data_channel::data_channel_state::RTCDataChannelState;
+use webrtc::data_channel::RTCDataChannel;
+
+use crate::broadcaster::{FrameTick, TrackBroadcaster};
+use crate::config::ContestClockConfig;
+
+pub const CLOCK_CHANNEL_LABEL: &str = "contest-clock";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ClockTick<'a> {
+    /// The subscriber's id for the track.
+    track_id: &'a str,
+    rtp_timestamp: u32,
+    /// Negative before the contest starts.
+    contest_time_ms: i64,
+}
+
+/// Sends ticks on `channel` for each of `tracks`, pairs of the subscriber's
+/// track id and the broadcaster feeding it, until the channel closes.
+pub(crate) fn serve(
+    channel: Arc<RTCDataChannel>,
+    tracks: Vec<(String, Arc<TrackBroadcaster>)>,
+    clock: ContestClockConfig,
+) {
+    for (track_id, broadcaster) in tracks {
+        let mut ticks = broadcaster.frame_ticks();
+        let channel = Arc::clone(&channel);
+        tokio::spawn(async move {
+            loop {
+                let tick = match ticks.recv().await {
+                    Ok(tick) => tick,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                match channel.ready_state() {
+                    RTCDataChannelState::Open => {}
+                    RTCDataChannelState::Connecting => continue,
+                    _ => break,
+                }
+                let message = serde_json::to_string(&ClockTick {
+                    track_id: &track_id,
+                    rtp_timestamp: tick.rtp_timestamp,
+                    contest_time_ms: contest_time_ms(&tick, clock),
+                })
+                .expect("clock ticks serialize");
+                if channel.send_text(message).await.is_err() {
+                    break;
+                }
+            }
+            trace!("Stopped contest clock for track {}", track_id);
+        });
+    }
+}
+
+fn contest_time_ms(tick: &FrameTick, clock: ContestClockConfig) -> i64 {
+    let unix_ms = tick
+        .at
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64);
+    unix_ms - clock.start_unix_ms
+}
@@ -20,6 +20,9 @@ pub enum SfuError {
     #[error("Failed to create answer: {0}")]
     CreateAnswer(String),
 
+    #[error("Failed to create offer: {0}")]
+    CreateOffer(String),
+
     #[error("Failed to set local description: {0}")]
     SetLocalDescription(String),
 
@@ -29,6 +32,9 @@ pub enum SfuError {
     #[error("Failed to add track: {0}")]
     AddTrack(String),
 
+    #[error("Negotiation timed out: {0}")]
+    NegotiationTimeout(String),
+
     #[error("WebRTC error: {0}")]
     WebRtc(#[from] webrtc::Error),
 
@@ -38,9 +44,15 @@ pub enum SfuError {
     #[error("Broadcaster channel closed")]
     BroadcastChannelClosed,
 
+    #[error("Limit reached: {0}")]
+    LimitReached(String),
+
     #[error("Configuration error: {0}")]
     Configuration(String),
 
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
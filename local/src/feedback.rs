@@ -0,0 +1,211 @@
+//! What subscribers' receivers report about a forwarded video track, folded
+//! into the bitrate the publisher's encoder is asked not to exceed. Without
+//! it the publisher only learns about its own link to the SFU, and keeps
+//! sending more than congested subscribers can take.
+
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+/// Reports older than this no longer count, e.g. from a subscriber that
+/// stopped sending RTCP.
+const REPORT_TTL: Duration = Duration::from_secs(5);
+/// Loss above this cuts a subscriber's estimate; below [`LOSS_LOW`] it grows
+/// again, as in GCC's loss-based controller.
+const LOSS_HIGH: f64 = 0.10;
+const LOSS_LOW: f64 = 0.02;
+const GROWTH: f64 = 1.08;
+/// Growth stops this far above what the track is actually sent at.
+const HEADROOM: f64 = 1.5;
+/// However congested one subscriber is, the publisher isn't asked to go below
+/// this.
+const MIN_ESTIMATE_BPS: u64 = 300_000;
+/// The publisher is asked for what subscribers at this percentile of the
+/// estimates can take, so a few viewers on bad links, who would otherwise
+/// hold the stream down for everyone for as long as they stay, don't count.
+/// With five subscribers or fewer it is the lowest estimate.
+const ESTIMATE_PERCENTILE: usize = 20;
+
+struct Report {
+    /// What the subscriber can take; `None` until it reports loss or a REMB.
+    estimate_bps: Option<u64>,
+    loss_ratio: f64,
+    /// Interarrival jitter in RTP timestamp units.
+    jitter: u32,
+    at: Instant,
+}
+
+/// What subscribers recently reported: the worst loss and jitter, and the
+/// estimate at [`ESTIMATE_PERCENTILE`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DownstreamSnapshot {
+    pub estimate_bps: Option<u64>,
+    pub loss_ratio: f64,
+    pub jitter: u32,
+}
+
+/// Receiver feedback by subscriber track id.
+#[derive(Default)]
+pub struct DownstreamFeedback {
+    reports: DashMap<String, Report>,
+}
+
+impl DownstreamFeedback {
+    /// A receiver report block from subscriber track `track_id`, with
+    /// `fraction_lost` out of 256 since its previous report. A subscriber
+    /// without an estimate yet starts from `sending_bps`, what the track is
+    /// currently sent at.
+    pub fn record_loss(&self, track_id: &str, fraction_lost: u8, jitter: u32, sending_bps: u64) {
+        let loss_ratio = fraction_lost as f64 / 256.0;
+        let mut report = self.report(track_id);
+        report.loss_ratio = loss_ratio;
+        report.jitter = jitter;
+        report.at = Instant::now();
+
+        // Nothing is flowing to judge the loss against.
+        if sending_bps == 0 {
+            return;
+        }
+        let current = report.estimate_bps.unwrap_or(sending_bps) as f64;
+        let estimate = if loss_ratio > LOSS_HIGH {
+            current * (1.0 - loss_ratio / 2.0)
+        } else if loss_ratio < LOSS_LOW {
+            (current * GROWTH).min((sending_bps as f64 * HEADROOM).max(current))
+        } else {
+            current
+        };
+        report.estimate_bps = Some((estimate as u64).max(MIN_ESTIMATE_BPS));
+    }
+
+    /// A REMB from subscriber track `track_id`: its receiver's own estimate,
+    /// which replaces the loss-based one.
+    pub fn record_remb(&self, track_id: &str, bitrate_bps: u64) {
+        let mut report = self.report(track_id);
+        report.estimate_bps = Some(bitrate_bps.max(MIN_ESTIMATE_BPS));
+        report.at = Instant::now();
+    }
+
+    pub fn remove(&self, track_id: &str) {
+        self.reports.remove(track_id);
+    }
+
+    pub fn snapshot(&self) -> DownstreamSnapshot {
+        let mut snapshot = DownstreamSnapshot::default();
+        let mut estimates = Vec::new();
+        for report in self.reports.iter() {
+            if report.at.elapsed() > REPORT_TTL {
+                continue;
+            }
+            estimates.extend(report.estimate_bps);
+            snapshot.loss_ratio = snapshot.loss_ratio.max(report.loss_ratio);
+            snapshot.jitter = snapshot.jitter.max(report.jitter);
+        }
+        estimates.sort_unstable();
+        snapshot.estimate_bps = estimates
+            .get(estimates.len().saturating_sub(1) * ESTIMATE_PERCENTILE / 100)
+            .copied();
+        snapshot
+    }
+
+    fn report(&self, track_id: &str) -> dashmap::mapref::one::RefMut<'_, String, Report> {
+        self.reports
+            .entry(track_id.to_string())
+            .or_insert_with(|| Report {
+                estimate_bps: None,
+                loss_ratio: 0.0,
+                jitter: 0,
+                at: Instant::now(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 25% loss, as a receiver report's fraction out of 256.
+    const HEAVY_LOSS: u8 = 64;
+
+    fn estimate(feedback: &DownstreamFeedback) -> Option<u64> {
+        feedback.snapshot().estimate_bps
+    }
+
+    #[test]
+    fn heavy_loss_backs_off_down_to_the_floor() {
+        let feedback = DownstreamFeedback::default();
+        feedback.record_loss("a", HEAVY_LOSS, 0, 2_000_000);
+        assert_eq!(estimate(&feedback), Some(1_750_000));
+        feedback.record_loss("a", HEAVY_LOSS, 0, 2_000_000);
+        assert_eq!(estimate(&feedback), Some(1_531_250));
+
+        for _ in 0..50 {
+            feedback.record_loss("a", HEAVY_LOSS, 0, 2_000_000);
+        }
+        assert_eq!(estimate(&feedback), Some(MIN_ESTIMATE_BPS));
+    }
+
+    #[test]
+    fn low_loss_grows_up_to_the_headroom() {
+        let feedback = DownstreamFeedback::default();
+        feedback.record_loss("a", 0, 0, 1_000_000);
+        assert_eq!(estimate(&feedback), Some(1_080_000));
+
+        for _ in 0..20 {
+            feedback.record_loss("a", 0, 0, 1_000_000);
+        }
+        assert_eq!(estimate(&feedback), Some(1_500_000));
+    }
+
+    #[test]
+    fn moderate_loss_holds_the_estimate() {
+        let feedback = DownstreamFeedback::default();
+        feedback.record_remb("a", 1_000_000);
+        feedback.record_loss("a", 13, 0, 2_000_000);
+        assert_eq!(estimate(&feedback), Some(1_000_000));
+    }
+
+    #[test]
+    fn nothing_sent_gives_no_estimate() {
+        let feedback = DownstreamFeedback::default();
+        feedback.record_loss("a", HEAVY_LOSS, 0, 0);
+        assert_eq!(estimate(&feedback), None);
+    }
+
+    #[test]
+    fn remb_replaces_the_loss_based_estimate() {
+        let feedback = DownstreamFeedback::default();
+        feedback.record_loss("a", HEAVY_LOSS, 0, 2_000_000);
+        feedback.record_remb("a", 900_000);
+        assert_eq!(estimate(&feedback), Some(900_000));
+        feedback.record_remb("a", 1000);
+        assert_eq!(estimate(&feedback), Some(MIN_ESTIMATE_BPS));
+    }
+
+    #[test]
+    fn snapshot_reports_the_most_congested_of_a_few_subscribers() {
+        let feedback = DownstreamFeedback::default();
+        feedback.record_remb("a", 800_000);
+        feedback.record_loss("b", HEAVY_LOSS, 90, 2_000_000);
+
+        let snapshot = feedback.snapshot();
+        assert_eq!(snapshot.estimate_bps, Some(800_000));
+        assert_eq!(snapshot.loss_ratio, 0.25);
+        assert_eq!(snapshot.jitter, 90);
+
+        feedback.remove("a");
+        assert_eq!(estimate(&feedback), Some(1_750_000));
+    }
+
+    #[test]
+    fn a_few_congested_subscribers_among_many_are_outvoted() {
+        let feedback = DownstreamFeedback::default();
+        feedback.record_remb("congested", 400_000);
+        feedback.record_remb("slow", 1_000_000);
+        for i in 0..8 {
+            feedback.record_remb(&i.to_string(), 2_000_000 + i * 100_000);
+        }
+        assert_eq!(estimate(&feedback), Some(1_000_000));
+
+        feedback.remove("slow");
+        assert_eq!(estimate(&feedback), Some(2_000_000));
+    }
+}
@@ -1,8 +1,22 @@
+pub mod audio_level;
+mod bitrate_cap;
 pub mod broadcaster;
 pub mod sfu;
 pub mod config;
+pub mod contest_clock;
 pub mod error;
+pub mod feedback;
+pub mod migration;
+pub mod munger;
+pub mod negotiation;
+pub mod pacer;
+pub mod packet;
+pub mod pool;
+pub mod selftest;
 pub mod session;
+pub mod stats;
+pub mod sync;
+mod tasks;
 
 pub use sfu::LocalSfu;
-pub use config::SfuConfig;
\ No newline at end of file
+pub use config::{ConfigHandle, SfuConfig};
\ No newline at end of file
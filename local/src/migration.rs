@@ -0,0 +1,116 @@
+//! Upgrades config files written for older schema versions. Files without a
+//! `version:` are version 1. Each step rewrites the YAML before it is
+//! deserialized and describes what it changed, so settings under an old
+//! name are carried over instead of silently falling back to defaults.
+
+use anyhow::{bail, Result};
+use serde_yaml::Value;
+
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Upgrades a config, or a profile's overrides, from the version at its
+/// index plus one to the next, describing each change.
+type Step = fn(&mut Value, &mut Vec<String>);
+
+/// No schema change has needed a step yet.
+const STEPS: [Step; CURRENT_VERSION as usize - 1] = [];
+
+/// What upgrading a config file changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub changes: Vec<String>,
+}
+
+/// Upgrades `root` in place and stamps it with [`CURRENT_VERSION`]. `None`
+/// when it was already current.
+pub fn migrate(root: &mut Value) -> Result<Option<MigrationReport>> {
+    let from_version = version(root)?;
+    if from_version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    let changes = migrate_overrides(from_version, root);
+    if let Some(root) = root.as_mapping_mut() {
+        root.insert("version".into(), CURRENT_VERSION.into());
+    }
+    Ok(Some(MigrationReport {
+        from_version,
+        changes,
+    }))
+}
+
+/// Applies the steps between `from_version` and now to a partial config,
+/// such as a profile's overrides.
+pub fn migrate_overrides(from_version: u32, value: &mut Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    for step in STEPS.iter().skip(from_version as usize - 1) {
+        step(value, &mut changes);
+    }
+    changes
+}
+
+/// The schema version `root` was written for.
+pub fn version(root: &Value) -> Result<u32> {
+    let Some(version) = root.get("version") else {
+        return Ok(1);
+    };
+    match version.as_u64().and_then(|v| u32::try_from(v).ok()) {
+        Some(v @ 1..=CURRENT_VERSION) => Ok(v),
+        Some(v) if v > CURRENT_VERSION => bail!(
+            "Config version {} is newer than this server supports ({}); upgrade the server",
+            v,
+            CURRENT_VERSION
+        ),
+        _ => bail!("Invalid config version {:?}", version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(source: &str) -> Value {
+        serde_yaml::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn unversioned_config_is_current() {
+        let mut root = yaml("server:\n  port: 3000\n");
+        assert_eq!(version(&root).unwrap(), 1);
+        assert_eq!(migrate(&mut root).unwrap(), None);
+        assert_eq!(root, yaml("server:\n  port: 3000\n"));
+    }
+
+    #[test]
+    fn current_config_is_left_alone() {
+        let mut root = yaml("version: 1\nserver:\n  port: 3000\n");
+        assert_eq!(migrate(&mut root).unwrap(), None);
+        assert_eq!(root, yaml("version: 1\nserver:\n  port: 3000\n"));
+    }
+
+    #[test]
+    fn newer_config_is_rejected() {
+        let mut root = yaml(&format!("version: {}\n", CURRENT_VERSION + 1));
+        let error = migrate(&mut root).unwrap_err().to_string();
+        assert!(
+            error.contains("newer than this server supports"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn invalid_versions_are_rejected() {
+        for source in ["version: 0\n", "version: -1\n", "version: one\n"] {
+            let error = migrate(&mut yaml(source)).unwrap_err().to_string();
+            assert!(error.starts_with("Invalid config version"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn overrides_need_no_steps_at_the_current_version() {
+        let mut overrides = yaml("server:\n  port: 3000\n");
+        assert!(migrate_overrides(CURRENT_VERSION, &mut overrides).is_empty());
+    }
+}
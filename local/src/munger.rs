@@ -0,0 +1,173 @@
+use std::time::Instant;
+use webrtc::rtp::header::Header;
+
+/// Rewrites the sequence numbers and timestamps one subscriber track sees,
+/// so the stream stays continuous when what feeds it changes: a grabber
+/// resuming on a new connection starts from fresh random values, and a
+/// paused track would otherwise resume with a gap the decoder reads as loss.
+/// The SSRC is already rewritten per subscriber by the local track.
+pub struct RtpMunger {
+    clock_rate: u32,
+    mapping: Option<Mapping>,
+    /// Set by [`Self::resync`]; the next packet starts a new mapping.
+    resync: bool,
+}
+
+struct Mapping {
+    source: u32,
+    seq_offset: u16,
+    ts_offset: u32,
+    /// Newest sequence number and timestamp sent, and when.
+    last_seq: u16,
+    last_ts: u32,
+    last_at: Instant,
+}
+
+impl RtpMunger {
+    pub fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate: clock_rate.max(1),
+            mapping: None,
+            resync: false,
+        }
+    }
+
+    /// Continue from the last packet sent, e.g. after a pause, even if the
+    /// source is unchanged.
+    pub fn resync(&mut self) {
+        self.resync = true;
+    }
+
+    /// The sequence number and timestamp to send `header`'s packet with,
+    /// received at `at` from `source`, which grows whenever the packets'
+    /// numbering changes. `None` for stragglers from an earlier source,
+    /// which are dropped.
+    pub fn map(&mut self, header: &Header, source: u32, at: Instant) -> Option<(u16, u32)> {
+        let current = self.mapping.as_ref().map(|mapping| mapping.source);
+        if current.is_some_and(|current| source < current) {
+            return None;
+        }
+        let resync = std::mem::take(&mut self.resync);
+        if current != Some(source) || resync {
+            // Pick up one packet after the last one sent, as far on in media
+            // time as has passed since.
+            let (seq_offset, ts_offset) = match &self.mapping {
+                Some(previous) => {
+                    let elapsed = at.saturating_duration_since(previous.last_at);
+                    let ts_step = ((elapsed.as_secs_f64() * self.clock_rate as f64) as u32).max(1);
+                    (
+                        previous
+                            .last_seq
+                            .wrapping_add(1)
+                            .wrapping_sub(header.sequence_number),
+                        previous
+                            .last_ts
+                            .wrapping_add(ts_step)
+                            .wrapping_sub(header.timestamp),
+                    )
+                }
+                None => (0, 0),
+            };
+            self.mapping = Some(Mapping {
+                source,
+                seq_offset,
+                ts_offset,
+                last_seq: header.sequence_number.wrapping_add(seq_offset),
+                last_ts: header.timestamp.wrapping_add(ts_offset),
+                last_at: at,
+            });
+        }
+
+        let mapping = self.mapping.as_mut().expect("set above");
+        let seq = header.sequence_number.wrapping_add(mapping.seq_offset);
+        let ts = header.timestamp.wrapping_add(mapping.ts_offset);
+        // Reordered packets keep their place but don't move the last one sent.
+        let ahead = seq.wrapping_sub(mapping.last_seq);
+        if ahead != 0 && ahead < u16::MAX / 2 {
+            mapping.last_seq = seq;
+            mapping.last_ts = ts;
+            mapping.last_at = at;
+        }
+        Some((seq, ts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn header(sequence_number: u16, timestamp: u32) -> Header {
+        Header {
+            sequence_number,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn first_source_passes_through_across_wraparound() {
+        let mut munger = RtpMunger::new(90000);
+        let at = Instant::now();
+        assert_eq!(
+            munger.map(&header(65534, u32::MAX), 0, at),
+            Some((65534, u32::MAX))
+        );
+        assert_eq!(munger.map(&header(65535, 899), 0, at), Some((65535, 899)));
+        assert_eq!(munger.map(&header(0, 1799), 0, at), Some((0, 1799)));
+    }
+
+    #[test]
+    fn new_source_continues_after_last_packet_sent() {
+        let mut munger = RtpMunger::new(90000);
+        let at = Instant::now();
+        munger.map(&header(65535, 1000), 0, at);
+
+        let later = at + Duration::from_millis(20);
+        assert_eq!(
+            munger.map(&header(1234, 5_000_000), 1, later),
+            Some((0, 1000 + 1800))
+        );
+        assert_eq!(
+            munger.map(&header(1235, 5_001_800), 1, later),
+            Some((1, 1000 + 3600))
+        );
+    }
+
+    #[test]
+    fn stragglers_from_an_earlier_source_are_dropped() {
+        let mut munger = RtpMunger::new(90000);
+        let at = Instant::now();
+        munger.map(&header(10, 0), 0, at);
+        munger.map(&header(500, 0), 1, at);
+        assert_eq!(munger.map(&header(11, 0), 0, at), None);
+    }
+
+    #[test]
+    fn resync_closes_the_gap_left_by_a_pause() {
+        let mut munger = RtpMunger::new(90000);
+        let at = Instant::now();
+        munger.map(&header(10, 9000), 0, at);
+
+        munger.resync();
+        let later = at + Duration::from_secs(1);
+        assert_eq!(
+            munger.map(&header(60, 99_000), 0, later),
+            Some((11, 9000 + 90000))
+        );
+    }
+
+    #[test]
+    fn reordered_packets_keep_their_place() {
+        let mut munger = RtpMunger::new(90000);
+        let at = Instant::now();
+        munger.map(&header(10, 0), 0, at);
+        munger.map(&header(12, 0), 0, at);
+        assert_eq!(munger.map(&header(11, 0), 0, at), Some((11, 0)));
+
+        assert_eq!(
+            munger.map(&header(7, 0), 1, at).map(|(seq, _)| seq),
+            Some(13)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use sfu_core::{NegotiatedCodec, NegotiatedExtension, NegotiationReport, TrackInfo};
+
+/// Codecs and header extensions of the accepted media sections in `sdp`,
+/// which should be the answer.
+pub fn report(sdp: &str) -> NegotiationReport {
+    let mut report = NegotiationReport::default();
+    let mut kind: Option<&str> = None;
+
+    for line in sdp.lines() {
+        if let Some(media) = line.strip_prefix("m=") {
+            let mut fields = media.split_whitespace();
+            let media_kind = fields.next();
+            // Port 0 rejects the section.
+            kind = media_kind.filter(|_| fields.next() != Some("0"));
+            continue;
+        }
+        let Some(kind) = kind else {
+            continue;
+        };
+
+        if let Some(rtpmap) = line.strip_prefix("a=rtpmap:") {
+            let Some((payload_type, encoding)) = rtpmap.split_once(' ') else {
+                continue;
+            };
+            let mut encoding = encoding.split('/');
+            let (Ok(payload_type), Some(name)) = (payload_type.parse(), encoding.next()) else {
+                continue;
+            };
+            let codec = NegotiatedCodec {
+                kind: kind.to_string(),
+                mime_type: format!("{}/{}", kind, name),
+                payload_type,
+                clock_rate: encoding
+                    .next()
+                    .and_then(|rate| rate.parse().ok())
+                    .unwrap_or(0),
+                fmtp: None,
+            };
+            // Bundled sections repeat the same codecs.
+            if !report
+                .codecs
+                .iter()
+                .any(|known| known.kind == codec.kind && known.payload_type == payload_type)
+            {
+                report.codecs.push(codec);
+            }
+        } else if let Some(fmtp) = line.strip_prefix("a=fmtp:") {
+            let Some((payload_type, params)) = fmtp.split_once(' ') else {
+                continue;
+            };
+            if let Some(codec) = report.codecs.iter_mut().find(|codec| {
+                codec.kind == kind && payload_type.parse::<u8>().ok() == Some(codec.payload_type)
+            }) {
+                codec.fmtp = Some(params.to_string());
+            }
+        } else if let Some(extmap) = line.strip_prefix("a=extmap:") {
+            let Some((id, uri)) = extmap.split_once(' ') else {
+                continue;
+            };
+            // The id may carry a direction, as in `3/recvonly`.
+            let Ok(id) = id.split('/').next().unwrap_or(id).parse() else {
+                continue;
+            };
+            let extension = NegotiatedExtension {
+                kind: kind.to_string(),
+                id,
+                uri: uri.split_whitespace().next().unwrap_or(uri).to_string(),
+            };
+            if !report.header_extensions.contains(&extension) {
+                report.header_extensions.push(extension);
+            }
+        }
+    }
+
+    report
+}
+
+/// For the log, e.g. `video/H264 (102), audio/opus (111); 3 header extensions`.
+pub fn summary(report: &NegotiationReport) -> String {
+    let codecs: Vec<String> = report
+        .codecs
+        .iter()
+        .map(|codec| format!("{} ({})", codec.mime_type, codec.payload_type))
+        .collect();
+    format!(
+        "{}; {} header extensions",
+        codecs.join(", "),
+        report.header_extensions.len()
+    )
+}
+
+/// Forwarded tracks whose codec isn't among those `report` negotiated, as
+/// when a player only accepted VP8 and the publisher sends H.264.
+pub fn codec_mismatches(report: &NegotiationReport, tracks: &[TrackInfo]) -> Vec<String> {
+    tracks
+        .iter()
+        .filter(|track| {
+            !report
+                .codecs
+                .iter()
+                .any(|codec| codec.mime_type.eq_ignore_ascii_case(&track.mime_type))
+        })
+        .map(|track| {
+            let negotiated: Vec<&str> = report
+                .codecs
+                .iter()
+                .filter(|codec| codec.kind == track.kind)
+                .map(|codec| codec.mime_type.as_str())
+                .collect();
+            format!(
+                "track {} is {} but the subscriber negotiated [{}]",
+                track.id,
+                track.mime_type,
+                negotiated.join(", ")
+            )
+        })
+        .collect()
+}
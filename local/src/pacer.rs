@@ -0,0 +1,39 @@
+use std::time::{Duration, Instant};
+
+use crate::config::PacingConfig;
+
+/// Token bucket pacing one subscriber track. Bytes accrue at the configured
+/// rate up to the burst size; a packet larger than what has accrued waits
+/// for the difference.
+pub struct Pacer {
+    bytes_per_sec: f64,
+    burst: f64,
+    /// Negative while packets already sent are still being paid off.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Pacer {
+    pub fn new(config: &PacingConfig) -> Self {
+        let burst = config.burst_bytes as f64;
+        Self {
+            bytes_per_sec: (config.rate_kbps * 1000 / 8).max(1) as f64,
+            burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Waits until `bytes` may be sent.
+    pub async fn wait(&mut self, bytes: usize) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.burst);
+        self.last_refill = now;
+
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(-self.tokens / self.bytes_per_sec)).await;
+        }
+    }
+}
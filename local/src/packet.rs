@@ -0,0 +1,31 @@
+//! Per-subscriber copies of forwarded RTP packets.
+
+use webrtc::rtp::header::Header;
+use webrtc::rtp::packet::Packet;
+
+/// The packet a forwarder writes to its subscriber, kept across packets so
+/// renumbering reuses its CSRC and extension lists instead of cloning the
+/// publisher's packet for every subscriber. The payload is `Bytes`, shared
+/// with the publisher's packet.
+#[derive(Default)]
+pub struct PacketBuffer(Packet);
+
+impl PacketBuffer {
+    /// `packet` as sequence number `sequence_number` at `timestamp`.
+    pub fn renumber(&mut self, packet: &Packet, sequence_number: u16, timestamp: u32) -> &Packet {
+        let header = &mut self.0.header;
+        let mut csrc = std::mem::take(&mut header.csrc);
+        let mut extensions = std::mem::take(&mut header.extensions);
+        csrc.clone_from(&packet.header.csrc);
+        extensions.clone_from(&packet.header.extensions);
+        *header = Header {
+            sequence_number,
+            timestamp,
+            csrc,
+            extensions,
+            ..packet.header
+        };
+        self.0.payload = packet.payload.clone();
+        &self.0
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{trace, warn};
+use webrtc::{
+    api::API,
+    peer_connection::{sdp::sdp_type::RTCSdpType, RTCPeerConnection},
+};
+
+use crate::config::ConfigHandle;
+use crate::error::{Result as SfuResult, SfuError};
+use crate::sfu::rtc_config;
+
+/// How long a pooled connection may spend gathering ICE candidates before it
+/// is pooled with whatever it found; the rest trickle to the subscriber.
+const GATHER_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Idle {
+    pc: Arc<RTCPeerConnection>,
+    /// The ICE servers the connection was created with.
+    ice_servers: Vec<String>,
+}
+
+/// Subscriber peer connections created ahead of time, so the DTLS
+/// certificate and transports aren't set up and ICE candidates aren't
+/// gathered while a viewer waits. Keeps `performance.subscriber_pool_size`
+/// connections ready.
+pub struct PeerConnectionPool {
+    api: Arc<API>,
+    config: ConfigHandle,
+    idle: Mutex<Vec<Idle>>,
+    filling: AtomicBool,
+}
+
+impl PeerConnectionPool {
+    pub fn new(api: Arc<API>, config: ConfigHandle) -> Arc<Self> {
+        Arc::new(Self {
+            api,
+            config,
+            idle: Mutex::new(Vec::new()),
+            filling: AtomicBool::new(false),
+        })
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// A pooled connection, or a new one when the pool is empty.
+    pub async fn take(self: &Arc<Self>) -> SfuResult<Arc<RTCPeerConnection>> {
+        let ice_servers = self.config.current().ice_servers.clone();
+        let (pooled, stale) = {
+            let mut idle = self.idle.lock().unwrap();
+            // Connections from before a config reload use the old ICE servers.
+            let (fresh, stale): (Vec<_>, Vec<_>) = idle
+                .drain(..)
+                .partition(|entry| entry.ice_servers == ice_servers);
+            *idle = fresh;
+            (idle.pop(), stale)
+        };
+
+        if !stale.is_empty() {
+            tokio::spawn(async move {
+                for entry in stale {
+                    let _ = entry.pc.close().await;
+                }
+            });
+        }
+        self.refill();
+
+        match pooled {
+            Some(entry) => {
+                trace!("Using a pooled peer connection");
+                Ok(entry.pc)
+            }
+            None => self.create(&ice_servers).await,
+        }
+    }
+
+    /// Tops the pool up in the background. Must run inside a Tokio runtime.
+    pub fn refill(self: &Arc<Self>) {
+        if self.config.current().performance.subscriber_pool_size == 0
+            || self.filling.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+
+        let pool = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                let config = pool.config.current();
+                if pool.idle_count() >= config.performance.subscriber_pool_size {
+                    break;
+                }
+                let pc = match pool.create(&config.ice_servers).await {
+                    Ok(pc) => pc,
+                    Err(e) => {
+                        warn!("Failed to pre-create a subscriber peer connection: {}", e);
+                        break;
+                    }
+                };
+                if let Err(e) = pre_gather(&pc).await {
+                    warn!("Failed to pre-gather ICE candidates: {}", e);
+                    let _ = pc.close().await;
+                    break;
+                }
+                pool.idle.lock().unwrap().push(Idle {
+                    pc,
+                    ice_servers: config.ice_servers.clone(),
+                });
+            }
+            pool.filling.store(false, Ordering::Release);
+        });
+    }
+
+    async fn create(&self, ice_servers: &[String]) -> SfuResult<Arc<RTCPeerConnection>> {
+        self.api
+            .new_peer_connection(rtc_config(ice_servers))
+            .await
+            .map(Arc::new)
+            .map_err(|e| SfuError::PeerConnectionCreation(e.to_string()))
+    }
+}
+
+/// Gathers ICE candidates on an idle connection. webrtc-rs only starts
+/// gathering with the first local description, so this sets a throwaway
+/// offer and rolls it back once gathering is done; the gathered candidates
+/// then go into the subscriber's answer.
+async fn pre_gather(pc: &RTCPeerConnection) -> webrtc::error::Result<()> {
+    let offer = pc.create_offer(None).await?;
+    let mut gathered = pc.gathering_complete_promise().await;
+    pc.set_local_description(offer.clone()).await?;
+    let _ = tokio::time::timeout(GATHER_TIMEOUT, gathered.recv()).await;
+
+    let mut rollback = offer;
+    rollback.sdp_type = RTCSdpType::Rollback;
+    pc.set_local_description(rollback).await
+}
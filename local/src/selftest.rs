@@ -0,0 +1,249 @@
+//! Loopback check of the media path: a synthetic publisher and a subscriber,
+//! both in-process, are connected through the SFU and RTP is pushed from one
+//! to the other.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use sfu_core::{PublisherRequest, Sfu, SubscriberRequest};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tracing::info;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::APIBuilder;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp::header::Header;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTPCodecType};
+use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+
+use crate::config::{CodecItem, SfuConfig};
+use crate::sfu::LocalSfu;
+
+const PUBLISHER_ID: &str = "self-test-publisher";
+const SUBSCRIBER_ID: &str = "self-test-subscriber";
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+const PACKET_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug)]
+pub struct SelfTestReport {
+    /// Video codecs the SFU accepted from the synthetic publisher.
+    pub video_codecs: Vec<String>,
+    /// Time from subscribing until the first forwarded packet arrived.
+    pub first_packet_after: Duration,
+}
+
+/// Publishes a synthetic video track to `sfu`, built from `config`,
+/// subscribes to it and waits for a forwarded packet. Fails if a configured
+/// video codec is not negotiated or nothing arrives in time. The test sessions
+/// are removed afterwards.
+pub async fn run(sfu: &LocalSfu, config: &SfuConfig) -> Result<SelfTestReport> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs()?;
+    LocalSfu::register_codecs_from_config(&mut media_engine, config)?;
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+    let mut setting_engine = SettingEngine::default();
+    setting_engine.set_include_loopback_candidate(true);
+    let api = APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .with_setting_engine(setting_engine)
+        .build();
+
+    let publisher = Arc::new(api.new_peer_connection(RTCConfiguration::default()).await?);
+    let subscriber = Arc::new(api.new_peer_connection(RTCConfiguration::default()).await?);
+
+    let result = exercise(sfu, &config.codecs.video, &publisher, &subscriber).await;
+
+    let _ = sfu.remove_subscriber(SUBSCRIBER_ID).await;
+    let _ = sfu.remove_publisher(PUBLISHER_ID).await;
+    let _ = subscriber.close().await;
+    let _ = publisher.close().await;
+    result
+}
+
+async fn exercise(
+    sfu: &LocalSfu,
+    configured_video: &[CodecItem],
+    publisher: &Arc<RTCPeerConnection>,
+    subscriber: &Arc<RTCPeerConnection>,
+) -> Result<SelfTestReport> {
+    let mime_type = configured_video
+        .first()
+        .map_or("video/VP8".to_string(), |codec| codec.mime.clone());
+    let track = Arc::new(TrackLocalStaticRTP::new(
+        RTCRtpCodecCapability {
+            mime_type: mime_type.clone(),
+            clock_rate: 90000,
+            ..Default::default()
+        },
+        "self-test-video".to_string(),
+        "self-test".to_string(),
+    ));
+    publisher
+        .add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>)
+        .await?;
+
+    let offer = local_offer(publisher).await?;
+    let (ice_tx, ice_rx) = mpsc::unbounded_channel();
+    forward_ice(ice_rx, Arc::clone(publisher));
+    let response = sfu
+        .add_publisher(PublisherRequest {
+            publisher_id: PUBLISHER_ID.to_string(),
+            session_id: PUBLISHER_ID.to_string(),
+            offer,
+            ice_candidate_tx: Some(ice_tx),
+        })
+        .await
+        .context("SFU rejected the publisher offer")?;
+
+    let answer_sdp = response.answer.sdp.to_lowercase();
+    let mut video_codecs = Vec::new();
+    for codec in configured_video
+        .iter()
+        .map(|c| c.mime.as_str())
+        .chain([mime_type.as_str()])
+    {
+        let (_, name) = codec.split_once('/').unwrap_or(("", codec));
+        if !answer_sdp.contains(&format!(" {}/", name.to_lowercase())) {
+            bail!("Video codec {} was not negotiated", codec);
+        }
+        if !video_codecs.iter().any(|c| c == codec) {
+            video_codecs.push(codec.to_string());
+        }
+    }
+    publisher.set_remote_description(response.answer).await?;
+
+    let writer_track = Arc::clone(&track);
+    let writer = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PACKET_INTERVAL);
+        for sequence_number in 0u16.. {
+            interval.tick().await;
+            let packet = Packet {
+                header: Header {
+                    version: 2,
+                    marker: true,
+                    sequence_number,
+                    timestamp: u32::from(sequence_number).wrapping_mul(1800),
+                    ..Default::default()
+                },
+                payload: Bytes::from_static(&[0u8; 100]),
+            };
+            if writer_track.write_rtp(&packet).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = subscribe(sfu, subscriber).await;
+    writer.abort();
+
+    Ok(SelfTestReport {
+        video_codecs,
+        first_packet_after: result?,
+    })
+}
+
+/// Waits for the publisher's track to reach the SFU, subscribes to it and
+/// returns how long the first forwarded packet took.
+async fn subscribe(sfu: &LocalSfu, subscriber: &Arc<RTCPeerConnection>) -> Result<Duration> {
+    let deadline = Instant::now() + STEP_TIMEOUT;
+    loop {
+        let tracks = sfu
+            .get_session(PUBLISHER_ID)
+            .await?
+            .map_or(0, |info| info.tracks.len());
+        if tracks > 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            bail!("Publisher track did not reach the SFU");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    subscriber
+        .add_transceiver_from_kind(
+            RTPCodecType::Video,
+            Some(RTCRtpTransceiverInit {
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                send_encodings: vec![],
+            }),
+        )
+        .await?;
+
+    let (received_tx, received_rx) = oneshot::channel();
+    let received_tx = Arc::new(std::sync::Mutex::new(Some(received_tx)));
+    subscriber.on_track(Box::new(move |track, _, _| {
+        let received_tx = Arc::clone(&received_tx);
+        Box::pin(async move {
+            if track.read_rtp().await.is_ok() {
+                if let Some(tx) = received_tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+            }
+        })
+    }));
+
+    let started = Instant::now();
+    let offer = local_offer(subscriber).await?;
+    let (ice_tx, ice_rx) = mpsc::unbounded_channel();
+    forward_ice(ice_rx, Arc::clone(subscriber));
+    let response = sfu
+        .add_subscriber(SubscriberRequest {
+            subscriber_id: SUBSCRIBER_ID.to_string(),
+            publisher_id: PUBLISHER_ID.to_string(),
+            offer,
+            ice_candidate_tx: Some(ice_tx),
+            renegotiation_tx: None,
+            stream_type: None,
+            sync_group: None,
+            upstream: false,
+        })
+        .await
+        .context("SFU rejected the subscriber offer")?;
+    subscriber
+        .set_remote_description(RTCSessionDescription::answer(response.answer.sdp)?)
+        .await?;
+
+    tokio::time::timeout(STEP_TIMEOUT, received_rx)
+        .await
+        .map_err(|_| anyhow!("No RTP was forwarded to the subscriber"))??;
+
+    let elapsed = started.elapsed();
+    info!("Self-test forwarded RTP after {:?}", elapsed);
+    Ok(elapsed)
+}
+
+/// Creates an offer and waits for ICE gathering, so the offer carries every
+/// candidate and nothing has to be trickled to the SFU.
+async fn local_offer(pc: &RTCPeerConnection) -> Result<RTCSessionDescription> {
+    let offer = pc.create_offer(None).await?;
+    let mut gathered = pc.gathering_complete_promise().await;
+    pc.set_local_description(offer).await?;
+    let _ = gathered.recv().await;
+    pc.local_description()
+        .await
+        .ok_or_else(|| anyhow!("Missing local description"))
+}
+
+/// Applies the SFU's trickled candidates to a test peer connection.
+fn forward_ice(
+    mut ice_rx: mpsc::UnboundedReceiver<RTCIceCandidateInit>,
+    pc: Arc<RTCPeerConnection>,
+) {
+    tokio::spawn(async move {
+        while let Some(candidate) = ice_rx.recv().await {
+            let _ = pc.add_ice_candidate(candidate).await;
+        }
+    });
+}
@@ -1,11 +1,30 @@
 use crate::broadcaster::TrackBroadcaster;
+use crate::config::StreamFilter;
+use crate::stats::EgressStats;
+use crate::sync::SyncGroup;
+use crate::tasks::SessionTasks;
 use dashmap::DashMap;
-use std::sync::Arc;
+use sfu_core::{NegotiationReport, RenegotiationSender, TrackMetadata};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use webrtc::data_channel::RTCDataChannel;
 use webrtc::peer_connection::RTCPeerConnection;
 
 pub struct PublisherSession {
     pub pc: Arc<RTCPeerConnection>,
     pub broadcasters: Arc<DashMap<String, Arc<TrackBroadcaster>>>,
+    /// Transceiver mid -> id of the broadcaster fed by it, so a replacement
+    /// track on the same transceiver reuses the broadcaster.
+    track_mids: DashMap<String, String>,
+    /// Channels opened by the publisher, keyed by label. Their messages are
+    /// relayed to every subscriber.
+    data_channels: DashMap<String, Arc<RTCDataChannel>>,
+    /// Labels sent by the publisher, keyed by track id.
+    track_metadata: DashMap<String, TrackMetadata>,
+    negotiated: Mutex<Option<NegotiationReport>>,
+    /// Set while an operator disabled the publisher's audio; applies to
+    /// audio tracks added later too.
+    audio_muted: Arc<AtomicBool>,
 }
 
 impl PublisherSession {
@@ -13,9 +32,48 @@ impl PublisherSession {
         Self {
             pc,
             broadcasters: Arc::new(DashMap::new()),
+            track_mids: DashMap::new(),
+            data_channels: DashMap::new(),
+            track_metadata: DashMap::new(),
+            negotiated: Mutex::new(None),
+            audio_muted: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// A session on a new peer connection that keeps `previous`'s
+    /// broadcasters, so tracks arriving on the same mids are rebound and
+    /// subscribers stay attached.
+    pub fn resume(pc: Arc<RTCPeerConnection>, previous: &PublisherSession) -> Self {
+        Self {
+            pc,
+            broadcasters: Arc::clone(&previous.broadcasters),
+            track_mids: previous.track_mids.clone(),
+            data_channels: DashMap::new(),
+            track_metadata: previous.track_metadata.clone(),
+            negotiated: Mutex::new(None),
+            audio_muted: Arc::clone(&previous.audio_muted),
+        }
+    }
+
+    pub fn set_negotiated(&self, report: NegotiationReport) {
+        *self.negotiated.lock().unwrap() = Some(report);
+    }
+
+    pub fn negotiated(&self) -> Option<NegotiationReport> {
+        self.negotiated.lock().unwrap().clone()
+    }
+
+    pub fn set_track_metadata(&self, tracks: Vec<TrackMetadata>) {
+        self.track_metadata.clear();
+        for track in tracks {
+            self.track_metadata.insert(track.track_id.clone(), track);
+        }
+    }
+
+    pub fn track_metadata(&self, track_id: &str) -> Option<TrackMetadata> {
+        self.track_metadata.get(track_id).map(|m| m.value().clone())
+    }
+
     pub fn get_broadcaster(&self, track_id: &str) -> Option<Arc<TrackBroadcaster>> {
         self.broadcasters
             .get(track_id)
@@ -23,7 +81,31 @@ impl PublisherSession {
     }
 
     pub fn add_broadcaster(&self, track_id: String, broadcaster: Arc<TrackBroadcaster>) {
-        self.broadcasters.insert(track_id, broadcaster);
+        // Inserted first, so a concurrent `set_audio_muted` reaches it either
+        // way.
+        self.broadcasters.insert(track_id, Arc::clone(&broadcaster));
+        if broadcaster.kind == "audio" && self.audio_muted.load(Ordering::Relaxed) {
+            broadcaster.set_muted(true);
+        }
+    }
+
+    /// Stops or restarts forwarding the publisher's audio to its subscribers.
+    pub fn set_audio_muted(&self, muted: bool) {
+        self.audio_muted.store(muted, Ordering::Relaxed);
+        for entry in self.broadcasters.iter() {
+            if entry.value().kind == "audio" {
+                entry.value().set_muted(muted);
+            }
+        }
+    }
+
+    pub fn bind_mid(&self, mid: String, track_id: String) {
+        self.track_mids.insert(mid, track_id);
+    }
+
+    pub fn broadcaster_for_mid(&self, mid: &str) -> Option<Arc<TrackBroadcaster>> {
+        let track_id = self.track_mids.get(mid)?.value().clone();
+        self.get_broadcaster(&track_id)
     }
 
     pub fn get_all_broadcasters(&self) -> Vec<(String, Arc<TrackBroadcaster>)> {
@@ -32,6 +114,18 @@ impl PublisherSession {
             .map(|entry| (entry.key().clone(), Arc::clone(entry.value())))
             .collect()
     }
+
+    pub fn add_data_channel(&self, channel: Arc<RTCDataChannel>) {
+        self.data_channels
+            .insert(channel.label().to_string(), channel);
+    }
+
+    pub fn data_channels(&self) -> Vec<Arc<RTCDataChannel>> {
+        self.data_channels
+            .iter()
+            .map(|entry| Arc::clone(entry.value()))
+            .collect()
+    }
 }
 
 impl Drop for PublisherSession {
@@ -48,21 +142,113 @@ impl Drop for PublisherSession {
 pub struct SubscriberSession {
     pub pc: Arc<RTCPeerConnection>,
     pub publisher_id: String,
-    pub track_mapping: Vec<(String, String)>,
+    /// (publisher track id, local track id) pairs; grows on renegotiation.
+    pub track_mapping: Mutex<Vec<(String, String)>>,
+    pub egress_stats: Arc<EgressStats>,
+    pub renegotiation_tx: Option<RenegotiationSender>,
+    /// Whether the subscriber's offer negotiated SCTP, without which relayed
+    /// data channels can't open.
+    pub accepts_data_channels: bool,
+    /// Only tracks of this stream are forwarded; `None` forwards all.
+    pub stream_filter: Option<StreamFilter>,
+    /// Tracks forwarded to the subscriber, including late ones, play in step
+    /// with this group's.
+    pub sync_group: Option<Arc<SyncGroup>>,
+    /// The publisher added tracks while an offer to the subscriber was
+    /// outstanding; they are attached once it answers.
+    pub late_tracks_pending: AtomicBool,
+    /// Messages on upstream data channels are passed back to the publisher.
+    pub upstream: bool,
+    /// Spawns the subscriber's forwarders and feedback readers.
+    pub(crate) tasks: SessionTasks,
+    data_channels: DashMap<String, Arc<RTCDataChannel>>,
+    negotiated: Mutex<Option<NegotiationReport>>,
 }
 
 impl SubscriberSession {
-    pub fn new(
+    pub(crate) fn new(
         pc: Arc<RTCPeerConnection>,
         publisher_id: String,
+        tasks: SessionTasks,
         track_mapping: Vec<(String, String)>,
+        egress_stats: Arc<EgressStats>,
+        renegotiation_tx: Option<RenegotiationSender>,
+        accepts_data_channels: bool,
     ) -> Self {
         Self {
             pc,
             publisher_id,
-            track_mapping,
+            track_mapping: Mutex::new(track_mapping),
+            egress_stats,
+            renegotiation_tx,
+            accepts_data_channels,
+            stream_filter: None,
+            sync_group: None,
+            late_tracks_pending: AtomicBool::new(false),
+            upstream: false,
+            tasks,
+            data_channels: DashMap::new(),
+            negotiated: Mutex::new(None),
         }
     }
+
+    pub fn with_stream_filter(mut self, stream_filter: Option<StreamFilter>) -> Self {
+        self.stream_filter = stream_filter;
+        self
+    }
+
+    pub fn with_sync_group(mut self, sync_group: Option<Arc<SyncGroup>>) -> Self {
+        self.sync_group = sync_group;
+        self
+    }
+
+    pub fn with_upstream(mut self, upstream: bool) -> Self {
+        self.upstream = upstream;
+        self
+    }
+
+    pub fn set_negotiated(&self, report: NegotiationReport) {
+        *self.negotiated.lock().unwrap() = Some(report);
+    }
+
+    pub fn negotiated(&self) -> Option<NegotiationReport> {
+        self.negotiated.lock().unwrap().clone()
+    }
+
+    pub fn track_mapping(&self) -> Vec<(String, String)> {
+        self.track_mapping.lock().unwrap().clone()
+    }
+
+    pub fn wants(&self, broadcaster: &TrackBroadcaster) -> bool {
+        self.stream_filter
+            .is_none_or(|filter| filter.matches(&broadcaster.kind, &broadcaster.stream_id))
+    }
+
+    pub fn has_track(&self, original_track_id: &str) -> bool {
+        self.track_mapping
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(id, _)| id == original_track_id)
+    }
+
+    pub fn data_channel_labels(&self) -> Vec<String> {
+        self.data_channels
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    pub fn data_channel(&self, label: &str) -> Option<Arc<RTCDataChannel>> {
+        self.data_channels
+            .get(label)
+            .map(|entry| Arc::clone(entry.value()))
+    }
+
+    pub fn add_data_channel(&self, channel: Arc<RTCDataChannel>) {
+        self.data_channels
+            .insert(channel.label().to_string(), channel);
+    }
 }
 
 impl Drop for SubscriberSession {
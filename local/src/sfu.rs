@@ -1,49 +1,106 @@
 use anyhow::{Context, Result};
 use dashmap::DashMap;
 use sfu_core::{
-    PublisherRequest, PublisherResponse, PublisherUpdateRequest, PublisherUpdateResponse, Sfu,
-    SubscriberRequest, SubscriberResponse, SubscriberUpdateRequest, SubscriberUpdateResponse,
+    PublisherRequest, PublisherResponse, PublisherStats, PublisherUpdateRequest,
+    PublisherUpdateResponse, SessionInfo, SessionKind, Sfu, SfuEvent, SubscriberRequest,
+    SubscriberResponse, SubscriberStats, SubscriberUpdateRequest, SubscriberUpdateResponse,
+    TrackInfo, TrackMetadata,
 };
 use sfu_proto::SfuMetrics;
-use std::sync::Arc;
-use tracing::{info, warn};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+use tokio::sync::broadcast;
+use tracing::{info, info_span, instrument, warn, Instrument};
 use webrtc::{
     api::{
-        interceptor_registry::register_default_interceptors, media_engine::MediaEngine, APIBuilder,
-        API,
+        interceptor_registry::register_default_interceptors, media_engine::MediaEngine,
+        setting_engine::SettingEngine, APIBuilder, API,
+    },
+    data_channel::{
+        data_channel_init::RTCDataChannelInit, data_channel_message::DataChannelMessage,
+        data_channel_state::RTCDataChannelState, RTCDataChannel,
+    },
+    ice::{
+        udp_mux::{UDPMuxDefault, UDPMuxParams},
+        udp_network::{EphemeralUDP, UDPNetwork},
+    },
+    ice_transport::{
+        ice_candidate::RTCIceCandidateInit, ice_candidate_type::RTCIceCandidateType,
+        ice_server::RTCIceServer,
     },
-    ice_transport::{ice_candidate::RTCIceCandidateInit, ice_server::RTCIceServer},
     interceptor::registry::Registry,
     peer_connection::{
         configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription, signaling_state::RTCSignalingState,
         RTCPeerConnection,
     },
-    rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType},
-    track::track_local::{track_local_static_rtp::TrackLocalStaticRTP, TrackLocal},
+    rtp_transceiver::rtp_codec::{
+        RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionCapability, RTPCodecType,
+    },
+    track::{
+        track_local::{track_local_static_rtp::TrackLocalStaticRTP, TrackLocal},
+        track_remote::TrackRemote,
+    },
 };
 
 use crate::error::{Result as SfuResult, SfuError};
 use crate::{
+    audio_level::AUDIO_LEVEL_URI,
+    bitrate_cap,
     broadcaster::TrackBroadcaster,
-    config::SfuConfig,
+    config::{
+        ConfigHandle, ContentKind, ContentProfile, ContentProfilesConfig, SfuConfig, StreamFilter,
+    },
+    contest_clock, negotiation,
+    pool::PeerConnectionPool,
     session::{PublisherSession, SubscriberSession},
+    stats::{connection_rtt_ms, uses_relay, EgressStats},
+    sync::{SyncGroup, SyncGroups},
+    tasks::{SessionTasks, TaskMonitor},
 };
 
 pub struct LocalSfu {
     id: String,
     api: Arc<API>,
-    config: SfuConfig,
+    config: ConfigHandle,
     publishers: DashMap<String, Arc<PublisherSession>>,
-    subscribers: DashMap<String, Arc<SubscriberSession>>,
+    subscribers: Arc<DashMap<String, Arc<SubscriberSession>>>,
+    subscriber_pool: Arc<PeerConnectionPool>,
+    /// Session id -> whether its connection was established through a relay.
+    relayed: Arc<DashMap<String, bool>>,
     metrics: Arc<DashMap<String, usize>>,
+    sync_groups: SyncGroups,
+    tasks: TaskMonitor,
+    started_at: Instant,
+    system: Mutex<System>,
 }
 
 impl LocalSfu {
     pub fn new(id: String, config: SfuConfig) -> SfuResult<Self> {
+        Self::with_config_handle(id, ConfigHandle::new(config))
+    }
+
+    /// Shares `config` with the caller so reloads apply to new connections.
+    /// Codecs are registered once here and need a restart to change.
+    pub fn with_config_handle(id: String, config: ConfigHandle) -> SfuResult<Self> {
         let mut media_engine = MediaEngine::default();
         let _ = media_engine.register_default_codecs();
 
-        Self::register_codecs_from_config(&mut media_engine, &config)?;
+        let initial = config.current();
+        Self::register_codecs_from_config(&mut media_engine, &initial)?;
+        media_engine
+            .register_header_extension(
+                RTCRtpHeaderExtensionCapability {
+                    uri: AUDIO_LEVEL_URI.to_owned(),
+                },
+                RTPCodecType::Audio,
+                None,
+            )
+            .map_err(|e| {
+                SfuError::Configuration(format!("Failed to register audio level extension: {}", e))
+            })?;
 
         let mut registry = Registry::new();
         registry = register_default_interceptors(registry, &mut media_engine).map_err(|e| {
@@ -53,19 +110,186 @@ impl LocalSfu {
         let api = APIBuilder::new()
             .with_media_engine(media_engine)
             .with_interceptor_registry(registry)
+            .with_setting_engine(Self::build_setting_engine(&initial)?)
             .build();
+        let api = Arc::new(api);
+
+        let subscriber_pool = PeerConnectionPool::new(Arc::clone(&api), config.clone());
+        if tokio::runtime::Handle::try_current().is_ok() {
+            subscriber_pool.refill();
+        }
 
         Ok(Self {
             id,
-            api: Arc::new(api),
+            api,
             config,
             publishers: DashMap::new(),
-            subscribers: DashMap::new(),
+            subscribers: Arc::new(DashMap::new()),
+            subscriber_pool,
+            relayed: Arc::new(DashMap::new()),
             metrics: Arc::new(DashMap::new()),
+            sync_groups: SyncGroups::default(),
+            tasks: TaskMonitor::new(),
+            started_at: Instant::now(),
+            system: Mutex::new(System::new()),
         })
     }
 
-    fn register_codecs_from_config(
+    pub fn publisher_ids(&self) -> Vec<String> {
+        self.publishers
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    pub fn subscriber_ids(&self) -> Vec<String> {
+        self.subscribers
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Snapshot of a publisher or subscriber session, without going through
+    /// the async [`Sfu`] trait.
+    pub fn session_info(&self, session_id: &str) -> Option<SessionInfo> {
+        if let Some(session) = self.publishers.get(session_id) {
+            return Some(self.publisher_info(session_id, &session));
+        }
+        self.subscribers
+            .get(session_id)
+            .map(|session| self.subscriber_info(session_id, &session))
+    }
+
+    fn publisher_info(&self, id: &str, session: &PublisherSession) -> SessionInfo {
+        SessionInfo {
+            id: id.to_string(),
+            kind: SessionKind::Publisher,
+            publisher_id: None,
+            connection_state: session.pc.connection_state(),
+            relayed: self.relayed.get(id).map(|relayed| *relayed),
+            tracks: session
+                .get_all_broadcasters()
+                .iter()
+                .map(|(_, broadcaster)| track_info(broadcaster, session))
+                .collect(),
+            negotiated: session.negotiated(),
+            codec_mismatches: Vec::new(),
+            sync_skew_ms: None,
+        }
+    }
+
+    fn subscriber_info(&self, id: &str, session: &SubscriberSession) -> SessionInfo {
+        let publisher = self
+            .publishers
+            .get(&session.publisher_id)
+            .map(|entry| Arc::clone(entry.value()));
+        let tracks = session
+            .track_mapping()
+            .iter()
+            .filter_map(|(original_track_id, _)| {
+                let publisher = publisher.as_ref()?;
+                publisher
+                    .get_broadcaster(original_track_id)
+                    .map(|broadcaster| track_info(&broadcaster, publisher))
+            })
+            .collect::<Vec<_>>();
+        let negotiated = session.negotiated();
+        let codec_mismatches = negotiated
+            .as_ref()
+            .map(|report| negotiation::codec_mismatches(report, &tracks))
+            .unwrap_or_default();
+
+        SessionInfo {
+            id: id.to_string(),
+            kind: SessionKind::Subscriber,
+            publisher_id: Some(session.publisher_id.clone()),
+            connection_state: session.pc.connection_state(),
+            relayed: self.relayed.get(id).map(|relayed| *relayed),
+            tracks,
+            negotiated,
+            codec_mismatches,
+            sync_skew_ms: session
+                .sync_group
+                .as_ref()
+                .and_then(|group| group.skew())
+                .map(|skew| skew.as_millis() as u64),
+        }
+    }
+
+    fn record_publisher_negotiation(&self, id: &str, session: &PublisherSession, answer: &str) {
+        let report = negotiation::report(answer);
+        info!(
+            "Publisher {} negotiated {}",
+            id,
+            negotiation::summary(&report)
+        );
+        session.set_negotiated(report);
+    }
+
+    /// Also warns about forwarded tracks the subscriber can't decode.
+    fn record_subscriber_negotiation(&self, id: &str, session: &SubscriberSession, answer: &str) {
+        let report = negotiation::report(answer);
+        info!(
+            "Subscriber {} negotiated {}",
+            id,
+            negotiation::summary(&report)
+        );
+        session.set_negotiated(report);
+        for mismatch in self.subscriber_info(id, session).codec_mismatches {
+            warn!("Subscriber {}: {}", id, mismatch);
+        }
+    }
+
+    fn build_setting_engine(config: &SfuConfig) -> SfuResult<SettingEngine> {
+        let mut setting_engine = SettingEngine::default();
+
+        match (config.webrtc.udp_mux_port, config.webrtc.port_range) {
+            (Some(_), Some(_)) => {
+                return Err(SfuError::Configuration(
+                    "webrtc.udp_mux_port and webrtc.port_range are mutually exclusive".to_string(),
+                ));
+            }
+            (Some(port), None) => {
+                setting_engine.set_udp_network(UDPNetwork::Muxed(Self::bind_udp_mux(port)?));
+            }
+            (None, Some(range)) => {
+                let udp = EphemeralUDP::new(range.min, range.max).map_err(|e| {
+                    SfuError::Configuration(format!(
+                        "Invalid port range {}-{}: {}",
+                        range.min, range.max, e
+                    ))
+                })?;
+                setting_engine.set_udp_network(UDPNetwork::Ephemeral(udp));
+            }
+            (None, None) => {}
+        }
+
+        if !config.webrtc.nat_1to1_ips.is_empty() {
+            setting_engine.set_nat_1to1_ips(
+                config.webrtc.nat_1to1_ips.clone(),
+                RTCIceCandidateType::Host,
+            );
+        }
+
+        Ok(setting_engine)
+    }
+
+    /// Binds the one UDP socket all peer connections share. Must run inside a
+    /// Tokio runtime.
+    fn bind_udp_mux(port: u16) -> SfuResult<Arc<UDPMuxDefault>> {
+        let bind_err = |e: std::io::Error| {
+            SfuError::Configuration(format!("Failed to bind UDP mux port {}: {}", port, e))
+        };
+
+        let socket = std::net::UdpSocket::bind(("0.0.0.0", port)).map_err(bind_err)?;
+        socket.set_nonblocking(true).map_err(bind_err)?;
+        let socket = tokio::net::UdpSocket::from_std(socket).map_err(bind_err)?;
+
+        info!("ICE UDP mux listening on port {}", port);
+        Ok(UDPMuxDefault::new(UDPMuxParams::new(socket)))
+    }
+
+    pub(crate) fn register_codecs_from_config(
         media_engine: &mut MediaEngine,
         config: &SfuConfig,
     ) -> SfuResult<()> {
@@ -118,27 +342,15 @@ impl LocalSfu {
     }
 
     fn build_rtc_config(&self) -> RTCConfiguration {
-        let ice_servers = self
-            .config
-            .ice_servers
-            .iter()
-            .map(|url| RTCIceServer {
-                urls: vec![url.clone()],
-                ..Default::default()
-            })
-            .collect();
-
-        RTCConfiguration {
-            ice_servers,
-            ..Default::default()
-        }
+        rtc_config(&self.config.current().ice_servers)
     }
 
     fn check_publisher_limit(&self) -> SfuResult<()> {
-        if self.publishers.len() >= self.config.performance.max_publishers {
-            return Err(SfuError::Internal(format!(
+        let max_publishers = self.config.current().performance.max_publishers;
+        if self.publishers.len() >= max_publishers {
+            return Err(SfuError::LimitReached(format!(
                 "Maximum publisher limit reached: {}",
-                self.config.performance.max_publishers
+                max_publishers
             )));
         }
         Ok(())
@@ -151,10 +363,15 @@ impl LocalSfu {
             .filter(|entry| entry.value().publisher_id == publisher_id)
             .count();
 
-        if subscriber_count >= self.config.performance.max_subscribers_per_publisher {
-            return Err(SfuError::Internal(format!(
+        let max_subscribers = self
+            .config
+            .current()
+            .performance
+            .max_subscribers_per_publisher;
+        if subscriber_count >= max_subscribers {
+            return Err(SfuError::LimitReached(format!(
                 "Maximum subscriber limit reached for publisher {}: {}",
-                publisher_id, self.config.performance.max_subscribers_per_publisher
+                publisher_id, max_subscribers
             )));
         }
         Ok(())
@@ -168,30 +385,75 @@ impl LocalSfu {
     ) {
         let peer_id_clone = peer_id.clone();
         let peer_type_str = peer_type.to_string();
+        let weak_pc = Arc::downgrade(pc);
+        let relayed = Arc::clone(&self.relayed);
 
         pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
             let id = peer_id_clone.clone();
             let ptype = peer_type_str.clone();
-            Box::pin(async move {
-                match state {
-                    RTCPeerConnectionState::Connected => {
-                        info!("{} {} connected", ptype, id);
-                    }
-                    RTCPeerConnectionState::Disconnected => {
-                        warn!("{} {} disconnected", ptype, id);
-                    }
-                    RTCPeerConnectionState::Failed => {
-                        warn!("{} {} connection failed", ptype, id);
-                    }
-                    RTCPeerConnectionState::Closed => {
-                        info!("{} {} connection closed", ptype, id);
+            let pc = weak_pc.upgrade();
+            let relayed = Arc::clone(&relayed);
+            let span = info_span!(
+                "peer_connection_state",
+                peer_type = %ptype,
+                peer_id = %id,
+                state = %state
+            );
+            Box::pin(
+                async move {
+                    match state {
+                        RTCPeerConnectionState::Connected => {
+                            info!("{} {} connected", ptype, id);
+                            if let Some(pc) = pc {
+                                if let Some(via_relay) = uses_relay(&pc).await {
+                                    if via_relay {
+                                        info!("{} {} is connected through a TURN relay", ptype, id);
+                                    }
+                                    relayed.insert(id, via_relay);
+                                }
+                            }
+                        }
+                        RTCPeerConnectionState::Disconnected => {
+                            warn!("{} {} disconnected", ptype, id);
+                        }
+                        RTCPeerConnectionState::Failed => {
+                            warn!("{} {} connection failed", ptype, id);
+                        }
+                        RTCPeerConnectionState::Closed => {
+                            info!("{} {} connection closed", ptype, id);
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
-            })
+                .instrument(span),
+            )
         }));
     }
 
+    /// Returns (system CPU %, process resident memory, total memory) in bytes.
+    fn sample_system(&self) -> (f64, u64, u64) {
+        let mut system = self.system.lock().unwrap();
+        system.refresh_cpu_usage();
+        system.refresh_memory();
+
+        let cpu_usage = system.global_cpu_usage() as f64;
+        let memory_total = system.total_memory();
+
+        let memory_usage = sysinfo::get_current_pid()
+            .ok()
+            .and_then(|pid| {
+                system.refresh_processes_specifics(
+                    ProcessesToUpdate::Some(&[pid]),
+                    true,
+                    ProcessRefreshKind::nothing().with_memory(),
+                );
+                system.process(pid).map(|p| p.memory())
+            })
+            .unwrap_or(0);
+
+        (cpu_usage, memory_usage, memory_total)
+    }
+
     fn update_metrics(&self, key: &str, delta: isize) {
         self.metrics
             .entry(key.to_string())
@@ -206,11 +468,18 @@ impl Sfu for LocalSfu {
         &self.id
     }
 
+    #[instrument(skip_all, fields(publisher_id = %req.publisher_id))]
     async fn add_publisher(&self, req: PublisherRequest) -> Result<PublisherResponse> {
         info!("Adding publisher: {}", req.publisher_id);
 
-        self.check_publisher_limit()
-            .context("Publisher limit check failed")?;
+        let previous = self
+            .publishers
+            .get(&req.publisher_id)
+            .map(|entry| Arc::clone(entry.value()));
+        if previous.is_none() {
+            self.check_publisher_limit()
+                .context("Publisher limit check failed")?;
+        }
 
         let pc = Arc::new(
             self.api
@@ -235,22 +504,87 @@ impl Sfu for LocalSfu {
             }));
         }
 
-        let session = Arc::new(PublisherSession::new(Arc::clone(&pc)));
+        // A grabber reconnecting under the same id resumes the old session:
+        // its tracks are rebound to the new connection by mid.
+        let session = Arc::new(match &previous {
+            Some(previous) => {
+                info!("Publisher {} resumed on a new connection", req.publisher_id);
+                PublisherSession::resume(Arc::clone(&pc), previous)
+            }
+            None => PublisherSession::new(Arc::clone(&pc)),
+        });
+        let unfinished = Unfinished::new(Arc::clone(&pc));
         let session_clone = Arc::clone(&session);
         let pub_id = req.publisher_id.clone();
-        let channel_capacity = self.config.performance.broadcast_channel_capacity;
+        let config = self.config.current();
+        let performance = Arc::new(config.performance.clone());
+        let profiles = Arc::new(config.content_profiles.clone());
         let pc_for_pli = Arc::clone(&pc);
+        let subscribers = Arc::clone(&self.subscribers);
+        let tasks = self
+            .tasks
+            .session(&req.publisher_id, SessionKind::Publisher);
+        tasks.spawn(
+            "bitrate cap",
+            bitrate_cap::enforce(Arc::downgrade(&session), self.config.clone(), tasks.clone()),
+        );
+
+        let dc_session = Arc::clone(&session);
+        let dc_pub_id = req.publisher_id.clone();
+        let dc_subscribers = Arc::clone(&self.subscribers);
+        let dc_config = self.config.clone();
 
-        pc.on_track(Box::new(move |track, receiver, _| {
+        pc.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+            let session = Arc::clone(&dc_session);
+            let pub_id = dc_pub_id.clone();
+            let subscribers = Arc::clone(&dc_subscribers);
+            let config = dc_config.current();
+
+            Box::pin(async move {
+                let label = channel.label().to_string();
+                info!("Publisher {} opened data channel '{}'", pub_id, label);
+                session.add_data_channel(Arc::clone(&channel));
+                let upstream = config.data_channels.allows_upstream(&label);
+
+                let targets: Vec<_> = subscribers
+                    .iter()
+                    .filter(|entry| entry.publisher_id == pub_id)
+                    .map(|entry| (entry.key().clone(), Arc::clone(entry.value())))
+                    .collect();
+                for (subscriber_id, subscriber) in targets {
+                    open_relay_channel(&subscriber, &subscriber_id, &channel, upstream).await;
+                }
+
+                channel.on_message(Box::new(move |msg: DataChannelMessage| {
+                    let subscribers = Arc::clone(&subscribers);
+                    let pub_id = pub_id.clone();
+                    let label = label.clone();
+                    Box::pin(async move {
+                        relay_data(&subscribers, &pub_id, &label, &msg).await;
+                    })
+                }));
+            })
+        }));
+
+        pc.on_track(Box::new(move |track, receiver, transceiver| {
             let session = Arc::clone(&session_clone);
             let pub_id = pub_id.clone();
             let pc_for_broadcaster = Arc::clone(&pc_for_pli);
+            let performance = Arc::clone(&performance);
+            let profiles = Arc::clone(&profiles);
+            let subscribers = Arc::clone(&subscribers);
+            let broadcaster_tasks = tasks.clone();
 
-            Box::pin(async move {
+            Box::pin(tasks.clone().run("track setup", async move {
                 let track_id = track.id();
                 let kind = track.kind();
 
                 let params = receiver.get_parameters().await;
+                let audio_level_extension = params
+                    .header_extensions
+                    .iter()
+                    .find(|extension| extension.uri == AUDIO_LEVEL_URI)
+                    .and_then(|extension| u8::try_from(extension.id).ok());
                 let (mime_type, codec_capability) = if let Some(codec) = params.codecs.first() {
                     (codec.capability.mime_type.clone(), codec.capability.clone())
                 } else {
@@ -266,6 +600,27 @@ impl Sfu for LocalSfu {
                     (default_mime, default_capability)
                 };
 
+                let profile = content_profile(&profiles, &track);
+
+                // A grabber switching camera or screen renegotiates the same
+                // transceiver; keep forwarding to subscribers through the
+                // existing broadcaster as long as the codec is unchanged.
+                let mid = transceiver.mid().map(|mid| mid.to_string());
+                if let Some(existing) = mid
+                    .as_deref()
+                    .and_then(|mid| session.broadcaster_for_mid(mid))
+                {
+                    if existing.mime_type == mime_type {
+                        info!(
+                            "Publisher {} replaced source of track {} with {}",
+                            pub_id, existing.id, track_id
+                        );
+                        existing.rebind(track, pc_for_broadcaster, profile);
+                        existing.set_audio_level_extension(audio_level_extension);
+                        return;
+                    }
+                }
+
                 info!(
                     "Publisher {} added track: {} ({}, codec: {}, fmtp: '{}')",
                     pub_id, track_id, kind, mime_type, codec_capability.sdp_fmtp_line
@@ -276,27 +631,35 @@ impl Sfu for LocalSfu {
                     pc_for_broadcaster,
                     mime_type,
                     codec_capability,
-                    channel_capacity,
+                    &performance,
+                    profile,
+                    broadcaster_tasks,
                 ));
-                session.add_broadcaster(track_id.to_string(), broadcaster);
-            })
-        }));
-
-        pc.set_remote_description(req.offer)
-            .await
-            .map_err(|e| SfuError::SetRemoteDescription(e.to_string()))?;
+                broadcaster.set_audio_level_extension(audio_level_extension);
+                session.add_broadcaster(track_id.to_string(), Arc::clone(&broadcaster));
+                if let Some(mid) = mid {
+                    session.bind_mid(mid, track_id.to_string());
+                }
 
-        let answer = pc
-            .create_answer(None)
-            .await
-            .map_err(|e| SfuError::CreateAnswer(e.to_string()))?;
+                renegotiate_subscribers(&subscribers, &pub_id, &track_id, &broadcaster).await;
+            }))
+        }));
 
-        pc.set_local_description(answer.clone())
-            .await
-            .map_err(|e| SfuError::SetLocalDescription(e.to_string()))?;
+        let step_timeout = config.server.negotiation.step_timeout();
+        let answer = answer_offer(&pc, req.offer, step_timeout).await?;
+        self.record_publisher_negotiation(&req.publisher_id, &session, &answer.sdp);
 
-        self.publishers.insert(req.publisher_id.clone(), session);
-        self.update_metrics("publishers", 1);
+        unfinished.done();
+        if self
+            .publishers
+            .insert(req.publisher_id.clone(), session)
+            .is_none()
+        {
+            self.update_metrics("publishers", 1);
+        }
+        if let Some(previous) = previous {
+            let _ = previous.pc.close().await;
+        }
 
         Ok(PublisherResponse {
             answer,
@@ -311,34 +674,43 @@ impl Sfu for LocalSfu {
         let pub_session = self
             .publishers
             .get(&req.publisher_id)
+            .map(|entry| Arc::clone(entry.value()))
             .ok_or_else(|| SfuError::PublisherNotFound(req.publisher_id.clone()))?;
 
         let pc = &pub_session.pc;
 
-        pc.set_remote_description(req.offer)
+        // New credentials mean the grabber restarted ICE, e.g. after changing
+        // networks; applying the offer restarts ICE on this side as well.
+        let ice_restart = pc
+            .remote_description()
             .await
-            .map_err(|e| SfuError::SetRemoteDescription(e.to_string()))?;
+            .is_some_and(|current| ice_ufrag(&current.sdp) != ice_ufrag(&req.offer.sdp));
 
-        let answer = pc
-            .create_answer(None)
-            .await
-            .map_err(|e| SfuError::CreateAnswer(e.to_string()))?;
+        let step_timeout = self.config.current().server.negotiation.step_timeout();
+        let answer = answer_offer(pc, req.offer, step_timeout).await?;
+        self.record_publisher_negotiation(&req.publisher_id, &pub_session, &answer.sdp);
 
-        pc.set_local_description(answer.clone())
-            .await
-            .map_err(|e| SfuError::SetLocalDescription(e.to_string()))?;
+        if ice_restart {
+            info!("Publisher {} restarted ICE", req.publisher_id);
+        }
 
         Ok(PublisherUpdateResponse { answer })
     }
 
+    #[instrument(skip(self))]
     async fn remove_publisher(&self, publisher_id: &str) -> Result<()> {
         if let Some((_, _session)) = self.publishers.remove(publisher_id) {
             info!("Removing publisher: {}", publisher_id);
+            self.relayed.remove(publisher_id);
             self.update_metrics("publishers", -1);
         }
         Ok(())
     }
 
+    #[instrument(
+        skip_all,
+        fields(subscriber_id = %req.subscriber_id, publisher_id = %req.publisher_id)
+    )]
     async fn add_subscriber(&self, req: SubscriberRequest) -> Result<SubscriberResponse> {
         self.check_subscriber_limit(&req.publisher_id)
             .context("Subscriber limit check failed")?;
@@ -346,6 +718,7 @@ impl Sfu for LocalSfu {
         let pub_session = self
             .publishers
             .get(&req.publisher_id)
+            .map(|entry| Arc::clone(entry.value()))
             .ok_or_else(|| SfuError::PublisherNotFound(req.publisher_id.clone()))?;
 
         info!(
@@ -353,12 +726,11 @@ impl Sfu for LocalSfu {
             req.subscriber_id, req.publisher_id
         );
 
-        let pc = Arc::new(
-            self.api
-                .new_peer_connection(self.build_rtc_config())
-                .await
-                .map_err(|e| SfuError::PeerConnectionCreation(e.to_string()))?,
-        );
+        let pc = self.subscriber_pool.take().await?;
+        let tasks = self
+            .tasks
+            .session(&req.subscriber_id, SessionKind::Subscriber);
+        let mut unfinished = Unfinished::new(Arc::clone(&pc));
 
         self.setup_connection_state_handler(&pc, req.subscriber_id.clone(), "Subscriber")
             .await;
@@ -376,84 +748,157 @@ impl Sfu for LocalSfu {
             }));
         }
 
-        let broadcasters = pub_session.get_all_broadcasters();
-        let mut track_mapping = Vec::with_capacity(broadcasters.len());
-
-        for (original_track_id, broadcaster) in broadcasters {
-            let local_track_id = format!("{}-{}", original_track_id, req.subscriber_id);
-
-            let local_track = Arc::new(TrackLocalStaticRTP::new(
-                broadcaster.codec_capability.clone(),
-                local_track_id.clone(),
-                format!("stream-{}", req.publisher_id),
-            ));
-
-            let rtp_sender = pc
-                .add_track(Arc::clone(&local_track) as Arc<dyn TrackLocal + Send + Sync>)
-                .await
-                .map_err(|e| SfuError::AddTrack(e.to_string()))?;
-
-            let broadcaster_for_rtcp = Arc::clone(&broadcaster);
-            let track_kind = broadcaster.kind.clone();
-            tokio::spawn(async move {
-                use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
-                use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
-
-                let mut rtcp_buf = vec![0u8; 1500];
-                while let Ok((packets, _)) = rtp_sender.read(&mut rtcp_buf).await {
-                    if track_kind != "video" {
-                        continue;
-                    }
+        let stream_filter = match req.stream_type.as_deref() {
+            Some(stream_type) => StreamFilter::from_stream_type(stream_type).ok_or_else(|| {
+                SfuError::InvalidRequest(format!("Unknown stream type '{}'", stream_type))
+            })?,
+            None => None,
+        };
 
-                    for packet in packets {
-                        if packet
-                            .as_any()
-                            .downcast_ref::<PictureLossIndication>()
-                            .is_some()
-                            || packet.as_any().downcast_ref::<FullIntraRequest>().is_some()
-                        {
-                            broadcaster_for_rtcp.request_keyframe();
-                            break;
-                        }
-                    }
-                }
-            });
+        // A player that offered no m-line of a kind, or rejected it with port
+        // 0, e.g. one listening to an audio-only grabber, gets no tracks of
+        // that kind.
+        let offered = |kind: &str| {
+            let prefix = format!("m={} ", kind);
+            req.offer.sdp.lines().any(|line| {
+                line.strip_prefix(&prefix)
+                    .and_then(|media| media.split_whitespace().next())
+                    .is_some_and(|port| port != "0")
+            })
+        };
+        let broadcasters: Vec<_> = pub_session
+            .get_all_broadcasters()
+            .into_iter()
+            .filter(|(_, broadcaster)| {
+                stream_filter
+                    .is_none_or(|filter| filter.matches(&broadcaster.kind, &broadcaster.stream_id))
+                    && offered(&broadcaster.kind)
+            })
+            .collect();
+        let sync_group = req
+            .sync_group
+            .as_deref()
+            .map(|name| self.sync_groups.get_or_create(name));
+        let mut track_mapping = Vec::with_capacity(broadcasters.len());
+        let mut clock_tracks = Vec::new();
+        let egress_stats = Arc::new(EgressStats::default());
+        let step_timeout = self.config.current().server.negotiation.step_timeout();
+        for (original_track_id, broadcaster) in &broadcasters {
+            unfinished.attaching(
+                Arc::clone(broadcaster),
+                subscriber_track_id(original_track_id, &req.subscriber_id),
+            );
+        }
 
-            broadcaster.add_subscriber(local_track).await;
+        // Each attach waits on the peer connection, which adds up over TURN,
+        // so tracks are attached together.
+        let attached = futures::future::join_all(broadcasters.iter().map(
+            |(original_track_id, broadcaster)| {
+                tokio::time::timeout(
+                    step_timeout,
+                    attach_track(
+                        &pc,
+                        broadcaster,
+                        original_track_id,
+                        &tasks,
+                        &req.publisher_id,
+                        &egress_stats,
+                        sync_group.as_ref(),
+                    ),
+                )
+            },
+        ))
+        .await;
+        for ((original_track_id, broadcaster), result) in broadcasters.into_iter().zip(attached) {
+            let Ok(local_track_id) = result else {
+                warn!(
+                    "Attaching track {} to subscriber {} timed out, leaving it out",
+                    original_track_id, req.subscriber_id
+                );
+                let local_track_id = subscriber_track_id(&original_track_id, &req.subscriber_id);
+                detach_track(&pc, &broadcaster, &local_track_id).await;
+                continue;
+            };
+            let local_track_id = local_track_id?;
+            if broadcaster.kind == "video" {
+                clock_tracks.push((local_track_id.clone(), Arc::clone(&broadcaster)));
+            }
             track_mapping.push((original_track_id, local_track_id));
         }
 
-        pc.set_remote_description(req.offer)
-            .await
-            .map_err(|e| SfuError::SetRemoteDescription(e.to_string()))?;
-
-        let answer = pc
-            .create_answer(None)
-            .await
-            .map_err(|e| SfuError::CreateAnswer(e.to_string()))?;
-
-        pc.set_local_description(answer.clone())
-            .await
-            .map_err(|e| SfuError::SetLocalDescription(e.to_string()))?;
-
-        let sub_session = Arc::new(SubscriberSession::new(
-            pc,
-            req.publisher_id.clone(),
-            track_mapping,
-        ));
+        let accepts_data_channels = req.offer.sdp.contains("m=application");
+
+        let answer = answer_offer(&pc, req.offer, step_timeout).await?;
+
+        let sub_session = Arc::new(
+            SubscriberSession::new(
+                pc,
+                req.publisher_id.clone(),
+                tasks,
+                track_mapping,
+                egress_stats,
+                req.renegotiation_tx,
+                accepts_data_channels,
+            )
+            .with_stream_filter(stream_filter)
+            .with_sync_group(sync_group)
+            .with_upstream(req.upstream),
+        );
+        self.record_subscriber_negotiation(&req.subscriber_id, &sub_session, &answer.sdp);
+
+        let config = self.config.current();
+        let channels = pub_session.data_channels();
+        let subscriber_id = &req.subscriber_id;
+        futures::future::join_all(channels.iter().map(|channel| {
+            let upstream = config.data_channels.allows_upstream(channel.label());
+            let open = open_relay_channel(&sub_session, subscriber_id, channel, upstream);
+            async move {
+                if tokio::time::timeout(step_timeout, open).await.is_err() {
+                    warn!(
+                        "Opening data channel '{}' for subscriber {} timed out",
+                        channel.label(),
+                        subscriber_id
+                    );
+                }
+            }
+        }))
+        .await;
+        // The subscriber opens the channel once it is connected, well after
+        // this handler is set.
+        if let Some(clock) = config.contest_clock.filter(|_| accepts_data_channels) {
+            sub_session
+                .pc
+                .on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+                    if channel.label() == contest_clock::CLOCK_CHANNEL_LABEL {
+                        contest_clock::serve(channel, clock_tracks.clone(), clock);
+                    }
+                    Box::pin(async {})
+                }));
+        }
+        let data_channels = sub_session.data_channel_labels();
 
+        unfinished.done();
         self.subscribers.insert(req.subscriber_id, sub_session);
         self.update_metrics("subscribers", 1);
 
-        Ok(SubscriberResponse { answer })
+        Ok(SubscriberResponse {
+            answer,
+            data_channels,
+        })
     }
 
+    #[instrument(skip(self))]
     async fn remove_subscriber(&self, subscriber_id: &str) -> Result<()> {
         if let Some((_, session)) = self.subscribers.remove(subscriber_id) {
             info!("Removing subscriber: {}", subscriber_id);
-
-            if let Some(pub_session) = self.publishers.get(&session.publisher_id) {
-                for (original_track_id, local_track_id) in &session.track_mapping {
+            self.relayed.remove(subscriber_id);
+
+            let pub_session = self
+                .publishers
+                .get(&session.publisher_id)
+                .map(|entry| Arc::clone(entry.value()));
+            if let Some(pub_session) = pub_session {
+                for (original_track_id, local_track_id) in &session.track_mapping() {
                     if let Some(broadcaster) = pub_session.get_broadcaster(original_track_id) {
                         broadcaster.remove_subscriber(local_track_id).await;
                     }
@@ -465,6 +910,126 @@ impl Sfu for LocalSfu {
         Ok(())
     }
 
+    #[instrument(skip(self, tracks))]
+    async fn set_publisher_track_metadata(
+        &self,
+        publisher_id: &str,
+        tracks: Vec<TrackMetadata>,
+    ) -> Result<()> {
+        let session = self
+            .publishers
+            .get(publisher_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::PublisherNotFound(publisher_id.to_string()))?;
+
+        info!(
+            "Publisher {} labelled {} tracks",
+            publisher_id,
+            tracks.len()
+        );
+        session.set_track_metadata(tracks);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn set_publisher_audio_muted(&self, publisher_id: &str, muted: bool) -> Result<()> {
+        let session = self
+            .publishers
+            .get(publisher_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::PublisherNotFound(publisher_id.to_string()))?;
+
+        info!(
+            "Publisher {} audio {}",
+            publisher_id,
+            if muted { "muted" } else { "unmuted" }
+        );
+        session.set_audio_muted(muted);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn set_subscriber_track_paused(
+        &self,
+        subscriber_id: &str,
+        track_id: &str,
+        paused: bool,
+    ) -> Result<()> {
+        let session = self
+            .subscribers
+            .get(subscriber_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::SubscriberNotFound(subscriber_id.to_string()))?;
+
+        let (original_track_id, local_track_id) = session
+            .track_mapping()
+            .into_iter()
+            .find(|(original, local)| original == track_id || local == track_id)
+            .ok_or_else(|| SfuError::TrackNotFound(track_id.to_string()))?;
+
+        let broadcaster = self
+            .publishers
+            .get(&session.publisher_id)
+            .and_then(|publisher| publisher.get_broadcaster(&original_track_id))
+            .ok_or_else(|| SfuError::TrackNotFound(original_track_id.clone()))?;
+
+        let found = if paused {
+            broadcaster.pause_subscriber(&local_track_id)
+        } else {
+            broadcaster.resume_subscriber(&local_track_id)
+        };
+        if !found {
+            return Err(SfuError::TrackNotFound(local_track_id).into());
+        }
+
+        info!(
+            "Subscriber {} {} track {}",
+            subscriber_id,
+            if paused { "paused" } else { "resumed" },
+            original_track_id
+        );
+        Ok(())
+    }
+
+    #[instrument(skip(self, answer))]
+    async fn set_subscriber_answer(
+        &self,
+        subscriber_id: &str,
+        answer: RTCSessionDescription,
+    ) -> Result<()> {
+        let session = self
+            .subscribers
+            .get(subscriber_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::SubscriberNotFound(subscriber_id.to_string()))?;
+
+        let sdp = answer.sdp.clone();
+        session
+            .pc
+            .set_remote_description(answer)
+            .await
+            .map_err(|e| SfuError::SetRemoteDescription(e.to_string()))?;
+        self.record_subscriber_negotiation(subscriber_id, &session, &sdp);
+
+        info!("Subscriber {} renegotiated", subscriber_id);
+        if session.late_tracks_pending.swap(false, Ordering::Relaxed) {
+            let publisher = self
+                .publishers
+                .get(&session.publisher_id)
+                .map(|entry| Arc::clone(entry.value()));
+            if let Some(publisher) = publisher {
+                if let Err(e) = add_pending_tracks(&session, subscriber_id, &publisher).await {
+                    warn!(
+                        "Failed to add pending tracks to subscriber {}: {}",
+                        subscriber_id, e
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, candidate))]
     async fn add_publisher_ice(
         &self,
         publisher_id: &str,
@@ -486,6 +1051,7 @@ impl Sfu for LocalSfu {
         Ok(())
     }
 
+    #[instrument(skip(self, candidate))]
     async fn add_subscriber_ice(
         &self,
         subscriber_id: &str,
@@ -507,37 +1073,172 @@ impl Sfu for LocalSfu {
         Ok(())
     }
 
+    async fn get_publisher_stats(&self, publisher_id: &str) -> Result<PublisherStats> {
+        let session = self
+            .publishers
+            .get(publisher_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::PublisherNotFound(publisher_id.to_string()))?;
+
+        let subscriber_count = self
+            .subscribers
+            .iter()
+            .filter(|entry| entry.value().publisher_id == publisher_id)
+            .count();
+
+        let broadcasters = session.get_all_broadcasters();
+        let mut stats = PublisherStats {
+            publisher_id: publisher_id.to_string(),
+            track_count: broadcasters.len(),
+            subscriber_count,
+            quality_score: if broadcasters.is_empty() { 0.0 } else { 100.0 },
+            ..Default::default()
+        };
+
+        for (_, broadcaster) in broadcasters {
+            let ingest = broadcaster.ingest_stats();
+            stats.bytes_received += ingest.bytes;
+            stats.packets_received += ingest.packets;
+            stats.packets_lost += ingest.lost;
+            stats.bitrate_bps += ingest.bitrate_bps;
+            if let Some(target) = broadcaster.target_bitrate_bps() {
+                *stats.target_bitrate_bps.get_or_insert(0) += target;
+            }
+            stats.quality_score = stats.quality_score.min(ingest.quality_score());
+
+            let downstream = broadcaster.downstream();
+            let clock_rate = broadcaster.codec_capability.clock_rate.max(1) as f64;
+            stats.downstream_loss_ratio = stats.downstream_loss_ratio.max(downstream.loss_ratio);
+            stats.downstream_jitter_ms = stats
+                .downstream_jitter_ms
+                .max(downstream.jitter as f64 * 1000.0 / clock_rate);
+        }
+
+        stats.rtt_ms = connection_rtt_ms(&session.pc).await;
+
+        Ok(stats)
+    }
+
+    async fn get_subscriber_stats(&self, subscriber_id: &str) -> Result<SubscriberStats> {
+        let session = self
+            .subscribers
+            .get(subscriber_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::SubscriberNotFound(subscriber_id.to_string()))?;
+
+        Ok(SubscriberStats {
+            subscriber_id: subscriber_id.to_string(),
+            publisher_id: session.publisher_id.clone(),
+            track_count: session.track_mapping().len(),
+            bytes_sent: session.egress_stats.bytes(),
+            packets_sent: session.egress_stats.packets(),
+            packets_dropped_stale: session.egress_stats.stale_packets(),
+            rtt_ms: connection_rtt_ms(&session.pc).await,
+        })
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        let mut sessions: Vec<SessionInfo> = self
+            .publishers
+            .iter()
+            .map(|entry| self.publisher_info(entry.key(), entry.value()))
+            .collect();
+        sessions.extend(
+            self.subscribers
+                .iter()
+                .map(|entry| self.subscriber_info(entry.key(), entry.value())),
+        );
+        Ok(sessions)
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Option<SessionInfo>> {
+        Ok(self.session_info(session_id))
+    }
+
     async fn get_metrics(&self) -> Result<SfuMetrics> {
-        let total_tracks = self
+        let publishers: Vec<Arc<PublisherSession>> = self
             .publishers
             .iter()
-            .map(|entry| entry.broadcasters.len())
+            .map(|entry| Arc::clone(entry.value()))
+            .collect();
+        let subscribers: Vec<Arc<SubscriberSession>> = self
+            .subscribers
+            .iter()
+            .map(|entry| Arc::clone(entry.value()))
+            .collect();
+
+        let total_tracks = publishers
+            .iter()
+            .map(|session| session.broadcasters.len())
             .sum::<usize>() as i32;
 
+        let (mut bitrate_bps, mut bytes_received, mut packets_received, mut packets_lost) =
+            (0u64, 0u64, 0u64, 0u64);
+        for session in &publishers {
+            for (_, broadcaster) in session.get_all_broadcasters() {
+                let ingest = broadcaster.ingest_stats();
+                bitrate_bps += ingest.bitrate_bps;
+                bytes_received += ingest.bytes;
+                packets_received += ingest.packets;
+                packets_lost += ingest.lost;
+            }
+        }
+
+        let bytes_sent = subscribers.iter().map(|s| s.egress_stats.bytes()).sum();
+        let packets_sent = subscribers.iter().map(|s| s.egress_stats.packets()).sum();
+
+        let mut rtts = Vec::new();
+        for pc in publishers
+            .iter()
+            .map(|s| &s.pc)
+            .chain(subscribers.iter().map(|s| &s.pc))
+        {
+            if let Some(rtt) = connection_rtt_ms(pc).await {
+                rtts.push(rtt);
+            }
+        }
+        let rtt_ms = if rtts.is_empty() {
+            0
+        } else {
+            rtts.iter().sum::<i64>() / rtts.len() as i64
+        };
+
+        let relayed_session_count = self.relayed.iter().filter(|entry| *entry.value()).count();
+        let relay_ratio = if self.relayed.is_empty() {
+            0.0
+        } else {
+            relayed_session_count as f64 / self.relayed.len() as f64
+        };
+
+        let (cpu_usage, memory_usage, memory_total) = self.sample_system();
+
         let metrics = SfuMetrics {
             instance_id: self.id.clone(),
             timestamp_ms: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap_or_default()
                 .as_millis() as i64,
-            cpu_usage: 0.0, // TODO: Implement actual CPU monitoring
-            memory_usage: 0,
-            memory_total: 0,
-            go_routines: 0,    // N/A for Rust
-            uptime_seconds: 0, // TODO: Track startup time
-            publisher_count: self.publishers.len() as i32,
-            subscriber_count: self.subscribers.len() as i32,
+            cpu_usage,
+            memory_usage,
+            memory_total,
+            go_routines: 0, // N/A for Rust
+            uptime_seconds: self.started_at.elapsed().as_secs(),
+            publisher_count: publishers.len() as i32,
+            subscriber_count: subscribers.len() as i32,
             track_count: total_tracks,
-            total_bitrate_bps: 0, // TODO: Track actual bitrate
-            bytes_received: 0,
-            bytes_sent: 0,
-            packets_received: 0,
-            packets_sent: 0,
-            packets_lost: 0,
-            rtt_ms: 0,
+            total_bitrate_bps: bitrate_bps,
+            bytes_received,
+            bytes_sent,
+            packets_received,
+            packets_sent,
+            packets_lost,
+            rtt_ms,
             nack_count: 0,
             pli_count: 0,
             fir_count: 0,
+            relay_ratio,
+            relayed_session_count: relayed_session_count as i32,
+            task_panics: self.tasks.panics(),
         };
         Ok(metrics)
     }
@@ -546,11 +1247,47 @@ impl Sfu for LocalSfu {
         Ok(())
     }
 
+    fn events(&self) -> broadcast::Receiver<SfuEvent> {
+        self.tasks.subscribe()
+    }
+
+    #[instrument(skip_all, fields(subscriber_id = %req.subscriber_id))]
     async fn update_subscriber(
         &self,
-        _req: SubscriberUpdateRequest,
+        req: SubscriberUpdateRequest,
     ) -> Result<SubscriberUpdateResponse> {
-        Ok(SubscriberUpdateResponse { success: true })
+        let session = self
+            .subscribers
+            .get(&req.subscriber_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::SubscriberNotFound(req.subscriber_id.clone()))?;
+        let publisher = self
+            .publishers
+            .get(&session.publisher_id)
+            .map(|entry| Arc::clone(entry.value()))
+            .ok_or_else(|| SfuError::PublisherNotFound(session.publisher_id.clone()))?;
+
+        // Tracks queued behind an earlier offer are attached here as well.
+        session.late_tracks_pending.store(false, Ordering::Relaxed);
+        sync_subscriber_tracks(&session, &publisher).await?;
+
+        let description = match req.offer {
+            Some(offer) => {
+                let step_timeout = self.config.current().server.negotiation.step_timeout();
+                let answer = answer_offer(&session.pc, offer, step_timeout).await?;
+                self.record_subscriber_negotiation(&req.subscriber_id, &session, &answer.sdp);
+                answer
+            }
+            None => create_local_offer(&session.pc).await?,
+        };
+
+        info!(
+            "Subscriber {} updated with {} tracks",
+            req.subscriber_id,
+            session.track_mapping().len()
+        );
+
+        Ok(SubscriberUpdateResponse { description })
     }
 }
 
@@ -559,3 +1296,495 @@ impl Drop for LocalSfu {
         info!("LocalSfu {} shutting down", self.id);
     }
 }
+
+/// Opens a channel on the subscriber mirroring the publisher's `source`; it
+/// becomes usable once the subscriber's SCTP association is up. With
+/// `upstream`, messages from a subscriber allowed to write back are passed
+/// back to the publisher.
+async fn open_relay_channel(
+    session: &SubscriberSession,
+    subscriber_id: &str,
+    source: &Arc<RTCDataChannel>,
+    upstream: bool,
+) {
+    let label = source.label();
+    if !session.accepts_data_channels || session.data_channel(label).is_some() {
+        return;
+    }
+
+    let init = RTCDataChannelInit {
+        ordered: Some(true),
+        ..Default::default()
+    };
+
+    match session.pc.create_data_channel(label, Some(init)).await {
+        Ok(channel) => {
+            if upstream && session.upstream {
+                let source = Arc::clone(source);
+                channel.on_message(Box::new(move |msg: DataChannelMessage| {
+                    let source = Arc::clone(&source);
+                    Box::pin(async move {
+                        if let Err(e) = send_data(&source, &msg).await {
+                            warn!(
+                                "Failed to relay data upstream on '{}': {}",
+                                source.label(),
+                                e
+                            );
+                        }
+                    })
+                }));
+            }
+            session.add_data_channel(channel);
+        }
+        Err(e) => warn!(
+            "Failed to open data channel '{}' for subscriber {}: {}",
+            label, subscriber_id, e
+        ),
+    }
+}
+
+async fn relay_data(
+    subscribers: &DashMap<String, Arc<SubscriberSession>>,
+    publisher_id: &str,
+    label: &str,
+    msg: &DataChannelMessage,
+) {
+    let channels: Vec<_> = subscribers
+        .iter()
+        .filter(|entry| entry.publisher_id == publisher_id)
+        .filter_map(|entry| entry.data_channel(label))
+        .collect();
+
+    for channel in channels {
+        if channel.ready_state() != RTCDataChannelState::Open {
+            continue;
+        }
+
+        if let Err(e) = send_data(&channel, msg).await {
+            warn!("Failed to relay data on channel '{}': {}", label, e);
+        }
+    }
+}
+
+async fn send_data(
+    channel: &RTCDataChannel,
+    msg: &DataChannelMessage,
+) -> std::result::Result<usize, webrtc::Error> {
+    if msg.is_string {
+        channel
+            .send_text(String::from_utf8_lossy(&msg.data).into_owned())
+            .await
+    } else {
+        channel.send(&msg.data).await
+    }
+}
+
+/// Picks the screen or camera policy from the grabber's stream id.
+fn content_profile(
+    profiles: &ContentProfilesConfig,
+    track: &TrackRemote,
+) -> Option<ContentProfile> {
+    (track.kind() == RTPCodecType::Video)
+        .then(|| profiles.profile(ContentKind::from_stream_id(&track.stream_id())))
+}
+
+fn track_info(broadcaster: &TrackBroadcaster, publisher: &PublisherSession) -> TrackInfo {
+    TrackInfo {
+        id: broadcaster.id.clone(),
+        kind: broadcaster.kind.clone(),
+        mime_type: broadcaster.mime_type.clone(),
+        ssrc: broadcaster.ssrc(),
+        metadata: publisher.track_metadata(&broadcaster.id),
+    }
+}
+
+/// Adds a forwarding track for `broadcaster` to a subscriber's connection and
+/// relays the subscriber's keyframe requests and receiver feedback. Returns
+/// the local track id.
+async fn attach_track(
+    pc: &Arc<RTCPeerConnection>,
+    broadcaster: &Arc<TrackBroadcaster>,
+    original_track_id: &str,
+    tasks: &SessionTasks,
+    publisher_id: &str,
+    egress_stats: &Arc<EgressStats>,
+    sync_group: Option<&Arc<SyncGroup>>,
+) -> SfuResult<String> {
+    let local_track_id = subscriber_track_id(original_track_id, tasks.session_id());
+
+    let local_track = Arc::new(TrackLocalStaticRTP::new(
+        broadcaster.codec_capability.clone(),
+        local_track_id.clone(),
+        format!("stream-{}", publisher_id),
+    ));
+
+    let rtp_sender = pc
+        .add_track(Arc::clone(&local_track) as Arc<dyn TrackLocal + Send + Sync>)
+        .await
+        .map_err(|e| SfuError::AddTrack(e.to_string()))?;
+
+    let broadcaster_for_rtcp = Arc::clone(broadcaster);
+    let track_kind = broadcaster.kind.clone();
+    let feedback_track_id = local_track_id.clone();
+    tasks.spawn("receiver feedback", async move {
+        use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+        use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+        use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+        use webrtc::rtcp::receiver_report::ReceiverReport;
+
+        let ssrc = rtp_sender
+            .get_parameters()
+            .await
+            .encodings
+            .first()
+            .map(|encoding| encoding.ssrc);
+        let mut rtcp_buf = vec![0u8; 1500];
+        while let Ok((packets, _)) = rtp_sender.read(&mut rtcp_buf).await {
+            if track_kind != "video" {
+                continue;
+            }
+
+            let mut keyframe_requested = false;
+            for packet in packets {
+                let packet = packet.as_any();
+                if packet.is::<PictureLossIndication>() || packet.is::<FullIntraRequest>() {
+                    keyframe_requested = true;
+                } else if let Some(receiver_report) = packet.downcast_ref::<ReceiverReport>() {
+                    for report in receiver_report
+                        .reports
+                        .iter()
+                        .filter(|report| Some(report.ssrc) == ssrc)
+                    {
+                        broadcaster_for_rtcp.record_receiver_report(
+                            &feedback_track_id,
+                            report.fraction_lost,
+                            report.jitter,
+                        );
+                    }
+                } else if let Some(remb) = packet.downcast_ref::<ReceiverEstimatedMaximumBitrate>()
+                {
+                    broadcaster_for_rtcp
+                        .record_receiver_estimate(&feedback_track_id, remb.bitrate as u64);
+                }
+            }
+            if keyframe_requested {
+                broadcaster_for_rtcp.request_keyframe();
+            }
+        }
+    });
+
+    broadcaster
+        .add_subscriber(
+            local_track,
+            Arc::clone(egress_stats),
+            sync_group,
+            tasks.clone(),
+        )
+        .await;
+
+    Ok(local_track_id)
+}
+
+/// Undoes whatever an [`attach_track`] cut short by its timeout got done,
+/// so no sender or forwarder is left behind for a track the subscriber
+/// won't be told about.
+async fn detach_track(
+    pc: &RTCPeerConnection,
+    broadcaster: &TrackBroadcaster,
+    local_track_id: &str,
+) {
+    broadcaster.remove_subscriber(local_track_id).await;
+    for sender in pc.get_senders().await {
+        let attached = sender
+            .track()
+            .await
+            .is_some_and(|track| track.id() == local_track_id);
+        if attached {
+            if let Err(e) = pc.remove_track(&sender).await {
+                warn!("Failed to remove timed out track {}: {}", local_track_id, e);
+            }
+        }
+    }
+}
+
+/// A peer connection whose negotiation hasn't finished. Unless marked done,
+/// dropping it detaches the subscriber tracks attached so far and closes
+/// the connection: a failed negotiation returns early, and one that runs
+/// out of time is dropped by the signalling server mid-way.
+struct Unfinished {
+    pc: Option<Arc<RTCPeerConnection>>,
+    /// Broadcasters and the subscriber track ids they may forward to.
+    attached: Vec<(Arc<TrackBroadcaster>, String)>,
+}
+
+impl Unfinished {
+    fn new(pc: Arc<RTCPeerConnection>) -> Self {
+        Self {
+            pc: Some(pc),
+            attached: Vec::new(),
+        }
+    }
+
+    fn attaching(&mut self, broadcaster: Arc<TrackBroadcaster>, local_track_id: String) {
+        self.attached.push((broadcaster, local_track_id));
+    }
+
+    /// The session now owns the connection.
+    fn done(mut self) {
+        self.pc = None;
+    }
+}
+
+impl Drop for Unfinished {
+    fn drop(&mut self) {
+        let Some(pc) = self.pc.take() else {
+            return;
+        };
+        let attached = std::mem::take(&mut self.attached);
+        tokio::spawn(async move {
+            // Nothing forwards to a subscriber that never got its answer.
+            for (broadcaster, local_track_id) in attached {
+                broadcaster.remove_subscriber(&local_track_id).await;
+            }
+            let _ = pc.close().await;
+        });
+    }
+}
+
+fn subscriber_track_id(original_track_id: &str, subscriber_id: &str) -> String {
+    format!("{}-{}", original_track_id, subscriber_id)
+}
+
+/// Applies `offer` to `pc` and returns the answer set as its local
+/// description. Each step is bounded by `step_timeout`, so a stalled WebRTC
+/// stack fails the request instead of hanging it.
+async fn answer_offer(
+    pc: &RTCPeerConnection,
+    offer: RTCSessionDescription,
+    step_timeout: Duration,
+) -> SfuResult<RTCSessionDescription> {
+    within(
+        step_timeout,
+        "applying the offer",
+        pc.set_remote_description(offer),
+    )
+    .await?
+    .map_err(|e| SfuError::SetRemoteDescription(e.to_string()))?;
+    let answer = within(step_timeout, "creating the answer", pc.create_answer(None))
+        .await?
+        .map_err(|e| SfuError::CreateAnswer(e.to_string()))?;
+    within(
+        step_timeout,
+        "applying the answer",
+        pc.set_local_description(answer.clone()),
+    )
+    .await?
+    .map_err(|e| SfuError::SetLocalDescription(e.to_string()))?;
+    Ok(answer)
+}
+
+async fn within<T>(
+    limit: Duration,
+    step: &str,
+    future: impl std::future::Future<Output = T>,
+) -> SfuResult<T> {
+    tokio::time::timeout(limit, future)
+        .await
+        .map_err(|_| SfuError::NegotiationTimeout(format!("{} took over {:?}", step, limit)))
+}
+
+/// Forwards a track that a publisher added after subscribers attached, and
+/// sends each of those subscribers a fresh offer.
+async fn renegotiate_subscribers(
+    subscribers: &DashMap<String, Arc<SubscriberSession>>,
+    publisher_id: &str,
+    track_id: &str,
+    broadcaster: &Arc<TrackBroadcaster>,
+) {
+    let affected: Vec<(String, Arc<SubscriberSession>)> = subscribers
+        .iter()
+        .filter(|entry| {
+            entry.value().publisher_id == publisher_id
+                && entry.value().wants(broadcaster)
+                && !entry.value().has_track(track_id)
+        })
+        .map(|entry| (entry.key().clone(), Arc::clone(entry.value())))
+        .collect();
+
+    for (subscriber_id, session) in affected {
+        if let Err(e) = add_late_track(
+            &session,
+            &subscriber_id,
+            publisher_id,
+            track_id,
+            broadcaster,
+        )
+        .await
+        {
+            warn!(
+                "Failed to add track {} to subscriber {}: {}",
+                track_id, subscriber_id, e
+            );
+        }
+    }
+}
+
+async fn add_late_track(
+    session: &SubscriberSession,
+    subscriber_id: &str,
+    publisher_id: &str,
+    track_id: &str,
+    broadcaster: &Arc<TrackBroadcaster>,
+) -> SfuResult<()> {
+    let Some(renegotiation_tx) = &session.renegotiation_tx else {
+        return Err(SfuError::Internal(
+            "subscriber does not support renegotiation".to_string(),
+        ));
+    };
+
+    // The subscriber has yet to answer an earlier offer; the track is
+    // attached once it does. The state is checked again after queueing in
+    // case the answer arrived in between.
+    if session.pc.signaling_state() != RTCSignalingState::Stable {
+        session.late_tracks_pending.store(true, Ordering::Relaxed);
+        if session.pc.signaling_state() != RTCSignalingState::Stable {
+            info!(
+                "Queued late track {} for subscriber {} until it answers",
+                track_id, subscriber_id
+            );
+            return Ok(());
+        }
+        session.late_tracks_pending.store(false, Ordering::Relaxed);
+    }
+
+    let local_track_id = attach_track(
+        &session.pc,
+        broadcaster,
+        track_id,
+        &session.tasks,
+        publisher_id,
+        &session.egress_stats,
+        session.sync_group.as_ref(),
+    )
+    .await?;
+    session
+        .track_mapping
+        .lock()
+        .unwrap()
+        .push((track_id.to_string(), local_track_id));
+
+    let offer = create_local_offer(&session.pc).await?;
+
+    info!(
+        "Renegotiating subscriber {} for late track {}",
+        subscriber_id, track_id
+    );
+    renegotiation_tx
+        .send(offer)
+        .map_err(|_| SfuError::Internal("subscriber signalling closed".to_string()))
+}
+
+pub(crate) fn rtc_config(ice_servers: &[String]) -> RTCConfiguration {
+    RTCConfiguration {
+        ice_servers: ice_servers
+            .iter()
+            .map(|url| RTCIceServer {
+                urls: vec![url.clone()],
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn ice_ufrag(sdp: &str) -> Option<&str> {
+    sdp.lines()
+        .find_map(|line| line.trim_end().strip_prefix("a=ice-ufrag:"))
+}
+
+async fn create_local_offer(pc: &RTCPeerConnection) -> SfuResult<RTCSessionDescription> {
+    let offer = pc
+        .create_offer(None)
+        .await
+        .map_err(|e| SfuError::CreateOffer(e.to_string()))?;
+    pc.set_local_description(offer.clone())
+        .await
+        .map_err(|e| SfuError::SetLocalDescription(e.to_string()))?;
+    Ok(offer)
+}
+
+/// Attaches the tracks queued while the subscriber was answering an earlier
+/// offer, and sends it a fresh offer for them.
+async fn add_pending_tracks(
+    session: &SubscriberSession,
+    subscriber_id: &str,
+    publisher: &PublisherSession,
+) -> SfuResult<()> {
+    let Some(renegotiation_tx) = &session.renegotiation_tx else {
+        return Ok(());
+    };
+    sync_subscriber_tracks(session, publisher).await?;
+    let offer = create_local_offer(&session.pc).await?;
+
+    info!(
+        "Renegotiating subscriber {} for queued tracks",
+        subscriber_id
+    );
+    renegotiation_tx
+        .send(offer)
+        .map_err(|_| SfuError::Internal("subscriber signalling closed".to_string()))
+}
+
+/// Brings a subscriber's forwarded tracks in line with what its publisher
+/// currently sends: new tracks are attached, vanished ones removed.
+async fn sync_subscriber_tracks(
+    session: &SubscriberSession,
+    publisher: &PublisherSession,
+) -> SfuResult<()> {
+    for (track_id, broadcaster) in publisher.get_all_broadcasters() {
+        if session.has_track(&track_id) || !session.wants(&broadcaster) {
+            continue;
+        }
+        let local_track_id = attach_track(
+            &session.pc,
+            &broadcaster,
+            &track_id,
+            &session.tasks,
+            &session.publisher_id,
+            &session.egress_stats,
+            session.sync_group.as_ref(),
+        )
+        .await?;
+        session
+            .track_mapping
+            .lock()
+            .unwrap()
+            .push((track_id, local_track_id));
+    }
+
+    let stale: Vec<String> = session
+        .track_mapping()
+        .into_iter()
+        .filter(|(track_id, _)| publisher.get_broadcaster(track_id).is_none())
+        .map(|(_, local_track_id)| local_track_id)
+        .collect();
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    for sender in session.pc.get_senders().await {
+        let Some(track) = sender.track().await else {
+            continue;
+        };
+        if stale.iter().any(|id| id == track.id()) {
+            session.pc.remove_track(&sender).await?;
+        }
+    }
+    session
+        .track_mapping
+        .lock()
+        .unwrap()
+        .retain(|(_, local_track_id)| !stale.contains(local_track_id));
+
+    Ok(())
+}
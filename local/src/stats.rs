@@ -0,0 +1,202 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::stats::StatsReportType;
+
+const WINDOW: Duration = Duration::from_secs(1);
+const STALE_AFTER_MS: u64 = 3000;
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Default)]
+pub struct IngestStats {
+    bytes: AtomicU64,
+    packets: AtomicU64,
+    lost: AtomicU64,
+    bitrate_bps: AtomicU64,
+    window_loss_permille: AtomicU64,
+    window_end_ms: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IngestSnapshot {
+    pub bytes: u64,
+    pub packets: u64,
+    pub lost: u64,
+    pub bitrate_bps: u64,
+    pub loss_ratio: f64,
+}
+
+impl IngestSnapshot {
+    pub fn quality_score(&self) -> f64 {
+        if self.bitrate_bps == 0 {
+            return 0.0;
+        }
+        (1.0 - self.loss_ratio).clamp(0.0, 1.0) * 100.0
+    }
+}
+
+impl IngestStats {
+    pub fn snapshot(&self) -> IngestSnapshot {
+        let stale =
+            now_ms().saturating_sub(self.window_end_ms.load(Ordering::Relaxed)) > STALE_AFTER_MS;
+
+        IngestSnapshot {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            packets: self.packets.load(Ordering::Relaxed),
+            lost: self.lost.load(Ordering::Relaxed),
+            bitrate_bps: if stale {
+                0
+            } else {
+                self.bitrate_bps.load(Ordering::Relaxed)
+            },
+            loss_ratio: self.window_loss_permille.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+pub struct IngestTracker {
+    stats: Arc<IngestStats>,
+    last_seq: Option<u16>,
+    window_start: Instant,
+    window_bytes: u64,
+    window_packets: u64,
+    window_lost: u64,
+}
+
+impl IngestTracker {
+    pub fn new(stats: Arc<IngestStats>) -> Self {
+        Self {
+            stats,
+            last_seq: None,
+            window_start: Instant::now(),
+            window_bytes: 0,
+            window_packets: 0,
+            window_lost: 0,
+        }
+    }
+
+    pub fn record(&mut self, sequence_number: u16, size: usize) {
+        if let Some(last) = self.last_seq {
+            let gap = sequence_number.wrapping_sub(last);
+            // Gaps in the upper half of the sequence space are reordered packets.
+            if gap < 0x8000 {
+                if gap > 1 {
+                    self.window_lost += (gap - 1) as u64;
+                }
+                self.last_seq = Some(sequence_number);
+            }
+        } else {
+            self.last_seq = Some(sequence_number);
+        }
+
+        self.window_bytes += size as u64;
+        self.window_packets += 1;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= WINDOW {
+            self.flush(elapsed);
+        }
+    }
+
+    fn flush(&mut self, elapsed: Duration) {
+        let stats = &self.stats;
+        stats.bytes.fetch_add(self.window_bytes, Ordering::Relaxed);
+        stats
+            .packets
+            .fetch_add(self.window_packets, Ordering::Relaxed);
+        stats.lost.fetch_add(self.window_lost, Ordering::Relaxed);
+        stats.bitrate_bps.store(
+            (self.window_bytes as f64 * 8.0 / elapsed.as_secs_f64()) as u64,
+            Ordering::Relaxed,
+        );
+
+        let expected = self.window_packets + self.window_lost;
+        let loss_permille = if expected > 0 {
+            self.window_lost * 1000 / expected
+        } else {
+            0
+        };
+        stats
+            .window_loss_permille
+            .store(loss_permille, Ordering::Relaxed);
+        stats.window_end_ms.store(now_ms(), Ordering::Relaxed);
+
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+        self.window_packets = 0;
+        self.window_lost = 0;
+    }
+}
+
+#[derive(Default)]
+pub struct EgressStats {
+    bytes: AtomicU64,
+    packets: AtomicU64,
+    stale_packets: AtomicU64,
+}
+
+impl EgressStats {
+    pub fn record(&self, size: usize) {
+        self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        self.packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_stale(&self, packets: u64) {
+        self.stale_packets.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    pub fn stale_packets(&self) -> u64 {
+        self.stale_packets.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether the nominated ICE candidate pair goes through a TURN relay on
+/// either end; `None` until a pair is nominated.
+pub async fn uses_relay(pc: &RTCPeerConnection) -> Option<bool> {
+    let report = pc.get_stats().await;
+    let pair = report.reports.values().find_map(|stat| match stat {
+        StatsReportType::CandidatePair(pair) if pair.nominated => Some(pair),
+        _ => None,
+    })?;
+
+    let is_relay = |id: &str| {
+        report.reports.values().any(|stat| match stat {
+            StatsReportType::LocalCandidate(candidate)
+            | StatsReportType::RemoteCandidate(candidate) => {
+                candidate.id == id && candidate.candidate_type == RTCIceCandidateType::Relay
+            }
+            _ => false,
+        })
+    };
+
+    Some(is_relay(&pair.local_candidate_id) || is_relay(&pair.remote_candidate_id))
+}
+
+/// Round-trip time of the nominated ICE candidate pair, if one has been measured.
+pub async fn connection_rtt_ms(pc: &RTCPeerConnection) -> Option<i64> {
+    let report = pc.get_stats().await;
+    report.reports.values().find_map(|stat| match stat {
+        StatsReportType::CandidatePair(pair)
+            if pair.nominated && pair.current_round_trip_time > 0.0 =>
+        {
+            Some((pair.current_round_trip_time * 1000.0) as i64)
+        }
+        _ => None,
+    })
+}
@@ -0,0 +1,289 @@
+//! Aligns the playback timing of subscriptions watched side by side, e.g. a
+//! team's webcam and screen. Each publisher's RTCP sender reports map its RTP
+//! timestamps to its capture clock; from it every subscription in a sync group
+//! measures how long its packets take from capture to the SFU, and the faster
+//! ones are held back to match the slowest. The sender reports webrtc
+//! generates towards the subscriber are based on when packets are forwarded,
+//! so once aligned they share one reference too.
+//!
+//! This needs the publishers' clocks to agree, e.g. grabbers synced over NTP:
+//! an offset between them reads as transit time and is aligned away with the
+//! rest. The measured spread is reported as the group's skew, and a track
+//! that would have to be held back longer than the SFU does is logged.
+
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// However far apart a group's streams are, none is held back longer than
+/// this.
+const MAX_SYNC_DELAY: Duration = Duration::from_secs(1);
+/// Weight of the newest packet in a subscription's smoothed transit time, so
+/// network jitter doesn't move the alignment.
+const SMOOTHING: i64 = 16;
+/// Packets this far in media time from the latest sender report are taken to
+/// come from another source, e.g. one that just replaced the track.
+const MAX_REPORT_DISTANCE_SECS: i64 = 60;
+/// Seconds from the NTP epoch (1900) to the Unix epoch.
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+const UNKNOWN: i64 = i64::MIN;
+
+/// A publisher track's RTP clock against its capture clock, from its latest
+/// sender report.
+pub struct SenderClock {
+    clock_rate: u32,
+    /// Capture time in microseconds since the Unix epoch, and the RTP
+    /// timestamp it corresponds to.
+    mapping: Mutex<Option<(i64, u32)>>,
+}
+
+impl SenderClock {
+    pub fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate: clock_rate.max(1),
+            mapping: Mutex::new(None),
+        }
+    }
+
+    pub fn record_sender_report(&self, ntp_time: u64, rtp_time: u32) {
+        let secs = (ntp_time >> 32).saturating_sub(NTP_UNIX_OFFSET_SECS);
+        let fraction = ((ntp_time & 0xffff_ffff) * 1_000_000) >> 32;
+        let micros = (secs * 1_000_000 + fraction) as i64;
+        *self.mapping.lock().unwrap() = Some((micros, rtp_time));
+    }
+
+    /// The track switched source; its timestamps start over.
+    pub fn reset(&self) {
+        *self.mapping.lock().unwrap() = None;
+    }
+
+    /// Microseconds from capturing the packet with `rtp_timestamp` to its
+    /// arrival at `arrived`, as far as the publisher's clock agrees with
+    /// ours. `None` before the first sender report.
+    fn transit_us(&self, rtp_timestamp: u32, arrived: SystemTime) -> Option<i64> {
+        let (report_us, report_rtp) = (*self.mapping.lock().unwrap())?;
+        let ticks = rtp_timestamp.wrapping_sub(report_rtp) as i32 as i64;
+        if ticks.abs() > MAX_REPORT_DISTANCE_SECS * self.clock_rate as i64 {
+            return None;
+        }
+        let captured_us = report_us + ticks * 1_000_000 / self.clock_rate as i64;
+        let arrived_us = arrived.duration_since(UNIX_EPOCH).ok()?.as_micros() as i64;
+        Some(arrived_us - captured_us)
+    }
+}
+
+/// Subscriptions whose playback is aligned, by subscriber track id.
+#[derive(Default)]
+pub struct SyncGroup {
+    /// Each member's smoothed transit time in microseconds; [`UNKNOWN`] until
+    /// its publisher sent a sender report.
+    transits: DashMap<String, Arc<AtomicI64>>,
+}
+
+impl SyncGroup {
+    pub fn join(self: &Arc<Self>, track_id: String, clock: Arc<SenderClock>) -> SyncMember {
+        let transit = Arc::new(AtomicI64::new(UNKNOWN));
+        self.transits.insert(track_id.clone(), Arc::clone(&transit));
+        SyncMember {
+            group: Arc::clone(self),
+            track_id,
+            clock,
+            transit,
+            clamped: AtomicBool::new(false),
+        }
+    }
+
+    /// How far apart the members' transit times are: the delay between the
+    /// slowest and the fastest, plus any offset between their publishers'
+    /// clocks. `None` until two members have one.
+    pub fn skew(&self) -> Option<Duration> {
+        let known: Vec<i64> = self.known_transits().collect();
+        if known.len() < 2 {
+            return None;
+        }
+        let spread = known.iter().max()? - known.iter().min()?;
+        Some(Duration::from_micros(spread as u64))
+    }
+
+    fn slowest_us(&self) -> Option<i64> {
+        self.known_transits().max()
+    }
+
+    fn known_transits(&self) -> impl Iterator<Item = i64> + '_ {
+        self.transits
+            .iter()
+            .map(|entry| entry.value().load(Ordering::Relaxed))
+            .filter(|transit| *transit != UNKNOWN)
+    }
+}
+
+/// One subscriber track in a [`SyncGroup`]; leaves it when dropped.
+pub struct SyncMember {
+    group: Arc<SyncGroup>,
+    track_id: String,
+    clock: Arc<SenderClock>,
+    transit: Arc<AtomicI64>,
+    /// Whether the delay is at [`MAX_SYNC_DELAY`], so it was logged once.
+    clamped: AtomicBool,
+}
+
+impl SyncMember {
+    /// Feeds the arrival of the packet with `rtp_timestamp`.
+    pub fn observe(&self, rtp_timestamp: u32, arrived: SystemTime) {
+        let Some(sample) = self.clock.transit_us(rtp_timestamp, arrived) else {
+            return;
+        };
+        let current = self.transit.load(Ordering::Relaxed);
+        let smoothed = if current == UNKNOWN {
+            sample
+        } else {
+            current + (sample - current) / SMOOTHING
+        };
+        self.transit.store(smoothed, Ordering::Relaxed);
+    }
+
+    /// How long to hold this track's packets back to match the slowest in
+    /// the group.
+    pub fn delay(&self) -> Duration {
+        let own = self.transit.load(Ordering::Relaxed);
+        let Some(slowest) = self.group.slowest_us().filter(|_| own != UNKNOWN) else {
+            return Duration::ZERO;
+        };
+        let delay = Duration::from_micros((slowest - own).max(0) as u64);
+        let clamped = delay > MAX_SYNC_DELAY;
+        let was_clamped = self.clamped.swap(clamped, Ordering::Relaxed);
+        if clamped && !was_clamped {
+            warn!(
+                "Sync group track {} is {:?} ahead, more than it is held back ({:?}); \
+                 check that its publishers' clocks agree",
+                self.track_id, delay, MAX_SYNC_DELAY
+            );
+        }
+        delay.min(MAX_SYNC_DELAY)
+    }
+}
+
+impl Drop for SyncMember {
+    fn drop(&mut self) {
+        self.group.transits.remove(&self.track_id);
+    }
+}
+
+/// Sync groups by the name subscribers join them under. A group lives as
+/// long as its members do.
+#[derive(Default)]
+pub struct SyncGroups(DashMap<String, Weak<SyncGroup>>);
+
+impl SyncGroups {
+    pub fn get_or_create(&self, name: &str) -> Arc<SyncGroup> {
+        self.0.retain(|_, group| group.strong_count() > 0);
+        let mut entry = self.0.entry(name.to_string()).or_default();
+        if let Some(group) = entry.upgrade() {
+            return group;
+        }
+        let group = Arc::new(SyncGroup::default());
+        *entry = Arc::downgrade(&group);
+        group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2023-11-14 22:13:20.5 UTC.
+    const REPORT_UNIX_US: u64 = 1_700_000_000_500_000;
+
+    /// An NTP timestamp for `REPORT_UNIX_US`: seconds since 1900 and half a
+    /// second as a 32-bit fraction.
+    fn report_ntp() -> u64 {
+        ((REPORT_UNIX_US / 1_000_000 + NTP_UNIX_OFFSET_SECS) << 32) | 0x8000_0000
+    }
+
+    fn unix(micros: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(micros)
+    }
+
+    #[test]
+    fn no_transit_before_a_sender_report() {
+        let clock = SenderClock::new(90000);
+        assert_eq!(clock.transit_us(0, SystemTime::now()), None);
+    }
+
+    #[test]
+    fn transit_from_the_sender_report_clock() {
+        let clock = SenderClock::new(90000);
+        clock.record_sender_report(report_ntp(), 90000);
+
+        // Captured 100 ms after the report, arrived 40 ms after that.
+        let arrived = unix(REPORT_UNIX_US + 140_000);
+        assert_eq!(clock.transit_us(90000 + 9000, arrived), Some(40_000));
+        // A packet from before the report.
+        let arrived = unix(REPORT_UNIX_US + 10_000);
+        assert_eq!(clock.transit_us(90000 - 900, arrived), Some(20_000));
+    }
+
+    #[test]
+    fn transit_across_rtp_timestamp_wraparound() {
+        let clock = SenderClock::new(90000);
+        clock.record_sender_report(report_ntp(), u32::MAX - 899);
+
+        let arrived = unix(REPORT_UNIX_US + 50_000);
+        assert_eq!(clock.transit_us(900, arrived), Some(30_000));
+    }
+
+    #[test]
+    fn packets_far_from_the_report_are_ignored() {
+        let clock = SenderClock::new(90000);
+        clock.record_sender_report(report_ntp(), 0);
+        let far = 90000 * (MAX_REPORT_DISTANCE_SECS as u32 + 1);
+        assert_eq!(clock.transit_us(far, unix(REPORT_UNIX_US)), None);
+
+        clock.reset();
+        assert_eq!(clock.transit_us(0, unix(REPORT_UNIX_US)), None);
+    }
+
+    #[test]
+    fn faster_members_are_held_back_by_the_skew() {
+        let group = Arc::new(SyncGroup::default());
+        let webcam_clock = Arc::new(SenderClock::new(90000));
+        let screen_clock = Arc::new(SenderClock::new(90000));
+        let webcam = group.join("webcam".to_string(), Arc::clone(&webcam_clock));
+        let screen = group.join("screen".to_string(), Arc::clone(&screen_clock));
+
+        webcam_clock.record_sender_report(report_ntp(), 0);
+        webcam.observe(0, unix(REPORT_UNIX_US + 30_000));
+        assert_eq!(group.skew(), None);
+        assert_eq!(webcam.delay(), Duration::ZERO);
+
+        screen_clock.record_sender_report(report_ntp(), 0);
+        screen.observe(0, unix(REPORT_UNIX_US + 130_000));
+        assert_eq!(group.skew(), Some(Duration::from_millis(100)));
+        assert_eq!(webcam.delay(), Duration::from_millis(100));
+        assert_eq!(screen.delay(), Duration::ZERO);
+
+        drop(screen);
+        assert_eq!(group.skew(), None);
+        assert_eq!(webcam.delay(), Duration::ZERO);
+    }
+
+    #[test]
+    fn delay_is_clamped_when_clocks_disagree() {
+        let group = Arc::new(SyncGroup::default());
+        let clock = Arc::new(SenderClock::new(90000));
+        let skewed_clock = Arc::new(SenderClock::new(90000));
+        let member = group.join("webcam".to_string(), Arc::clone(&clock));
+        let skewed = group.join("screen".to_string(), Arc::clone(&skewed_clock));
+
+        clock.record_sender_report(report_ntp(), 0);
+        member.observe(0, unix(REPORT_UNIX_US + 30_000));
+        // The screen's publisher clock is 5 s behind.
+        skewed_clock.record_sender_report(report_ntp(), 0);
+        skewed.observe(0, unix(REPORT_UNIX_US + 5_030_000));
+
+        assert_eq!(group.skew(), Some(Duration::from_secs(5)));
+        assert_eq!(member.delay(), MAX_SYNC_DELAY);
+    }
+}
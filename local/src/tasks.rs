@@ -0,0 +1,164 @@
+//! Catches panics in a session's media tasks. A panicking task would
+//! otherwise just end, leaving its stream half working with nothing in the
+//! logs; instead it is reported as an [`SfuEvent`] so the caller can close
+//! the session. The tasks report their other events the same way. This
+//! relies on panics unwinding, so no profile may set `panic = "abort"`.
+
+use futures::FutureExt;
+use sfu_core::{SessionKind, SfuEvent};
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+const EVENT_CAPACITY: usize = 64;
+
+#[derive(Clone)]
+pub(crate) struct TaskMonitor {
+    events: broadcast::Sender<SfuEvent>,
+    panics: Arc<AtomicU64>,
+}
+
+impl TaskMonitor {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            events,
+            panics: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SfuEvent> {
+        self.events.subscribe()
+    }
+
+    /// Tasks that have panicked since start.
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Scopes the monitor to one session, whose tasks are spawned through the
+    /// result.
+    pub fn session(&self, session_id: &str, kind: SessionKind) -> SessionTasks {
+        SessionTasks {
+            monitor: self.clone(),
+            session_id: Arc::from(session_id),
+            kind,
+        }
+    }
+}
+
+/// Spawns and runs a session's tasks, reporting any that panic against it.
+#[derive(Clone)]
+pub(crate) struct SessionTasks {
+    monitor: TaskMonitor,
+    session_id: Arc<str>,
+    kind: SessionKind,
+}
+
+impl SessionTasks {
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn emit(&self, event: SfuEvent) {
+        let _ = self.monitor.events.send(event);
+    }
+
+    pub fn spawn<F>(&self, task: &'static str, future: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        tokio::spawn(self.clone().run(task, future))
+    }
+
+    /// Runs `future` to completion, catching a panic in it. For handlers that
+    /// webrtc awaits itself.
+    pub async fn run<F>(self, task: &'static str, future: F)
+    where
+        F: Future<Output = ()>,
+    {
+        let Err(panic) = AssertUnwindSafe(future).catch_unwind().await else {
+            return;
+        };
+        let message = panic_message(panic.as_ref());
+        let kind = match self.kind {
+            SessionKind::Publisher => "Publisher",
+            SessionKind::Subscriber => "Subscriber",
+        };
+        error!(
+            "{} {}: {} task panicked: {}",
+            kind, self.session_id, task, message
+        );
+        self.monitor.panics.fetch_add(1, Ordering::Relaxed);
+        let _ = self.monitor.events.send(SfuEvent::TaskPanicked {
+            session_id: self.session_id.to_string(),
+            kind: self.kind,
+            task: task.to_string(),
+            message,
+        });
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn a_panicking_task_is_reported_and_other_sessions_keep_running() {
+        let monitor = TaskMonitor::new();
+        let mut events = monitor.subscribe();
+        let broken = monitor.session("broken", SessionKind::Subscriber);
+        let healthy = monitor.session("healthy", SessionKind::Publisher);
+
+        let (go_tx, go_rx) = oneshot::channel::<()>();
+        let (done_tx, done_rx) = oneshot::channel();
+        let running = healthy.spawn("rtcp reader", async move {
+            let _ = go_rx.await;
+            let _ = done_tx.send(());
+        });
+
+        broken
+            .spawn("forwarder", async {
+                panic!("bad packet");
+            })
+            .await
+            .expect("the panic is caught inside the task");
+
+        match events.recv().await.unwrap() {
+            SfuEvent::TaskPanicked {
+                session_id,
+                kind,
+                task,
+                message,
+            } => {
+                assert_eq!(session_id, "broken");
+                assert_eq!(kind, SessionKind::Subscriber);
+                assert_eq!(task, "forwarder");
+                assert_eq!(message, "bad packet");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert_eq!(monitor.panics(), 1);
+
+        go_tx.send(()).unwrap();
+        done_rx.await.unwrap();
+        running.await.unwrap();
+        assert_eq!(monitor.panics(), 1);
+    }
+}
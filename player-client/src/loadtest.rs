@@ -0,0 +1,269 @@
+//! `loadtest`: many headless subscribers to one grabber at once, to check the
+//! server holds up at the subscriber counts its `performance` config is
+//! sized for.
+
+use anyhow::{bail, Result};
+use grabber_protocol_client::SubscriberClient;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{info, warn};
+use webrtc::track::track_remote::TrackRemote;
+
+use crate::receiver;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+/// Lag is measured against the fastest packet of the last window, so clock
+/// drift between the publisher's timestamps and ours doesn't add up.
+const LAG_WINDOW: Duration = Duration::from_secs(5);
+
+pub struct LoadTestConfig {
+    pub url: String,
+    pub credential: String,
+    pub peer: String,
+    pub stream_type: Option<String>,
+    pub subscribers: usize,
+    /// Between starting one subscriber and the next.
+    pub ramp: Duration,
+    /// How long every subscriber stays connected once all have started.
+    pub duration: Duration,
+}
+
+/// What one subscriber has received so far, across its tracks.
+#[derive(Default)]
+struct SubscriberStats {
+    receiving: AtomicBool,
+    packets: AtomicU64,
+    /// Missing sequence numbers.
+    lost: AtomicU64,
+    /// Most a packet arrived behind the media timeline, in microseconds.
+    max_lag_us: AtomicU64,
+}
+
+/// Subscribes `config.subscribers` times, holds them for `config.duration`
+/// and logs a summary. Fails if any subscriber did.
+pub async fn run(config: LoadTestConfig) -> Result<()> {
+    let config = Arc::new(config);
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let stats: Vec<Arc<SubscriberStats>> = (0..config.subscribers)
+        .map(|_| Arc::new(SubscriberStats::default()))
+        .collect();
+
+    info!(
+        "Starting {} subscribers to '{}', one every {:?}",
+        config.subscribers, config.peer, config.ramp
+    );
+    let mut tasks = Vec::with_capacity(config.subscribers);
+    let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+    progress.tick().await;
+    for subscriber_stats in &stats {
+        tasks.push(tokio::spawn(subscriber(
+            Arc::clone(&config),
+            Arc::clone(subscriber_stats),
+            stop_rx.clone(),
+        )));
+        // Logging progress mustn't cut the wait for the next subscriber short.
+        let ramp = tokio::time::sleep(config.ramp);
+        tokio::pin!(ramp);
+        loop {
+            tokio::select! {
+                _ = &mut ramp => break,
+                _ = progress.tick() => log_progress(&stats),
+            }
+        }
+    }
+
+    info!("All subscribers started, holding for {:?}", config.duration);
+    let deadline = tokio::time::sleep(config.duration);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = progress.tick() => log_progress(&stats),
+            _ = tokio::signal::ctrl_c() => {
+                info!("Interrupted, stopping subscribers");
+                break;
+            }
+        }
+    }
+    let _ = stop_tx.send(true);
+
+    let mut answer_latencies = Vec::new();
+    let mut failures = 0;
+    for (index, task) in tasks.into_iter().enumerate() {
+        match task.await? {
+            Ok(latency) => answer_latencies.push(latency),
+            Err(e) => {
+                warn!("Subscriber {} failed: {:#}", index, e);
+                failures += 1;
+            }
+        }
+    }
+
+    summarize(&stats, &mut answer_latencies);
+    if failures > 0 {
+        bail!("{} of {} subscribers failed", failures, config.subscribers);
+    }
+    Ok(())
+}
+
+/// Subscribes once and receives until told to stop; returns how long the
+/// server took to answer the offer.
+async fn subscriber(
+    config: Arc<LoadTestConfig>,
+    stats: Arc<SubscriberStats>,
+    mut stop: watch::Receiver<bool>,
+) -> Result<Duration> {
+    let mut client = SubscriberClient::connect(&config.url, &config.credential).await?;
+    let video = config.stream_type.as_deref() != Some("audio");
+    let pc = receiver::new_receiver(&client, video).await?;
+
+    let track_stats = Arc::clone(&stats);
+    pc.on_track(Box::new(move |track, _, _| {
+        let stats = Arc::clone(&track_stats);
+        Box::pin(async move {
+            tokio::spawn(measure_track(track, stats));
+        })
+    }));
+
+    let result = async {
+        let started = Instant::now();
+        client
+            .subscribe(&pc, &config.peer, config.stream_type.as_deref())
+            .await?;
+        let answer_latency = started.elapsed();
+
+        loop {
+            tokio::select! {
+                _ = stop.changed() => break,
+                event = client.next_event(&pc) => match event {
+                    Ok(Some(_)) => {}
+                    Ok(None) => bail!("Signalling connection closed"),
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+        if !stats.receiving.load(Ordering::Relaxed) {
+            bail!("No media received");
+        }
+        Ok(answer_latency)
+    }
+    .await;
+
+    let _ = pc.close().await;
+    client.close().await;
+    result
+}
+
+/// Counts packets and sequence gaps on `track`, and how late packets arrive
+/// compared to their RTP timestamps, until it ends.
+async fn measure_track(track: Arc<TrackRemote>, stats: Arc<SubscriberStats>) {
+    let clock_rate = track.codec().capability.clock_rate.max(1) as f64;
+    let mut last_seq: Option<u16> = None;
+    let mut first: Option<(Instant, u32)> = None;
+    let mut min_transit = f64::MAX;
+    let mut window_start = Instant::now();
+
+    while let Ok((pkt, _)) = track.read_rtp().await {
+        let now = Instant::now();
+        stats.receiving.store(true, Ordering::Relaxed);
+        stats.packets.fetch_add(1, Ordering::Relaxed);
+
+        let seq = pkt.header.sequence_number;
+        match last_seq.map(|last| seq.wrapping_sub(last)) {
+            // Reordered or repeated packets are behind the highest seen.
+            Some(ahead) if ahead == 0 || ahead > u16::MAX / 2 => {}
+            Some(ahead) => {
+                stats.lost.fetch_add(ahead as u64 - 1, Ordering::Relaxed);
+                last_seq = Some(seq);
+            }
+            None => last_seq = Some(seq),
+        }
+
+        // Arrival time less media time: constant for packets that aren't
+        // held up anywhere, growing with the queueing they see.
+        let (first_at, first_ts) = *first.get_or_insert((now, pkt.header.timestamp));
+        let media = pkt.header.timestamp.wrapping_sub(first_ts) as f64 / clock_rate;
+        let transit = now.duration_since(first_at).as_secs_f64() - media;
+        if now.duration_since(window_start) >= LAG_WINDOW {
+            window_start = now;
+            min_transit = transit;
+        }
+        min_transit = min_transit.min(transit);
+        let lag_us = ((transit - min_transit) * 1_000_000.0) as u64;
+        stats.max_lag_us.fetch_max(lag_us, Ordering::Relaxed);
+    }
+}
+
+fn log_progress(stats: &[Arc<SubscriberStats>]) {
+    let receiving = stats
+        .iter()
+        .filter(|s| s.receiving.load(Ordering::Relaxed))
+        .count();
+    let (packets, lost) = totals(stats);
+    info!(
+        "{} subscribers receiving, {} packets, {:.2}% lost",
+        receiving,
+        packets,
+        loss_percent(packets, lost)
+    );
+}
+
+fn summarize(stats: &[Arc<SubscriberStats>], answer_latencies: &mut [Duration]) {
+    let (packets, lost) = totals(stats);
+    info!(
+        "{} of {} subscribers finished, {} packets, {:.2}% lost",
+        answer_latencies.len(),
+        stats.len(),
+        packets,
+        loss_percent(packets, lost)
+    );
+
+    answer_latencies.sort();
+    if let Some(max) = answer_latencies.last() {
+        info!(
+            "Answer latency: median {:?}, p95 {:?}, max {:?}",
+            percentile(answer_latencies, 50),
+            percentile(answer_latencies, 95),
+            max
+        );
+    }
+
+    let mut lags: Vec<Duration> = stats
+        .iter()
+        .filter(|s| s.receiving.load(Ordering::Relaxed))
+        .map(|s| Duration::from_micros(s.max_lag_us.load(Ordering::Relaxed)))
+        .collect();
+    lags.sort();
+    if let Some(max) = lags.last() {
+        info!(
+            "Worst lag per subscriber: median {:?}, p95 {:?}, max {:?}",
+            percentile(&lags, 50),
+            percentile(&lags, 95),
+            max
+        );
+    }
+}
+
+fn totals(stats: &[Arc<SubscriberStats>]) -> (u64, u64) {
+    stats.iter().fold((0, 0), |(packets, lost), s| {
+        (
+            packets + s.packets.load(Ordering::Relaxed),
+            lost + s.lost.load(Ordering::Relaxed),
+        )
+    })
+}
+
+fn loss_percent(packets: u64, lost: u64) -> f64 {
+    if packets + lost == 0 {
+        0.0
+    } else {
+        lost as f64 * 100.0 / (packets + lost) as f64
+    }
+}
+
+/// `percent`th of `sorted`, which must not be empty.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    sorted[(sorted.len() - 1) * percent / 100]
+}
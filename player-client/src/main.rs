@@ -0,0 +1,324 @@
+mod loadtest;
+mod probe;
+mod receiver;
+mod recorder;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use grabber_protocol_client::SubscriberClient;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+#[derive(Parser)]
+#[command(name = "player-client")]
+#[command(about = "Headless player for recording and probing grabber streams")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Subscribe to one grabber and write its stream to disk or stdout.
+    Record {
+        #[arg(short, long, default_value = "ws://localhost:5000/player")]
+        url: String,
+
+        #[arg(short, long, default_value = "test")]
+        credential: String,
+
+        /// Name of the grabber to subscribe to.
+        #[arg(short, long)]
+        peer: String,
+
+        #[arg(long)]
+        stream_type: Option<String>,
+
+        /// Video output file; `-` writes raw H.264 (Annex B) to stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Audio output file (Ogg/Opus).
+        #[arg(long)]
+        audio_output: Option<PathBuf>,
+
+        /// Stop after this many seconds instead of waiting for Ctrl-C.
+        #[arg(short, long)]
+        duration: Option<u64>,
+
+        /// Write the server's contest clock ticks here, one JSON line per
+        /// video frame, to align the recording to contest time.
+        #[arg(long)]
+        clock_output: Option<PathBuf>,
+    },
+
+    /// Cycle through every online grabber and check that RTP and a keyframe
+    /// arrive. Keyframes are recognised from their RTP payload headers, not
+    /// decoded, so a grabber sending black frames still passes.
+    Probe {
+        /// Base HTTP URL of the signalling server.
+        #[arg(short, long, default_value = "http://localhost:5000")]
+        server: String,
+
+        #[arg(short, long, default_value = "test")]
+        credential: String,
+
+        /// How long to wait for RTP and a keyframe from each grabber.
+        #[arg(long, default_value = "5")]
+        timeout: u64,
+
+        /// Pause between probe rounds.
+        #[arg(long, default_value = "60")]
+        interval: u64,
+
+        /// Run a single round and exit non-zero if any grabber failed.
+        #[arg(long)]
+        once: bool,
+
+        /// Webhook URLs that receive a JSON event for each failed probe.
+        #[arg(long = "webhook")]
+        webhooks: Vec<String>,
+    },
+
+    /// Subscribe to one grabber many times at once and report answer
+    /// latency, packet loss and lag, to check the server's capacity.
+    Loadtest {
+        #[arg(short, long, default_value = "ws://localhost:5000/player")]
+        url: String,
+
+        #[arg(short, long, default_value = "test")]
+        credential: String,
+
+        /// Name of the grabber to subscribe to.
+        #[arg(short, long)]
+        peer: String,
+
+        #[arg(long)]
+        stream_type: Option<String>,
+
+        #[arg(short = 'n', long, default_value = "50")]
+        subscribers: usize,
+
+        /// Milliseconds between starting one subscriber and the next.
+        #[arg(long, default_value = "100")]
+        ramp: u64,
+
+        /// Seconds to keep every subscriber connected once all have started.
+        #[arg(short, long, default_value = "30")]
+        duration: u64,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Logs go to stderr so that stdout can carry the media stream.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+
+    match cli.command {
+        Commands::Record {
+            url,
+            credential,
+            peer,
+            stream_type,
+            output,
+            audio_output,
+            duration,
+            clock_output,
+        } => {
+            let outputs = RecordOutputs {
+                video: output,
+                audio: audio_output,
+                clock: clock_output,
+            };
+            handle_record(url, credential, peer, stream_type, outputs, duration).await
+        }
+        Commands::Probe {
+            server,
+            credential,
+            timeout,
+            interval,
+            once,
+            webhooks,
+        } => {
+            let config = probe::ProbeConfig {
+                server,
+                credential,
+                timeout: Duration::from_secs(timeout),
+                interval: Duration::from_secs(interval),
+                webhooks,
+            };
+            probe::run(config, once).await
+        }
+        Commands::Loadtest {
+            url,
+            credential,
+            peer,
+            stream_type,
+            subscribers,
+            ramp,
+            duration,
+        } => {
+            let config = loadtest::LoadTestConfig {
+                url,
+                credential,
+                peer,
+                stream_type,
+                subscribers,
+                ramp: Duration::from_millis(ramp),
+                duration: Duration::from_secs(duration),
+            };
+            loadtest::run(config).await
+        }
+    }
+}
+
+struct RecordOutputs {
+    video: Option<PathBuf>,
+    audio: Option<PathBuf>,
+    clock: Option<PathBuf>,
+}
+
+async fn handle_record(
+    url: String,
+    credential: String,
+    peer: String,
+    stream_type: Option<String>,
+    outputs: RecordOutputs,
+    duration: Option<u64>,
+) -> Result<()> {
+    let RecordOutputs {
+        video: video_output,
+        audio: audio_output,
+        clock: clock_output,
+    } = outputs;
+    let mut client = SubscriberClient::connect(&url, &credential).await?;
+    let pc = receiver::new_receiver(&client, stream_type.as_deref() != Some("audio")).await?;
+    if let Some(path) = clock_output {
+        recorder::record_clock(&pc, &path).await?;
+    }
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let track_tasks: Arc<Mutex<Vec<JoinHandle<()>>>> = Arc::new(Mutex::new(Vec::new()));
+
+    let tasks = Arc::clone(&track_tasks);
+    pc.on_track(Box::new(move |track, _, _| {
+        let tasks = Arc::clone(&tasks);
+        let shutdown_rx = shutdown_rx.clone();
+        let output = match track.kind() {
+            RTPCodecType::Audio => audio_output.clone(),
+            _ => video_output.clone(),
+        };
+
+        Box::pin(async move {
+            let mime_type = track.codec().capability.mime_type;
+            info!(
+                "Receiving {} track {} ({})",
+                track.kind(),
+                track.id(),
+                mime_type
+            );
+
+            let writer = match output {
+                Some(path) => match recorder::open_writer(&mime_type, &path) {
+                    Ok(writer) => Some(writer),
+                    Err(e) => {
+                        error!("Cannot record track {}: {}", track.id(), e);
+                        None
+                    }
+                },
+                None => None,
+            };
+
+            let handle = tokio::spawn(recorder::record_track(track, writer, shutdown_rx));
+            tasks.lock().await.push(handle);
+        })
+    }));
+
+    client.subscribe(&pc, &peer, stream_type.as_deref()).await?;
+    info!("Subscribed to {}", peer);
+
+    let deadline = async {
+        match duration {
+            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    // Armed by a `NOTICE` that the grabber is being shut down, so the
+    // recording is finished before its stream is cut.
+    let shutdown = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(shutdown);
+    let mut shutdown_scheduled = false;
+
+    loop {
+        tokio::select! {
+            event = client.next_event(&pc) => match event {
+                Ok(Some(msg)) if msg.event == "SESSION_EXPIRING" => {
+                    info!("Session expiring, renewing");
+                    client.renew()?;
+                }
+                Ok(Some(msg)) if msg.event == "NOTICE" => {
+                    let Some(notice) = msg.notice.filter(|notice| notice.peer_name == peer) else {
+                        continue;
+                    };
+                    match notice.shutdown_in_ms {
+                        Some(ms) => {
+                            warn!(
+                                "'{}' shuts down in {}s{}",
+                                peer,
+                                ms / 1000,
+                                notice.message.map_or(String::new(), |m| format!(": {}", m))
+                            );
+                            shutdown
+                                .as_mut()
+                                .reset(tokio::time::Instant::now() + Duration::from_millis(ms));
+                            shutdown_scheduled = true;
+                        }
+                        None => {
+                            info!("Shutdown of '{}' cancelled", peer);
+                            shutdown_scheduled = false;
+                        }
+                    }
+                }
+                Ok(Some(msg)) => info!("Signalling event: {}", msg.event),
+                Ok(None) => {
+                    warn!("Signalling connection closed");
+                    break;
+                }
+                Err(e) => {
+                    warn!("Signalling connection error: {}", e);
+                    break;
+                }
+            },
+            _ = &mut deadline => break,
+            _ = &mut shutdown, if shutdown_scheduled => {
+                info!("'{}' is shutting down, finishing the recording", peer);
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    let _ = shutdown_tx.send(true);
+    for handle in track_tasks.lock().await.drain(..) {
+        let _ = handle.await;
+    }
+
+    client.close().await;
+    pc.close().await?;
+
+    Ok(())
+}
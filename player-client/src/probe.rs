@@ -0,0 +1,451 @@
+use anyhow::{anyhow, bail, Result};
+use grabber_protocol_client::SubscriberClient;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+use crate::receiver;
+
+pub struct ProbeConfig {
+    pub server: String,
+    pub credential: String,
+    pub timeout: Duration,
+    pub interval: Duration,
+    pub webhooks: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PeersResponse {
+    peers: Vec<PeerStatus>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PeerStatus {
+    name: String,
+    online: bool,
+    #[serde(default)]
+    stream_types: Vec<String>,
+}
+
+impl PeerStatus {
+    /// Grabbers publishing only audio are probed for RTP, not keyframes.
+    fn audio_only(&self) -> bool {
+        !self.stream_types.is_empty() && self.stream_types.iter().all(|t| t == "audio")
+    }
+}
+
+/// Same shape as the server's webhook events so both can feed one receiver.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProbeEvent<'a> {
+    timestamp: i64,
+    peer_name: &'a str,
+    kind: &'a str,
+    message: Option<String>,
+    context: Option<&'a str>,
+}
+
+struct ProbeReport {
+    mime_type: String,
+    packets: u64,
+    /// Whether a keyframe was recognised, rather than any packet accepted
+    /// for a codec [`is_keyframe`] can't parse.
+    keyframe: bool,
+}
+
+pub async fn run(config: ProbeConfig, once: bool) -> Result<()> {
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let ws_url = player_url(&config.server)?;
+
+    loop {
+        match probe_round(&http, &ws_url, &config).await {
+            Ok(0) => info!("Probe round finished, all grabbers healthy"),
+            Ok(failures) if once => bail!("{} grabber(s) failed the probe", failures),
+            Ok(failures) => warn!("Probe round finished, {} grabber(s) failed", failures),
+            Err(e) if once => return Err(e),
+            Err(e) => warn!("Probe round failed: {:#}", e),
+        }
+
+        if once {
+            return Ok(());
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(config.interval) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+fn player_url(server: &str) -> Result<String> {
+    let base = server.trim_end_matches('/');
+    if let Some(rest) = base.strip_prefix("https://") {
+        Ok(format!("wss://{}/player", rest))
+    } else if let Some(rest) = base.strip_prefix("http://") {
+        Ok(format!("ws://{}/player", rest))
+    } else {
+        bail!(
+            "Server URL must start with http:// or https://, got {}",
+            server
+        )
+    }
+}
+
+async fn probe_round(http: &reqwest::Client, ws_url: &str, config: &ProbeConfig) -> Result<usize> {
+    let peers: PeersResponse = http
+        .get(format!("{}/api/peers", config.server.trim_end_matches('/')))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let mut failures = 0;
+
+    for peer in peers.peers.iter().filter(|p| p.online) {
+        let audio_only = peer.audio_only();
+        let result = probe_peer(
+            ws_url,
+            &config.credential,
+            &peer.name,
+            config.timeout,
+            audio_only,
+        )
+        .await;
+        match result {
+            Ok(report) if audio_only => info!(
+                "Grabber '{}' healthy: audio received ({})",
+                peer.name, report.mime_type
+            ),
+            Ok(report) if report.keyframe => info!(
+                "Grabber '{}' healthy: keyframe received after {} packets ({})",
+                peer.name, report.packets, report.mime_type
+            ),
+            Ok(report) => info!(
+                "Grabber '{}' healthy: RTP received, keyframes of {} aren't recognised",
+                peer.name, report.mime_type
+            ),
+            Err(e) => {
+                warn!("Grabber '{}' failed probe: {:#}", peer.name, e);
+                failures += 1;
+                alert(http, &config.webhooks, &peer.name, &e).await;
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+async fn probe_peer(
+    ws_url: &str,
+    credential: &str,
+    peer_name: &str,
+    timeout: Duration,
+    audio_only: bool,
+) -> Result<ProbeReport> {
+    let mut client = SubscriberClient::connect(ws_url, credential).await?;
+    let pc = receiver::new_receiver(&client, !audio_only).await?;
+
+    let result = check_media(&mut client, &pc, peer_name, timeout, audio_only).await;
+
+    let _ = pc.close().await;
+    client.close().await;
+
+    result
+}
+
+async fn check_media(
+    client: &mut SubscriberClient,
+    pc: &Arc<RTCPeerConnection>,
+    peer_name: &str,
+    timeout: Duration,
+    audio_only: bool,
+) -> Result<ProbeReport> {
+    let packets = Arc::new(AtomicU64::new(0));
+    let (keyframe_tx, mut keyframe_rx) = mpsc::channel::<(String, bool)>(1);
+
+    let packets_for_track = Arc::clone(&packets);
+    pc.on_track(Box::new(move |track, _, _| {
+        let packets = Arc::clone(&packets_for_track);
+        let keyframe_tx = keyframe_tx.clone();
+
+        Box::pin(async move {
+            let wanted = if audio_only {
+                RTPCodecType::Audio
+            } else {
+                RTPCodecType::Video
+            };
+            if track.kind() != wanted {
+                return;
+            }
+
+            let mime_type = track.codec().capability.mime_type;
+            tokio::spawn(async move {
+                while let Ok((pkt, _)) = track.read_rtp().await {
+                    packets.fetch_add(1, Ordering::Relaxed);
+                    // Any audio packet will do, as will any packet of a
+                    // codec whose keyframes can't be recognised.
+                    let keyframe = match is_keyframe(&mime_type, &pkt.payload) {
+                        _ if audio_only => false,
+                        Some(false) => continue,
+                        Some(true) => true,
+                        None => false,
+                    };
+                    let _ = keyframe_tx.send((mime_type, keyframe)).await;
+                    break;
+                }
+            });
+        })
+    }));
+
+    let stream_type = audio_only.then_some("audio");
+    tokio::time::timeout(timeout, client.subscribe(pc, peer_name, stream_type))
+        .await
+        .map_err(|_| anyhow!("No answer from server within {:?}", timeout))??;
+
+    let outcome = tokio::time::timeout(timeout, async {
+        loop {
+            tokio::select! {
+                received = keyframe_rx.recv() => return Ok(received),
+                event = client.next_event(pc) => match event {
+                    Ok(Some(_)) => {}
+                    Ok(None) => bail!("Signalling connection closed"),
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+    })
+    .await;
+
+    let packets = packets.load(Ordering::Relaxed);
+    match outcome {
+        Ok(Ok(Some((mime_type, keyframe)))) => Ok(ProbeReport {
+            mime_type,
+            packets,
+            keyframe,
+        }),
+        Ok(Ok(None)) if audio_only => bail!("Audio track ended before any packet arrived"),
+        Ok(Ok(None)) => bail!("Video track ended before a keyframe arrived"),
+        Ok(Err(e)) => Err(e),
+        Err(_) if packets == 0 => bail!("No RTP received within {:?}", timeout),
+        Err(_) => bail!(
+            "Received {} packets but no keyframe within {:?}",
+            packets,
+            timeout
+        ),
+    }
+}
+
+async fn alert(
+    http: &reqwest::Client,
+    webhooks: &[String],
+    peer_name: &str,
+    error: &anyhow::Error,
+) {
+    let event = ProbeEvent {
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64,
+        peer_name,
+        kind: "probe.failed",
+        message: Some(format!("{:#}", error)),
+        context: Some("probe"),
+    };
+
+    for url in webhooks {
+        let result = http
+            .post(url)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        if let Err(e) = result {
+            warn!("Failed to deliver probe alert to {}: {}", url, e);
+        }
+    }
+}
+
+/// Whether `payload` is the start of a keyframe, going by its payload
+/// header; `None` for codecs this can't parse.
+fn is_keyframe(mime_type: &str, payload: &[u8]) -> Option<bool> {
+    match mime_type.to_lowercase().as_str() {
+        "video/h264" => Some(h264_is_keyframe(payload)),
+        "video/vp8" => Some(vp8_is_keyframe(payload)),
+        "video/vp9" => Some(vp9_is_keyframe(payload)),
+        "video/av1" => Some(av1_is_keyframe(payload)),
+        _ => None,
+    }
+}
+
+fn h264_is_keyframe(payload: &[u8]) -> bool {
+    const IDR: u8 = 5;
+    const SPS: u8 = 7;
+    const STAP_A: u8 = 24;
+    const FU_A: u8 = 28;
+
+    let Some(&header) = payload.first() else {
+        return false;
+    };
+
+    match header & 0x1F {
+        IDR | SPS => true,
+        STAP_A => {
+            let mut offset = 1;
+            while offset + 2 < payload.len() {
+                let size = u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize;
+                if matches!(payload[offset + 2] & 0x1F, IDR | SPS) {
+                    return true;
+                }
+                offset += 2 + size;
+            }
+            false
+        }
+        FU_A => payload
+            .get(1)
+            .is_some_and(|&fu| fu & 0x80 != 0 && fu & 0x1F == IDR),
+        _ => false,
+    }
+}
+
+fn vp8_is_keyframe(payload: &[u8]) -> bool {
+    let Some(&descriptor) = payload.first() else {
+        return false;
+    };
+
+    // Only the start of partition 0 carries the frame header.
+    if descriptor & 0x10 == 0 || descriptor & 0x07 != 0 {
+        return false;
+    }
+
+    let mut offset = 1;
+    if descriptor & 0x80 != 0 {
+        let Some(&extension) = payload.get(1) else {
+            return false;
+        };
+        offset = 2;
+        if extension & 0x80 != 0 {
+            let Some(&picture_id) = payload.get(offset) else {
+                return false;
+            };
+            offset += if picture_id & 0x80 != 0 { 2 } else { 1 };
+        }
+        if extension & 0x40 != 0 {
+            offset += 1;
+        }
+        if extension & 0x30 != 0 {
+            offset += 1;
+        }
+    }
+
+    payload.get(offset).is_some_and(|&frame| frame & 0x01 == 0)
+}
+
+fn vp9_is_keyframe(payload: &[u8]) -> bool {
+    // Not inter-predicted (P = 0) and the start of a frame (B = 1).
+    payload
+        .first()
+        .is_some_and(|&descriptor| descriptor & 0x40 == 0 && descriptor & 0x08 != 0)
+}
+
+fn av1_is_keyframe(payload: &[u8]) -> bool {
+    // The first packet of a coded video sequence (N = 1), which starts with
+    // a key frame.
+    payload
+        .first()
+        .is_some_and(|&aggregation| aggregation & 0x08 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn h264_single_nal_units() {
+        assert!(h264_is_keyframe(&[0x65, 0x88]));
+        assert!(h264_is_keyframe(&[0x67, 0x42]));
+        assert!(!h264_is_keyframe(&[0x41, 0x9a]));
+        assert!(!h264_is_keyframe(&[]));
+    }
+
+    #[test]
+    fn h264_stap_a_with_an_sps() {
+        // An SEI, then an SPS, each with a two-byte size.
+        let stap_a = [0x78, 0x00, 0x02, 0x06, 0x05, 0x00, 0x02, 0x67, 0x42];
+        assert!(h264_is_keyframe(&stap_a));
+
+        let without_sps = [0x78, 0x00, 0x02, 0x06, 0x05, 0x00, 0x02, 0x41, 0x9a];
+        assert!(!h264_is_keyframe(&without_sps));
+    }
+
+    #[test]
+    fn h264_truncated_stap_a() {
+        assert!(!h264_is_keyframe(&[0x78, 0x00]));
+        // The size points past the end of the packet.
+        assert!(!h264_is_keyframe(&[0x78, 0x00, 0x20, 0x06, 0x05]));
+    }
+
+    #[test]
+    fn h264_fu_a_starting_an_idr() {
+        assert!(h264_is_keyframe(&[0x7c, 0x85, 0x88]));
+        // A middle fragment of the IDR.
+        assert!(!h264_is_keyframe(&[0x7c, 0x05, 0x88]));
+        // The start of a non-IDR slice.
+        assert!(!h264_is_keyframe(&[0x7c, 0x81, 0x9a]));
+        assert!(!h264_is_keyframe(&[0x7c]));
+    }
+
+    #[test]
+    fn vp8_keyframe_without_extensions() {
+        // S = 1, partition 0, then a frame tag with P = 0.
+        assert!(vp8_is_keyframe(&[0x10, 0x50]));
+        assert!(!vp8_is_keyframe(&[0x10, 0x51]));
+        // Not the start of a partition.
+        assert!(!vp8_is_keyframe(&[0x00, 0x50]));
+        // The start of partition 1.
+        assert!(!vp8_is_keyframe(&[0x11, 0x50]));
+    }
+
+    #[test]
+    fn vp8_keyframe_after_extensions() {
+        // X = 1 with I, L and T set and a two-byte picture id.
+        let keyframe = [0x90, 0xe0, 0x80, 0x01, 0x00, 0x40, 0x50];
+        assert!(vp8_is_keyframe(&keyframe));
+        let interframe = [0x90, 0xe0, 0x80, 0x01, 0x00, 0x40, 0x51];
+        assert!(!vp8_is_keyframe(&interframe));
+        // One-byte picture id only.
+        assert!(vp8_is_keyframe(&[0x90, 0x80, 0x01, 0x50]));
+        assert!(!vp8_is_keyframe(&[0x90, 0x80, 0x81]));
+    }
+
+    #[test]
+    fn vp9_start_of_an_intra_frame() {
+        assert!(vp9_is_keyframe(&[0x08]));
+        assert!(vp9_is_keyframe(&[0x88]));
+        // Inter-predicted.
+        assert!(!vp9_is_keyframe(&[0x48]));
+        // Not the start of a frame.
+        assert!(!vp9_is_keyframe(&[0x80]));
+        assert!(!vp9_is_keyframe(&[]));
+    }
+
+    #[test]
+    fn av1_new_coded_video_sequence() {
+        assert!(av1_is_keyframe(&[0x18]));
+        assert!(!av1_is_keyframe(&[0x10]));
+    }
+
+    #[test]
+    fn unparsed_codecs_are_not_judged() {
+        assert_eq!(is_keyframe("video/VP8", &[0x10, 0x50]), Some(true));
+        assert_eq!(is_keyframe("video/H265", &[0x26, 0x01]), None);
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::Result;
+use grabber_protocol_client::SubscriberClient;
+use std::sync::Arc;
+use tracing::warn;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::APIBuilder;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+
+/// Creates a peer connection with a recvonly audio transceiver, and a video
+/// one unless `video` is false, using the ICE servers the server handed out
+/// in INIT_PEER.
+pub async fn new_receiver(
+    client: &SubscriberClient,
+    video: bool,
+) -> Result<Arc<RTCPeerConnection>> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs()?;
+
+    let mut registry = Registry::new();
+    registry = register_default_interceptors(registry, &mut media_engine)?;
+
+    let api = APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build();
+
+    let pc_config = &client.init_peer().pc_config;
+    if pc_config.encoded_insertable_streams {
+        warn!("The SFU advertises end-to-end encryption; encrypted streams can't be decoded here");
+    }
+    let config = pc_config.to_rtc_configuration();
+    let pc = Arc::new(api.new_peer_connection(config).await?);
+
+    let kinds: &[RTPCodecType] = if video {
+        &[RTPCodecType::Video, RTPCodecType::Audio]
+    } else {
+        &[RTPCodecType::Audio]
+    };
+    for &kind in kinds {
+        pc.add_transceiver_from_kind(
+            kind,
+            Some(RTCRtpTransceiverInit {
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                send_encodings: vec![],
+            }),
+        )
+        .await?;
+    }
+
+    Ok(pc)
+}
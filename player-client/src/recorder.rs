@@ -0,0 +1,110 @@
+use anyhow::{bail, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tracing::{info, warn};
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::media::io::h264_writer::H264Writer;
+use webrtc::media::io::ivf_reader::IVFFileHeader;
+use webrtc::media::io::ivf_writer::IVFWriter;
+use webrtc::media::io::ogg_writer::OggWriter;
+use webrtc::media::io::Writer;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::track::track_remote::TrackRemote;
+
+pub type TrackWriter = Box<dyn Writer + Send>;
+
+/// Opens a depacketizing writer for the track's codec. `-` writes to stdout,
+/// which only works for H.264 since the IVF and Ogg containers need to seek.
+pub fn open_writer(mime_type: &str, path: &Path) -> Result<TrackWriter> {
+    let mime_type = mime_type.to_lowercase();
+    let to_stdout = path.as_os_str() == "-";
+
+    match mime_type.as_str() {
+        "video/h264" if to_stdout => Ok(Box::new(H264Writer::new(std::io::stdout()))),
+        "video/h264" => Ok(Box::new(H264Writer::new(File::create(path)?))),
+        _ if to_stdout => bail!("{} cannot be streamed to stdout, use a file", mime_type),
+        "video/vp8" | "video/vp9" | "video/av1" => {
+            let four_cc = match mime_type.as_str() {
+                "video/vp8" => *b"VP80",
+                "video/vp9" => *b"VP90",
+                _ => *b"AV01",
+            };
+            let header = IVFFileHeader {
+                signature: *b"DKIF",
+                version: 0,
+                header_size: 32,
+                four_cc,
+                width: 0,
+                height: 0,
+                timebase_denominator: 90000,
+                timebase_numerator: 1,
+                num_frames: 0,
+                unused: 0,
+            };
+            Ok(Box::new(IVFWriter::new(File::create(path)?, &header)?))
+        }
+        "audio/opus" => Ok(Box::new(OggWriter::new(File::create(path)?, 48000, 2)?)),
+        other => bail!("Unsupported codec {}", other),
+    }
+}
+
+/// Reads RTP from `track` until it ends or `shutdown` fires, then finalizes
+/// the output container.
+pub async fn record_track(
+    track: Arc<TrackRemote>,
+    mut writer: Option<TrackWriter>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let track_id = track.id();
+    let mut packets = 0u64;
+
+    loop {
+        tokio::select! {
+            result = track.read_rtp() => match result {
+                Ok((pkt, _)) => {
+                    packets += 1;
+                    if let Some(w) = writer.as_mut() {
+                        if let Err(e) = w.write_rtp(&pkt) {
+                            warn!("Failed to write packet for track {}: {}", track_id, e);
+                            break;
+                        }
+                    }
+                }
+                Err(e) => {
+                    info!("Track {} ended: {}", track_id, e);
+                    break;
+                }
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    if let Some(mut w) = writer {
+        if let Err(e) = w.close() {
+            warn!("Failed to finalize output for track {}: {}", track_id, e);
+        }
+    }
+
+    info!("Track {} finished after {} packets", track_id, packets);
+}
+
+/// Opens the `contest-clock` data channel, which must happen before the
+/// offer, and appends each tick the server sends to `path` as a JSON line.
+pub async fn record_clock(pc: &RTCPeerConnection, path: &Path) -> Result<()> {
+    let file = Arc::new(Mutex::new(File::create(path)?));
+    let channel = pc.create_data_channel("contest-clock", None).await?;
+    channel.on_message(Box::new(move |msg: DataChannelMessage| {
+        let mut file = file.lock().unwrap();
+        if let Err(e) = file
+            .write_all(&msg.data)
+            .and_then(|_| file.write_all(b"\n"))
+        {
+            warn!("Failed to write contest clock tick: {}", e);
+        }
+        Box::pin(async {})
+    }));
+    Ok(())
+}
@@ -0,0 +1,8 @@
+pub mod messages;
+pub mod publisher;
+pub mod signalling;
+pub mod subscriber;
+
+pub use publisher::PublisherClient;
+pub use signalling::{SignallingChannel, SignallingSender};
+pub use subscriber::SubscriberClient;
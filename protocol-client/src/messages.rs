@@ -0,0 +1,332 @@
+use serde::{Deserialize, Serialize};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GrabberMessage {
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grabber_auth: Option<Auth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_peer: Option<GrabberInitPeer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offer: Option<OfferMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<OfferMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ice: Option<IceMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ice_error: Option<IceErrorMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<PingMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingest_quality: Option<IngestQualityMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_metadata: Option<Vec<TrackMetadata>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher_state: Option<PublisherStateMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<PeerSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutdown: Option<ShutdownMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerMessage {
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_auth: Option<Auth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_peer: Option<PlayerInitPeer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offer: Option<OfferMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ice: Option<IceMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ice_error: Option<IceErrorMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offer_failed: Option<OfferFailedMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_expiry: Option<SessionExpiryMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<TrackControlMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_channels: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_metadata: Option<Vec<TrackMetadata>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<PingMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notice: Option<NoticeMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Auth {
+    pub credential: String,
+    /// Grabbers only: the token from the previous connection's `INIT_PEER`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PcConfig {
+    pub ice_servers: Vec<IceServer>,
+    /// Set when the SFU advertises end-to-end encryption: clients may
+    /// encrypt frames, and the ones they receive may be encrypted.
+    #[serde(default)]
+    pub encoded_insertable_streams: bool,
+}
+
+impl PcConfig {
+    pub fn to_rtc_configuration(&self) -> RTCConfiguration {
+        RTCConfiguration {
+            ice_servers: self
+                .ice_servers
+                .iter()
+                .map(|server| RTCIceServer {
+                    urls: server.urls.clone(),
+                    username: server.username.clone().unwrap_or_default(),
+                    credential: server.credential.clone().unwrap_or_default(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GrabberInitPeer {
+    pub pc_config: PcConfig,
+    pub ping_interval: u64,
+    /// Pass to [`PublisherClient::resume`](crate::PublisherClient::resume)
+    /// after a drop to keep the published stream and its viewers.
+    #[serde(default)]
+    pub resume_token: Option<String>,
+    /// Operator overrides for this grabber; `SETTINGS` messages replace them.
+    #[serde(default)]
+    pub settings: PeerSettings,
+}
+
+/// The server also sends `audioDisabled`, which it enforces itself by not
+/// forwarding the grabber's audio.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PeerSettings {
+    pub max_bitrate_kbps: Option<u32>,
+}
+
+/// Capture size and framerate to switch to, sent in `SET_QUALITY`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityMessage {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+}
+
+/// Why an admin shut the grabber down, sent in `SHUTDOWN`. The grabber stops
+/// publishing instead of reconnecting.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ShutdownMessage {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerInitPeer {
+    pub pc_config: PcConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferMessage {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub sdp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_group: Option<String>,
+}
+
+impl OfferMessage {
+    pub fn offer(sdp: String) -> Self {
+        Self {
+            type_: "offer".to_string(),
+            sdp,
+            peer_id: None,
+            peer_name: None,
+            stream_type: None,
+            sync_group: None,
+        }
+    }
+
+    pub fn answer(sdp: String) -> Self {
+        Self {
+            type_: "answer".to_string(),
+            ..Self::offer(sdp)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IceMessage {
+    pub candidate: RTCIceCandidateInit,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+}
+
+/// The server's reply to a candidate the SFU rejected.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IceErrorMessage {
+    pub candidate: RTCIceCandidateInit,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorMessage {
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferFailedMessage {
+    pub reason: String,
+    pub retryable: bool,
+    pub retry_after_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+}
+
+/// Sent by the server every few seconds while publishing. `degraded` is set
+/// when packet loss since the previous report is high.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestQualityMessage {
+    pub bitrate_bps: u64,
+    pub target_bitrate_bps: Option<u64>,
+    pub loss_ratio: f64,
+    pub quality_score: f64,
+    pub degraded: bool,
+    /// Worst packet loss subscribers recently reported.
+    #[serde(default)]
+    pub downstream_loss_ratio: f64,
+}
+
+/// Sent by a grabber to label its tracks; players receive the labels of the
+/// tracks they subscribed to with the `ANSWER`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackMetadata {
+    pub track_id: String,
+    /// `"screen"`, `"webcam"` or `"audio"`.
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_name: Option<String>,
+}
+
+/// Sent by a grabber when it stops or restarts sending media on its own, so
+/// viewers see why the stream froze.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PublisherStateMessage {
+    pub paused: bool,
+    /// Why publishing paused, e.g. `"locked"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Server `PING`s carry a millisecond timestamp that must be echoed back in
+/// the `PONG` so the server can measure signalling RTT. Grabber `PING`s also
+/// report what is being published.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PingMessage {
+    pub timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connections_count: Option<u32>,
+    /// Streams currently sending frames, e.g. `["webcam"]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_types: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipeline: Option<PipelineStats>,
+}
+
+/// How the grabber's capture pipeline is keeping up, over the last few
+/// seconds unless noted.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineStats {
+    pub fps: f64,
+    /// Measured from the encoder's output, not its target.
+    pub bitrate_kbps: u32,
+    /// Raw frames dropped because the encoder fell behind, since the
+    /// grabber started.
+    pub dropped_frames: u64,
+    /// Raw frames waiting for the encoder.
+    pub queued_frames: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackControlMessage {
+    pub track_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionExpiryMessage {
+    pub expires_in_ms: u64,
+}
+
+/// Sent in `NOTICE` when an operator scheduled a grabber to shut down.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoticeMessage {
+    pub peer_name: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    /// `None` when the shutdown was cancelled.
+    #[serde(default)]
+    pub shutdown_in_ms: Option<u64>,
+}
@@ -0,0 +1,250 @@
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use webrtc::peer_connection::offer_answer_options::RTCOfferOptions;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::messages::{
+    Auth, ErrorMessage, GrabberInitPeer, GrabberMessage, IceMessage, OfferMessage, PingMessage,
+    PipelineStats, PublisherStateMessage, TrackMetadata,
+};
+use crate::signalling::{SignallingChannel, SignallingSender};
+
+/// Grabber side of the signalling protocol: authenticates, publishes an offer
+/// for a caller-provided peer connection and trickles ICE in both directions.
+pub struct PublisherClient {
+    channel: SignallingChannel<GrabberMessage>,
+    init_peer: GrabberInitPeer,
+}
+
+impl PublisherClient {
+    pub async fn connect(url: &str, credential: &str) -> Result<Self> {
+        Self::resume(url, credential, None).await
+    }
+
+    /// Connects with the resume token of a dropped connection, so the server
+    /// keeps the stream it published and its viewers. The stream must still
+    /// be published again, from a new peer connection.
+    pub async fn resume(url: &str, credential: &str, resume_token: Option<&str>) -> Result<Self> {
+        let mut channel = SignallingChannel::connect(url).await?;
+
+        channel.send(&GrabberMessage {
+            event: "AUTH".to_string(),
+            grabber_auth: Some(Auth {
+                credential: credential.to_string(),
+                resume_token: resume_token.map(str::to_string),
+            }),
+            ..Default::default()
+        })?;
+
+        while let Some(msg) = channel.recv().await? {
+            match msg.event.as_str() {
+                "INIT_PEER" => {
+                    return Ok(Self {
+                        channel,
+                        init_peer: msg.init_peer.unwrap_or_default(),
+                    });
+                }
+                "AUTH_FAILED" => bail!(
+                    "Server rejected credentials: {}",
+                    msg.access_message.as_deref().unwrap_or("AUTH_FAILED")
+                ),
+                _ => {}
+            }
+        }
+
+        bail!("Connection closed before INIT_PEER")
+    }
+
+    pub fn init_peer(&self) -> &GrabberInitPeer {
+        &self.init_peer
+    }
+
+    pub fn sender(&self) -> SignallingSender<GrabberMessage> {
+        self.channel.sender()
+    }
+
+    pub async fn publish(&mut self, pc: &Arc<RTCPeerConnection>) -> Result<()> {
+        let sender = self.sender();
+        pc.on_ice_candidate(Box::new(move |candidate| {
+            let sender = sender.clone();
+            Box::pin(async move {
+                if let Some(candidate) = candidate {
+                    if let Ok(init) = candidate.to_json() {
+                        let _ = sender.send(&GrabberMessage {
+                            event: "GRABBER_ICE".to_string(),
+                            ice: Some(IceMessage {
+                                candidate: init,
+                                peer_id: None,
+                            }),
+                            ..Default::default()
+                        });
+                    }
+                }
+            })
+        }));
+
+        let offer = pc.create_offer(None).await?;
+        pc.set_local_description(offer.clone()).await?;
+
+        self.channel.send(&GrabberMessage {
+            event: "OFFER".to_string(),
+            offer: Some(OfferMessage::offer(offer.sdp)),
+            ..Default::default()
+        })?;
+
+        // Candidates can race ahead of the answer; they are applied once it is set.
+        let mut pending_ice = Vec::new();
+
+        while let Some(msg) = self.channel.recv().await? {
+            match msg.event.as_str() {
+                "ANSWER" => {
+                    if let Some(answer) = msg.answer {
+                        pc.set_remote_description(RTCSessionDescription::answer(answer.sdp)?)
+                            .await?;
+                        for candidate in pending_ice {
+                            if let Err(e) = pc.add_ice_candidate(candidate).await {
+                                warn!("Failed to add server ICE candidate: {}", e);
+                            }
+                        }
+                        return Ok(());
+                    }
+                }
+                "SERVER_ICE" => {
+                    if let Some(ice) = msg.ice {
+                        pending_ice.push(ice.candidate);
+                    }
+                }
+                "OFFER_FAILED" => bail!("Server rejected offer: OFFER_FAILED"),
+                _ => {}
+            }
+        }
+
+        bail!("Connection closed before receiving answer")
+    }
+
+    /// Sends an offer that restarts ICE on the published connection, for
+    /// when the network changed under it. The answer is applied by
+    /// [`Self::next_event`].
+    pub async fn restart_ice(&self, pc: &RTCPeerConnection) -> Result<()> {
+        let offer = pc
+            .create_offer(Some(RTCOfferOptions {
+                ice_restart: true,
+                ..Default::default()
+            }))
+            .await?;
+        pc.set_local_description(offer.clone()).await?;
+
+        self.channel.send(&GrabberMessage {
+            event: "OFFER".to_string(),
+            offer: Some(OfferMessage::offer(offer.sdp)),
+            ..Default::default()
+        })
+    }
+
+    /// Applies trickled server ICE candidates and answers to
+    /// [`Self::restart_ice`], answers server `PING`s, and yields every other
+    /// message.
+    pub async fn next_event(&mut self, pc: &RTCPeerConnection) -> Result<Option<GrabberMessage>> {
+        while let Some(msg) = self.channel.recv().await? {
+            match msg.event.as_str() {
+                "ANSWER" => {
+                    if let Some(answer) = msg.answer {
+                        pc.set_remote_description(RTCSessionDescription::answer(answer.sdp)?)
+                            .await?;
+                    }
+                }
+                "SERVER_ICE" => {
+                    if let Some(ice) = msg.ice {
+                        if let Err(e) = pc.add_ice_candidate(ice.candidate).await {
+                            warn!("Failed to add server ICE candidate: {}", e);
+                        }
+                    }
+                }
+                "PING" => {
+                    self.channel.send(&GrabberMessage {
+                        event: "PONG".to_string(),
+                        ping: msg.ping,
+                        ..Default::default()
+                    })?;
+                }
+                "ICE_ERROR" => {
+                    if let Some(error) = msg.ice_error {
+                        warn!(
+                            "Server rejected ICE candidate {}: {}",
+                            error.candidate.candidate, error.reason
+                        );
+                    }
+                }
+                _ => return Ok(Some(msg)),
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn report_error(&self, message: &str, context: Option<&str>) -> Result<()> {
+        self.channel.send(&error_message(message, context))
+    }
+
+    /// Labels the published tracks for viewers. Send after [`Self::publish`].
+    pub fn set_track_metadata(&self, tracks: Vec<TrackMetadata>) -> Result<()> {
+        self.channel.send(&GrabberMessage {
+            event: "TRACK_METADATA".to_string(),
+            track_metadata: Some(tracks),
+            ..Default::default()
+        })
+    }
+
+    /// Reports the grabber's state to the server, which shows it in the peer
+    /// list. Send every [`GrabberInitPeer::ping_interval`] milliseconds.
+    pub fn ping(
+        &self,
+        connections_count: u32,
+        stream_types: Vec<String>,
+        pipeline: Option<PipelineStats>,
+    ) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        self.channel.send(&GrabberMessage {
+            event: "PING".to_string(),
+            ping: Some(PingMessage {
+                timestamp,
+                connections_count: Some(connections_count),
+                stream_types: Some(stream_types),
+                pipeline,
+            }),
+            ..Default::default()
+        })
+    }
+
+    pub async fn close(self) {
+        self.channel.close().await;
+    }
+}
+
+pub fn error_message(message: &str, context: Option<&str>) -> GrabberMessage {
+    GrabberMessage {
+        event: "ERROR".to_string(),
+        error: Some(ErrorMessage {
+            message: message.to_string(),
+            context: context.map(str::to_string),
+        }),
+        ..Default::default()
+    }
+}
+
+/// Tells the server publishing paused or resumed without a renegotiation.
+pub fn publisher_state_message(paused: bool, reason: Option<&str>) -> GrabberMessage {
+    GrabberMessage {
+        event: "PUBLISHER_STATE".to_string(),
+        publisher_state: Some(PublisherStateMessage {
+            paused,
+            reason: reason.map(str::to_string),
+        }),
+        ..Default::default()
+    }
+}
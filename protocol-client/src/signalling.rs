@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use futures::stream::SplitStream;
+use futures::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{trace, warn};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct SignallingSender<M> {
+    outbound: mpsc::UnboundedSender<Message>,
+    _marker: PhantomData<fn(M)>,
+}
+
+impl<M> Clone for SignallingSender<M> {
+    fn clone(&self) -> Self {
+        Self {
+            outbound: self.outbound.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M: Serialize> SignallingSender<M> {
+    pub fn send(&self, msg: &M) -> Result<()> {
+        let text = serde_json::to_string(msg)?;
+        self.outbound
+            .send(Message::Text(text))
+            .context("Signalling connection closed")
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.outbound.is_closed()
+    }
+
+    /// Queues a Close frame; the writer task stops after sending it.
+    pub fn close(&self) {
+        let _ = self.outbound.send(Message::Close(None));
+    }
+}
+
+/// A JSON-over-WebSocket signalling connection with a dedicated writer task,
+/// so messages can be queued from callbacks without holding a lock on the sink.
+pub struct SignallingChannel<M> {
+    sender: SignallingSender<M>,
+    inbound: SplitStream<WsStream>,
+    writer_task: JoinHandle<()>,
+}
+
+impl<M: Serialize + DeserializeOwned> SignallingChannel<M> {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (ws_stream, _) = connect_async(url)
+            .await
+            .context("Failed to connect to WebSocket")?;
+
+        let (mut ws_tx, inbound) = ws_stream.split();
+        let (outbound, mut outbound_rx) = mpsc::unbounded_channel::<Message>();
+
+        let writer_task = tokio::spawn(async move {
+            while let Some(msg) = outbound_rx.recv().await {
+                let is_close = matches!(msg, Message::Close(_));
+                if let Err(e) = ws_tx.send(msg).await {
+                    warn!("Failed to send signalling message: {}", e);
+                    break;
+                }
+                if is_close {
+                    break;
+                }
+            }
+            trace!("Signalling writer task terminated");
+        });
+
+        Ok(Self {
+            sender: SignallingSender {
+                outbound,
+                _marker: PhantomData,
+            },
+            inbound,
+            writer_task,
+        })
+    }
+
+    pub fn send(&self, msg: &M) -> Result<()> {
+        self.sender.send(msg)
+    }
+
+    pub fn sender(&self) -> SignallingSender<M> {
+        self.sender.clone()
+    }
+
+    /// Next decoded message, or `None` once the server closes the connection.
+    pub async fn recv(&mut self) -> Result<Option<M>> {
+        while let Some(msg) = self.inbound.next().await {
+            match msg.context("WebSocket error")? {
+                Message::Text(text) => return Ok(Some(serde_json::from_str(&text)?)),
+                Message::Close(_) => return Ok(None),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn close(self) {
+        self.sender.close();
+        let _ = tokio::time::timeout(Duration::from_secs(2), self.writer_task).await;
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use tracing::warn;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::messages::{
+    Auth, IceMessage, OfferMessage, PlayerInitPeer, PlayerMessage, TrackControlMessage,
+};
+use crate::signalling::{SignallingChannel, SignallingSender};
+
+/// Player side of the signalling protocol: authenticates and subscribes a
+/// caller-provided peer connection to a named grabber.
+pub struct SubscriberClient {
+    channel: SignallingChannel<PlayerMessage>,
+    init_peer: PlayerInitPeer,
+}
+
+impl SubscriberClient {
+    pub async fn connect(url: &str, credential: &str) -> Result<Self> {
+        let mut channel = SignallingChannel::connect(url).await?;
+
+        while let Some(msg) = channel.recv().await? {
+            match msg.event.as_str() {
+                "AUTH_REQUEST" => {
+                    channel.send(&PlayerMessage {
+                        event: "AUTH".to_string(),
+                        player_auth: Some(Auth {
+                            credential: credential.to_string(),
+                            resume_token: None,
+                        }),
+                        ..Default::default()
+                    })?;
+                }
+                "INIT_PEER" => {
+                    return Ok(Self {
+                        channel,
+                        init_peer: msg.init_peer.unwrap_or_default(),
+                    });
+                }
+                "AUTH_FAILED" => bail!(
+                    "Server rejected credentials: {}",
+                    msg.access_message.as_deref().unwrap_or("AUTH_FAILED")
+                ),
+                _ => {}
+            }
+        }
+
+        bail!("Connection closed before INIT_PEER")
+    }
+
+    pub fn init_peer(&self) -> &PlayerInitPeer {
+        &self.init_peer
+    }
+
+    pub fn sender(&self) -> SignallingSender<PlayerMessage> {
+        self.channel.sender()
+    }
+
+    /// Sends an offer for `pc` (which must already carry its recvonly
+    /// transceivers) and waits for the SFU's answer. To receive the
+    /// publisher's relayed data channels, create any data channel on `pc`
+    /// first so the offer negotiates SCTP; they arrive via `on_data_channel`.
+    pub async fn subscribe(
+        &mut self,
+        pc: &Arc<RTCPeerConnection>,
+        peer_name: &str,
+        stream_type: Option<&str>,
+    ) -> Result<()> {
+        let sender = self.sender();
+        pc.on_ice_candidate(Box::new(move |candidate| {
+            let sender = sender.clone();
+            Box::pin(async move {
+                if let Some(candidate) = candidate {
+                    if let Ok(init) = candidate.to_json() {
+                        let _ = sender.send(&PlayerMessage {
+                            event: "PLAYER_ICE".to_string(),
+                            ice: Some(IceMessage {
+                                candidate: init,
+                                peer_id: None,
+                            }),
+                            ..Default::default()
+                        });
+                    }
+                }
+            })
+        }));
+
+        let offer = pc.create_offer(None).await?;
+        pc.set_local_description(offer.clone()).await?;
+
+        let mut offer_msg = OfferMessage::offer(offer.sdp);
+        offer_msg.peer_name = Some(peer_name.to_string());
+        offer_msg.stream_type = stream_type.map(str::to_string);
+
+        self.channel.send(&PlayerMessage {
+            event: "OFFER".to_string(),
+            offer: Some(offer_msg),
+            ..Default::default()
+        })?;
+
+        // Candidates can race ahead of the answer; they are applied once it is set.
+        let mut pending_ice = Vec::new();
+
+        while let Some(msg) = self.channel.recv().await? {
+            match msg.event.as_str() {
+                "ANSWER" => {
+                    if let Some(answer) = msg.offer {
+                        pc.set_remote_description(RTCSessionDescription::answer(answer.sdp)?)
+                            .await?;
+                        for candidate in pending_ice {
+                            if let Err(e) = pc.add_ice_candidate(candidate).await {
+                                warn!("Failed to add server ICE candidate: {}", e);
+                            }
+                        }
+                        return Ok(());
+                    }
+                }
+                "SERVER_ICE" => {
+                    if let Some(ice) = msg.ice {
+                        pending_ice.push(ice.candidate);
+                    }
+                }
+                "OFFER_FAILED" => bail!(
+                    "Server rejected offer: {}",
+                    msg.offer_failed
+                        .map(|f| f.reason)
+                        .unwrap_or_else(|| "OFFER_FAILED".to_string())
+                ),
+                _ => {}
+            }
+        }
+
+        bail!("Connection closed before receiving answer")
+    }
+
+    /// Asks the server to extend a time-limited session; answered with
+    /// `SESSION_RENEWED` or `RENEW_FAILED`.
+    pub fn renew(&self) -> Result<()> {
+        self.channel.send(&PlayerMessage {
+            event: "RENEW".to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// Stops (`paused = true`) or restarts media on one received track
+    /// without renegotiating.
+    pub fn set_track_paused(&self, track_id: &str, paused: bool) -> Result<()> {
+        self.channel.send(&PlayerMessage {
+            event: if paused {
+                "PAUSE_TRACK"
+            } else {
+                "RESUME_TRACK"
+            }
+            .to_string(),
+            track: Some(TrackControlMessage {
+                track_id: track_id.to_string(),
+                peer_id: None,
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// Applies trickled server ICE candidates, answers `RENEGOTIATE` offers
+    /// for tracks added later and server `PING`s, and yields every other
+    /// message.
+    pub async fn next_event(&mut self, pc: &RTCPeerConnection) -> Result<Option<PlayerMessage>> {
+        while let Some(msg) = self.channel.recv().await? {
+            match msg.event.as_str() {
+                "SERVER_ICE" => {
+                    if let Some(ice) = msg.ice {
+                        if let Err(e) = pc.add_ice_candidate(ice.candidate).await {
+                            warn!("Failed to add server ICE candidate: {}", e);
+                        }
+                    }
+                }
+                "RENEGOTIATE" => {
+                    if let Some(offer) = msg.offer {
+                        self.answer_renegotiation(pc, offer.sdp).await?;
+                    }
+                }
+                "PING" => {
+                    self.channel.send(&PlayerMessage {
+                        event: "PONG".to_string(),
+                        ping: msg.ping,
+                        ..Default::default()
+                    })?;
+                }
+                "ICE_ERROR" => {
+                    if let Some(error) = msg.ice_error {
+                        warn!(
+                            "Server rejected ICE candidate {}: {}",
+                            error.candidate.candidate, error.reason
+                        );
+                    }
+                }
+                _ => return Ok(Some(msg)),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn answer_renegotiation(&self, pc: &RTCPeerConnection, sdp: String) -> Result<()> {
+        pc.set_remote_description(RTCSessionDescription::offer(sdp)?)
+            .await?;
+        let answer = pc.create_answer(None).await?;
+        pc.set_local_description(answer.clone()).await?;
+
+        self.channel.send(&PlayerMessage {
+            event: "RENEGOTIATE_ANSWER".to_string(),
+            offer: Some(OfferMessage::answer(answer.sdp)),
+            ..Default::default()
+        })
+    }
+
+    pub async fn close(self) {
+        self.channel.close().await;
+    }
+}
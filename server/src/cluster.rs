@@ -0,0 +1,143 @@
+use sfu_local::config::ClusterConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::handlers::api::HealthResponse;
+use crate::protocol::JsonRtcConfiguration;
+use crate::state::{AppState, ClientClass};
+
+/// How often to check whether a cluster was configured by a reload.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Load of the other cluster nodes that answered their last health check,
+/// keyed by URL.
+#[derive(Default)]
+pub(crate) struct NodeLoads {
+    loads: Mutex<HashMap<String, usize>>,
+    /// Asks nodes for the ICE config of the clients sent to them.
+    client: reqwest::Client,
+}
+
+/// Where a client should connect.
+pub(crate) struct Endpoint {
+    pub url: String,
+    pub region: Option<String>,
+}
+
+/// The healthy server with the lowest load, among those in `region` if any
+/// are, this one winning ties.
+pub(crate) fn select_endpoint(
+    config: &ClusterConfig,
+    loads: &NodeLoads,
+    local_load: usize,
+    region: Option<&str>,
+) -> Endpoint {
+    let loads = loads.loads.lock().unwrap();
+    let mut candidates = vec![(&config.self_url, &config.region, local_load)];
+    candidates.extend(
+        config
+            .nodes
+            .iter()
+            .filter_map(|node| Some((&node.url, &node.region, *loads.get(&node.url)?))),
+    );
+
+    let in_region = |candidate_region: &Option<String>| {
+        region.is_some() && candidate_region.as_deref() == region
+    };
+    if candidates.iter().any(|(_, r, _)| in_region(r)) {
+        candidates.retain(|(_, r, _)| in_region(r));
+    }
+    // `min_by_key` keeps the first of equals, so this server wins ties.
+    let (url, region, _) = candidates
+        .into_iter()
+        .min_by_key(|(_, _, load)| *load)
+        .expect("there is always a candidate");
+    Endpoint {
+        url: url.clone(),
+        region: region.clone(),
+    }
+}
+
+/// Polls the configured nodes' `/api/health` every `poll_secs`. A node that
+/// doesn't answer isn't offered to clients until it does again.
+pub async fn watch_nodes(state: Arc<AppState>) {
+    let client = reqwest::Client::builder()
+        .timeout(HEALTH_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+
+    loop {
+        let Some(config) = state.config.current().cluster.clone() else {
+            state.cluster.loads.lock().unwrap().clear();
+            tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+            continue;
+        };
+
+        let checks = config.nodes.iter().map(|node| {
+            let client = &client;
+            async move {
+                let health = async {
+                    client
+                        .get(format!("{}/api/health", node.url.trim_end_matches('/')))
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json::<HealthResponse>()
+                        .await
+                }
+                .await;
+                (node.url.clone(), health)
+            }
+        });
+        let results = futures::future::join_all(checks).await;
+
+        let previous = state.cluster.loads.lock().unwrap().clone();
+        let mut loads = HashMap::new();
+        for (url, health) in results {
+            match health {
+                Ok(health) => {
+                    if !previous.contains_key(&url) {
+                        info!("Cluster node {} is healthy", url);
+                    }
+                    loads.insert(url, health.publishers + health.subscribers);
+                }
+                Err(e) if previous.contains_key(&url) => {
+                    warn!("Cluster node {} failed its health check: {}", url, e);
+                }
+                Err(_) => {}
+            }
+        }
+        *state.cluster.loads.lock().unwrap() = loads;
+
+        tokio::time::sleep(Duration::from_secs(config.poll_secs.max(1))).await;
+    }
+}
+
+/// The ICE config node `url` gives clients of `role`, passing on the
+/// client's `authorization`, as grabbers need their credential for it.
+pub(crate) async fn fetch_ice_config(
+    loads: &NodeLoads,
+    url: &str,
+    role: ClientClass,
+    authorization: Option<&str>,
+) -> reqwest::Result<JsonRtcConfiguration> {
+    let role = match role {
+        ClientClass::Grabber => "grabber",
+        ClientClass::Player => "player",
+    };
+    let mut request = loads
+        .client
+        .get(format!(
+            "{}/api/ice-config?role={}",
+            url.trim_end_matches('/'),
+            role
+        ))
+        .timeout(HEALTH_TIMEOUT);
+    if let Some(authorization) = authorization {
+        request = request.header(reqwest::header::AUTHORIZATION, authorization);
+    }
+    request.send().await?.error_for_status()?.json().await
+}
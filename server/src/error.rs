@@ -14,6 +14,9 @@ pub enum SignallingError {
     #[error("Authentication failed: {0}")]
     AuthenticationFailed(String),
 
+    #[error("Access denied: {0}")]
+    Forbidden(String),
+
     #[error("Timeout: {0}")]
     Timeout(String),
 
@@ -26,6 +29,9 @@ pub enum SignallingError {
     #[error("Peer not found: {0}")]
     PeerNotFound(String),
 
+    #[error("Tenant not found: {0}")]
+    TenantNotFound(String),
+
     #[error("Session error: {0}")]
     SessionError(String),
 
@@ -37,7 +43,9 @@ impl IntoResponse for SignallingError {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
             SignallingError::AuthenticationFailed(msg) => (StatusCode::UNAUTHORIZED, msg),
+            SignallingError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
             SignallingError::PeerNotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            SignallingError::TenantNotFound(msg) => (StatusCode::NOT_FOUND, msg),
             SignallingError::Timeout(msg) => (StatusCode::REQUEST_TIMEOUT, msg),
             SignallingError::InvalidMessageFormat(msg) => (StatusCode::BAD_REQUEST, msg),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
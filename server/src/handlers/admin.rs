@@ -0,0 +1,587 @@
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use sfu_core::{RTCPeerConnectionState, SessionInfo, SessionKind};
+
+use super::player::socket_id;
+use crate::error::{Result, SignallingError};
+use crate::peer_feed::PeersUpdate;
+use crate::peer_settings;
+use crate::protocol::{GrabberMessage, PeerSettings, PeerStatus, PeersStatusDelta, QualityMessage};
+use crate::reload::{self, ReloadReport};
+use crate::state::AppState;
+use crate::telemetry;
+
+pub async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response> {
+    if state.config.current().auth.admin_token.is_none() {
+        return Err(SignallingError::Forbidden(
+            "Admin API is disabled".to_string(),
+        ));
+    }
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) if state.config.current().validate_admin_token(token) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(SignallingError::AuthenticationFailed(
+            "Invalid admin token".to_string(),
+        )),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminSession {
+    pub id: String,
+    pub kind: String,
+    pub peer_name: Option<String>,
+    pub publisher_id: Option<String>,
+    pub connection_state: String,
+    pub relayed: Option<bool>,
+    pub track_count: usize,
+    pub signalling_connected: bool,
+    pub signalling_rtt_ms: Option<u64>,
+    /// Candidates the SFU rejected on this signalling connection.
+    pub ice_errors: u64,
+    /// Empty until the session's first offer/answer exchange completes.
+    pub codecs: Vec<AdminCodec>,
+    pub header_extensions: Vec<AdminHeaderExtension>,
+    pub codec_mismatches: Vec<String>,
+    /// For subscribers in a sync group; the publishers' clocks disagree if
+    /// this is well above their network delay.
+    pub sync_skew_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminCodec {
+    pub kind: String,
+    pub mime_type: String,
+    pub payload_type: u8,
+    pub clock_rate: u32,
+    pub fmtp: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminHeaderExtension {
+    pub kind: String,
+    pub id: u16,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminSessionsResponse {
+    pub sessions: Vec<AdminSession>,
+}
+
+pub async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<AdminSessionsResponse>> {
+    let names: HashMap<String, String> = state
+        .storage
+        .get_all_statuses()
+        .into_iter()
+        .map(|peer| (peer.socket_id, peer.name))
+        .collect();
+
+    let sessions = state
+        .sfu
+        .list_sessions()
+        .await?
+        .into_iter()
+        .map(|info| {
+            let negotiated = info.negotiated.unwrap_or_default();
+            AdminSession {
+                peer_name: names.get(&info.id).cloned(),
+                kind: match info.kind {
+                    SessionKind::Publisher => "publisher".to_string(),
+                    SessionKind::Subscriber => "subscriber".to_string(),
+                },
+                connection_state: info.connection_state.to_string(),
+                relayed: info.relayed,
+                signalling_connected: state.storage.has_session(socket_id(&info.id)),
+                signalling_rtt_ms: state.storage.rtt_ms(socket_id(&info.id)),
+                ice_errors: state.storage.ice_errors(socket_id(&info.id)),
+                id: info.id,
+                publisher_id: info.publisher_id,
+                track_count: info.tracks.len(),
+                codecs: negotiated
+                    .codecs
+                    .into_iter()
+                    .map(|codec| AdminCodec {
+                        kind: codec.kind,
+                        mime_type: codec.mime_type,
+                        payload_type: codec.payload_type,
+                        clock_rate: codec.clock_rate,
+                        fmtp: codec.fmtp,
+                    })
+                    .collect(),
+                header_extensions: negotiated
+                    .header_extensions
+                    .into_iter()
+                    .map(|extension| AdminHeaderExtension {
+                        kind: extension.kind,
+                        id: extension.id,
+                        uri: extension.uri,
+                    })
+                    .collect(),
+                codec_mismatches: info.codec_mismatches,
+                sync_skew_ms: info.sync_skew_ms,
+            }
+        })
+        .collect();
+
+    Ok(Json(AdminSessionsResponse { sessions }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DisconnectResponse {
+    pub disconnected: String,
+}
+
+pub async fn disconnect_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<DisconnectResponse>> {
+    let known_to_sfu = state.sfu.get_session(&id).await?.is_some();
+    let ws_session = state.storage.get_session(&id);
+
+    if !known_to_sfu && ws_session.is_none() {
+        return Err(SignallingError::PeerNotFound(id));
+    }
+
+    info!("Admin disconnecting session {}", id);
+
+    if let Some(session) = ws_session {
+        let _ = session.close();
+    }
+    teardown(&state, &id).await;
+
+    Ok(Json(DisconnectResponse { disconnected: id }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CleanupResponse {
+    pub removed: Vec<String>,
+}
+
+/// Removes SFU sessions whose peer connection has failed or closed, or whose
+/// signalling socket is gone, along with grabber entries left without a socket.
+pub async fn cleanup_sessions(State(state): State<Arc<AppState>>) -> Result<Json<CleanupResponse>> {
+    let mut removed: Vec<String> = state
+        .sfu
+        .list_sessions()
+        .await?
+        .into_iter()
+        .filter(|info| is_stale(&state, info))
+        .map(|info| info.id)
+        .collect();
+
+    for peer in state.storage.get_all_statuses() {
+        if !state.storage.has_session(&peer.socket_id) && !removed.contains(&peer.socket_id) {
+            removed.push(peer.socket_id);
+        }
+    }
+
+    for id in &removed {
+        teardown(&state, id).await;
+    }
+
+    info!("Admin cleanup removed {} stale sessions", removed.len());
+
+    Ok(Json(CleanupResponse { removed }))
+}
+
+fn is_stale(state: &AppState, info: &SessionInfo) -> bool {
+    matches!(
+        info.connection_state,
+        RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+    ) || !state.storage.has_session(socket_id(&info.id))
+}
+
+async fn teardown(state: &AppState, id: &str) {
+    if let Some(peer) = state
+        .storage
+        .get_all_statuses()
+        .into_iter()
+        .find(|peer| peer.socket_id == id)
+    {
+        state
+            .storage
+            .record_event(&peer.name, "disconnected", None, Some("admin".to_string()));
+    }
+
+    state.storage.remove_peer_by_socket_id(id);
+    state.storage.unregister_session(id);
+    let _ = state.sfu.remove_publisher(id).await;
+    let _ = state.sfu.remove_subscriber(id).await;
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PeersStatusQuery {
+    pub since: Option<u64>,
+}
+
+/// `peers` is set when a full list was needed (no `since`, or deltas after it
+/// are no longer retained); otherwise apply `deltas` in order.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeersStatusResponse {
+    pub seq: u64,
+    pub peers: Option<Vec<PeerStatus>>,
+    pub deltas: Vec<PeersStatusDelta>,
+}
+
+pub async fn peers_status(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PeersStatusQuery>,
+) -> Json<PeersStatusResponse> {
+    if let Some(since) = query.since {
+        if let Some(deltas) = state.peer_feed.deltas_since(since) {
+            return Json(PeersStatusResponse {
+                seq: deltas.last().map_or(since, |delta| delta.seq),
+                peers: None,
+                deltas,
+            });
+        }
+    }
+
+    let PeersUpdate::Full { seq, peers } = state.peer_feed.snapshot() else {
+        unreachable!("snapshot is always a full list");
+    };
+    Json(PeersStatusResponse {
+        seq,
+        peers: Some(peers),
+        deltas: Vec::new(),
+    })
+}
+
+pub async fn get_peer_settings(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Json<PeerSettings> {
+    Json(state.storage.peer_settings(&name))
+}
+
+/// Stores the peer's overrides, applied now if it is connected and again
+/// whenever it reconnects.
+pub async fn set_peer_settings(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(settings): Json<PeerSettings>,
+) -> Json<PeerSettings> {
+    info!("Admin set settings for peer '{}': {:?}", name, settings);
+    apply_peer_settings(&state, &name, settings.clone()).await;
+    Json(settings)
+}
+
+pub async fn clear_peer_settings(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Json<PeerSettings> {
+    info!("Admin cleared settings for peer '{}'", name);
+    apply_peer_settings(&state, &name, PeerSettings::default()).await;
+    Json(PeerSettings::default())
+}
+
+async fn apply_peer_settings(state: &AppState, name: &str, settings: PeerSettings) {
+    state.storage.set_peer_settings(name, settings.clone());
+    peer_settings::save(state);
+    state
+        .storage
+        .record_event(name, "settings", None, Some("admin".to_string()));
+
+    let Some(peer) = state.storage.get_peer_by_name(name) else {
+        return;
+    };
+    if let Err(e) = state
+        .sfu
+        .set_publisher_audio_muted(&peer.socket_id, settings.audio_disabled)
+        .await
+    {
+        debug!("Peer '{}' isn't publishing yet: {}", name, e);
+    }
+    if let Some(session) = state.storage.get_session(&peer.socket_id) {
+        let _ = session.send_json(&GrabberMessage::Settings { settings });
+    }
+}
+
+/// Asks a connected grabber to change its capture size and framerate. Unlike
+/// settings, this isn't kept for its next connection.
+pub async fn set_peer_quality(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(quality): Json<QualityMessage>,
+) -> Result<Json<QualityMessage>> {
+    if quality.width == 0 || quality.height == 0 || quality.fps == 0 {
+        return Err(SignallingError::InvalidMessageFormat(
+            "width, height and fps must be positive".to_string(),
+        ));
+    }
+    let session = state
+        .storage
+        .get_peer_by_name(&name)
+        .and_then(|peer| state.storage.get_session(&peer.socket_id))
+        .ok_or_else(|| {
+            SignallingError::PeerNotFound(format!("Peer '{}' is not connected", name))
+        })?;
+
+    info!("Admin set quality for peer '{}': {:?}", name, quality);
+    session.send_json(&GrabberMessage::SetQuality { quality })?;
+    Ok(Json(quality))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShutdownRequest {
+    /// How long viewers get to see the notice before the grabber goes.
+    pub delay_secs: u64,
+    /// Shown to viewers, e.g. why the machine is being serviced.
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShutdownResponse {
+    pub peer_name: String,
+    /// `None` once cancelled.
+    pub shutdown_in_ms: Option<u64>,
+}
+
+/// Schedules a connected grabber to be shut down after `delay_secs`, counting
+/// down to its viewers with `NOTICE`s so recordings can be finished first.
+pub async fn schedule_shutdown(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(request): Json<ShutdownRequest>,
+) -> Result<Json<ShutdownResponse>> {
+    if state.storage.get_peer_by_name(&name).is_none() {
+        return Err(SignallingError::PeerNotFound(format!(
+            "Peer '{}' is not connected",
+            name
+        )));
+    }
+
+    let delay = Duration::from_secs(request.delay_secs);
+    info!(
+        "Admin scheduled peer '{}' to shut down in {:?}",
+        name, delay
+    );
+    state.storage.record_event(
+        &name,
+        "shutdown_scheduled",
+        request.message.clone(),
+        Some(format!("in {}s", request.delay_secs)),
+    );
+    state
+        .shutdowns
+        .schedule(&state, name.clone(), delay, request.message);
+    Ok(Json(ShutdownResponse {
+        peer_name: name,
+        shutdown_in_ms: Some(delay.as_millis() as u64),
+    }))
+}
+
+pub async fn cancel_shutdown(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<ShutdownResponse>> {
+    if !state.shutdowns.cancel(&state, &name) {
+        return Err(SignallingError::PeerNotFound(format!(
+            "No shutdown is scheduled for peer '{}'",
+            name
+        )));
+    }
+
+    info!("Admin cancelled the shutdown of peer '{}'", name);
+    state
+        .storage
+        .record_event(&name, "shutdown_cancelled", None, Some("admin".to_string()));
+    Ok(Json(ShutdownResponse {
+        peer_name: name,
+        shutdown_in_ms: None,
+    }))
+}
+
+pub async fn reload_config(State(state): State<Arc<AppState>>) -> Result<Json<ReloadReport>> {
+    info!("Admin requested config reload");
+    Ok(Json(reload::reload_config(&state)?))
+}
+
+/// How long a session is traced when the request doesn't say.
+const DEFAULT_TRACE_SECS: u64 = 300;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilterStatus {
+    /// The filter replacing the configured one, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in_secs: Option<u64>,
+    pub traced_sessions: Vec<TracedSession>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TracedSession {
+    pub id: String,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetLogFilter {
+    pub filter: String,
+    /// Reverts to the configured filter afterwards; kept until cleared if unset.
+    pub duration_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceSession {
+    pub duration_secs: Option<u64>,
+}
+
+pub async fn get_log_filter(State(state): State<Arc<AppState>>) -> Json<LogFilterStatus> {
+    Json(log_filter_status(&state))
+}
+
+pub async fn set_log_filter(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SetLogFilter>,
+) -> Result<Json<LogFilterStatus>> {
+    let until = request
+        .duration_secs
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    state
+        .log_overrides
+        .set_filter(request.filter.clone(), until)
+        .map_err(|e| SignallingError::InvalidMessageFormat(format!("Invalid log filter: {}", e)))?;
+    info!(
+        "Admin set the log filter to '{}' for {:?}s",
+        request.filter, request.duration_secs
+    );
+    apply_log_overrides(&state, until)?;
+    Ok(Json(log_filter_status(&state)))
+}
+
+pub async fn reset_log_filter(State(state): State<Arc<AppState>>) -> Result<Json<LogFilterStatus>> {
+    state.log_overrides.clear_filter();
+    info!("Admin reset the log filter");
+    apply_log_overrides(&state, None)?;
+    Ok(Json(log_filter_status(&state)))
+}
+
+/// Logs everything for one session, matched by its socket, publisher or
+/// subscriber id, on top of the current filter.
+pub async fn trace_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    request: Option<Json<TraceSession>>,
+) -> Result<Json<LogFilterStatus>> {
+    let secs = request
+        .and_then(|Json(request)| request.duration_secs)
+        .unwrap_or(DEFAULT_TRACE_SECS);
+    let until = Instant::now() + Duration::from_secs(secs);
+    state
+        .log_overrides
+        .trace_session(&id, until)
+        .map_err(|e| SignallingError::InvalidMessageFormat(e.to_string()))?;
+    info!(
+        "Admin enabled trace logging for session {} for {}s",
+        id, secs
+    );
+    apply_log_overrides(&state, Some(until))?;
+    Ok(Json(log_filter_status(&state)))
+}
+
+pub async fn stop_tracing_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<LogFilterStatus>> {
+    if !state.log_overrides.stop_tracing_session(&id) {
+        return Err(SignallingError::PeerNotFound(format!(
+            "Session {} isn't traced",
+            id
+        )));
+    }
+    info!("Admin disabled trace logging for session {}", id);
+    apply_log_overrides(&state, None)?;
+    Ok(Json(log_filter_status(&state)))
+}
+
+/// Installs the overrides, then again once `until` passes to drop them.
+fn apply_log_overrides(state: &Arc<AppState>, until: Option<Instant>) -> Result<()> {
+    let Some(handle) = state
+        .reload
+        .as_ref()
+        .and_then(|source| source.log_filter.clone())
+    else {
+        return Err(SignallingError::Forbidden(
+            "Log filter control is disabled".to_string(),
+        ));
+    };
+    telemetry::reload_log_filter(
+        &handle,
+        &state.config.current().telemetry,
+        &state.log_overrides,
+    )?;
+
+    if let Some(until) = until {
+        let state = Arc::clone(state);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(until.into()).await;
+            if state.log_overrides.expire() {
+                info!("Log filter overrides expired");
+                if let Err(e) = telemetry::reload_log_filter(
+                    &handle,
+                    &state.config.current().telemetry,
+                    &state.log_overrides,
+                ) {
+                    warn!("Failed to restore the log filter: {}", e);
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+fn log_filter_status(state: &AppState) -> LogFilterStatus {
+    let now = Instant::now();
+    let remaining = |until: Instant| until.saturating_duration_since(now).as_secs();
+    let current = state.log_overrides.current_filter();
+    let mut traced_sessions: Vec<_> = state
+        .log_overrides
+        .traced_sessions()
+        .into_iter()
+        .map(|(id, until)| TracedSession {
+            id,
+            expires_in_secs: remaining(until),
+        })
+        .collect();
+    traced_sessions.sort_by(|a, b| a.id.cmp(&b.id));
+
+    LogFilterStatus {
+        expires_in_secs: current.as_ref().and_then(|(_, until)| until.map(remaining)),
+        filter: current.map(|(filter, _)| filter),
+        traced_sessions,
+    }
+}
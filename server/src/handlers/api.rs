@@ -1,9 +1,22 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderName},
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
+use sfu_core::{SessionInfo, SessionKind};
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use tracing::warn;
 
-use crate::protocol::PeerStatus;
-use crate::state::AppState;
+use super::check_tenant;
+use crate::cluster;
+use crate::error::{Result, SignallingError};
+use crate::history::{self, PeerReport};
+use crate::protocol::{JsonRtcConfiguration, PeerEvent, PeerStatus};
+use crate::state::{AppState, ClientClass};
+use crate::tenant;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PeersResponse {
@@ -11,25 +24,430 @@ pub struct PeersResponse {
 }
 
 pub async fn get_peers(State(state): State<Arc<AppState>>) -> Json<PeersResponse> {
-    let peers = state.storage.get_all_statuses();
+    let peers = tenant::scope_peers(None, state.storage.get_all_statuses());
     Json(PeersResponse { peers })
 }
 
+pub async fn get_tenant_peers(
+    State(state): State<Arc<AppState>>,
+    Path(tenant): Path<String>,
+) -> Result<Json<PeersResponse>> {
+    check_tenant(&state, &tenant)?;
+    let peers = tenant::scope_peers(Some(&tenant), state.storage.get_all_statuses());
+    Ok(Json(PeersResponse { peers }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CapabilitiesRequest {
+    /// MIME types the client can decode, e.g. `"video/VP8"`, as from
+    /// `RTCRtpReceiver.getCapabilities()`.
+    pub codecs: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CapabilitiesResponse {
+    pub peers: Vec<PeerCompatibility>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerCompatibility {
+    pub name: String,
+    /// Whether the client can decode every track the peer publishes.
+    pub compatible: bool,
+    /// Codecs of the peer's tracks the client didn't list. Playing those
+    /// tracks would need transcoding, which this server doesn't do, so an
+    /// `OFFER` for them fails.
+    pub unsupported_codecs: Vec<String>,
+}
+
+/// `POST /api/capabilities`: which publishing peers a client with the posted
+/// codecs can play, so the player can warn before sending an `OFFER`.
+pub async fn check_capabilities(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CapabilitiesRequest>,
+) -> Json<CapabilitiesResponse> {
+    Json(compatibility(&state, None, &request.codecs).await)
+}
+
+pub async fn check_tenant_capabilities(
+    State(state): State<Arc<AppState>>,
+    Path(tenant): Path<String>,
+    Json(request): Json<CapabilitiesRequest>,
+) -> Result<Json<CapabilitiesResponse>> {
+    check_tenant(&state, &tenant)?;
+    let response = compatibility(&state, Some(&tenant), &request.codecs).await;
+    Ok(Json(response))
+}
+
+async fn compatibility(
+    state: &AppState,
+    tenant: Option<&str>,
+    codecs: &[String],
+) -> CapabilitiesResponse {
+    let mut peers = Vec::new();
+    for peer in state.storage.get_all_statuses() {
+        let Some(name) = tenant::local_name(tenant, &peer.name) else {
+            continue;
+        };
+        if !peer.online {
+            continue;
+        }
+        // Peers that haven't published yet have nothing to play.
+        let Ok(Some(session)) = state.sfu.get_session(&peer.socket_id).await else {
+            continue;
+        };
+
+        let mut unsupported_codecs: Vec<String> = Vec::new();
+        for track in session.tracks {
+            let supported = codecs
+                .iter()
+                .any(|codec| codec.eq_ignore_ascii_case(&track.mime_type));
+            if !supported && !unsupported_codecs.contains(&track.mime_type) {
+                unsupported_codecs.push(track.mime_type);
+            }
+        }
+        peers.push(PeerCompatibility {
+            name: name.to_string(),
+            compatible: unsupported_codecs.is_empty(),
+            unsupported_codecs,
+        });
+    }
+    CapabilitiesResponse { peers }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventsResponse {
+    pub events: Vec<PeerEvent>,
+}
+
+pub async fn get_events(State(state): State<Arc<AppState>>) -> Json<EventsResponse> {
+    Json(EventsResponse {
+        events: tenant::scope_events(None, state.storage.recent_events()),
+    })
+}
+
+pub async fn get_tenant_events(
+    State(state): State<Arc<AppState>>,
+    Path(tenant): Path<String>,
+) -> Result<Json<EventsResponse>> {
+    check_tenant(&state, &tenant)?;
+    Ok(Json(EventsResponse {
+        events: tenant::scope_events(Some(&tenant), state.storage.recent_events()),
+    }))
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+const REPORT_TRUNCATED: HeaderName = HeaderName::from_static("x-report-truncated");
+
+/// `from` and `to` are unix timestamps in seconds.
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    #[serde(default)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportResponse {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// The window reaches back past the oldest samples or events still kept,
+    /// so the totals miss part of it.
+    pub truncated: bool,
+    pub peers: Vec<PeerReport>,
+}
+
+/// Per-peer uptime, viewer-minutes, average bitrate and incidents as a
+/// downloadable JSON or CSV file. The history behind it is kept in memory
+/// only, so it covers the time since the server started; a CSV says whether
+/// it is truncated in the `X-Report-Truncated` header.
+pub async fn export_report(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let dropped_until = state
+        .history
+        .dropped_until()
+        .max(state.storage.events_dropped_until());
+    let truncated = dropped_until.is_some_and(|until| query.from.is_none_or(|from| from <= until));
+    let events = tenant::scope_events(None, state.storage.recent_events());
+    let peers: Vec<PeerReport> = state
+        .history
+        .report(query.from, query.to, &events)
+        .into_iter()
+        .filter(|report| tenant::local_name(None, &report.peer).is_some())
+        .collect();
+
+    match query.format {
+        ReportFormat::Json => (
+            [(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"media-report.json\"",
+            )],
+            Json(ReportResponse {
+                from: query.from,
+                to: query.to,
+                truncated,
+                peers,
+            }),
+        )
+            .into_response(),
+        ReportFormat::Csv => (
+            [
+                (header::CONTENT_TYPE, "text/csv"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"media-report.csv\"",
+                ),
+                (REPORT_TRUNCATED, if truncated { "true" } else { "false" }),
+            ],
+            history::to_csv(&peers),
+        )
+            .into_response(),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HealthResponse {
     pub status: String,
     pub sfu_id: String,
     pub publishers: usize,
     pub subscribers: usize,
+    pub avg_signalling_rtt_ms: Option<u64>,
+    /// Share of connected SFU sessions going through a TURN relay.
+    pub relay_ratio: Option<f64>,
 }
 
 pub async fn health(State(state): State<Arc<AppState>>) -> Json<HealthResponse> {
-    use sfu_core::Sfu;
+    let sessions = state.sfu.list_sessions().await.unwrap_or_default();
+    let connected: Vec<bool> = sessions.iter().filter_map(|info| info.relayed).collect();
+    let relay_ratio = (!connected.is_empty()).then(|| {
+        connected.iter().filter(|relayed| **relayed).count() as f64 / connected.len() as f64
+    });
 
     Json(HealthResponse {
         status: "ok".to_string(),
         sfu_id: state.sfu.id().to_string(),
         publishers: state.storage.get_all_statuses().len(),
-        subscribers: 0, // TODO: track subscribers in storage
+        subscribers: subscriber_count(&sessions),
+        avg_signalling_rtt_ms: state.storage.average_rtt_ms(),
+        relay_ratio,
+    })
+}
+
+fn subscriber_count(sessions: &[SessionInfo]) -> usize {
+    sessions
+        .iter()
+        .filter(|info| info.kind == SessionKind::Subscriber)
+        .count()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EndpointQuery {
+    pub region: Option<String>,
+    #[serde(default)]
+    pub role: ClientClass,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IceConfigQuery {
+    #[serde(default)]
+    pub role: ClientClass,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointResponse {
+    /// The server to connect to; `None` when this one isn't part of a
+    /// cluster, so clients stay on it.
+    pub endpoint: Option<String>,
+    pub region: Option<String>,
+    pub pc_config: JsonRtcConfiguration,
+}
+
+/// Picks the server a client should connect to, by load and the client's
+/// `region`, with that server's ICE servers for the client's `role`.
+/// Grabbers authenticate with `Authorization: Bearer <credential>`, as the
+/// ICE servers may include TURN credentials.
+pub async fn ice_and_endpoint(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<EndpointQuery>,
+) -> Result<Json<EndpointResponse>> {
+    check_role(&state, &headers, query.role)?;
+    let config = state.config.current();
+    let Some(cluster) = &config.cluster else {
+        return Ok(Json(EndpointResponse {
+            endpoint: None,
+            region: None,
+            pc_config: state.get_client_rtc_config(query.role),
+        }));
+    };
+
+    let sessions = state.sfu.list_sessions().await.unwrap_or_default();
+    let local_load = state.storage.get_all_statuses().len() + subscriber_count(&sessions);
+    let endpoint =
+        cluster::select_endpoint(cluster, &state.cluster, local_load, query.region.as_deref());
+    if endpoint.url != cluster.self_url {
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        match cluster::fetch_ice_config(&state.cluster, &endpoint.url, query.role, authorization)
+            .await
+        {
+            Ok(pc_config) => {
+                return Ok(Json(EndpointResponse {
+                    endpoint: Some(endpoint.url),
+                    region: endpoint.region,
+                    pc_config,
+                }))
+            }
+            Err(e) => warn!(
+                "Cluster node {} didn't give its ICE config, keeping the client here: {}",
+                endpoint.url, e
+            ),
+        }
+    }
+
+    Ok(Json(EndpointResponse {
+        endpoint: Some(cluster.self_url.clone()),
+        region: cluster.region.clone(),
+        pc_config: state.get_client_rtc_config(query.role),
+    }))
+}
+
+/// This server's ICE servers for clients of `role`, which other cluster
+/// nodes fetch for the clients they send here.
+pub async fn ice_config(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<IceConfigQuery>,
+) -> Result<Json<JsonRtcConfiguration>> {
+    check_role(&state, &headers, query.role)?;
+    Ok(Json(state.get_client_rtc_config(query.role)))
+}
+
+/// Grabbers get their ICE servers only with a valid grabber credential.
+fn check_role(state: &AppState, headers: &HeaderMap, role: ClientClass) -> Result<()> {
+    if role != ClientClass::Grabber {
+        return Ok(());
+    }
+    let credential = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if state
+        .config
+        .current()
+        .auth
+        .validate_grabber_credentials(credential)
+    {
+        Ok(())
+    } else {
+        Err(SignallingError::AuthenticationFailed(
+            "Invalid grabber credentials".to_string(),
+        ))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TenantHealthResponse {
+    pub tenant: String,
+    pub grabbers: usize,
+    pub grabbers_online: usize,
+    pub players: usize,
+}
+
+pub async fn tenant_health(
+    State(state): State<Arc<AppState>>,
+    Path(tenant): Path<String>,
+) -> Result<Json<TenantHealthResponse>> {
+    check_tenant(&state, &tenant)?;
+    let peers = tenant::scope_peers(Some(&tenant), state.storage.get_all_statuses());
+
+    Ok(Json(TenantHealthResponse {
+        grabbers: peers.len(),
+        grabbers_online: peers.iter().filter(|peer| peer.online).count(),
+        players: state.tenant_players.count(&tenant),
+        tenant,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GroupSummary {
+    pub name: String,
+    pub grabbers_online: usize,
+    pub grabbers_total: usize,
+    pub ingest_bitrate_bps: u64,
+    pub viewers: usize,
+    pub worst_quality_score: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupsResponse {
+    pub groups: Vec<GroupSummary>,
+}
+
+const UNGROUPED: &str = "ungrouped";
+
+pub async fn get_groups(State(state): State<Arc<AppState>>) -> Json<GroupsResponse> {
+    let config = state.config.current();
+    let mut groups: BTreeMap<String, GroupSummary> = config
+        .groups
+        .iter()
+        .map(|g| {
+            (
+                g.name.clone(),
+                GroupSummary {
+                    name: g.name.clone(),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+
+    for peer in tenant::scope_peers(None, state.storage.get_all_statuses()) {
+        let group_name = config.group_for(&peer.name).unwrap_or(UNGROUPED);
+        let summary = groups
+            .entry(group_name.to_string())
+            .or_insert_with(|| GroupSummary {
+                name: group_name.to_string(),
+                ..Default::default()
+            });
+
+        summary.grabbers_total += 1;
+        if !peer.online {
+            continue;
+        }
+        summary.grabbers_online += 1;
+
+        let quality = match state.sfu.get_publisher_stats(&peer.socket_id).await {
+            Ok(stats) => {
+                summary.ingest_bitrate_bps += stats.bitrate_bps;
+                summary.viewers += stats.subscriber_count;
+                stats.quality_score
+            }
+            // Online but not publishing yet counts as the worst possible quality.
+            Err(_) => 0.0,
+        };
+        summary.worst_quality_score = Some(
+            summary
+                .worst_quality_score
+                .map_or(quality, |worst| worst.min(quality)),
+        );
+    }
+
+    Json(GroupsResponse {
+        groups: groups.into_values().collect(),
     })
 }
@@ -1,138 +1,462 @@
-use axum::extract::ws::{Message, WebSocket};
+use axum::extract::ws::WebSocket;
 use axum::extract::{ConnectInfo, Path, State, WebSocketUpgrade};
-use axum::response::IntoResponse;
-use futures::StreamExt;
+use axum::response::{IntoResponse, Response};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tracing::{error, info, instrument, warn};
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
-use sfu_core::PublisherRequest;
+use sfu_core::{PublisherRequest, PublisherUpdateRequest};
 
+use super::{check_tenant, negotiate, receive_auth, record_pong, reject_auth, spawn_rtt_probe};
 use crate::error::{Result, SignallingError};
-use crate::protocol::{self, GrabberMessage};
-use crate::state::AppState;
-use crate::websocket::WsSession;
+use crate::liveness;
+use crate::protocol::{self, GrabberAuth, GrabberMessage};
+use crate::rate_limit::{message_limiter, RATE_LIMITED};
+use crate::state::{AppState, ClientClass};
+use crate::tenant;
+use crate::websocket::{WsSession, CBOR_PROTOCOL};
 
 pub async fn ws_grabber_handler(
     ws: WebSocketUpgrade,
     Path(name): Path<String>,
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| async move {
-        if let Err(e) = handle_grabber_connection(socket, addr, name, state).await {
-            error!("Grabber connection error from {}: {:?}", addr, e);
-        }
-    })
+) -> Result<Response> {
+    upgrade(ws, None, name, state, addr)
+}
+
+pub async fn ws_tenant_grabber_handler(
+    ws: WebSocketUpgrade,
+    Path((tenant, name)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Response> {
+    check_tenant(&state, &tenant)?;
+    upgrade(ws, Some(tenant), name, state, addr)
 }
 
+fn upgrade(
+    ws: WebSocketUpgrade,
+    tenant: Option<String>,
+    name: String,
+    state: Arc<AppState>,
+    addr: SocketAddr,
+) -> Result<Response> {
+    let name = tenant::qualify(tenant.as_deref(), &name).ok_or_else(|| {
+        SignallingError::InvalidMessageFormat(format!("Invalid grabber name '{}'", name))
+    })?;
+
+    Ok(ws
+        .protocols([CBOR_PROTOCOL])
+        .on_upgrade(move |socket| async move {
+            if let Err(e) = handle_grabber_connection(socket, addr, tenant, name, state).await {
+                error!("Grabber connection error from {}: {:?}", addr, e);
+            }
+        })
+        .into_response())
+}
+
+/// `name` is already qualified with `tenant`.
 #[instrument(skip(socket, state), fields(name = %name, ip = %addr))]
 async fn handle_grabber_connection(
     socket: WebSocket,
     addr: SocketAddr,
+    tenant: Option<String>,
     name: String,
     state: Arc<AppState>,
 ) -> Result<()> {
-    let session_id = format!("grabber-{}", addr);
     info!("Grabber connecting");
 
-    let (session, mut receiver) = WsSession::new(socket, session_id.clone());
+    let (mut session, mut receiver) = WsSession::new(
+        socket,
+        format!("grabber-{}", addr),
+        state.config.current().server.peer_liveness.socket_timeout(),
+    );
+
+    session.send_critical(&GrabberMessage::AuthRequest)?;
+
+    let auth_msg = receive_auth(&mut receiver).await?;
+
+    let Some(auth) = authenticate_grabber(&auth_msg, tenant.as_deref(), &state)? else {
+        return Err(reject_auth(
+            &session,
+            &GrabberMessage::AuthFailed {
+                access_message: "Invalid credentials".to_string(),
+            },
+        ));
+    };
+
+    if let Some(tenant) = tenant.as_deref() {
+        let max_grabbers = state
+            .config
+            .current()
+            .tenant(tenant)
+            .and_then(|t| t.limits.max_grabbers);
+        if max_grabbers
+            .is_some_and(|max| tenant::grabber_count(&state.storage, tenant, &name) >= max)
+        {
+            let _ = session.send_critical(&GrabberMessage::AuthFailed {
+                access_message: "Tenant grabber limit reached".to_string(),
+            });
+            let _ = session.close();
+            return Err(SignallingError::Forbidden(format!(
+                "Tenant '{}' grabber limit reached",
+                tenant
+            )));
+        }
+    }
+
+    // Reusing the id of a publisher retained after a recent disconnect lets
+    // the SFU resume it, keeping its viewers attached.
+    let resumed = state
+        .reconnecting
+        .resume(&name, auth.resume_token.as_deref());
+    if let Some(session_id) = &resumed {
+        session.id = session_id.clone();
+    } else if let Some(stale_id) = state.reconnecting.release(&name) {
+        info!("Grabber '{}' reconnected without its resume token", name);
+        liveness::remove_grabber(&state, &name, &stale_id).await;
+    }
+    let session_id = session.id.clone();
+    let resume_token = uuid::Uuid::new_v4().to_string();
 
     state.storage.add_peer(name.clone(), session_id.clone());
+    state.storage.register_session(&session);
+    let rtt_probe = spawn_rtt_probe(session.clone());
+    let ingest_report = spawn_ingest_report(session.clone(), Arc::clone(&state));
+    if resumed.is_some() {
+        info!("Grabber '{}' reconnected within the grace period", name);
+        state.storage.record_event(&name, "reconnected", None, None);
+    } else {
+        state.storage.record_event(&name, "connected", None, None);
+        state
+            .notifier
+            .notify("grabber.connected", &name, None, None);
+    }
 
-    session.send_json(&GrabberMessage {
-        event: "INIT_PEER".to_string(),
-        init_peer: Some(protocol::GrabberInitPeerMessage {
-            pc_config: state.get_client_rtc_config(),
-            ping_interval: 5000,
-        }),
-        ..Default::default()
+    session.send_critical(&GrabberMessage::InitPeer {
+        init_peer: protocol::GrabberInitPeerMessage {
+            pc_config: state.get_client_rtc_config(ClientClass::Grabber),
+            ping_interval: state.config.current().server.peer_liveness.ping_interval_ms,
+            resume_token: resume_token.clone(),
+            settings: state.storage.peer_settings(&name),
+        },
     })?;
 
     info!("Grabber '{}' initialized", name);
 
-    while let Some(result) = receiver.next().await {
-        match result {
-            Ok(Message::Text(text)) => {
-                if let Err(e) = handle_grabber_message(&session, &text, &state).await {
-                    warn!("Error processing grabber message: {}", e);
-                }
-            }
-            Ok(Message::Close(_)) => {
-                info!("Grabber closed connection");
-                break;
-            }
-            Ok(Message::Ping(_)) => {
-                let _ = session.send_text(format!("{{\"event\":\"PONG\"}}"));
-            }
+    let mut limiter = message_limiter(&state.config.current().server.rate_limit);
+    // Whether this socket has published; a publisher kept through a reconnect
+    // is bound to the previous socket's peer connection until then.
+    let mut published = false;
+
+    while let Some(result) = receiver.recv().await {
+        let text = match result {
+            Ok(text) => text,
             Err(e) => {
                 warn!("WebSocket error: {}", e);
+                let _ = session.close();
                 break;
             }
-            _ => {}
+        };
+        state.storage.touch(&session_id);
+
+        if limiter.as_mut().is_some_and(|l| !l.try_acquire()) {
+            warn!("Grabber message rate exceeded, dropping message");
+            let _ = session.send_json(&rate_limited(&text));
+            continue;
+        }
+        if let Err(e) = handle_grabber_message(&session, &name, &text, &state, &mut published).await
+        {
+            warn!("Error processing grabber message: {}", e);
+            if let SignallingError::InvalidMessageFormat(reason) = e {
+                let _ = session.send_json(&GrabberMessage::Error {
+                    error: protocol::GrabberErrorMessage {
+                        message: reason,
+                        context: None,
+                    },
+                });
+            }
         }
     }
 
     info!("Grabber '{}' disconnected", name);
-    state.storage.remove_peer_by_socket_id(&session_id);
-    let _ = state.sfu.remove_publisher(&session_id).await;
+    rtt_probe.abort();
+    ingest_report.abort();
+    state.storage.unregister_session(&session_id);
+
+    let grace = state
+        .config
+        .current()
+        .server
+        .peer_liveness
+        .reconnect_grace();
+    let publishing = matches!(state.sfu.get_session(&session_id).await, Ok(Some(_)));
+    if publishing && !grace.is_zero() {
+        liveness::retain_publisher(&state, name, session_id, resume_token, grace);
+    } else {
+        liveness::remove_grabber(&state, &name, &session_id).await;
+    }
 
     Ok(())
 }
 
-async fn handle_grabber_message(session: &WsSession, text: &str, state: &AppState) -> Result<()> {
+const INGEST_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Packet loss above this share marks the grabber's uplink as degraded.
+const DEGRADED_LOSS_RATIO: f64 = 0.05;
+
+/// Tells the grabber how its stream arrives at the SFU, so on-site staff can
+/// be warned about a poor uplink.
+fn spawn_ingest_report(session: WsSession, state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(INGEST_REPORT_INTERVAL);
+        let mut previous = (0, 0);
+        loop {
+            interval.tick().await;
+            // Not publishing yet, or between renegotiations.
+            let Ok(stats) = state.sfu.get_publisher_stats(&session.id).await else {
+                continue;
+            };
+
+            let received = stats.packets_received.saturating_sub(previous.0);
+            let lost = stats.packets_lost.saturating_sub(previous.1);
+            previous = (stats.packets_received, stats.packets_lost);
+            let loss_ratio = if received + lost == 0 {
+                0.0
+            } else {
+                lost as f64 / (received + lost) as f64
+            };
+
+            let report = GrabberMessage::IngestQuality {
+                ingest_quality: protocol::IngestQualityMessage {
+                    bitrate_bps: stats.bitrate_bps,
+                    target_bitrate_bps: stats.target_bitrate_bps,
+                    loss_ratio,
+                    quality_score: stats.quality_score,
+                    degraded: loss_ratio > DEGRADED_LOSS_RATIO,
+                    downstream_loss_ratio: stats.downstream_loss_ratio,
+                },
+            };
+            if session.send_status(&report).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// The grabber's `AUTH` data if its credentials are valid.
+fn authenticate_grabber(
+    text: &str,
+    tenant: Option<&str>,
+    state: &AppState,
+) -> Result<Option<GrabberAuth>> {
+    let grabber_msg: GrabberMessage = serde_json::from_str(text)
+        .map_err(|e| SignallingError::InvalidMessageFormat(e.to_string()))?;
+
+    let GrabberMessage::Auth { grabber_auth } = grabber_msg else {
+        return Ok(None);
+    };
+    Ok(grabber_auth.filter(|a| {
+        state
+            .config
+            .current()
+            .auth_for(tenant)
+            .is_some_and(|auth| auth.validate_grabber_credentials(&a.credential))
+    }))
+}
+
+async fn handle_grabber_message(
+    session: &WsSession,
+    name: &str,
+    text: &str,
+    state: &AppState,
+    published: &mut bool,
+) -> Result<()> {
     let msg: GrabberMessage = serde_json::from_str(text)
         .map_err(|e| SignallingError::InvalidMessageFormat(e.to_string()))?;
 
-    match msg.event.as_str() {
-        "PING" => handle_ping(session, msg, state),
-        "OFFER" | "OFFER_ANSWER" => handle_publisher_offer(session, msg, state).await,
-        "GRABBER_ICE" => handle_grabber_ice(session, msg, state).await,
-        _ => {
-            warn!("Unknown grabber event: {}", msg.event);
+    match msg {
+        GrabberMessage::Ping { ping } => handle_ping(session, ping, state),
+        GrabberMessage::Pong { ping } => {
+            record_pong(&state.storage, &session.id, ping);
             Ok(())
         }
+        GrabberMessage::Error { error } => handle_grabber_error(name, error, state),
+        GrabberMessage::Offer { offer } | GrabberMessage::OfferAnswer { offer } => {
+            handle_publisher_offer(session, name, offer, state, published).await
+        }
+        GrabberMessage::GrabberIce { ice } => handle_grabber_ice(session, ice, state).await,
+        GrabberMessage::TrackMetadata { track_metadata } => {
+            handle_track_metadata(session, track_metadata, state).await
+        }
+        GrabberMessage::PublisherState { publisher_state } => {
+            handle_publisher_state(session, name, publisher_state, state)
+        }
+        msg => Err(SignallingError::InvalidMessageFormat(format!(
+            "Unexpected grabber event {}",
+            protocol::event_name(&msg)
+        ))),
     }
 }
 
-fn handle_ping(session: &WsSession, msg: GrabberMessage, state: &AppState) -> Result<()> {
-    if let Some(ping) = msg.ping {
-        state.storage.update_ping(
-            &session.id,
-            ping.connections_count.unwrap_or(0),
-            ping.stream_types.unwrap_or_default(),
-        );
+/// The reply to a message dropped over the rate limit. A dropped candidate
+/// comes back as an `ICE_ERROR`, so the grabber can send it again.
+fn rate_limited(text: &str) -> GrabberMessage {
+    match serde_json::from_str::<GrabberMessage>(text) {
+        Ok(GrabberMessage::GrabberIce { ice }) => GrabberMessage::IceError {
+            ice_error: protocol::IceErrorMessage {
+                candidate: ice.candidate,
+                reason: RATE_LIMITED.to_string(),
+                peer_id: None,
+            },
+        },
+        _ => GrabberMessage::Error {
+            error: protocol::GrabberErrorMessage {
+                message: RATE_LIMITED.to_string(),
+                context: None,
+            },
+        },
     }
+}
+
+fn handle_ping(
+    session: &WsSession,
+    ping: Option<protocol::PingMessage>,
+    state: &AppState,
+) -> Result<()> {
+    let Some(ping) = ping else {
+        return Ok(());
+    };
+
+    state.storage.update_ping(
+        &session.id,
+        ping.connections_count.unwrap_or(0),
+        ping.stream_types.clone().unwrap_or_default(),
+        ping.pipeline.clone(),
+    );
+
+    // Echo the timestamp so the grabber can measure RTT from its side too.
+    session.send_json(&GrabberMessage::Pong {
+        ping: Some(protocol::PingMessage {
+            timestamp: ping.timestamp,
+            connections_count: None,
+            stream_types: None,
+            pipeline: None,
+        }),
+    })
+}
+
+fn handle_grabber_error(
+    name: &str,
+    err: protocol::GrabberErrorMessage,
+    state: &AppState,
+) -> Result<()> {
+    warn!(
+        "Grabber '{}' reported error: {} (context: {})",
+        name,
+        err.message,
+        err.context.as_deref().unwrap_or("none")
+    );
+    state.notifier.notify(
+        "grabber.error",
+        name,
+        Some(err.message.clone()),
+        err.context.clone(),
+    );
+    state
+        .storage
+        .record_event(name, "error", Some(err.message), err.context);
     Ok(())
 }
 
-async fn handle_publisher_offer(
+async fn handle_track_metadata(
+    session: &WsSession,
+    tracks: Vec<protocol::TrackMetadata>,
+    state: &AppState,
+) -> Result<()> {
+    state
+        .storage
+        .set_track_metadata(&session.id, tracks.clone());
+    // Before the first offer there is no publisher yet; the labels are
+    // applied from storage once it is added.
+    if let Err(e) = state
+        .sfu
+        .set_publisher_track_metadata(&session.id, tracks.into_iter().map(Into::into).collect())
+        .await
+    {
+        warn!("Failed to store track metadata for '{}': {}", session.id, e);
+    }
+    Ok(())
+}
+
+fn handle_publisher_state(
     session: &WsSession,
-    msg: GrabberMessage,
+    name: &str,
+    publisher_state: protocol::PublisherStateMessage,
     state: &AppState,
 ) -> Result<()> {
-    let offer_data = msg
-        .offer
-        .or(msg.answer)
-        .ok_or_else(|| SignallingError::InvalidMessageFormat("Missing offer data".to_string()))?;
+    let (kind, reason) = if publisher_state.paused {
+        // Paused without a reason still has to read as paused.
+        let reason = publisher_state
+            .reason
+            .unwrap_or_else(|| "paused".to_string());
+        ("paused", Some(reason))
+    } else {
+        ("resumed", None)
+    };
+    info!("Grabber '{}' {} publishing", name, kind);
+    state.storage.set_paused(&session.id, reason.clone());
+    state.storage.record_event(name, kind, reason, None);
+    Ok(())
+}
 
+#[instrument(skip_all, fields(socket_id = %session.id))]
+async fn handle_publisher_offer(
+    session: &WsSession,
+    name: &str,
+    offer_data: protocol::OfferMessage,
+    state: &AppState,
+    published: &mut bool,
+) -> Result<()> {
     let offer = RTCSessionDescription::offer(offer_data.sdp)
         .map_err(|e| SignallingError::InvalidMessageFormat(format!("Invalid SDP offer: {}", e)))?;
 
+    // A grabber that swaps its camera or screen, or restarts ICE after a
+    // network change, renegotiates on the existing peer connection so
+    // subscribers stay attached. An offer from a brand new
+    // peer connection can't be applied there, so fall back to replacing it.
+    if *published && state.sfu.get_session(&session.id).await?.is_some() {
+        let req = PublisherUpdateRequest {
+            publisher_id: session.id.clone(),
+            offer: offer.clone(),
+        };
+        match negotiate(state, state.sfu.update_publisher(req)).await {
+            Ok(res) => {
+                send_answer(session, res.answer)?;
+                info!("Publisher '{}' renegotiated", session.id);
+                return Ok(());
+            }
+            Err(e) => warn!(
+                "Renegotiating publisher '{}' failed, recreating it: {}",
+                session.id, e
+            ),
+        }
+    }
+
     let (ice_tx, mut ice_rx) = mpsc::unbounded_channel();
     let session_for_ice = session.clone();
 
     tokio::spawn(async move {
         while let Some(candidate) = ice_rx.recv().await {
-            let _ = session_for_ice.send_json(&GrabberMessage {
-                event: "SERVER_ICE".to_string(),
-                ice: Some(protocol::IceMessage {
+            let _ = session_for_ice.send_json(&GrabberMessage::ServerIce {
+                ice: protocol::IceMessage {
                     candidate,
                     peer_id: None,
-                }),
-                ..Default::default()
+                },
             });
         }
     });
@@ -144,47 +468,75 @@ async fn handle_publisher_offer(
         ice_candidate_tx: Some(ice_tx),
     };
 
-    match state.sfu.add_publisher(req).await {
+    match negotiate(state, state.sfu.add_publisher(req)).await {
         Ok(res) => {
-            session.send_json(&GrabberMessage {
-                event: "ANSWER".to_string(),
-                answer: Some(protocol::OfferMessage {
-                    type_: "answer".to_string(),
-                    sdp: res.answer.sdp,
-                    peer_id: None,
-                    peer_name: None,
-                    stream_type: None,
-                }),
-                ..Default::default()
-            })?;
+            *published = true;
+            send_answer(session, res.answer)?;
+            let tracks = state
+                .storage
+                .get_peer_by_name(name)
+                .map(|peer| peer.tracks)
+                .unwrap_or_default();
+            if !tracks.is_empty() {
+                let tracks = tracks.into_iter().map(Into::into).collect();
+                let _ = state
+                    .sfu
+                    .set_publisher_track_metadata(&session.id, tracks)
+                    .await;
+            }
+            let audio_disabled = state.storage.peer_settings(name).audio_disabled;
+            let _ = state
+                .sfu
+                .set_publisher_audio_muted(&session.id, audio_disabled)
+                .await;
             info!("Publisher '{}' added successfully", session.id);
             Ok(())
         }
         Err(e) => {
             error!("SFU add publisher error: {}", e);
-            session.send_json(&GrabberMessage {
-                event: "OFFER_FAILED".to_string(),
-                ..Default::default()
-            })?;
+            state
+                .notifier
+                .notify("publisher.failed", name, Some(e.to_string()), None);
+            session.send_json(&GrabberMessage::OfferFailed)?;
             Err(SignallingError::SfuError(e))
         }
     }
 }
 
+fn send_answer(session: &WsSession, answer: RTCSessionDescription) -> Result<()> {
+    session.send_critical(&GrabberMessage::Answer {
+        answer: protocol::OfferMessage {
+            type_: "answer".to_string(),
+            sdp: answer.sdp,
+            peer_id: None,
+            peer_name: None,
+            stream_type: None,
+            sync_group: None,
+        },
+    })
+}
+
+#[instrument(skip_all, fields(socket_id = %session.id))]
 async fn handle_grabber_ice(
     session: &WsSession,
-    msg: GrabberMessage,
+    ice_msg: protocol::IceMessage,
     state: &AppState,
 ) -> Result<()> {
-    let ice_msg = msg
-        .ice
-        .ok_or_else(|| SignallingError::InvalidMessageFormat("Missing ICE data".to_string()))?;
-
-    state
+    if let Err(e) = state
         .sfu
-        .add_publisher_ice(&session.id, ice_msg.candidate)
+        .add_publisher_ice(&session.id, ice_msg.candidate.clone())
         .await
-        .map_err(SignallingError::SfuError)?;
+    {
+        warn!("Rejected grabber ICE candidate: {:#}", e);
+        state.storage.record_ice_error(&session.id);
+        session.send_json(&GrabberMessage::IceError {
+            ice_error: protocol::IceErrorMessage {
+                candidate: ice_msg.candidate,
+                reason: format!("{:#}", e),
+                peer_id: None,
+            },
+        })?;
+    }
 
     Ok(())
 }
@@ -1,7 +1,91 @@
+pub mod admin;
 pub mod api;
 pub mod grabber;
 pub mod player;
+pub mod poll;
 
-pub use api::{get_peers, health};
+pub use api::{get_events, get_groups, get_peers, health};
 pub use grabber::ws_grabber_handler;
 pub use player::ws_player_handler;
+
+use serde::Serialize;
+use serde_json::json;
+use sfu_local::error::SfuError;
+use std::future::Future;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::trace;
+
+use crate::error::{Result, SignallingError};
+use crate::protocol::PingMessage;
+use crate::state::AppState;
+use crate::storage::Storage;
+use crate::websocket::{WsReceiver, WsSession};
+
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+const RTT_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Waits for the client's reply to `AUTH_REQUEST`.
+async fn receive_auth(receiver: &mut WsReceiver) -> Result<String> {
+    tokio::time::timeout(AUTH_TIMEOUT, receiver.recv())
+        .await
+        .map_err(|_| SignallingError::Timeout("Authentication timeout".to_string()))?
+        .ok_or_else(|| SignallingError::SessionError("Connection closed during auth".to_string()))?
+}
+
+/// Sends the `AUTH_FAILED` message and closes the socket.
+fn reject_auth<M: Serialize>(session: &WsSession, auth_failed: &M) -> SignallingError {
+    let _ = session.send_critical(auth_failed);
+    let _ = session.close();
+    SignallingError::AuthenticationFailed("Invalid credentials".to_string())
+}
+
+/// Sends a timestamped `PING` every few seconds; clients echo it in a `PONG`
+/// so [`record_pong`] can measure signalling round-trip time.
+fn spawn_rtt_probe(session: WsSession) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RTT_PROBE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let ping = json!({
+                "event": "PING",
+                "ping": { "timestamp": chrono::Utc::now().timestamp_millis() },
+            });
+            if session.send_json(&ping).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+fn check_tenant(state: &AppState, tenant: &str) -> Result<()> {
+    match state.config.current().tenant(tenant) {
+        Some(_) => Ok(()),
+        None => Err(SignallingError::TenantNotFound(tenant.to_string())),
+    }
+}
+
+/// Runs an SFU negotiation, failing it if it outlasts
+/// `server.negotiation.timeout_ms` so the client gets OFFER_FAILED instead
+/// of waiting forever. The SFU closes the connection and detaches the tracks
+/// of a negotiation dropped this way.
+async fn negotiate<T>(
+    state: &AppState,
+    negotiation: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let timeout = state.config.current().server.negotiation.timeout();
+    tokio::time::timeout(timeout, negotiation)
+        .await
+        .unwrap_or_else(|_| {
+            Err(SfuError::NegotiationTimeout(format!("no answer within {:?}", timeout)).into())
+        })
+}
+
+fn record_pong(storage: &Storage, session_id: &str, ping: Option<PingMessage>) {
+    let Some(ping) = ping else {
+        return;
+    };
+    let rtt_ms = (chrono::Utc::now().timestamp_millis() - ping.timestamp).max(0) as u64;
+    trace!("Signalling RTT for {}: {} ms", session_id, rtt_ms);
+    storage.record_rtt(session_id, rtt_ms);
+}
@@ -1,224 +1,818 @@
-use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{ConnectInfo, State, WebSocketUpgrade};
-use axum::response::IntoResponse;
-use futures::StreamExt;
+use axum::extract::ws::WebSocket;
+use axum::extract::{ConnectInfo, Path, State, WebSocketUpgrade};
+use axum::response::{IntoResponse, Response};
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 use tracing::{error, info, instrument, warn};
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
-use sfu_core::SubscriberRequest;
+use sfu_core::{IceCandidateSender, RenegotiationSender, SubscriberRequest, SubscriberResponse};
+use sfu_local::error::SfuError;
 
+use super::{check_tenant, negotiate, receive_auth, record_pong, reject_auth, spawn_rtt_probe};
 use crate::error::{Result, SignallingError};
+use crate::peer_feed::PeersUpdate;
 use crate::protocol::{self, PlayerMessage};
-use crate::state::AppState;
-use crate::websocket::WsSession;
+use crate::rate_limit::{message_limiter, RATE_LIMITED};
+use crate::state::{AppState, ClientClass};
+use crate::tenant;
+use crate::websocket::{WsReceiver, WsSession, CBOR_PROTOCOL};
 
 pub async fn ws_player_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| async move {
-        if let Err(e) = handle_player_connection(socket, addr, state).await {
-            error!("Player connection error from {}: {:?}", addr, e);
-        }
-    })
+    upgrade(ws, None, state, addr)
+}
+
+pub async fn ws_tenant_player_handler(
+    ws: WebSocketUpgrade,
+    Path(tenant): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Response> {
+    check_tenant(&state, &tenant)?;
+    Ok(upgrade(ws, Some(tenant), state, addr))
+}
+
+fn upgrade(
+    ws: WebSocketUpgrade,
+    tenant: Option<String>,
+    state: Arc<AppState>,
+    addr: SocketAddr,
+) -> Response {
+    ws.protocols([CBOR_PROTOCOL])
+        .on_upgrade(move |socket| async move {
+            if let Err(e) = handle_player_connection(socket, addr, tenant, state).await {
+                error!("Player connection error from {}: {:?}", addr, e);
+            }
+        })
+        .into_response()
 }
 
-#[instrument(skip(socket, state), fields(ip = %addr))]
 async fn handle_player_connection(
     socket: WebSocket,
     addr: SocketAddr,
+    tenant: Option<String>,
     state: Arc<AppState>,
 ) -> Result<()> {
-    let session_id = format!("player-{}", addr);
+    let (session, receiver) = WsSession::new(
+        socket,
+        format!("player-{}", addr),
+        state.config.current().server.peer_liveness.socket_timeout(),
+    );
+    run_player(session, receiver, addr, tenant, state).await
+}
+
+/// Runs the player protocol over any transport, from `AUTH_REQUEST` until
+/// the client leaves.
+#[instrument(skip(session, receiver, state), fields(ip = %addr))]
+pub(super) async fn run_player(
+    session: WsSession,
+    mut receiver: WsReceiver,
+    addr: SocketAddr,
+    tenant: Option<String>,
+    state: Arc<AppState>,
+) -> Result<()> {
+    let tenant = tenant.as_deref();
+    let session_id = session.id.clone();
     info!("Player connecting");
 
-    let (session, mut receiver) = WsSession::new(socket, session_id.clone());
+    session.send_critical(&PlayerMessage::AuthRequest)?;
 
-    session.send_json(&PlayerMessage {
-        event: "AUTH_REQUEST".to_string(),
-        ..Default::default()
-    })?;
+    let auth_msg = receive_auth(&mut receiver).await?;
 
-    let auth_msg = tokio::time::timeout(Duration::from_secs(10), receiver.next())
-        .await
-        .map_err(|_| SignallingError::Timeout("Authentication timeout".to_string()))?
-        .ok_or_else(|| SignallingError::SessionError("Connection closed during auth".to_string()))?
-        .map_err(|e| SignallingError::WebSocket(format!("WebSocket error: {}", e)))?;
-
-    if !authenticate_player(&auth_msg, &state)? {
-        session.send_json(&PlayerMessage {
-            event: "AUTH_FAILED".to_string(),
-            access_message: Some("Invalid credentials".to_string()),
-            ..Default::default()
-        })?;
-        return Err(SignallingError::AuthenticationFailed(
-            "Invalid credentials".to_string(),
+    let Some(credential) = authenticate_player(&auth_msg, tenant, &state)? else {
+        return Err(reject_auth(
+            &session,
+            &PlayerMessage::AuthFailed {
+                access_message: "Invalid credentials".to_string(),
+            },
         ));
-    }
+    };
+
+    let _slot = match tenant {
+        Some(tenant) => {
+            let max_players = state
+                .config
+                .current()
+                .tenant(tenant)
+                .and_then(|t| t.limits.max_players);
+            let Some(slot) = state.tenant_players.join(tenant, max_players) else {
+                let _ = session.send_critical(&PlayerMessage::AuthFailed {
+                    access_message: "Tenant player limit reached".to_string(),
+                });
+                let _ = session.close();
+                return Err(SignallingError::Forbidden(format!(
+                    "Tenant '{}' player limit reached",
+                    tenant
+                )));
+            };
+            Some(slot)
+        }
+        None => None,
+    };
+
+    let mut lifetime = SessionLifetime::new(max_session_duration(&state, tenant, &credential));
 
-    session.send_json(&PlayerMessage {
-        event: "INIT_PEER".to_string(),
-        init_peer: Some(protocol::PcConfigMessage {
-            pc_config: state.get_client_rtc_config(),
-        }),
-        ..Default::default()
+    session.send_critical(&PlayerMessage::InitPeer {
+        init_peer: protocol::PcConfigMessage {
+            pc_config: state.get_client_rtc_config(ClientClass::Player),
+        },
+        session_expiry: lifetime.expiry_message(),
     })?;
 
+    let (peers_status, mut peer_updates) = state.peer_feed.subscribe();
+    let mut speakers = state.speakers.subscribe();
+    let mut notices = state.notices.subscribe();
+    session.send_status(&peers_status_message(tenant::scope_update(
+        tenant,
+        peers_status,
+    )))?;
+
+    state.storage.register_session(&session);
+    let rtt_probe = spawn_rtt_probe(session.clone());
     info!("Player authenticated and initialized");
 
-    while let Some(result) = receiver.next().await {
-        match result {
-            Ok(Message::Text(text)) => {
-                if let Err(e) = handle_player_message(&session, &text, &state).await {
-                    warn!("Error processing player message: {}", e);
+    let mut limiter = message_limiter(&state.config.current().server.rate_limit);
+    let mut subscriptions = Subscriptions::default();
+
+    loop {
+        let result = tokio::select! {
+            result = receiver.recv() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            _ = sleep_until(lifetime.next_deadline()) => {
+                if lifetime.notified {
+                    info!("Player session expired");
+                    let _ = session.send_critical(&PlayerMessage::SessionExpired);
+                    let _ = session.close();
+                    break;
                 }
+                lifetime.notified = true;
+                session.send_json(&PlayerMessage::SessionExpiring {
+                    session_expiry: lifetime.expiry_message(),
+                })?;
+                continue;
             }
-            Ok(Message::Close(_)) => {
-                info!("Player closed connection");
-                break;
+            update = peer_updates.recv() => {
+                let update = match update {
+                    // A queued update about to be replaced may be a delta the
+                    // client needs, so replace it with the full list.
+                    Ok(_) if session.status_pending() => state.peer_feed.snapshot(),
+                    Ok(update) => update,
+                    Err(broadcast::error::RecvError::Lagged(_)) => state.peer_feed.snapshot(),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                session.send_status(&peers_status_message(tenant::scope_update(tenant, update)))?;
+                continue;
             }
-            Ok(Message::Ping(data)) => {
-                let _ = session.send_text(format!("{{\"event\":\"PONG\"}}"));
+            speaker = speakers.recv() => {
+                match speaker {
+                    Ok(mut speaker) => {
+                        if let Some(name) = tenant::local_name(tenant, &speaker.peer_name) {
+                            speaker.peer_name = name.to_string();
+                            session.send_json(&PlayerMessage::ActiveSpeaker {
+                                active_speaker: speaker,
+                            })?;
+                        }
+                    }
+                    // Missed changes still reach the client as `speaking` in
+                    // the peer statuses.
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                continue;
+            }
+            notice = notices.recv() => {
+                match notice {
+                    Ok(mut notice) => {
+                        if let Some(name) = tenant::local_name(tenant, &notice.peer_name) {
+                            notice.peer_name = name.to_string();
+                            session.send_json(&PlayerMessage::Notice { notice })?;
+                        }
+                    }
+                    // The next countdown notice makes up for a missed one.
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                continue;
             }
+            Some(retried) = subscriptions.retrying.join_next() => {
+                match retried {
+                    Ok(subscribed) => {
+                        if let Err(e) =
+                            reply_to_subscribe(&session, &mut subscriptions.active, subscribed, &state)
+                                .await
+                        {
+                            warn!("Error retrying a subscribe offer: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("Subscribe retry failed: {}", e),
+                }
+                continue;
+            }
+        };
+
+        let text = match result {
+            Ok(text) => text,
             Err(e) => {
                 warn!("WebSocket error: {}", e);
+                let _ = session.close();
                 break;
             }
-            _ => {}
+        };
+
+        if limiter.as_mut().is_some_and(|l| !l.try_acquire()) {
+            warn!("Player message rate exceeded, dropping message");
+            let _ = session.send_json(&rate_limited(&text));
+            continue;
+        }
+        if let Err(e) = handle_player_message(
+            &session,
+            tenant,
+            &credential,
+            &mut lifetime,
+            &mut subscriptions,
+            &text,
+            &state,
+        )
+        .await
+        {
+            warn!("Error processing player message: {}", e);
+            if let SignallingError::InvalidMessageFormat(reason) = e {
+                let _ = session.send_json(&PlayerMessage::Error {
+                    error: protocol::ErrorMessage { message: reason },
+                });
+            }
         }
     }
 
     info!("Player disconnected");
-    let _ = state.sfu.remove_subscriber(&session_id).await;
+    rtt_probe.abort();
+    state.storage.unregister_session(&session_id);
+    // A retry dropped mid-negotiation is cleaned up by the SFU; one that
+    // already subscribed is removed with the rest.
+    subscriptions.retrying.abort_all();
+    while let Some(retried) = subscriptions.retrying.join_next().await {
+        if let Ok(Subscribed {
+            subscriber_id,
+            result: Ok(_),
+            ..
+        }) = retried
+        {
+            subscriptions.active.insert(subscriber_id);
+        }
+    }
+    for subscriber_id in subscriptions.active {
+        let _ = state.sfu.remove_subscriber(&subscriber_id).await;
+    }
 
     Ok(())
 }
 
-fn authenticate_player(msg: &Message, state: &AppState) -> Result<bool> {
-    let Message::Text(text) = msg else {
-        return Ok(false);
-    };
-
+fn authenticate_player(
+    text: &str,
+    tenant: Option<&str>,
+    state: &AppState,
+) -> Result<Option<String>> {
     let player_msg: PlayerMessage = serde_json::from_str(text)
         .map_err(|e| SignallingError::InvalidMessageFormat(e.to_string()))?;
 
-    Ok(player_msg.event == "AUTH"
-        && player_msg
-            .player_auth
-            .map(|a| state.config.validate_credentials(&a.credential))
-            .unwrap_or(false))
+    let PlayerMessage::Auth { player_auth } = player_msg else {
+        return Ok(None);
+    };
+
+    Ok(player_auth.map(|a| a.credential).filter(|c| {
+        state
+            .config
+            .current()
+            .auth_for(tenant)
+            .is_some_and(|auth| auth.validate_credentials(c))
+    }))
 }
 
-async fn handle_player_message(session: &WsSession, text: &str, state: &AppState) -> Result<()> {
+fn max_session_duration(
+    state: &AppState,
+    tenant: Option<&str>,
+    credential: &str,
+) -> Option<Duration> {
+    state
+        .config
+        .current()
+        .auth_for(tenant)
+        .and_then(|auth| auth.max_session_duration(credential))
+}
+
+/// The reply to a message dropped over the rate limit. A dropped candidate
+/// comes back as an `ICE_ERROR`, so the player can send it again.
+fn rate_limited(text: &str) -> PlayerMessage {
+    match serde_json::from_str::<PlayerMessage>(text) {
+        Ok(PlayerMessage::PlayerIce { ice }) => PlayerMessage::IceError {
+            ice_error: protocol::IceErrorMessage {
+                candidate: ice.candidate,
+                reason: RATE_LIMITED.to_string(),
+                peer_id: ice.peer_id,
+            },
+        },
+        _ => PlayerMessage::Error {
+            error: protocol::ErrorMessage {
+                message: RATE_LIMITED.to_string(),
+            },
+        },
+    }
+}
+
+async fn handle_player_message(
+    session: &WsSession,
+    tenant: Option<&str>,
+    credential: &str,
+    lifetime: &mut SessionLifetime,
+    subscriptions: &mut Subscriptions,
+    text: &str,
+    state: &Arc<AppState>,
+) -> Result<()> {
     let msg: PlayerMessage = serde_json::from_str(text)
         .map_err(|e| SignallingError::InvalidMessageFormat(e.to_string()))?;
 
-    match msg.event.as_str() {
-        "OFFER" => handle_subscribe_offer(session, msg, state).await,
-        "PLAYER_ICE" => handle_player_ice(session, msg, state).await,
-        "PING" => {
-            session.send_json(&PlayerMessage {
-                event: "PONG".to_string(),
-                ..Default::default()
-            })?;
+    match msg {
+        PlayerMessage::Offer { offer } => {
+            handle_subscribe_offer(session, tenant, credential, subscriptions, offer, state).await
+        }
+        PlayerMessage::PlayerIce { ice } => handle_player_ice(session, ice, state).await,
+        PlayerMessage::PauseTrack { track } => {
+            handle_track_control(session, track, true, state).await
+        }
+        PlayerMessage::ResumeTrack { track } => {
+            handle_track_control(session, track, false, state).await
+        }
+        PlayerMessage::RenegotiateAnswer { offer } => {
+            handle_renegotiate_answer(session, offer, state).await
+        }
+        PlayerMessage::Renew => handle_renew(session, tenant, credential, lifetime, state),
+        PlayerMessage::PeersStatus { .. } => session.send_status(&peers_status_message(
+            tenant::scope_update(tenant, state.peer_feed.snapshot()),
+        )),
+        PlayerMessage::Ping { ping } => {
+            session.send_json(&PlayerMessage::Pong { ping })?;
             Ok(())
         }
-        _ => {
-            warn!("Unknown player event: {}", msg.event);
+        PlayerMessage::Pong { ping } => {
+            record_pong(&state.storage, &session.id, ping);
             Ok(())
         }
+        msg => Err(SignallingError::InvalidMessageFormat(format!(
+            "Unexpected player event {}",
+            protocol::event_name(&msg)
+        ))),
     }
 }
 
-async fn handle_subscribe_offer(
+fn peers_status_message(update: PeersUpdate) -> PlayerMessage {
+    match update {
+        PeersUpdate::Full { seq, peers } => PlayerMessage::PeersStatus {
+            peers_status: peers,
+            peers_status_seq: seq,
+        },
+        PeersUpdate::Delta(delta) => PlayerMessage::PeersStatusDelta {
+            peers_status_delta: delta,
+        },
+    }
+}
+
+/// Re-checks the credential against the current config, so revoking it (and
+/// reloading) stops further renewals.
+fn handle_renew(
     session: &WsSession,
-    msg: PlayerMessage,
+    tenant: Option<&str>,
+    credential: &str,
+    lifetime: &mut SessionLifetime,
     state: &AppState,
 ) -> Result<()> {
-    let offer_data = msg
-        .offer
-        .ok_or_else(|| SignallingError::InvalidMessageFormat("Missing offer data".to_string()))?;
+    let config = state.config.current();
+    let Some(auth) = config
+        .auth_for(tenant)
+        .filter(|auth| auth.validate_credentials(credential))
+    else {
+        session.send_json(&PlayerMessage::RenewFailed {
+            access_message: "Credential is no longer valid".to_string(),
+            session_expiry: lifetime.expiry_message(),
+        })?;
+        return Ok(());
+    };
+
+    lifetime.renew(auth.max_session_duration(credential));
+    info!("Player session renewed");
+
+    session.send_json(&PlayerMessage::SessionRenewed {
+        session_expiry: lifetime.expiry_message(),
+    })
+}
+
+/// How long before expiry players are asked to renew.
+const RENEWAL_NOTICE: Duration = Duration::from_secs(60);
+
+/// Expiry of a time-limited player session. Players get `SESSION_EXPIRING`
+/// shortly before the deadline and are disconnected unless they `RENEW`.
+struct SessionLifetime {
+    expires_at: Option<Instant>,
+    notice: Duration,
+    notified: bool,
+}
+
+impl SessionLifetime {
+    fn new(max_duration: Option<Duration>) -> Self {
+        let mut lifetime = Self {
+            expires_at: None,
+            notice: RENEWAL_NOTICE,
+            notified: false,
+        };
+        lifetime.renew(max_duration);
+        lifetime
+    }
+
+    fn renew(&mut self, max_duration: Option<Duration>) {
+        self.expires_at = max_duration.map(|d| Instant::now() + d);
+        self.notice = max_duration.map_or(RENEWAL_NOTICE, |d| RENEWAL_NOTICE.min(d / 2));
+        self.notified = false;
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let expires_at = self.expires_at?;
+        if self.notified {
+            Some(expires_at)
+        } else {
+            Some(expires_at - self.notice)
+        }
+    }
+
+    fn expiry_message(&self) -> Option<protocol::SessionExpiryMessage> {
+        self.expires_at.map(|at| protocol::SessionExpiryMessage {
+            expires_in_ms: at.saturating_duration_since(Instant::now()).as_millis() as u64,
+        })
+    }
+}
 
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[instrument(skip_all, fields(socket_id = %session.id))]
+async fn handle_subscribe_offer(
+    session: &WsSession,
+    tenant: Option<&str>,
+    credential: &str,
+    subscriptions: &mut Subscriptions,
+    offer_data: protocol::OfferMessage,
+    state: &Arc<AppState>,
+) -> Result<()> {
     let target_peer = offer_data
         .peer_name
         .ok_or_else(|| SignallingError::InvalidMessageFormat("Missing peer_name".to_string()))?;
 
-    let peer_status = state
-        .storage
-        .get_peer_by_name(&target_peer)
-        .ok_or_else(|| SignallingError::PeerNotFound(target_peer.clone()))?;
+    let subscription = offer_data.peer_id;
+    let config = state.config.current();
+    let options = SubscribeOptions {
+        stream_type: offer_data.stream_type,
+        sync_group: offer_data
+            .sync_group
+            .and_then(|group| tenant::qualify(tenant, &group)),
+        upstream: config
+            .auth_for(tenant)
+            .is_some_and(|auth| auth.is_operator(credential)),
+    };
+    let subscriber_id = subscriber_id(&session.id, subscription.as_deref());
+
+    let peer_key = tenant::qualify(tenant, &target_peer).filter(|_| {
+        config
+            .auth_for(tenant)
+            .is_some_and(|auth| auth.is_peer_allowed(credential, &target_peer))
+    });
+    let Some(peer_key) = peer_key else {
+        session.send_json(&PlayerMessage::OfferFailed {
+            offer_failed: protocol::OfferFailedMessage {
+                reason: format!("Access to '{}' denied", target_peer),
+                retryable: false,
+                retry_after_ms: None,
+                peer_id: subscription,
+            },
+        })?;
+        return Err(SignallingError::Forbidden(target_peer));
+    };
 
     let offer = RTCSessionDescription::offer(offer_data.sdp)
         .map_err(|e| SignallingError::InvalidMessageFormat(format!("Invalid SDP offer: {}", e)))?;
 
     let (ice_tx, mut ice_rx) = mpsc::unbounded_channel();
     let session_for_ice = session.clone();
+    let subscription_for_ice = subscription.clone();
 
     tokio::spawn(async move {
         while let Some(candidate) = ice_rx.recv().await {
-            let _ = session_for_ice.send_json(&PlayerMessage {
-                event: "SERVER_ICE".to_string(),
-                ice: Some(protocol::IceMessage {
+            let _ = session_for_ice.send_json(&PlayerMessage::ServerIce {
+                ice: protocol::IceMessage {
                     candidate,
-                    peer_id: None,
-                }),
-                ..Default::default()
+                    peer_id: subscription_for_ice.clone(),
+                },
             });
         }
     });
 
-    let req = SubscriberRequest {
-        subscriber_id: session.id.clone(),
-        publisher_id: peer_status.socket_id,
-        offer,
-        ice_candidate_tx: Some(ice_tx),
+    let (renegotiation_tx, mut renegotiation_rx) = mpsc::unbounded_channel();
+    let session_for_renegotiation = session.clone();
+    let peer_for_renegotiation = target_peer.clone();
+    let subscription_for_renegotiation = subscription.clone();
+
+    tokio::spawn(async move {
+        while let Some(offer) = renegotiation_rx.recv().await {
+            let _ = session_for_renegotiation.send_critical(&PlayerMessage::Renegotiate {
+                offer: protocol::OfferMessage {
+                    type_: "offer".to_string(),
+                    sdp: offer.sdp,
+                    peer_id: subscription_for_renegotiation.clone(),
+                    peer_name: Some(peer_for_renegotiation.clone()),
+                    stream_type: None,
+                    sync_group: None,
+                },
+            });
+        }
+    });
+
+    let result = try_subscribe(
+        state,
+        &subscriber_id,
+        &peer_key,
+        &options,
+        &offer,
+        &ice_tx,
+        &renegotiation_tx,
+    )
+    .await;
+    let mut subscribed = Subscribed {
+        subscriber_id,
+        subscription,
+        target_peer,
+        peer_key,
+        stream_type: options.stream_type.clone(),
+        result,
     };
 
-    match state.sfu.add_subscriber(req).await {
+    let transient = subscribed
+        .result
+        .as_ref()
+        .is_err_and(is_transient_subscribe_error);
+    if transient && config.server.subscribe_auto_retry {
+        // Retried in the background, so the socket's other messages are
+        // handled meanwhile; the player is answered once it is done.
+        let retry_after = Duration::from_millis(config.server.subscribe_retry_after_ms);
+        if let Err(e) = &subscribed.result {
+            warn!(
+                "Subscribe to '{}' failed transiently ({}), retrying in {:?}",
+                subscribed.target_peer, e, retry_after
+            );
+        }
+        let state = Arc::clone(state);
+        subscriptions.retrying.spawn(async move {
+            tokio::time::sleep(retry_after).await;
+            subscribed.result = try_subscribe(
+                &state,
+                &subscribed.subscriber_id,
+                &subscribed.peer_key,
+                &options,
+                &offer,
+                &ice_tx,
+                &renegotiation_tx,
+            )
+            .await;
+            subscribed
+        });
+        return Ok(());
+    }
+
+    reply_to_subscribe(session, &mut subscriptions.active, subscribed, state).await
+}
+
+/// A player's subscriptions, and offers waiting to be retried.
+#[derive(Default)]
+struct Subscriptions {
+    /// SFU subscriber ids, removed when the player leaves.
+    active: HashSet<String>,
+    retrying: JoinSet<Subscribed>,
+}
+
+/// How subscribing for one of a player's offers went.
+struct Subscribed {
+    subscriber_id: String,
+    subscription: Option<String>,
+    target_peer: String,
+    /// `target_peer` qualified with the player's tenant.
+    peer_key: String,
+    stream_type: Option<String>,
+    result: anyhow::Result<SubscriberResponse>,
+}
+
+/// Answers the player's offer, or tells it why subscribing failed.
+async fn reply_to_subscribe(
+    session: &WsSession,
+    active: &mut HashSet<String>,
+    subscribed: Subscribed,
+    state: &AppState,
+) -> Result<()> {
+    match subscribed.result {
         Ok(res) => {
-            session.send_json(&PlayerMessage {
-                event: "ANSWER".to_string(),
-                offer: Some(protocol::OfferMessage {
+            session.send_critical(&PlayerMessage::Answer {
+                offer: protocol::OfferMessage {
                     type_: "answer".to_string(),
                     sdp: res.answer.sdp,
-                    peer_id: None,
-                    peer_name: Some(target_peer),
-                    stream_type: None,
-                }),
-                ..Default::default()
+                    peer_id: subscribed.subscription,
+                    peer_name: Some(subscribed.target_peer),
+                    stream_type: subscribed.stream_type,
+                    sync_group: None,
+                },
+                data_channels: res.data_channels,
+                track_metadata: subscribed_track_metadata(state, &subscribed.subscriber_id).await,
             })?;
+            active.insert(subscribed.subscriber_id);
             Ok(())
         }
         Err(e) => {
             error!("SFU subscribe error: {}", e);
-            session.send_json(&PlayerMessage {
-                event: "OFFER_FAILED".to_string(),
-                ..Default::default()
+            if let Some(SfuError::LimitReached(reason)) = e.downcast_ref::<SfuError>() {
+                state.notifier.notify(
+                    "subscriber.limit_reached",
+                    &subscribed.peer_key,
+                    Some(reason.clone()),
+                    None,
+                );
+            }
+            let retryable = is_transient_subscribe_error(&e);
+            let retry_after_ms = state.config.current().server.subscribe_retry_after_ms;
+            session.send_json(&PlayerMessage::OfferFailed {
+                offer_failed: protocol::OfferFailedMessage {
+                    reason: e.to_string(),
+                    retryable,
+                    retry_after_ms: retryable.then_some(retry_after_ms),
+                    peer_id: subscribed.subscription,
+                },
             })?;
             Err(SignallingError::SfuError(e))
         }
     }
 }
 
-async fn handle_player_ice(
+/// SFU subscriber id for one subscription on a player socket. A player can
+/// hold several subscriptions by tagging each with its own `peer_id`; players
+/// that omit it get a single subscription under the socket id.
+fn subscriber_id(session_id: &str, subscription: Option<&str>) -> String {
+    match subscription {
+        Some(subscription) => format!("{}#{}", session_id, subscription),
+        None => session_id.to_string(),
+    }
+}
+
+/// The signalling socket a subscriber id belongs to.
+pub(crate) fn socket_id(subscriber_id: &str) -> &str {
+    subscriber_id
+        .split_once('#')
+        .map_or(subscriber_id, |(socket_id, _)| socket_id)
+}
+
+/// What a player's offer asks of its subscription besides the peer.
+struct SubscribeOptions {
+    stream_type: Option<String>,
+    sync_group: Option<String>,
+    upstream: bool,
+}
+
+async fn try_subscribe(
+    state: &AppState,
+    subscriber_id: &str,
+    target_peer: &str,
+    options: &SubscribeOptions,
+    offer: &RTCSessionDescription,
+    ice_tx: &IceCandidateSender,
+    renegotiation_tx: &RenegotiationSender,
+) -> anyhow::Result<SubscriberResponse> {
+    let peer_status = state
+        .storage
+        .get_peer_by_name(target_peer)
+        .ok_or_else(|| SignallingError::PeerNotFound(target_peer.to_string()))?;
+
+    let req = SubscriberRequest {
+        subscriber_id: subscriber_id.to_string(),
+        publisher_id: peer_status.socket_id,
+        offer: offer.clone(),
+        ice_candidate_tx: Some(ice_tx.clone()),
+        renegotiation_tx: Some(renegotiation_tx.clone()),
+        stream_type: options.stream_type.clone(),
+        sync_group: options.sync_group.clone(),
+        upstream: options.upstream,
+    };
+
+    negotiate(state, state.sfu.add_subscriber(req)).await
+}
+
+/// Labels of the tracks forwarded to a subscriber, as sent by their grabber.
+async fn subscribed_track_metadata(
+    state: &AppState,
+    subscriber_id: &str,
+) -> Vec<protocol::TrackMetadata> {
+    let Ok(Some(info)) = state.sfu.get_session(subscriber_id).await else {
+        return Vec::new();
+    };
+    info.tracks
+        .into_iter()
+        .filter_map(|track| track.metadata)
+        .map(Into::into)
+        .collect()
+}
+
+fn is_transient_subscribe_error(err: &anyhow::Error) -> bool {
+    if let Some(SignallingError::PeerNotFound(_)) = err.downcast_ref::<SignallingError>() {
+        return true;
+    }
+
+    matches!(
+        err.downcast_ref::<SfuError>(),
+        Some(SfuError::PublisherNotFound(_) | SfuError::LimitReached(_))
+    )
+}
+
+#[instrument(skip_all, fields(socket_id = %session.id))]
+async fn handle_track_control(
+    session: &WsSession,
+    track: protocol::TrackControlMessage,
+    paused: bool,
+    state: &AppState,
+) -> Result<()> {
+    state
+        .sfu
+        .set_subscriber_track_paused(
+            &subscriber_id(&session.id, track.peer_id.as_deref()),
+            &track.track_id,
+            paused,
+        )
+        .await
+        .map_err(SignallingError::SfuError)?;
+
+    Ok(())
+}
+
+#[instrument(skip_all, fields(socket_id = %session.id))]
+async fn handle_renegotiate_answer(
     session: &WsSession,
-    msg: PlayerMessage,
+    answer_data: protocol::OfferMessage,
     state: &AppState,
 ) -> Result<()> {
-    let ice_msg = msg
-        .ice
-        .ok_or_else(|| SignallingError::InvalidMessageFormat("Missing ICE data".to_string()))?;
+    let answer = RTCSessionDescription::answer(answer_data.sdp)
+        .map_err(|e| SignallingError::InvalidMessageFormat(format!("Invalid SDP answer: {}", e)))?;
 
     state
         .sfu
-        .add_subscriber_ice(&session.id, ice_msg.candidate)
+        .set_subscriber_answer(
+            &subscriber_id(&session.id, answer_data.peer_id.as_deref()),
+            answer,
+        )
         .await
         .map_err(SignallingError::SfuError)?;
 
     Ok(())
 }
+
+#[instrument(skip_all, fields(socket_id = %session.id))]
+async fn handle_player_ice(
+    session: &WsSession,
+    ice_msg: protocol::IceMessage,
+    state: &AppState,
+) -> Result<()> {
+    if let Err(e) = state
+        .sfu
+        .add_subscriber_ice(
+            &subscriber_id(&session.id, ice_msg.peer_id.as_deref()),
+            ice_msg.candidate.clone(),
+        )
+        .await
+    {
+        warn!("Rejected player ICE candidate: {:#}", e);
+        state.storage.record_ice_error(&session.id);
+        session.send_json(&PlayerMessage::IceError {
+            ice_error: protocol::IceErrorMessage {
+                candidate: ice_msg.candidate,
+                reason: format!("{:#}", e),
+                peer_id: ice_msg.peer_id,
+            },
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::error;
+
+use super::{check_tenant, player::run_player};
+use crate::error::Result;
+use crate::long_poll::POLL_TIMEOUT;
+use crate::state::AppState;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenPollResponse {
+    /// Identifies the session in the poll URLs; keep it secret.
+    pub token: String,
+    pub poll_timeout_ms: u64,
+}
+
+/// `POST /poll/player`: starts a player session over long polling. The first
+/// poll returns `AUTH_REQUEST`, as on the WebSocket.
+pub async fn open_player_poll(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Json<OpenPollResponse> {
+    open(None, state, addr)
+}
+
+pub async fn open_tenant_player_poll(
+    Path(tenant): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Json<OpenPollResponse>> {
+    check_tenant(&state, &tenant)?;
+    Ok(open(Some(tenant), state, addr))
+}
+
+fn open(tenant: Option<String>, state: Arc<AppState>, addr: SocketAddr) -> Json<OpenPollResponse> {
+    let (token, session, receiver) = state.long_poll.open(format!("player-poll-{}", addr));
+    tokio::spawn(async move {
+        if let Err(e) = run_player(session, receiver, addr, tenant, state).await {
+            error!("Long-poll player error from {}: {:?}", addr, e);
+        }
+    });
+    Json(OpenPollResponse {
+        token,
+        poll_timeout_ms: POLL_TIMEOUT.as_millis() as u64,
+    })
+}
+
+/// `GET /poll/player/:token`: a JSON array of the server's messages, empty
+/// when none arrived in time. 404 once the session has ended.
+pub async fn poll_player(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response> {
+    let messages = state.long_poll.poll(&token).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/json")],
+        format!("[{}]", messages.join(",")),
+    )
+        .into_response())
+}
+
+/// `POST /poll/player/:token`: a JSON array of player messages.
+pub async fn send_player_messages(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(messages): Json<Vec<serde_json::Value>>,
+) -> Result<StatusCode> {
+    state
+        .long_poll
+        .deliver(&token, messages.iter().map(|msg| msg.to_string()).collect())?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,174 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::protocol::PeerEvent;
+use crate::state::AppState;
+
+/// How often every grabber's ingest is sampled for reports.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+/// About a day of samples for a hundred grabbers.
+const MAX_SAMPLES: usize = 300_000;
+/// Event kinds counted as incidents in reports.
+const INCIDENT_KINDS: &[&str] = &["disconnected", "offline", "timeout", "error"];
+
+struct Sample {
+    timestamp: i64,
+    peer: String,
+    publishing: bool,
+    viewers: usize,
+    bitrate_bps: u64,
+}
+
+/// Periodic samples of each grabber's ingest, kept in memory since the server
+/// started, for post-contest reports. Nothing is written to disk, so a restart
+/// starts the history over.
+#[derive(Default)]
+pub struct MediaHistory {
+    samples: Mutex<VecDeque<Sample>>,
+    /// Timestamp of the newest sample dropped to stay within `MAX_SAMPLES`.
+    dropped_until: Mutex<Option<i64>>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct PeerReport {
+    pub peer: String,
+    /// Time spent publishing within the window.
+    pub uptime_secs: u64,
+    pub viewer_minutes: f64,
+    /// Mean ingest bitrate while publishing.
+    pub average_bitrate_bps: u64,
+    pub incidents: Vec<PeerEvent>,
+}
+
+impl MediaHistory {
+    fn record(&self, sample: Sample) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() >= MAX_SAMPLES {
+            if let Some(dropped) = samples.pop_front() {
+                *self.dropped_until.lock().unwrap() = Some(dropped.timestamp);
+            }
+        }
+        samples.push_back(sample);
+    }
+
+    /// Samples up to this unix timestamp may be missing from reports; `None`
+    /// while none were dropped.
+    pub fn dropped_until(&self) -> Option<i64> {
+        *self.dropped_until.lock().unwrap()
+    }
+
+    /// Per-peer totals for samples and incident `events` between the unix
+    /// timestamps `from` and `to`, both inclusive and open when `None`.
+    pub fn report(
+        &self,
+        from: Option<i64>,
+        to: Option<i64>,
+        events: &[PeerEvent],
+    ) -> Vec<PeerReport> {
+        let in_window = |timestamp: i64| {
+            from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp <= to)
+        };
+        let interval = SAMPLE_INTERVAL.as_secs();
+
+        // Reports with the sum of their publishing samples' bitrates.
+        let mut reports: BTreeMap<String, (PeerReport, u64)> = BTreeMap::new();
+
+        for sample in self.samples.lock().unwrap().iter() {
+            if !in_window(sample.timestamp) {
+                continue;
+            }
+            let (report, bitrate_total) = entry(&mut reports, &sample.peer);
+            if sample.publishing {
+                report.uptime_secs += interval;
+                *bitrate_total += sample.bitrate_bps;
+            }
+            report.viewer_minutes += (sample.viewers as u64 * interval) as f64 / 60.0;
+        }
+
+        for event in events {
+            if in_window(event.timestamp) && INCIDENT_KINDS.contains(&event.kind.as_str()) {
+                entry(&mut reports, &event.peer_name)
+                    .0
+                    .incidents
+                    .push(event.clone());
+            }
+        }
+
+        reports
+            .into_values()
+            .map(|(mut report, bitrate_total)| {
+                let publishing_samples = report.uptime_secs / interval;
+                if publishing_samples > 0 {
+                    report.average_bitrate_bps = bitrate_total / publishing_samples;
+                }
+                report
+            })
+            .collect()
+    }
+}
+
+fn entry<'a>(
+    reports: &'a mut BTreeMap<String, (PeerReport, u64)>,
+    peer: &str,
+) -> &'a mut (PeerReport, u64) {
+    reports.entry(peer.to_string()).or_insert_with(|| {
+        (
+            PeerReport {
+                peer: peer.to_string(),
+                ..Default::default()
+            },
+            0,
+        )
+    })
+}
+
+/// One row per peer; incidents are reduced to a count.
+pub fn to_csv(reports: &[PeerReport]) -> String {
+    let mut csv = String::from("peer,uptime_secs,viewer_minutes,average_bitrate_bps,incidents\n");
+    for report in reports {
+        let _ = writeln!(
+            csv,
+            "{},{},{:.1},{},{}",
+            csv_field(&report.peer),
+            report.uptime_secs,
+            report.viewer_minutes,
+            report.average_bitrate_bps,
+            report.incidents.len()
+        );
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub async fn record_history(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let timestamp = chrono::Utc::now().timestamp();
+
+        for peer in state.storage.get_all_statuses() {
+            let stats = if peer.online {
+                state.sfu.get_publisher_stats(&peer.socket_id).await.ok()
+            } else {
+                None
+            };
+            state.history.record(Sample {
+                timestamp,
+                peer: peer.name,
+                publishing: stats.is_some(),
+                viewers: stats.as_ref().map_or(0, |stats| stats.subscriber_count),
+                bitrate_bps: stats.as_ref().map_or(0, |stats| stats.bitrate_bps),
+            });
+        }
+    }
+}
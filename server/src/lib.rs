@@ -1,20 +1,40 @@
+mod cluster;
 mod error;
 mod handlers;
+mod history;
+mod listener;
+mod liveness;
+mod long_poll;
+mod notifier;
+mod peer_feed;
+mod peer_settings;
 mod protocol;
+mod rate_limit;
+mod reload;
+mod seating;
+mod sfu_events;
+mod shutdown;
 mod state;
 mod storage;
+pub mod telemetry;
+mod tenant;
 mod websocket;
 
 pub use error::{Result, SignallingError};
-pub use handlers::{get_peers, health, ws_grabber_handler, ws_player_handler};
-pub use state::AppState;
+pub use listener::Listener;
+pub use handlers::{
+    get_events, get_groups, get_peers, health, ws_grabber_handler, ws_player_handler,
+};
+pub use state::{AppState, ClientClass};
 pub use storage::Storage;
 
 use axum::{
-    routing::get,
+    middleware,
+    routing::{delete, get, post, put},
     Router,
 };
 use std::sync::Arc;
+use std::time::Duration;
 use tower_http::{
     cors::{Any, CorsLayer},
     services::ServeDir,
@@ -27,31 +47,133 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         .allow_methods(Any)
         .allow_headers(Any);
 
-    Router::new()
+    let admin = Router::new()
+        .route("/api/admin/sessions", get(handlers::admin::list_sessions))
+        .route(
+            "/api/admin/sessions/cleanup",
+            post(handlers::admin::cleanup_sessions),
+        )
+        .route(
+            "/api/admin/sessions/:id",
+            delete(handlers::admin::disconnect_session),
+        )
+        .route("/api/admin/reload", post(handlers::admin::reload_config))
+        .route(
+            "/api/admin/log-filter",
+            get(handlers::admin::get_log_filter)
+                .put(handlers::admin::set_log_filter)
+                .delete(handlers::admin::reset_log_filter),
+        )
+        .route(
+            "/api/admin/sessions/:id/trace",
+            put(handlers::admin::trace_session).delete(handlers::admin::stop_tracing_session),
+        )
+        .route("/api/admin/peers/status", get(handlers::admin::peers_status))
+        .route(
+            "/api/admin/peers/:name/settings",
+            get(handlers::admin::get_peer_settings)
+                .put(handlers::admin::set_peer_settings)
+                .delete(handlers::admin::clear_peer_settings),
+        )
+        .route(
+            "/api/admin/peers/:name/quality",
+            post(handlers::admin::set_peer_quality),
+        )
+        .route(
+            "/api/admin/peers/:name/shutdown",
+            post(handlers::admin::schedule_shutdown).delete(handlers::admin::cancel_shutdown),
+        )
+        .route("/api/reports/export", get(handlers::api::export_report))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            handlers::admin::require_admin,
+        ));
+
+    let limited = Router::new()
         .route("/player", get(ws_player_handler))
         .route("/grabber/:name", get(ws_grabber_handler))
+        .route("/poll/player", post(handlers::poll::open_player_poll))
+        .route(
+            "/poll/player/:token",
+            get(handlers::poll::poll_player).post(handlers::poll::send_player_messages),
+        )
         .route("/api/peers", get(get_peers))
+        .route("/api/groups", get(get_groups))
+        .route("/api/capabilities", post(handlers::api::check_capabilities))
+        .route("/api/events", get(get_events))
         .route("/api/health", get(health))
+        .route("/api/ice-and-endpoint", get(handlers::api::ice_and_endpoint))
+        .route("/api/ice-config", get(handlers::api::ice_config))
+        .route("/t/:tenant/player", get(handlers::player::ws_tenant_player_handler))
+        .route(
+            "/t/:tenant/poll/player",
+            post(handlers::poll::open_tenant_player_poll),
+        )
+        .route(
+            "/t/:tenant/grabber/:name",
+            get(handlers::grabber::ws_tenant_grabber_handler),
+        )
+        .route("/t/:tenant/api/peers", get(handlers::api::get_tenant_peers))
+        .route("/t/:tenant/api/events", get(handlers::api::get_tenant_events))
+        .route(
+            "/t/:tenant/api/capabilities",
+            post(handlers::api::check_tenant_capabilities),
+        )
+        .route("/t/:tenant/api/health", get(handlers::api::tenant_health))
+        .merge(admin)
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&state.rate_limiter),
+            rate_limit::limit_by_ip,
+        ));
+
+    Router::new()
+        .merge(limited)
         .nest_service("/", ServeDir::new("web"))
         .layer(cors)
         .with_state(state)
 }
 
 pub async fn start_server(bind_addr: &str, state: Arc<AppState>) -> Result<()> {
-    let app = create_router(state);
+    let listener = Listener::bind(bind_addr).await?;
+    start_server_with_listener(listener, state).await
+}
+
+pub async fn start_server_with_listener(listener: Listener, state: Arc<AppState>) -> Result<()> {
+    peer_settings::load(&state).await;
+
+    let limiter = Arc::clone(&state.rate_limiter);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            limiter.prune(Duration::from_secs(300));
+        }
+    });
 
-    let listener = tokio::net::TcpListener::bind(bind_addr)
-        .await
-        .map_err(|e| SignallingError::WebSocket(format!("Failed to bind: {}", e)))?;
+    tokio::spawn(liveness::sweep_stale_peers(Arc::clone(&state)));
+    tokio::spawn(sfu_events::watch(Arc::clone(&state)));
+    tokio::spawn(history::record_history(Arc::clone(&state)));
+    tokio::spawn(seating::watch_seating(Arc::clone(&state)));
+    tokio::spawn(cluster::watch_nodes(Arc::clone(&state)));
 
-    info!("Signalling server listening on {}", bind_addr);
+    let feed_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(peer_feed::PEERS_STATUS_INTERVAL);
+        loop {
+            interval.tick().await;
+            feed_state.peer_feed.tick(&feed_state.storage);
+        }
+    });
+
+    #[cfg(unix)]
+    if state.reload.is_some() {
+        tokio::spawn(reload::reload_on_sighup(Arc::clone(&state)));
+    }
+
+    let config = state.config.clone();
+    let app = create_router(state);
 
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
-    )
-    .await
-    .map_err(|e| SignallingError::WebSocket(format!("Server error: {}", e)))?;
+    info!("Signalling server listening on {}", listener.describe());
 
-    Ok(())
+    listener.serve(app, config).await
 }
@@ -0,0 +1,227 @@
+use axum::Router;
+use sfu_local::ConfigHandle;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+use crate::error::{Result, SignallingError};
+
+/// Where the signalling server accepts connections.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, std::path::PathBuf),
+}
+
+impl Listener {
+    /// Accepts `host:port`, `unix:/path/to.sock`, or `systemd` to take over the
+    /// first socket passed in by systemd socket activation.
+    pub async fn bind(addr: &str) -> Result<Self> {
+        if addr == "systemd" {
+            return Self::from_systemd();
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = addr.strip_prefix("unix:") {
+            return Self::bind_unix(path);
+        }
+
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| SignallingError::WebSocket(format!("Failed to bind: {}", e)))?;
+        Ok(Self::Tcp(listener))
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: &str) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        // A socket file left behind by a previous run would make bind fail.
+        if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            let _ = std::fs::remove_file(path);
+        }
+
+        let listener = tokio::net::UnixListener::bind(path)
+            .map_err(|e| SignallingError::WebSocket(format!("Failed to bind {}: {}", path, e)))?;
+        Ok(Self::Unix(listener, path.into()))
+    }
+
+    #[cfg(unix)]
+    fn from_systemd() -> Result<Self> {
+        use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+
+        const SD_LISTEN_FDS_START: RawFd = 3;
+
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let fds = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(0);
+
+        if !for_us || fds == 0 {
+            return Err(SignallingError::WebSocket(
+                "No listening socket passed by systemd".to_string(),
+            ));
+        }
+
+        let io_err = |e: std::io::Error| {
+            SignallingError::WebSocket(format!("Invalid systemd socket: {}", e))
+        };
+
+        // SAFETY: with LISTEN_PID matching, systemd hands this process ownership of
+        // the descriptors starting at SD_LISTEN_FDS_START.
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        if tcp.local_addr().is_ok() {
+            tcp.set_nonblocking(true).map_err(io_err)?;
+            return Ok(Self::Tcp(TcpListener::from_std(tcp).map_err(io_err)?));
+        }
+
+        // Not an inet socket, so it must be a ListenStream= path.
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+        unix.set_nonblocking(true).map_err(io_err)?;
+        let path = unix
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|p| p.to_path_buf()))
+            .unwrap_or_default();
+        Ok(Self::Unix(
+            tokio::net::UnixListener::from_std(unix).map_err(io_err)?,
+            path,
+        ))
+    }
+
+    #[cfg(not(unix))]
+    fn from_systemd() -> Result<Self> {
+        Err(SignallingError::WebSocket(
+            "Socket activation is only supported on Unix".to_string(),
+        ))
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Tcp(listener) => listener
+                .local_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|_| "tcp".to_string()),
+            #[cfg(unix)]
+            Self::Unix(_, path) => format!("unix:{}", path.display()),
+        }
+    }
+
+    /// `config` supplies the trusted proxies for Unix socket connections.
+    pub async fn serve(self, app: Router, config: ConfigHandle) -> Result<()> {
+        #[cfg(not(unix))]
+        let _ = config;
+        match self {
+            Self::Tcp(listener) => axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .map_err(|e| SignallingError::WebSocket(format!("Server error: {}", e))),
+            #[cfg(unix)]
+            Self::Unix(listener, _) => serve_unix(listener, app, config).await,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    listener: tokio::net::UnixListener,
+    app: Router,
+    config: ConfigHandle,
+) -> Result<()> {
+    use axum::extract::ConnectInfo;
+    use hyper::body::Incoming;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tracing::{debug, warn};
+
+    // Unix socket peers have no address. Handlers key sessions and rate limits on
+    // ConnectInfo, so use the proxy's forwarded client IP and a per-connection
+    // port to keep session ids unique. The port comes from a counter that
+    // skips ports still held by open connections, since it wraps.
+    let mut connections: u64 = 0;
+    let open_ports = Arc::new(Mutex::new(HashSet::new()));
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Failed to accept Unix socket connection: {}", e);
+                continue;
+            }
+        };
+
+        let port = {
+            let mut open = open_ports.lock().unwrap();
+            if open.len() >= u16::MAX as usize {
+                warn!("Refusing Unix socket connection: every port is in use");
+                continue;
+            }
+            loop {
+                connections += 1;
+                let port = (connections % u16::MAX as u64) as u16 + 1;
+                if open.insert(port) {
+                    break port;
+                }
+            }
+        };
+        let app = app.clone();
+        let config = config.clone();
+        let open_ports = Arc::clone(&open_ports);
+
+        tokio::spawn(async move {
+            let service =
+                hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+                    let trusted = config.current().server.trusted_proxies.clone();
+                    let ip = forwarded_ip(request.headers(), &trusted)
+                        .unwrap_or(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+                    request
+                        .extensions_mut()
+                        .insert(ConnectInfo(SocketAddr::new(ip, port)));
+                    app.clone().oneshot(request)
+                });
+
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Unix socket connection closed with error: {}", e);
+            }
+            open_ports.lock().unwrap().remove(&port);
+        });
+    }
+}
+
+/// The client address a proxy forwarded. Clients can put anything in
+/// `X-Forwarded-For` and each proxy appends the address it saw, so entries
+/// are read from the right, skipping `trusted` proxies; the first other one
+/// is the client. `X-Real-IP` is only used without `X-Forwarded-For`, and
+/// must then be set by the proxy.
+#[cfg(unix)]
+fn forwarded_ip(
+    headers: &axum::http::HeaderMap,
+    trusted: &[std::net::IpAddr],
+) -> Option<std::net::IpAddr> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(forwarded_for) = header("x-forwarded-for") {
+        let hops: Vec<std::net::IpAddr> = forwarded_for
+            .split(',')
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        return hops
+            .iter()
+            .rev()
+            .find(|hop| !trusted.contains(hop))
+            .or(hops.first())
+            .copied();
+    }
+    header("x-real-ip").and_then(|ip| ip.trim().parse().ok())
+}
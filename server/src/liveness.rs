@@ -0,0 +1,184 @@
+use dashmap::DashMap;
+use sfu_core::SessionKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::handlers::player::socket_id;
+use crate::state::AppState;
+
+/// Publishers of grabbers that disconnected less than
+/// `peer_liveness.reconnect_grace_ms` ago, by peer name.
+#[derive(Default)]
+pub struct Reconnecting(DashMap<String, Retained>);
+
+struct Retained {
+    /// The session id the publisher was published under.
+    session_id: String,
+    /// The token the grabber was given in `INIT_PEER`.
+    resume_token: String,
+    /// Removes the publisher once the grace period ends.
+    expiry: JoinHandle<()>,
+}
+
+impl Reconnecting {
+    /// Cancels removal of the publisher retained for `name` if `resume_token`
+    /// is the one its grabber was given, and returns the session id to reuse.
+    pub fn resume(&self, name: &str, resume_token: Option<&str>) -> Option<String> {
+        let resume_token = resume_token?;
+        self.0
+            .remove_if(name, |_, retained| retained.resume_token == resume_token)
+            .map(|(_, retained)| {
+                retained.expiry.abort();
+                retained.session_id
+            })
+    }
+
+    /// Cancels removal of the publisher retained for `name` and returns its
+    /// session id, for a grabber that reconnected without a valid token.
+    pub fn release(&self, name: &str) -> Option<String> {
+        self.0.remove(name).map(|(_, retained)| {
+            retained.expiry.abort();
+            retained.session_id
+        })
+    }
+}
+
+/// Keeps a disconnected grabber's publisher, and its viewers, for `grace`;
+/// removes it afterwards unless the grabber reconnected in the meantime.
+pub fn retain_publisher(
+    state: &Arc<AppState>,
+    name: String,
+    session_id: String,
+    resume_token: String,
+    grace: Duration,
+) {
+    info!(
+        "Grabber '{}' disconnected, keeping its publisher for {:?}",
+        name, grace
+    );
+    state.storage.mark_offline(&session_id);
+
+    // Holding the entry until it is filled in keeps the expiry task from
+    // looking for it before it exists, however short the grace period.
+    let entry = state.reconnecting.0.entry(name.clone());
+    let task_state = Arc::clone(state);
+    let (task_name, task_session_id) = (name, session_id.clone());
+    let expiry = tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        let expired = task_state
+            .reconnecting
+            .0
+            .remove_if(&task_name, |_, retained| {
+                retained.session_id == task_session_id
+            })
+            .is_some();
+        if expired {
+            info!("Grabber '{}' did not reconnect in time", task_name);
+            remove_grabber(&task_state, &task_name, &task_session_id).await;
+        }
+    });
+    entry.insert(Retained {
+        session_id,
+        resume_token,
+        expiry,
+    });
+}
+
+/// Drops a disconnected grabber from storage and the SFU.
+pub async fn remove_grabber(state: &AppState, name: &str, session_id: &str) {
+    state.storage.remove_peer_by_socket_id(session_id);
+    state.storage.record_event(name, "disconnected", None, None);
+    state
+        .notifier
+        .notify("grabber.disconnected", name, None, None);
+    let _ = state.sfu.remove_publisher(session_id).await;
+}
+
+/// Periodically expires grabbers that went silent, when
+/// `peer_liveness.sweep_stale` is set: first marking them offline, then
+/// dropping them from storage and the SFU.
+pub async fn sweep_stale_peers(state: Arc<AppState>) {
+    loop {
+        let liveness = state.config.current().server.peer_liveness.clone();
+        tokio::time::sleep(liveness.ping_interval()).await;
+        if !liveness.sweep_stale {
+            continue;
+        }
+
+        let (went_offline, removed) = state
+            .storage
+            .sweep_stale(liveness.offline_after(), liveness.remove_after());
+
+        for peer in went_offline {
+            warn!("Grabber '{}' went silent, marking offline", peer.name);
+            state
+                .storage
+                .record_event(&peer.name, "offline", None, None);
+            state
+                .notifier
+                .notify("grabber.offline", &peer.name, None, None);
+        }
+
+        for peer in removed {
+            info!("Removing unresponsive grabber '{}'", peer.name);
+            state
+                .storage
+                .record_event(&peer.name, "timeout", None, None);
+            state
+                .notifier
+                .notify("grabber.timeout", &peer.name, None, None);
+
+            if let Some(session) = state.storage.get_session(&peer.socket_id) {
+                let _ = session.close();
+            }
+            state.storage.unregister_session(&peer.socket_id);
+            let _ = state.sfu.remove_publisher(&peer.socket_id).await;
+        }
+    }
+}
+
+/// Closes a session whose `task` the SFU reports failed, so its client
+/// reconnects and starts over instead of staying attached to a stream that
+/// went quiet.
+pub async fn close_failed_session(
+    state: &AppState,
+    session_id: &str,
+    kind: SessionKind,
+    task: String,
+    message: String,
+) {
+    match kind {
+        SessionKind::Publisher => {
+            if let Some(peer) = state
+                .storage
+                .get_all_statuses()
+                .into_iter()
+                .find(|peer| peer.socket_id == session_id)
+            {
+                warn!("Closing grabber '{}': its {} task failed", peer.name, task);
+                state.storage.record_event(
+                    &peer.name,
+                    "failed",
+                    Some(message.clone()),
+                    Some(task.clone()),
+                );
+                state
+                    .notifier
+                    .notify("publisher.failed", &peer.name, Some(message), Some(task));
+            }
+            let _ = state.sfu.remove_publisher(session_id).await;
+        }
+        SessionKind::Subscriber => {
+            warn!(
+                "Closing subscriber {}: its {} task failed",
+                session_id, task
+            );
+            let _ = state.sfu.remove_subscriber(session_id).await;
+        }
+    }
+    if let Some(session) = state.storage.get_session(socket_id(session_id)) {
+        let _ = session.close();
+    }
+}
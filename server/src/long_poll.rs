@@ -0,0 +1,123 @@
+//! Long-polling transport for the player protocol, a last resort for networks
+//! whose proxies break WebSockets. A client opens a session and gets a token,
+//! then POSTs its messages and GETs the server's, each GET waiting up to
+//! [`POLL_TIMEOUT`] for something to arrive.
+
+use axum::extract::ws::Message;
+use dashmap::DashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::error::{Result, SignallingError};
+use crate::websocket::{OutboundReceiver, WsReceiver, WsSession};
+
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+/// Sessions nobody polled for this long are closed, as if the socket dropped.
+const ABANDON_AFTER: Duration = Duration::from_secs(60);
+
+struct PollSession {
+    inbox: mpsc::UnboundedSender<Message>,
+    outbox: tokio::sync::Mutex<OutboundReceiver>,
+    last_poll: Mutex<Instant>,
+}
+
+/// Open long-poll sessions by token.
+#[derive(Clone, Default)]
+pub struct LongPollSessions {
+    sessions: Arc<DashMap<String, Arc<PollSession>>>,
+}
+
+impl LongPollSessions {
+    /// Registers a session and returns its token, along with the session the
+    /// player handler runs on.
+    pub fn open(&self, session_id: String) -> (String, WsSession, WsReceiver) {
+        let token = uuid::Uuid::new_v4().to_string();
+        let (inbox, inbox_rx) = mpsc::unbounded_channel();
+        let (session, receiver, outbox) = WsSession::detached(session_id, inbox_rx);
+        self.sessions.insert(
+            token.clone(),
+            Arc::new(PollSession {
+                inbox,
+                outbox: tokio::sync::Mutex::new(outbox),
+                last_poll: Mutex::new(Instant::now()),
+            }),
+        );
+
+        let sessions = Arc::clone(&self.sessions);
+        let watched = token.clone();
+        let id = session.id.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ABANDON_AFTER / 4);
+            loop {
+                interval.tick().await;
+                let Some(session) = sessions.get(&watched).map(|s| Arc::clone(&s)) else {
+                    return;
+                };
+                if session.last_poll.lock().unwrap().elapsed() > ABANDON_AFTER {
+                    info!("Long-poll session {} abandoned", id);
+                    // Dropping the inbox ends the player's session.
+                    sessions.remove(&watched);
+                    return;
+                }
+            }
+        });
+
+        (token, session, receiver)
+    }
+
+    /// Queues client messages, each a JSON protocol message.
+    pub fn deliver(&self, token: &str, messages: Vec<String>) -> Result<()> {
+        let session = self.get(token)?;
+        for text in messages {
+            session
+                .inbox
+                .send(Message::Text(text))
+                .map_err(|_| SignallingError::SessionError("Session closed".to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// The messages queued for the client, waiting up to [`POLL_TIMEOUT`]
+    /// for the first; empty if none arrived. The session is gone once a poll
+    /// returns the server's last messages.
+    pub async fn poll(&self, token: &str) -> Result<Vec<String>> {
+        let session = self.get(token)?;
+        *session.last_poll.lock().unwrap() = Instant::now();
+
+        let mut outbox = session.outbox.lock().await;
+        let mut next = match tokio::time::timeout(POLL_TIMEOUT, outbox.recv()).await {
+            Ok(msg) => msg,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut messages = Vec::new();
+        let closed = loop {
+            match next {
+                Some(Message::Text(text)) => messages.push(text),
+                Some(Message::Close(_)) | None => break true,
+                Some(_) => {}
+            }
+            next = match outbox.try_recv() {
+                Ok(msg) => Some(msg),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => None,
+            };
+        };
+
+        *session.last_poll.lock().unwrap() = Instant::now();
+        if closed {
+            self.sessions.remove(token);
+        }
+        Ok(messages)
+    }
+
+    fn get(&self, token: &str) -> Result<Arc<PollSession>> {
+        self.sessions
+            .get(token)
+            .map(|session| Arc::clone(&session))
+            .ok_or_else(|| SignallingError::PeerNotFound("Unknown long-poll session".to_string()))
+    }
+}
@@ -1,50 +1,120 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::sync::Arc;
-use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing::{info, warn};
 
 use sfu_core::Sfu;
-use sfu_local::{LocalSfu, SfuConfig};
-use webrtc_grabber_rs_server::{start_server, AppState};
+use sfu_local::{selftest, ConfigHandle, LocalSfu, SfuConfig};
+use webrtc_grabber_rs_server::{start_server, telemetry, AppState};
+
+const CONFIG_PATH: &str = "config.yaml";
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "info,webrtc_grabber_rs_server=debug,sfu_local=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let profile = profile_arg();
+    let loaded = SfuConfig::load_reported(CONFIG_PATH, profile.as_deref());
+    // Defaults stand in for a missing config file, not for a profile that was
+    // asked for but could not be loaded.
+    let loaded = match loaded {
+        Err(e) if profile.is_some() => return Err(e),
+        loaded => loaded,
+    };
+    let using_default = loaded.is_err();
+    let (config, report) = loaded.unwrap_or_else(|_| (create_default_config(), Default::default()));
 
-    info!("Starting WebRTC SFU Server");
+    let telemetry_guard = telemetry::init_tracing(&config.telemetry)?;
 
-    let config = SfuConfig::load("config.yaml").unwrap_or_else(|_| {
+    info!("Starting WebRTC SFU Server");
+    if using_default {
         info!("Using default configuration");
-        create_default_config()
-    });
+    } else if let Some(profile) = &profile {
+        info!("Using config profile '{}'", profile);
+    }
+    if let Some(migration) = report.migration.filter(|m| !m.changes.is_empty()) {
+        warn!(
+            "{} uses config version {}, migrated to {}; update the file to keep these settings:",
+            CONFIG_PATH, migration.from_version, config.version
+        );
+        for change in &migration.changes {
+            warn!("  {}", change);
+        }
+    }
+    if !report.unknown_keys.is_empty() {
+        warn!(
+            "{} has settings this server doesn't know, ignored: {:?}",
+            CONFIG_PATH, report.unknown_keys
+        );
+    }
+    if let Some(endpoint) = &config.telemetry.otlp_endpoint {
+        info!("Exporting traces to {}", endpoint);
+    }
 
     let bind_addr = config.server.bind_address.clone();
 
-    let sfu = LocalSfu::new("local-sfu-1".to_string(), config.clone())?;
+    let config = ConfigHandle::new(config);
+
+    let sfu = LocalSfu::with_config_handle("local-sfu-1".to_string(), config.clone())?;
     info!("SFU instance created with ID: {}", sfu.id());
 
-    let state = Arc::new(AppState::new(Box::new(sfu), config));
+    if std::env::args().any(|arg| arg == "--self-test") {
+        let report = selftest::run(&sfu, &config.current())
+            .await
+            .context("Media self-test failed")?;
+        info!(
+            "Self-test passed: codecs {:?}, first packet forwarded after {:?}",
+            report.video_codecs, report.first_packet_after
+        );
+        return Ok(());
+    }
+
+    let state = Arc::new(
+        AppState::with_config_handle(Box::new(sfu), config).enable_reload(
+            CONFIG_PATH,
+            profile,
+            Some(telemetry_guard.log_filter()),
+        ),
+    );
 
     start_server(&bind_addr, state).await?;
 
     Ok(())
 }
 
+/// The value of `--profile <name>` or `--profile=<name>`.
+fn profile_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            return args.next();
+        }
+        if let Some(profile) = arg.strip_prefix("--profile=") {
+            return Some(profile.to_string());
+        }
+    }
+    None
+}
+
 fn create_default_config() -> SfuConfig {
-    use sfu_local::config::{CodecItem, CodecsConfig, PerformanceConfig, ServerConfig};
+    use sfu_local::config::{
+        AuthConfig, ClientIceServersConfig, CodecItem, CodecsConfig, ContentProfilesConfig,
+        DataChannelsConfig, E2eeConfig, NegotiationConfig, PeerLivenessConfig, PerformanceConfig,
+        RateLimitConfig, ServerConfig, TelemetryConfig, WebRtcConfig, WebhookConfig,
+    };
 
     SfuConfig {
+        version: sfu_local::migration::CURRENT_VERSION,
         server: ServerConfig {
             bind_address: "0.0.0.0:8080".to_string(),
             enable_metrics: true,
+            subscribe_auto_retry: true,
+            subscribe_retry_after_ms: 2000,
+            rate_limit: RateLimitConfig::default(),
+            peer_liveness: PeerLivenessConfig::default(),
+            negotiation: NegotiationConfig::default(),
+            trusted_proxies: vec![],
+            peer_settings_file: None,
         },
         ice_servers: vec![],
+        client_ice_servers: ClientIceServersConfig::default(),
         codecs: CodecsConfig {
             audio: vec![CodecItem {
                 mime: "audio/opus".to_string(),
@@ -61,6 +131,20 @@ fn create_default_config() -> SfuConfig {
                     channels: None,
                     sdp_fmtp: None,
                 },
+                CodecItem {
+                    mime: "video/VP9".to_string(),
+                    payload_type: 98,
+                    clock_rate: 90000,
+                    channels: None,
+                    sdp_fmtp: Some("profile-id=0".to_string()),
+                },
+                CodecItem {
+                    mime: "video/AV1".to_string(),
+                    payload_type: 45,
+                    clock_rate: 90000,
+                    channels: None,
+                    sdp_fmtp: None,
+                },
                 CodecItem {
                     mime: "video/H264".to_string(),
                     payload_type: 102,
@@ -77,6 +161,21 @@ fn create_default_config() -> SfuConfig {
             broadcast_channel_capacity: 1000,
             max_publishers: 100,
             max_subscribers_per_publisher: 50,
+            audio_channel_capacity: Some(200),
+            video_channel_capacity: Some(1000),
+            ..PerformanceConfig::default()
         },
+        auth: AuthConfig::default(),
+        groups: vec![],
+        telemetry: TelemetryConfig::default(),
+        webhooks: WebhookConfig::default(),
+        webrtc: WebRtcConfig::default(),
+        content_profiles: ContentProfilesConfig::default(),
+        data_channels: DataChannelsConfig::default(),
+        e2ee: E2eeConfig::default(),
+        tenants: vec![],
+        seating: None,
+        cluster: None,
+        contest_clock: None,
     }
 }
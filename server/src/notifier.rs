@@ -0,0 +1,109 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use sfu_local::config::WebhookConfig;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::protocol::PeerEvent;
+
+/// Delivers events to the configured webhook URLs in the background.
+/// Cloning is cheap; with no URLs configured every call is a no-op.
+#[derive(Clone)]
+pub struct Notifier {
+    tx: Option<mpsc::UnboundedSender<PeerEvent>>,
+}
+
+impl Notifier {
+    pub fn new(config: WebhookConfig) -> Self {
+        if config.urls.is_empty() {
+            return Self { tx: None };
+        }
+
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Webhooks disabled, failed to build HTTP client: {}", e);
+                return Self { tx: None };
+            }
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<PeerEvent>();
+        let config = Arc::new(config);
+
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let event = Arc::new(event);
+                for url in &config.urls {
+                    tokio::spawn(deliver(
+                        client.clone(),
+                        url.clone(),
+                        Arc::clone(&event),
+                        Arc::clone(&config),
+                    ));
+                }
+            }
+        });
+
+        Self { tx: Some(tx) }
+    }
+
+    pub fn notify(
+        &self,
+        kind: &str,
+        peer_name: &str,
+        message: Option<String>,
+        context: Option<String>,
+    ) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(PeerEvent {
+                timestamp: chrono::Utc::now().timestamp(),
+                peer_name: peer_name.to_string(),
+                kind: kind.to_string(),
+                message,
+                context,
+            });
+        }
+    }
+}
+
+async fn deliver(
+    client: reqwest::Client,
+    url: String,
+    event: Arc<PeerEvent>,
+    config: Arc<WebhookConfig>,
+) {
+    let mut backoff = Duration::from_millis(config.retry_backoff_ms);
+
+    for attempt in 0..=config.max_retries {
+        let error = match client.post(&url).json(event.as_ref()).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                debug!("Delivered '{}' webhook to {}", event.kind, url);
+                return;
+            }
+            Ok(resp) => format!("HTTP {}", resp.status()),
+            Err(e) => e.to_string(),
+        };
+
+        if attempt == config.max_retries {
+            warn!(
+                "Giving up on '{}' webhook to {} after {} attempts: {}",
+                event.kind,
+                url,
+                attempt + 1,
+                error
+            );
+            return;
+        }
+
+        debug!(
+            "Webhook '{}' to {} failed ({}), retrying in {:?}",
+            event.kind, url, error, backoff
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::protocol::{PeerStatus, PeersStatusDelta};
+use crate::storage::Storage;
+
+/// How often peer statuses are diffed and changes pushed to players.
+pub const PEERS_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+/// Ticks between full lists, so a client that mishandled a delta converges.
+const FULL_SYNC_TICKS: u64 = 30;
+/// Deltas retained for admin polling with `?since=`.
+const DELTA_HISTORY: usize = 64;
+const CHANNEL_CAPACITY: usize = 32;
+
+#[derive(Debug, Clone)]
+pub enum PeersUpdate {
+    /// Every known peer as of `seq`.
+    Full {
+        seq: u64,
+        peers: Vec<PeerStatus>,
+    },
+    Delta(PeersStatusDelta),
+}
+
+/// Publishes peer status changes as sequenced deltas. `seq` only advances
+/// when a delta is emitted; full lists carry the seq they are current as of.
+pub struct PeerFeed {
+    tx: broadcast::Sender<PeersUpdate>,
+    inner: Mutex<FeedState>,
+}
+
+#[derive(Default)]
+struct FeedState {
+    seq: u64,
+    ticks: u64,
+    peers: HashMap<String, PeerStatus>,
+    history: VecDeque<PeersStatusDelta>,
+}
+
+impl FeedState {
+    fn full(&self) -> PeersUpdate {
+        PeersUpdate::Full {
+            seq: self.seq,
+            peers: self.peers.values().cloned().collect(),
+        }
+    }
+}
+
+impl PeerFeed {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            tx,
+            inner: Mutex::new(FeedState::default()),
+        }
+    }
+
+    /// The current full list and a receiver for every update after it.
+    pub fn subscribe(&self) -> (PeersUpdate, broadcast::Receiver<PeersUpdate>) {
+        let inner = self.inner.lock().unwrap();
+        (inner.full(), self.tx.subscribe())
+    }
+
+    pub fn snapshot(&self) -> PeersUpdate {
+        self.inner.lock().unwrap().full()
+    }
+
+    /// Deltas after `since`, or `None` when they are no longer retained and
+    /// the caller needs a full list instead.
+    pub fn deltas_since(&self, since: u64) -> Option<Vec<PeersStatusDelta>> {
+        let inner = self.inner.lock().unwrap();
+        if since > inner.seq {
+            return None;
+        }
+        if since == inner.seq {
+            return Some(Vec::new());
+        }
+
+        let oldest = inner.history.front()?.seq;
+        if since + 1 < oldest {
+            return None;
+        }
+
+        Some(
+            inner
+                .history
+                .iter()
+                .filter(|delta| delta.seq > since)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Diffs `storage` against the last state seen, pushes a delta if
+    /// anything changed, and a full list every `FULL_SYNC_TICKS` ticks.
+    pub fn tick(&self, storage: &Storage) {
+        let mut inner = self.inner.lock().unwrap();
+
+        let current: HashMap<String, PeerStatus> = storage
+            .get_all_statuses()
+            .into_iter()
+            .map(|peer| (peer.name.clone(), peer))
+            .collect();
+
+        let mut delta = PeersStatusDelta {
+            seq: inner.seq + 1,
+            ..Default::default()
+        };
+        for (name, status) in &current {
+            match inner.peers.get(name) {
+                None => delta.added.push(status.clone()),
+                Some(previous) if changed(previous, status) => delta.changed.push(status.clone()),
+                Some(_) => {}
+            }
+        }
+        delta.removed = inner
+            .peers
+            .keys()
+            .filter(|name| !current.contains_key(*name))
+            .cloned()
+            .collect();
+        inner.peers = current;
+
+        if !delta.added.is_empty() || !delta.changed.is_empty() || !delta.removed.is_empty() {
+            inner.seq = delta.seq;
+            if inner.history.len() == DELTA_HISTORY {
+                inner.history.pop_front();
+            }
+            inner.history.push_back(delta.clone());
+            let _ = self.tx.send(PeersUpdate::Delta(delta));
+        }
+
+        inner.ticks += 1;
+        if inner.ticks == FULL_SYNC_TICKS {
+            inner.ticks = 0;
+            let _ = self.tx.send(inner.full());
+        }
+    }
+}
+
+/// Whether a peer changed in more than the fields refreshed by every ping.
+/// Those would put nearly every peer in every delta, so they only go out
+/// with full lists.
+fn changed(previous: &PeerStatus, current: &PeerStatus) -> bool {
+    let steady = |peer: &PeerStatus| PeerStatus {
+        last_ping: 0,
+        signalling_rtt_ms: None,
+        pipeline: None,
+        ..peer.clone()
+    };
+    steady(previous) != steady(current)
+}
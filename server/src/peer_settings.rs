@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::protocol::PeerSettings;
+use crate::state::AppState;
+use crate::storage::Storage;
+
+/// Keeps saves from interleaving; each writes the settings current when it
+/// runs, so the last one to finish is never stale.
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// Restores the overrides saved in `server.peer_settings_file`, if set. A
+/// missing file is a first start; one that fails to load leaves none.
+pub async fn load(state: &AppState) {
+    let Some(path) = state.config.current().server.peer_settings_file.clone() else {
+        return;
+    };
+
+    let settings = match tokio::fs::read_to_string(&path).await {
+        Ok(body) => serde_json::from_str::<HashMap<String, PeerSettings>>(&body)
+            .with_context(|| format!("{} is not a JSON object of peer settings", path)),
+        Err(e) if e.kind() == ErrorKind::NotFound => return,
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path)),
+    };
+    match settings {
+        Ok(settings) => {
+            info!(
+                "Restored settings for {} peers from {}",
+                settings.len(),
+                path
+            );
+            state.storage.restore_peer_settings(settings);
+        }
+        Err(e) => warn!("Failed to restore peer settings: {:#}", e),
+    }
+}
+
+/// Writes every peer's overrides to `server.peer_settings_file` in the
+/// background, if set.
+pub fn save(state: &AppState) {
+    let Some(path) = state.config.current().server.peer_settings_file.clone() else {
+        return;
+    };
+
+    let storage = state.storage.clone();
+    tokio::task::spawn_blocking(move || {
+        let _saving = SAVING.lock().unwrap();
+        if let Err(e) = write(&path, &storage) {
+            warn!("Failed to save peer settings: {:#}", e);
+        }
+    });
+}
+
+/// Replaces the file in one step, so a crash mid-write keeps the old one.
+fn write(path: &str, storage: &Storage) -> Result<()> {
+    let body = serde_json::to_vec_pretty(&storage.all_peer_settings())?;
+    let temp = format!("{}.tmp", path);
+    std::fs::write(&temp, body).with_context(|| format!("Failed to write {}", temp))?;
+    std::fs::rename(&temp, path).with_context(|| format!("Failed to replace {}", path))
+}
@@ -7,40 +7,124 @@ pub struct PingMessage {
     pub timestamp: i64,
     pub connections_count: Option<u32>,
     pub stream_types: Option<Vec<String>>,
+    pub pipeline: Option<PipelineStats>,
 }
 
+/// A grabber's capture pipeline health from its last `PING`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineStats {
+    pub fps: f64,
+    pub bitrate_kbps: u32,
+    pub dropped_frames: u64,
+    pub queued_frames: u32,
+}
+
+/// A message to or from a player, tagged by its `event`. Each event carries
+/// its payload under the same field as before, e.g. `{"event": "OFFER",
+/// "offer": {...}}`; messages with any other event, or without the payload
+/// their event needs, fail to parse.
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "event", content = "data")]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")] 
-pub enum PlayerEvent {
-    Auth,
+#[serde(
+    tag = "event",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    rename_all_fields = "camelCase"
+)]
+pub enum PlayerMessage {
+    Ping {
+        ping: Option<PingMessage>,
+    },
+    Pong {
+        ping: Option<PingMessage>,
+    },
+    Auth {
+        player_auth: Option<PlayerAuth>,
+    },
     AuthRequest,
-    AuthFailed,
-    InitPeer,
-    Offer,
-    OfferFailed,
-    Answer,
-    PlayerIce,
-    Ping,
-    Pong,
-    PeerStatus,
+    AuthFailed {
+        access_message: String,
+    },
+    InitPeer {
+        init_peer: PcConfigMessage,
+        session_expiry: Option<SessionExpiryMessage>,
+    },
+    Offer {
+        offer: OfferMessage,
+    },
+    OfferFailed {
+        offer_failed: OfferFailedMessage,
+    },
+    Answer {
+        offer: OfferMessage,
+        data_channels: Vec<String>,
+        track_metadata: Vec<TrackMetadata>,
+    },
+    PlayerIce {
+        ice: IceMessage,
+    },
+    ServerIce {
+        ice: IceMessage,
+    },
+    IceError {
+        ice_error: IceErrorMessage,
+    },
+    Renegotiate {
+        offer: OfferMessage,
+    },
+    RenegotiateAnswer {
+        offer: OfferMessage,
+    },
+    PauseTrack {
+        track: TrackControlMessage,
+    },
+    ResumeTrack {
+        track: TrackControlMessage,
+    },
+    Renew,
+    RenewFailed {
+        access_message: String,
+        session_expiry: Option<SessionExpiryMessage>,
+    },
+    SessionRenewed {
+        session_expiry: Option<SessionExpiryMessage>,
+    },
+    SessionExpiring {
+        session_expiry: Option<SessionExpiryMessage>,
+    },
+    SessionExpired,
+    /// Players send it without a list to ask for the full one.
+    PeersStatus {
+        #[serde(default)]
+        peers_status: Vec<PeerStatus>,
+        #[serde(default)]
+        peers_status_seq: u64,
+    },
+    PeersStatusDelta {
+        peers_status_delta: PeersStatusDelta,
+    },
+    ActiveSpeaker {
+        active_speaker: ActiveSpeakerMessage,
+    },
+    Notice {
+        notice: NoticeMessage,
+    },
+    Error {
+        error: ErrorMessage,
+    },
 }
 
+/// The `event` a message is tagged with, for logs.
+pub fn event_name<M: Serialize>(msg: &M) -> String {
+    serde_json::to_value(msg)
+        .ok()
+        .and_then(|value| Some(value.get("event")?.as_str()?.to_string()))
+        .unwrap_or_default()
+}
 
-#[derive(Debug, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct PlayerMessage {
-    pub event: String,
-    
-    pub player_auth: Option<PlayerAuth>,
-    pub access_message: Option<String>,
-    
-    pub init_peer: Option<PcConfigMessage>,
-    pub offer: Option<OfferMessage>,
-    pub ice: Option<IceMessage>,
-    pub ping: Option<PingMessage>,
-    
-    pub peers_status: Option<Vec<PeerStatus>>,
+/// Why the server couldn't act on a player's message.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorMessage {
+    pub message: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,6 +141,50 @@ pub struct OfferMessage {
     pub peer_id: Option<String>,
     pub peer_name: Option<String>,
     pub stream_type: Option<String>,
+    /// Subscriptions offered with the same group play in step, e.g. a team's
+    /// webcam and screen watched side by side.
+    pub sync_group: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferFailedMessage {
+    pub reason: String,
+    pub retryable: bool,
+    pub retry_after_ms: Option<u64>,
+    pub peer_id: Option<String>,
+}
+
+/// A grabber started or stopped speaking: any of its audio tracks is loud.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSpeakerMessage {
+    pub peer_name: String,
+    pub speaking: bool,
+}
+
+/// An operator scheduled a peer to shut down, e.g. to service its machine;
+/// sent when it is scheduled and again as the time nears.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoticeMessage {
+    pub peer_name: String,
+    pub message: Option<String>,
+    /// `None` when the shutdown was cancelled.
+    pub shutdown_in_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackControlMessage {
+    pub track_id: String,
+    pub peer_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionExpiryMessage {
+    pub expires_in_ms: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -66,6 +194,16 @@ pub struct IceMessage {
     pub peer_id: Option<String>,
 }
 
+/// Sent back when the SFU rejects a client's candidate, e.g. one for a
+/// session that is gone or with a stale ufrag.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IceErrorMessage {
+    pub candidate: RTCIceCandidateInit,
+    pub reason: String,
+    pub peer_id: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct JsonIceServer {
@@ -80,6 +218,9 @@ pub struct JsonIceServer {
 #[serde(rename_all = "camelCase")]
 pub struct JsonRtcConfiguration {
     pub ice_servers: Vec<JsonIceServer>,
+    /// Set when the SFU advertises end-to-end encryption. Browsers need it
+    /// to transform encoded frames.
+    pub encoded_insertable_streams: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -88,16 +229,149 @@ pub struct PcConfigMessage {
     pub pc_config: JsonRtcConfiguration,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+/// A message to or from a grabber, tagged by its `event` like
+/// [`PlayerMessage`].
+#[derive(Serialize, Deserialize)]
+#[serde(
+    tag = "event",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    rename_all_fields = "camelCase"
+)]
+pub enum GrabberMessage {
+    Ping {
+        ping: Option<PingMessage>,
+    },
+    Pong {
+        ping: Option<PingMessage>,
+    },
+    Auth {
+        grabber_auth: Option<GrabberAuth>,
+    },
+    AuthRequest,
+    AuthFailed {
+        access_message: String,
+    },
+    InitPeer {
+        init_peer: GrabberInitPeerMessage,
+    },
+    Offer {
+        #[serde(alias = "answer")]
+        offer: OfferMessage,
+    },
+    /// Older grabbers send their offer under this name.
+    OfferAnswer {
+        #[serde(alias = "answer")]
+        offer: OfferMessage,
+    },
+    OfferFailed,
+    Answer {
+        answer: OfferMessage,
+    },
+    GrabberIce {
+        ice: IceMessage,
+    },
+    ServerIce {
+        ice: IceMessage,
+    },
+    IceError {
+        ice_error: IceErrorMessage,
+    },
+    Error {
+        error: GrabberErrorMessage,
+    },
+    TrackMetadata {
+        track_metadata: Vec<TrackMetadata>,
+    },
+    IngestQuality {
+        ingest_quality: IngestQualityMessage,
+    },
+    PublisherState {
+        publisher_state: PublisherStateMessage,
+    },
+    Settings {
+        settings: PeerSettings,
+    },
+    SetQuality {
+        quality: QualityMessage,
+    },
+    /// Tells the grabber to stop publishing instead of reconnecting.
+    Shutdown {
+        shutdown: ShutdownMessage,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct GrabberMessage {
-    pub event: String,
-    
-    pub init_peer: Option<GrabberInitPeerMessage>,
-    pub offer: Option<OfferMessage>,
-    pub answer: Option<OfferMessage>,
-    pub ice: Option<IceMessage>,
-    pub ping: Option<PingMessage>,
+pub struct GrabberAuth {
+    pub credential: String,
+    /// Token from the previous connection's `INIT_PEER`, to resume its
+    /// publisher after a drop.
+    #[serde(default)]
+    pub resume_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GrabberErrorMessage {
+    pub message: String,
+    pub context: Option<String>,
+}
+
+/// The SFU's view of a grabber's uplink, sent periodically while publishing.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestQualityMessage {
+    pub bitrate_bps: u64,
+    pub target_bitrate_bps: Option<u64>,
+    /// Share of packets lost since the previous report.
+    pub loss_ratio: f64,
+    pub quality_score: f64,
+    pub degraded: bool,
+    /// Worst packet loss subscribers recently reported; high while the
+    /// uplink is fine means viewers are congested, and the target bitrate
+    /// drops to suit them.
+    pub downstream_loss_ratio: f64,
+}
+
+/// Sent by a grabber that stopped or restarted sending media on its own,
+/// e.g. while the screen is locked.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublisherStateMessage {
+    pub paused: bool,
+    pub reason: Option<String>,
+}
+
+/// Describes one of a grabber's tracks so players can show a meaningful name.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackMetadata {
+    pub track_id: String,
+    /// `"screen"`, `"webcam"` or `"audio"`.
+    pub label: String,
+    pub display_index: Option<u32>,
+    pub camera_name: Option<String>,
+}
+
+impl From<sfu_core::TrackMetadata> for TrackMetadata {
+    fn from(metadata: sfu_core::TrackMetadata) -> Self {
+        Self {
+            track_id: metadata.track_id,
+            label: metadata.label,
+            display_index: metadata.display_index,
+            camera_name: metadata.camera_name,
+        }
+    }
+}
+
+impl From<TrackMetadata> for sfu_core::TrackMetadata {
+    fn from(metadata: TrackMetadata) -> Self {
+        Self {
+            track_id: metadata.track_id,
+            label: metadata.label,
+            display_index: metadata.display_index,
+            camera_name: metadata.camera_name,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -105,10 +379,43 @@ pub struct GrabberMessage {
 pub struct GrabberInitPeerMessage {
     pub pc_config: JsonRtcConfiguration,
     pub ping_interval: u64,
+    /// Sent back in `AUTH` after a reconnect to keep this connection's
+    /// publisher and its viewers.
+    pub resume_token: String,
+    pub settings: PeerSettings,
 }
 
+/// Operator overrides for a grabber, kept across its reconnects and, with
+/// `server.peer_settings_file`, across restarts. Sent in `INIT_PEER` and in
+/// `SETTINGS` whenever they change.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PeerSettings {
+    /// Caps the bitrate the grabber encodes at.
+    pub max_bitrate_kbps: Option<u32>,
+    /// Stops forwarding the grabber's audio; the SFU enforces it.
+    pub audio_disabled: bool,
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Capture size and framerate a `SET_QUALITY` asks a grabber to switch to
+/// without reconnecting.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityMessage {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+}
+
+/// Why an admin shut a grabber down, sent in `SHUTDOWN`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShutdownMessage {
+    pub reason: Option<String>,
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct PeerStatus {
     pub name: String,
@@ -117,4 +424,41 @@ pub struct PeerStatus {
     pub connections: u32,
     pub stream_types: Vec<String>,
     pub last_ping: i64,
+    pub signalling_rtt_ms: Option<u64>,
+    /// Labels the grabber sent for its tracks.
+    pub tracks: Vec<TrackMetadata>,
+    /// Why the grabber paused publishing, e.g. `"locked"`; `None` while it
+    /// publishes normally.
+    pub paused_reason: Option<String>,
+    /// From the seating map, when one is configured and lists this peer.
+    pub display_name: Option<String>,
+    pub team: Option<String>,
+    /// `None` until the grabber reports it, and for grabbers that don't.
+    pub pipeline: Option<PipelineStats>,
+    /// Whether any of the grabber's audio tracks is speaking.
+    pub speaking: bool,
+}
+
+/// Changes since the previous delta; `seq` increases by one per delta, so a
+/// gap means the client should ask for a full `PEERS_STATUS`. A peer whose
+/// only change is its `last_ping`, `signalling_rtt_ms` or `pipeline` isn't
+/// in `changed`; those are refreshed by the periodic full list.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PeersStatusDelta {
+    pub seq: u64,
+    pub added: Vec<PeerStatus>,
+    pub changed: Vec<PeerStatus>,
+    /// Names of peers that went away.
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerEvent {
+    pub timestamp: i64,
+    pub peer_name: String,
+    pub kind: String,
+    pub message: Option<String>,
+    pub context: Option<String>,
 }
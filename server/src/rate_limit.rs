@@ -0,0 +1,111 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use dashmap::DashMap;
+use serde_json::json;
+use sfu_local::config::RateLimitConfig;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(refill_per_sec: f64, burst: u32) -> Self {
+        Self {
+            capacity: burst as f64,
+            tokens: burst as f64,
+            refill_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_refill.elapsed()
+    }
+}
+
+pub struct IpRateLimiter {
+    buckets: DashMap<IpAddr, TokenBucket>,
+    config: RwLock<RateLimitConfig>,
+}
+
+impl IpRateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            buckets: DashMap::new(),
+            config: RwLock::new(config),
+        }
+    }
+
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let config = self.config.read().unwrap();
+        if !config.enabled {
+            return true;
+        }
+
+        self.buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(config.requests_per_second, config.request_burst))
+            .try_acquire()
+    }
+
+    /// Swaps in new limits; existing buckets are dropped so they pick them up.
+    pub fn update_config(&self, config: RateLimitConfig) {
+        *self.config.write().unwrap() = config;
+        self.buckets.clear();
+    }
+
+    pub fn prune(&self, idle: Duration) {
+        self.buckets.retain(|_, bucket| bucket.idle_for() < idle);
+    }
+}
+
+/// The reason given to a client for a message dropped over its rate limit.
+pub const RATE_LIMITED: &str = "Message rate limit exceeded, message dropped";
+
+pub fn message_limiter(config: &RateLimitConfig) -> Option<TokenBucket> {
+    config
+        .enabled
+        .then(|| TokenBucket::new(config.ws_messages_per_second, config.ws_message_burst))
+}
+
+pub async fn limit_by_ip(
+    State(limiter): State<Arc<IpRateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limiter.check(addr.ip()) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({ "error": "Rate limit exceeded" })),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use sfu_local::config::{SfuConfig, RESTART_REQUIRED};
+use tracing::{info, warn};
+
+use crate::error::{Result, SignallingError};
+use crate::state::AppState;
+use crate::telemetry;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReloadReport {
+    /// Sections that took effect immediately.
+    pub applied: Vec<&'static str>,
+    /// Sections that changed on disk but are only read at startup.
+    pub requires_restart: Vec<&'static str>,
+    /// How a file written for an older config version was read.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub migrated: Vec<String>,
+    /// Keys in the file no setting reads.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unknown_keys: Vec<String>,
+}
+
+/// Re-reads the config file and swaps it in. Existing peer connections keep
+/// running; new sessions and requests see the new values.
+pub fn reload_config(state: &AppState) -> Result<ReloadReport> {
+    let source = state
+        .reload
+        .as_ref()
+        .ok_or_else(|| SignallingError::Forbidden("Config reload is disabled".to_string()))?;
+
+    let (new_config, report) = SfuConfig::load_reported(&source.path, source.profile.as_deref())?;
+    let current = state.config.current();
+
+    let (requires_restart, applied): (Vec<_>, Vec<_>) = current
+        .changed_sections(&new_config)
+        .into_iter()
+        .partition(|section| RESTART_REQUIRED.contains(section));
+
+    if applied.contains(&"server.rate_limit") {
+        state
+            .rate_limiter
+            .update_config(new_config.server.rate_limit.clone());
+    }
+
+    if applied.contains(&"telemetry.log_level") {
+        if let Some(handle) = &source.log_filter {
+            telemetry::reload_log_filter(handle, &new_config.telemetry, &state.log_overrides)?;
+        }
+    }
+
+    state.config.replace(new_config);
+
+    info!("Reloaded {}: applied {:?}", source.path, applied);
+    if !requires_restart.is_empty() {
+        warn!(
+            "Changes to {:?} will take effect after a restart",
+            requires_restart
+        );
+    }
+
+    let migrated = report
+        .migration
+        .map(|migration| migration.changes)
+        .unwrap_or_default();
+    if !migrated.is_empty() {
+        warn!(
+            "{} uses an older config version, migrated: {:?}",
+            source.path, migrated
+        );
+    }
+    if !report.unknown_keys.is_empty() {
+        warn!(
+            "{} has settings this server doesn't know, ignored: {:?}",
+            source.path, report.unknown_keys
+        );
+    }
+
+    Ok(ReloadReport {
+        applied,
+        requires_restart,
+        migrated,
+        unknown_keys: report.unknown_keys,
+    })
+}
+
+/// Reloads the config whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(state: std::sync::Arc<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading configuration");
+        if let Err(e) = reload_config(&state) {
+            warn!("Config reload failed: {}", e);
+        }
+    }
+}
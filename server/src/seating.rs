@@ -0,0 +1,80 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sfu_local::config::SeatingConfig;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::state::AppState;
+
+/// How often to check whether a seating map was configured by a reload.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A grabber's entry in the seating map.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct SeatLabel {
+    pub name: Option<String>,
+    pub team: Option<String>,
+}
+
+/// Re-reads the configured seating map every `refresh_secs` and relabels
+/// peers when it changes. A map that fails to load keeps the previous one.
+pub async fn watch_seating(state: Arc<AppState>) {
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let mut current: Option<HashMap<String, SeatLabel>> = None;
+
+    loop {
+        let Some(config) = state.config.current().seating.clone() else {
+            if current.take().is_some() {
+                state.storage.set_seating(HashMap::new());
+                info!("Seating map removed from the config");
+            }
+            tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+            continue;
+        };
+
+        match load(&config, &client).await {
+            Ok(seating) if current.as_ref() != Some(&seating) => {
+                let seats = seating.len();
+                let relabelled = state.storage.set_seating(seating.clone());
+                info!(
+                    "Loaded seating map with {} seats, relabelled {} peers",
+                    seats, relabelled
+                );
+                current = Some(seating);
+            }
+            Ok(_) => debug!("Seating map unchanged"),
+            Err(e) => warn!("Failed to load seating map: {:#}", e),
+        }
+
+        tokio::time::sleep(Duration::from_secs(config.refresh_secs.max(1))).await;
+    }
+}
+
+async fn load(
+    config: &SeatingConfig,
+    client: &reqwest::Client,
+) -> Result<HashMap<String, SeatLabel>> {
+    let body = match (&config.file, &config.url) {
+        (Some(path), None) => tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path))?,
+        (None, Some(url)) => {
+            client
+                .get(url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("Failed to fetch {}", url))?
+                .text()
+                .await?
+        }
+        _ => bail!("Set exactly one of seating.file and seating.url"),
+    };
+    serde_json::from_str(&body).context("Seating map is not a JSON object of seats")
+}
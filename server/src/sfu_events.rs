@@ -0,0 +1,79 @@
+//! Acts on the events the SFU reports about its sessions.
+
+use sfu_core::SfuEvent;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use crate::liveness;
+use crate::protocol::ActiveSpeakerMessage;
+use crate::state::AppState;
+
+pub async fn watch(state: Arc<AppState>) {
+    let mut events = state.sfu.events();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Missed {} SFU events", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        match event {
+            SfuEvent::TaskPanicked {
+                session_id,
+                kind,
+                task,
+                message,
+            } => liveness::close_failed_session(&state, &session_id, kind, task, message).await,
+            SfuEvent::SpeakingChanged {
+                publisher_id,
+                track_id,
+                speaking,
+            } => {
+                let Some((peer_name, speaking)) =
+                    state
+                        .storage
+                        .set_speaking(&publisher_id, &track_id, speaking)
+                else {
+                    continue;
+                };
+                debug!("Peer '{}' speaking: {}", peer_name, speaking);
+                let _ = state.speakers.send(ActiveSpeakerMessage {
+                    peer_name,
+                    speaking,
+                });
+            }
+            SfuEvent::BitrateCapExceeded {
+                publisher_id,
+                bitrate_bps,
+                cap_bps,
+            } => {
+                let Some(peer) = state
+                    .storage
+                    .get_all_statuses()
+                    .into_iter()
+                    .find(|peer| peer.socket_id == publisher_id)
+                else {
+                    continue;
+                };
+                let message = format!(
+                    "Sending {} kbps, over the {} kbps cap",
+                    bitrate_bps / 1000,
+                    cap_bps / 1000
+                );
+                state
+                    .storage
+                    .record_event(&peer.name, "bitrate_cap", Some(message.clone()), None);
+                state.notifier.notify(
+                    "publisher.bitrate_exceeded",
+                    &peer.name,
+                    Some(message),
+                    None,
+                );
+            }
+        }
+    }
+}
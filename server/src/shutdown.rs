@@ -0,0 +1,137 @@
+//! Grabber shutdowns an admin schedules ahead, so viewers see them coming
+//! instead of the stream just cutting out.
+
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::{sleep_until, Instant};
+use tracing::info;
+
+use crate::liveness;
+use crate::protocol::{GrabberMessage, NoticeMessage, ShutdownMessage};
+use crate::state::AppState;
+
+/// Time left at which viewers are reminded of a scheduled shutdown, on top of
+/// the notice sent when it is scheduled.
+const COUNTDOWN: [Duration; 4] = [
+    Duration::from_secs(60),
+    Duration::from_secs(30),
+    Duration::from_secs(10),
+    Duration::from_secs(5),
+];
+
+/// A pending shutdown. Dropping it, when it is cancelled or replaced, stops
+/// its countdown.
+struct Scheduled {
+    id: u64,
+    _cancel: oneshot::Sender<()>,
+}
+
+/// Grabbers an admin scheduled to shut down, by peer name. An entry is
+/// removed by whoever cancels it, or by its own countdown when it goes
+/// ahead, so only one of the two happens.
+#[derive(Default)]
+pub struct ScheduledShutdowns {
+    pending: DashMap<String, Scheduled>,
+    next_id: AtomicU64,
+}
+
+impl ScheduledShutdowns {
+    /// Announces to viewers that the grabber `name` goes away in `delay`,
+    /// then removes it. Replaces a shutdown already scheduled for it.
+    pub fn schedule(
+        &self,
+        state: &Arc<AppState>,
+        name: String,
+        delay: Duration,
+        message: Option<String>,
+    ) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (cancel, cancelled) = oneshot::channel();
+        self.pending.insert(
+            name.clone(),
+            Scheduled {
+                id,
+                _cancel: cancel,
+            },
+        );
+        tokio::spawn(run(Arc::clone(state), name, id, delay, message, cancelled));
+    }
+
+    /// Returns `false` if no shutdown was scheduled for `name`.
+    pub fn cancel(&self, state: &AppState, name: &str) -> bool {
+        if self.pending.remove(name).is_none() {
+            return false;
+        }
+        announce(state, name, None, None);
+        true
+    }
+}
+
+async fn run(
+    state: Arc<AppState>,
+    name: String,
+    id: u64,
+    delay: Duration,
+    message: Option<String>,
+    cancelled: oneshot::Receiver<()>,
+) {
+    let countdown = async {
+        let deadline = Instant::now() + delay;
+        announce(&state, &name, Some(delay), message.clone());
+        for left in COUNTDOWN.into_iter().filter(|left| *left < delay) {
+            sleep_until(deadline - left).await;
+            announce(&state, &name, Some(left), message.clone());
+        }
+        sleep_until(deadline).await;
+    };
+    tokio::select! {
+        _ = countdown => {}
+        _ = cancelled => return,
+    }
+    // Cancelled or replaced just as the countdown ended.
+    let ours = state
+        .shutdowns
+        .pending
+        .remove_if(&name, |_, scheduled| scheduled.id == id);
+    if ours.is_none() {
+        return;
+    }
+
+    let Some(peer) = state.storage.get_peer_by_name(&name) else {
+        return;
+    };
+    info!("Shutting down grabber '{}' as scheduled", name);
+    state.storage.record_event(
+        &name,
+        "shutdown",
+        message.clone(),
+        Some("admin".to_string()),
+    );
+    let _ = state.sfu.remove_publisher(&peer.socket_id).await;
+    match state.storage.get_session(&peer.socket_id) {
+        // Its handler drops it from storage once the socket has closed. The
+        // grabber would reconnect and publish again unless told to stop.
+        Some(session) => {
+            let _ = session.send_critical(&GrabberMessage::Shutdown {
+                shutdown: ShutdownMessage { reason: message },
+            });
+            let _ = session.close();
+        }
+        None => {
+            state.reconnecting.release(&name);
+            liveness::remove_grabber(&state, &name, &peer.socket_id).await;
+        }
+    }
+}
+
+/// `shutdown_in` is `None` when the shutdown was cancelled.
+fn announce(state: &AppState, name: &str, shutdown_in: Option<Duration>, message: Option<String>) {
+    let _ = state.notices.send(NoticeMessage {
+        peer_name: name.to_string(),
+        message,
+        shutdown_in_ms: shutdown_in.map(|left| left.as_millis() as u64),
+    });
+}
@@ -1,37 +1,129 @@
+use serde::Deserialize;
 use std::sync::Arc;
+use tokio::sync::broadcast;
 
 use sfu_core::Sfu;
-use sfu_local::config::SfuConfig;
+use sfu_local::config::{ConfigHandle, SfuConfig};
 
-use crate::{protocol, storage::Storage};
+use crate::{
+    cluster::NodeLoads, history::MediaHistory, liveness::Reconnecting, long_poll::LongPollSessions, notifier::Notifier,
+    peer_feed::PeerFeed, protocol::{self, ActiveSpeakerMessage, NoticeMessage}, rate_limit::IpRateLimiter,
+    shutdown::ScheduledShutdowns, storage::Storage,
+    telemetry::{LogFilterHandle, LogOverrides}, tenant::TenantPlayers,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientClass {
+    Grabber,
+    #[default]
+    Player,
+}
+
+const SPEAKER_CAPACITY: usize = 64;
+const NOTICE_CAPACITY: usize = 16;
 
 pub struct AppState {
     pub sfu: Box<dyn Sfu + Send + Sync>,
     pub storage: Storage,
-    pub config: Arc<SfuConfig>,
+    pub config: ConfigHandle,
+    pub rate_limiter: Arc<IpRateLimiter>,
+    pub notifier: Notifier,
+    pub(crate) peer_feed: PeerFeed,
+    pub(crate) tenant_players: TenantPlayers,
+    pub(crate) reconnecting: Reconnecting,
+    pub(crate) history: MediaHistory,
+    pub(crate) long_poll: LongPollSessions,
+    pub(crate) reload: Option<ReloadSource>,
+    pub(crate) log_overrides: LogOverrides,
+    pub(crate) cluster: NodeLoads,
+    /// Peers starting or stopping speaking, for players.
+    pub(crate) speakers: broadcast::Sender<ActiveSpeakerMessage>,
+    pub(crate) shutdowns: ScheduledShutdowns,
+    /// Scheduled shutdowns announced to players.
+    pub(crate) notices: broadcast::Sender<NoticeMessage>,
+}
+
+pub(crate) struct ReloadSource {
+    pub path: String,
+    pub profile: Option<String>,
+    pub log_filter: Option<LogFilterHandle>,
 }
 
 impl AppState {
     pub fn new(sfu: Box<dyn Sfu + Send + Sync>, config: SfuConfig) -> Self {
+        Self::with_config_handle(sfu, ConfigHandle::new(config))
+    }
+
+    /// Use the same handle the SFU was built with so reloads reach both.
+    pub fn with_config_handle(sfu: Box<dyn Sfu + Send + Sync>, config: ConfigHandle) -> Self {
+        let current = config.current();
         Self {
             sfu,
             storage: Storage::new(),
-            config: Arc::new(config),
+            rate_limiter: Arc::new(IpRateLimiter::new(current.server.rate_limit.clone())),
+            notifier: Notifier::new(current.webhooks.clone()),
+            peer_feed: PeerFeed::new(),
+            tenant_players: TenantPlayers::default(),
+            reconnecting: Reconnecting::default(),
+            history: MediaHistory::default(),
+            long_poll: LongPollSessions::default(),
+            config,
+            reload: None,
+            log_overrides: LogOverrides::default(),
+            cluster: NodeLoads::default(),
+            speakers: broadcast::channel(SPEAKER_CAPACITY).0,
+            shutdowns: ScheduledShutdowns::default(),
+            notices: broadcast::channel(NOTICE_CAPACITY).0,
         }
     }
 
-    pub fn get_client_rtc_config(&self) -> protocol::JsonRtcConfiguration {
-        let ice_servers = self
-            .config
-            .ice_servers
-            .iter()
-            .map(|url| protocol::JsonIceServer {
-                urls: vec![url.clone()],
-                username: None,
-                credential: None,
-            })
-            .collect();
-
-        protocol::JsonRtcConfiguration { ice_servers }
+    /// Enables SIGHUP and `POST /api/admin/reload` to re-read `path` with the
+    /// same `profile`.
+    pub fn enable_reload(
+        mut self,
+        path: impl Into<String>,
+        profile: Option<String>,
+        log_filter: Option<LogFilterHandle>,
+    ) -> Self {
+        self.reload = Some(ReloadSource {
+            path: path.into(),
+            profile,
+            log_filter,
+        });
+        self
+    }
+
+    pub fn get_client_rtc_config(&self, class: ClientClass) -> protocol::JsonRtcConfiguration {
+        let config = self.config.current();
+        let overrides = match class {
+            ClientClass::Grabber => &config.client_ice_servers.grabber,
+            ClientClass::Player => &config.client_ice_servers.player,
+        };
+
+        let ice_servers = match overrides {
+            Some(servers) => servers
+                .iter()
+                .map(|server| protocol::JsonIceServer {
+                    urls: server.urls.clone(),
+                    username: server.username.clone(),
+                    credential: server.credential.clone(),
+                })
+                .collect(),
+            None => config
+                .ice_servers
+                .iter()
+                .map(|url| protocol::JsonIceServer {
+                    urls: vec![url.clone()],
+                    username: None,
+                    credential: None,
+                })
+                .collect(),
+        };
+
+        protocol::JsonRtcConfiguration {
+            ice_servers,
+            encoded_insertable_streams: config.e2ee.enabled,
+        }
     }
 }
@@ -1,20 +1,49 @@
 use dashmap::DashMap;
-use std::sync::Arc;
-use crate::protocol::PeerStatus;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::protocol::{PeerEvent, PeerSettings, PeerStatus, PipelineStats, TrackMetadata};
+use crate::seating::SeatLabel;
+use crate::websocket::WsSession;
+
+const MAX_EVENTS: usize = 1000;
 
 #[derive(Clone)]
 pub struct Storage {
     peers: Arc<DashMap<String, PeerStatus>>,
+    events: Arc<Mutex<VecDeque<PeerEvent>>>,
+    /// Timestamp of the newest event dropped to stay within `MAX_EVENTS`.
+    events_dropped_until: Arc<Mutex<Option<i64>>>,
+    sessions: Arc<DashMap<String, WsSession>>,
+    rtts: Arc<DashMap<String, u64>>,
+    /// Rejected ICE candidates by socket id.
+    ice_errors: Arc<DashMap<String, u64>>,
+    /// Operator overrides by peer name, kept when the peer goes away so they
+    /// apply again when it reconnects.
+    peer_settings: Arc<DashMap<String, PeerSettings>>,
+    /// The seating map by peer name.
+    seating: Arc<Mutex<HashMap<String, SeatLabel>>>,
+    /// Audio tracks currently speaking, by socket id.
+    speaking_tracks: Arc<DashMap<String, HashSet<String>>>,
 }
 
 impl Storage {
     pub fn new() -> Self {
         Self {
             peers: Arc::new(DashMap::new()),
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(MAX_EVENTS))),
+            events_dropped_until: Arc::new(Mutex::new(None)),
+            sessions: Arc::new(DashMap::new()),
+            rtts: Arc::new(DashMap::new()),
+            ice_errors: Arc::new(DashMap::new()),
+            peer_settings: Arc::new(DashMap::new()),
+            seating: Arc::new(Mutex::new(HashMap::new())),
+            speaking_tracks: Arc::new(DashMap::new()),
         }
     }
 
     pub fn add_peer(&self, name: String, socket_id: String) {
+        let seat = self.seating.lock().unwrap().get(&name).cloned().unwrap_or_default();
         self.peers.insert(name.clone(), PeerStatus {
             name,
             socket_id,
@@ -22,18 +51,59 @@ impl Storage {
             connections: 0,
             stream_types: vec![],
             last_ping: chrono::Utc::now().timestamp(),
+            signalling_rtt_ms: None,
+            tracks: vec![],
+            paused_reason: None,
+            display_name: seat.name,
+            team: seat.team,
+            pipeline: None,
+            speaking: false,
         });
     }
 
+    /// Replaces the seating map and relabels connected peers. Returns how
+    /// many peers' labels changed.
+    pub fn set_seating(&self, seating: HashMap<String, SeatLabel>) -> usize {
+        let mut changed = 0;
+        for mut peer in self.peers.iter_mut() {
+            let seat = seating.get(&peer.name).cloned().unwrap_or_default();
+            if peer.display_name != seat.name || peer.team != seat.team {
+                peer.display_name = seat.name;
+                peer.team = seat.team;
+                changed += 1;
+            }
+        }
+        *self.seating.lock().unwrap() = seating;
+        changed
+    }
+
     pub fn get_peer_by_name(&self, name: &str) -> Option<PeerStatus> {
         self.peers.get(name).map(|p| p.clone())
     }
 
-    pub fn update_ping(&self, socket_id: &str, connections: u32, streams: Vec<String>) {
+    pub fn update_ping(
+        &self,
+        socket_id: &str,
+        connections: u32,
+        streams: Vec<String>,
+        pipeline: Option<PipelineStats>,
+    ) {
         for mut peer in self.peers.iter_mut() {
             if peer.socket_id == socket_id {
                 peer.connections = connections;
                 peer.stream_types = streams;
+                peer.pipeline = pipeline;
+                peer.last_ping = chrono::Utc::now().timestamp();
+                peer.online = true;
+                break;
+            }
+        }
+    }
+
+    /// Counts any message from the peer's socket as a sign of life.
+    pub fn touch(&self, socket_id: &str) {
+        for mut peer in self.peers.iter_mut() {
+            if peer.socket_id == socket_id {
                 peer.last_ping = chrono::Utc::now().timestamp();
                 peer.online = true;
                 break;
@@ -41,11 +111,203 @@ impl Storage {
         }
     }
 
+    /// Marks peers whose last ping is older than `offline_after` offline and
+    /// removes those older than `remove_after`. Returns both sets.
+    pub fn sweep_stale(
+        &self,
+        offline_after: Duration,
+        remove_after: Duration,
+    ) -> (Vec<PeerStatus>, Vec<PeerStatus>) {
+        let now = chrono::Utc::now().timestamp();
+        let silent_for =
+            |peer: &PeerStatus| Duration::from_secs((now - peer.last_ping).max(0) as u64);
+
+        let mut went_offline = Vec::new();
+        for mut peer in self.peers.iter_mut() {
+            if peer.online && silent_for(peer.value()) >= offline_after {
+                peer.online = false;
+                went_offline.push(peer.clone());
+            }
+        }
+
+        let mut removed = Vec::new();
+        self.peers.retain(|_, peer| {
+            let stale = silent_for(peer) >= remove_after;
+            if stale {
+                removed.push(peer.clone());
+            }
+            !stale
+        });
+
+        (went_offline, removed)
+    }
+
+    pub fn set_track_metadata(&self, socket_id: &str, tracks: Vec<TrackMetadata>) {
+        for mut peer in self.peers.iter_mut() {
+            if peer.socket_id == socket_id {
+                peer.tracks = tracks;
+                break;
+            }
+        }
+    }
+
+    pub fn set_paused(&self, socket_id: &str, reason: Option<String>) {
+        for mut peer in self.peers.iter_mut() {
+            if peer.socket_id == socket_id {
+                peer.paused_reason = reason;
+                break;
+            }
+        }
+    }
+
+    /// Marks one of a grabber's audio tracks speaking or silent. Returns the
+    /// peer's name and whether it is now speaking, when that changed.
+    pub fn set_speaking(&self, socket_id: &str, track_id: &str, speaking: bool) -> Option<(String, bool)> {
+        let mut tracks = self.speaking_tracks.entry(socket_id.to_string()).or_default();
+        if speaking {
+            tracks.insert(track_id.to_string());
+        } else {
+            tracks.remove(track_id);
+        }
+        let peer_speaking = !tracks.is_empty();
+        drop(tracks);
+        self.speaking_tracks.remove_if(socket_id, |_, tracks| tracks.is_empty());
+
+        for mut peer in self.peers.iter_mut() {
+            if peer.socket_id == socket_id {
+                if peer.speaking == peer_speaking {
+                    return None;
+                }
+                peer.speaking = peer_speaking;
+                return Some((peer.name.clone(), peer_speaking));
+            }
+        }
+        None
+    }
+
+    pub fn mark_offline(&self, socket_id: &str) {
+        for mut peer in self.peers.iter_mut() {
+            if peer.socket_id == socket_id {
+                peer.online = false;
+            }
+        }
+    }
+
     pub fn remove_peer_by_socket_id(&self, socket_id: &str) {
         self.peers.retain(|_, v| v.socket_id != socket_id);
     }
 
+    pub fn register_session(&self, session: &WsSession) {
+        self.sessions.insert(session.id.clone(), session.clone());
+    }
+
+    pub fn unregister_session(&self, socket_id: &str) {
+        self.sessions.remove(socket_id);
+        self.rtts.remove(socket_id);
+        self.ice_errors.remove(socket_id);
+    }
+
+    pub fn get_session(&self, socket_id: &str) -> Option<WsSession> {
+        self.sessions.get(socket_id).map(|s| s.clone())
+    }
+
+    pub fn has_session(&self, socket_id: &str) -> bool {
+        self.sessions.contains_key(socket_id)
+    }
+
+    pub fn record_rtt(&self, socket_id: &str, rtt_ms: u64) {
+        self.rtts.insert(socket_id.to_string(), rtt_ms);
+        for mut peer in self.peers.iter_mut() {
+            if peer.socket_id == socket_id {
+                peer.signalling_rtt_ms = Some(rtt_ms);
+                break;
+            }
+        }
+    }
+
+    pub fn record_ice_error(&self, socket_id: &str) {
+        *self.ice_errors.entry(socket_id.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn ice_errors(&self, socket_id: &str) -> u64 {
+        self.ice_errors.get(socket_id).map_or(0, |count| *count)
+    }
+
+    pub fn rtt_ms(&self, socket_id: &str) -> Option<u64> {
+        self.rtts.get(socket_id).map(|rtt| *rtt)
+    }
+
+    pub fn average_rtt_ms(&self) -> Option<u64> {
+        let count = self.rtts.len() as u64;
+        let total: u64 = self.rtts.iter().map(|rtt| *rtt).sum();
+        (count > 0).then(|| total / count)
+    }
+
+    pub fn peer_settings(&self, name: &str) -> PeerSettings {
+        self.peer_settings
+            .get(name)
+            .map(|s| s.clone())
+            .unwrap_or_default()
+    }
+
+    /// Default settings clear the peer's overrides.
+    pub fn set_peer_settings(&self, name: &str, settings: PeerSettings) {
+        if settings == PeerSettings::default() {
+            self.peer_settings.remove(name);
+        } else {
+            self.peer_settings.insert(name.to_string(), settings);
+        }
+    }
+
+    pub fn all_peer_settings(&self) -> HashMap<String, PeerSettings> {
+        self.peer_settings
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Replaces every peer's overrides, e.g. with those saved before a
+    /// restart.
+    pub fn restore_peer_settings(&self, settings: HashMap<String, PeerSettings>) {
+        self.peer_settings.clear();
+        for (name, settings) in settings {
+            self.set_peer_settings(&name, settings);
+        }
+    }
+
     pub fn get_all_statuses(&self) -> Vec<PeerStatus> {
         self.peers.iter().map(|p| p.value().clone()).collect()
     }
+
+    pub fn record_event(
+        &self,
+        peer_name: &str,
+        kind: &str,
+        message: Option<String>,
+        context: Option<String>,
+    ) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= MAX_EVENTS {
+            if let Some(dropped) = events.pop_front() {
+                *self.events_dropped_until.lock().unwrap() = Some(dropped.timestamp);
+            }
+        }
+        events.push_back(PeerEvent {
+            timestamp: chrono::Utc::now().timestamp(),
+            peer_name: peer_name.to_string(),
+            kind: kind.to_string(),
+            message,
+            context,
+        });
+    }
+
+    pub fn recent_events(&self) -> Vec<PeerEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Events up to this unix timestamp may be missing from
+    /// [`Self::recent_events`]; `None` while none were dropped.
+    pub fn events_dropped_until(&self) -> Option<i64> {
+        *self.events_dropped_until.lock().unwrap()
+    }
 }
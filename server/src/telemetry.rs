@@ -0,0 +1,216 @@
+use anyhow::Result;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use sfu_local::config::TelemetryConfig;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::warn;
+use tracing_subscriber::{
+    filter::Directive, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+const DEFAULT_LOG_FILTER: &str = "info,webrtc_grabber_rs_server=debug,sfu_local=debug";
+/// Span fields that carry a session id, in the server's handlers and the SFU.
+const SESSION_FIELDS: &[&str] = &["socket_id", "publisher_id", "subscriber_id"];
+
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Keeps the OTLP pipeline alive; dropping it flushes pending spans.
+pub struct TelemetryGuard {
+    provider: Option<TracerProvider>,
+    log_filter: LogFilterHandle,
+}
+
+impl TelemetryGuard {
+    pub fn log_filter(&self) -> LogFilterHandle {
+        self.log_filter.clone()
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush OTLP spans: {}", e);
+            }
+        }
+    }
+}
+
+pub fn init_tracing(config: &TelemetryConfig) -> Result<TelemetryGuard> {
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()?;
+
+            Some(
+                TracerProvider::builder()
+                    .with_batch_exporter(exporter, runtime::Tokio)
+                    .with_resource(Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        config.service_name.clone(),
+                    )]))
+                    .build(),
+            )
+        }
+        None => None,
+    };
+
+    let otel_layer = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("webrtc-grabber-rs-server"))
+    });
+
+    let (filter, log_filter) = reload::Layer::new(build_filter(config));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
+        .init();
+
+    Ok(TelemetryGuard {
+        provider,
+        log_filter,
+    })
+}
+
+/// `RUST_LOG` always wins over `telemetry.log_level`.
+fn build_filter(config: &TelemetryConfig) -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::try_new(config.log_level.as_deref().unwrap_or(DEFAULT_LOG_FILTER))
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid telemetry.log_level, using default: {}", e);
+                EnvFilter::new(DEFAULT_LOG_FILTER)
+            })
+    })
+}
+
+/// Applies `config` with `overrides` on top.
+pub fn reload_log_filter(
+    handle: &LogFilterHandle,
+    config: &TelemetryConfig,
+    overrides: &LogOverrides,
+) -> Result<()> {
+    handle.reload(overrides.filter(config))?;
+    Ok(())
+}
+
+/// Changes to the log filter made at runtime, e.g. `sfu_local=trace` for a
+/// few minutes while debugging a live issue. They outlast config reloads
+/// and are dropped by [`Self::expire`].
+#[derive(Default)]
+pub struct LogOverrides {
+    state: Mutex<OverrideState>,
+}
+
+#[derive(Default)]
+struct OverrideState {
+    /// Replaces the configured filter, `RUST_LOG` included.
+    filter: Option<(String, Option<Instant>)>,
+    /// Sessions logged at trace level, until the given time.
+    sessions: HashMap<String, Instant>,
+}
+
+impl LogOverrides {
+    /// Fails if `filter` doesn't parse.
+    pub fn set_filter(&self, filter: String, until: Option<Instant>) -> Result<()> {
+        EnvFilter::try_new(&filter)?;
+        self.state.lock().unwrap().filter = Some((filter, until));
+        Ok(())
+    }
+
+    pub fn clear_filter(&self) {
+        self.state.lock().unwrap().filter = None;
+    }
+
+    /// The replacement filter and when it expires.
+    pub fn current_filter(&self) -> Option<(String, Option<Instant>)> {
+        self.state.lock().unwrap().filter.clone()
+    }
+
+    /// Logs everything within spans for session `id`, which must be a plain
+    /// identifier such as a socket id.
+    pub fn trace_session(&self, id: &str, until: Instant) -> Result<()> {
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("'{}' is not a session id", id);
+        }
+        session_directives(id)?;
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .insert(id.to_string(), until);
+        Ok(())
+    }
+
+    /// Returns whether the session was traced.
+    pub fn stop_tracing_session(&self, id: &str) -> bool {
+        self.state.lock().unwrap().sessions.remove(id).is_some()
+    }
+
+    pub fn traced_sessions(&self) -> Vec<(String, Instant)> {
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .iter()
+            .map(|(id, until)| (id.clone(), *until))
+            .collect()
+    }
+
+    /// Drops overrides past their time. Returns whether any were.
+    pub fn expire(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let mut expired = false;
+        if state
+            .filter
+            .as_ref()
+            .is_some_and(|(_, until)| until.is_some_and(|until| until <= now))
+        {
+            state.filter = None;
+            expired = true;
+        }
+        let traced = state.sessions.len();
+        state.sessions.retain(|_, until| *until > now);
+        expired || state.sessions.len() != traced
+    }
+
+    fn filter(&self, config: &TelemetryConfig) -> EnvFilter {
+        let state = self.state.lock().unwrap();
+        let mut filter = match &state.filter {
+            Some((filter, _)) => EnvFilter::new(filter),
+            None => build_filter(config),
+        };
+        for id in state.sessions.keys() {
+            // `trace_session` already rejected ids that don't parse.
+            match session_directives(id) {
+                Ok(directives) => {
+                    for directive in directives {
+                        filter = filter.add_directive(directive);
+                    }
+                }
+                Err(e) => warn!("Invalid directive for session {}: {}", id, e),
+            }
+        }
+        filter
+    }
+}
+
+/// The directives that log session `id` at trace level.
+fn session_directives(id: &str) -> Result<Vec<Directive>> {
+    SESSION_FIELDS
+        .iter()
+        .map(|field| Ok(format!("[{{{}={}}}]=trace", field, id).parse()?))
+        .collect()
+}
@@ -0,0 +1,115 @@
+use dashmap::DashMap;
+use std::sync::Arc;
+
+use crate::peer_feed::PeersUpdate;
+use crate::protocol::{PeerEvent, PeerStatus, PeersStatusDelta};
+use crate::storage::Storage;
+
+/// Storage key for peer `name` of `tenant`. Tenant peers are stored as
+/// `<tenant>/<name>`, so names containing `/` are refused to keep namespaces
+/// from overlapping.
+pub fn qualify(tenant: Option<&str>, name: &str) -> Option<String> {
+    if name.contains('/') {
+        return None;
+    }
+    Some(match tenant {
+        Some(tenant) => format!("{}/{}", tenant, name),
+        None => name.to_string(),
+    })
+}
+
+/// The name `tenant` knows a stored peer by, or `None` if the peer belongs to
+/// another namespace.
+pub fn local_name<'a>(tenant: Option<&str>, name: &'a str) -> Option<&'a str> {
+    match tenant {
+        Some(tenant) => name.strip_prefix(tenant)?.strip_prefix('/'),
+        None => (!name.contains('/')).then_some(name),
+    }
+}
+
+pub fn scope_peers(tenant: Option<&str>, peers: Vec<PeerStatus>) -> Vec<PeerStatus> {
+    peers
+        .into_iter()
+        .filter_map(|mut peer| {
+            peer.name = local_name(tenant, &peer.name)?.to_string();
+            Some(peer)
+        })
+        .collect()
+}
+
+pub fn scope_events(tenant: Option<&str>, events: Vec<PeerEvent>) -> Vec<PeerEvent> {
+    events
+        .into_iter()
+        .filter_map(|mut event| {
+            event.peer_name = local_name(tenant, &event.peer_name)?.to_string();
+            Some(event)
+        })
+        .collect()
+}
+
+/// Restricts a feed update to one namespace. Deltas are kept even when
+/// nothing in the namespace changed, so clients still see every `seq`.
+pub fn scope_update(tenant: Option<&str>, update: PeersUpdate) -> PeersUpdate {
+    match update {
+        PeersUpdate::Full { seq, peers } => PeersUpdate::Full {
+            seq,
+            peers: scope_peers(tenant, peers),
+        },
+        PeersUpdate::Delta(delta) => PeersUpdate::Delta(PeersStatusDelta {
+            seq: delta.seq,
+            added: scope_peers(tenant, delta.added),
+            changed: scope_peers(tenant, delta.changed),
+            removed: delta
+                .removed
+                .iter()
+                .filter_map(|name| local_name(tenant, name).map(str::to_string))
+                .collect(),
+        }),
+    }
+}
+
+/// Grabbers registered under `tenant`, other than `except`.
+pub fn grabber_count(storage: &Storage, tenant: &str, except: &str) -> usize {
+    storage
+        .get_all_statuses()
+        .iter()
+        .filter(|peer| peer.name != except && local_name(Some(tenant), &peer.name).is_some())
+        .count()
+}
+
+/// Connected players per tenant, for `limits.max_players`.
+#[derive(Default)]
+pub struct TenantPlayers(Arc<DashMap<String, usize>>);
+
+impl TenantPlayers {
+    /// Takes a player slot, or `None` when the tenant already has `max`
+    /// players. The slot is released when dropped.
+    pub fn join(&self, tenant: &str, max: Option<usize>) -> Option<PlayerSlot> {
+        let mut count = self.0.entry(tenant.to_string()).or_insert(0);
+        if max.is_some_and(|max| *count >= max) {
+            return None;
+        }
+        *count += 1;
+        Some(PlayerSlot {
+            players: Arc::clone(&self.0),
+            tenant: tenant.to_string(),
+        })
+    }
+
+    pub fn count(&self, tenant: &str) -> usize {
+        self.0.get(tenant).map_or(0, |count| *count)
+    }
+}
+
+pub struct PlayerSlot {
+    players: Arc<DashMap<String, usize>>,
+    tenant: String,
+}
+
+impl Drop for PlayerSlot {
+    fn drop(&mut self) {
+        if let Some(mut count) = self.players.get_mut(&self.tenant) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
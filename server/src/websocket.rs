@@ -1,27 +1,225 @@
 use axum::extract::ws::{Message, WebSocket};
-use futures::{stream::SplitStream, SinkExt, StreamExt};
+use futures::stream::BoxStream;
+use futures::{SinkExt, StreamExt};
 use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tracing::{trace, warn};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::time::Instant;
+use tracing::{debug, trace, warn};
 
 use crate::error::{Result, SignallingError};
 
+/// Subprotocol a client offers to exchange CBOR-encoded binary frames instead
+/// of JSON text, which saves a good share of the size of SDP-heavy messages.
+pub const CBOR_PROTOCOL: &str = "webrtc-grabber.cbor";
+
+/// Queued messages a session may have outstanding before its client is
+/// considered stalled and disconnected. Critical messages and the pending
+/// status update don't count.
+const OUTBOUND_CAPACITY: usize = 256;
+/// Ping frames sent per idle timeout, so a peer that answers them, as
+/// browsers do on their own, never reaches it.
+const PINGS_PER_IDLE_TIMEOUT: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Json,
+    Cbor,
+}
+
+/// How a queued message is treated when the client falls behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageClass {
+    /// Never dropped: authentication, answers and the close frame.
+    Critical,
+    /// Counts against [`OUTBOUND_CAPACITY`]; overflowing it disconnects.
+    Normal,
+    /// Periodic state such as peer statuses. A newer one replaces one still
+    /// queued, so at most one is pending.
+    Status,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<(MessageClass, Message)>,
+    /// Messages of class [`MessageClass::Normal`] in `messages`.
+    normal: usize,
+    /// Set on overflow and once the close frame is queued; nothing more is
+    /// accepted after it.
+    closed: bool,
+}
+
+/// Sending half of a session's outbound queue. The writer is woken through
+/// a one-slot channel, which also tells it when every sender is gone.
+#[derive(Clone)]
+struct OutboundSender {
+    id: String,
+    state: Arc<Mutex<QueueState>>,
+    wake: mpsc::Sender<()>,
+}
+
+impl OutboundSender {
+    fn push(&self, class: MessageClass, message: Message) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        // The writer is gone once the socket failed.
+        if state.closed || self.wake.is_closed() {
+            return Err(SignallingError::WebSocket("Session is closed".to_string()));
+        }
+        match class {
+            MessageClass::Status => {
+                if let Some(pending) = state
+                    .messages
+                    .iter_mut()
+                    .find(|(class, _)| *class == MessageClass::Status)
+                {
+                    pending.1 = message;
+                    return Ok(());
+                }
+            }
+            MessageClass::Normal if state.normal >= OUTBOUND_CAPACITY => {
+                warn!("Outbound queue for {} overflowed, disconnecting", self.id);
+                state
+                    .messages
+                    .retain(|(class, _)| *class == MessageClass::Critical);
+                state.normal = 0;
+                state
+                    .messages
+                    .push_back((MessageClass::Critical, Message::Close(None)));
+                state.closed = true;
+                drop(state);
+                let _ = self.wake.try_send(());
+                return Err(SignallingError::WebSocket(format!(
+                    "Outbound queue overflowed after {} messages",
+                    OUTBOUND_CAPACITY
+                )));
+            }
+            MessageClass::Normal => state.normal += 1,
+            MessageClass::Critical => {}
+        }
+        if matches!(message, Message::Close(_)) {
+            state.closed = true;
+        }
+        state.messages.push_back((class, message));
+        drop(state);
+        // A full channel already holds a wake-up.
+        let _ = self.wake.try_send(());
+        Ok(())
+    }
+
+    fn status_pending(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .messages
+            .iter()
+            .any(|(class, _)| *class == MessageClass::Status)
+    }
+}
+
+/// Receiving half of a session's outbound queue: the socket writer, or the
+/// long-poll transport.
+pub struct OutboundReceiver {
+    state: Arc<Mutex<QueueState>>,
+    wake: mpsc::Receiver<()>,
+}
+
+impl OutboundReceiver {
+    /// The next message, or `None` once the close frame went out or every
+    /// sender is gone and the queue is drained.
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            if let Some(message) = self.pop() {
+                return Some(message);
+            }
+            if self.state.lock().unwrap().closed {
+                return None;
+            }
+            if self.wake.recv().await.is_none() {
+                return self.pop();
+            }
+        }
+    }
+
+    pub fn try_recv(&mut self) -> std::result::Result<Message, TryRecvError> {
+        if let Some(message) = self.pop() {
+            return Ok(message);
+        }
+        if self.state.lock().unwrap().closed {
+            return Err(TryRecvError::Disconnected);
+        }
+        match self.wake.try_recv() {
+            Err(TryRecvError::Disconnected) => self.pop().ok_or(TryRecvError::Disconnected),
+            _ => self.pop().ok_or(TryRecvError::Empty),
+        }
+    }
+
+    fn pop(&self) -> Option<Message> {
+        let mut state = self.state.lock().unwrap();
+        let (class, message) = state.messages.pop_front()?;
+        if class == MessageClass::Normal {
+            state.normal -= 1;
+        }
+        Some(message)
+    }
+}
+
+fn outbound_queue(id: &str) -> (OutboundSender, OutboundReceiver) {
+    let state = Arc::new(Mutex::new(QueueState::default()));
+    let (wake_tx, wake_rx) = mpsc::channel(1);
+    (
+        OutboundSender {
+            id: id.to_string(),
+            state: Arc::clone(&state),
+            wake: wake_tx,
+        },
+        OutboundReceiver {
+            state,
+            wake: wake_rx,
+        },
+    )
+}
+
 #[derive(Clone)]
 pub struct WsSession {
     pub id: String,
-    sender: mpsc::UnboundedSender<Message>,
+    encoding: Encoding,
+    sender: OutboundSender,
 }
 
 impl WsSession {
-    pub fn new(socket: WebSocket, id: String) -> (Self, SplitStream<WebSocket>) {
+    /// Messages are sent as CBOR when the upgrade selected [`CBOR_PROTOCOL`]
+    /// and as JSON otherwise.
+    pub fn new(
+        socket: WebSocket,
+        id: String,
+        idle_timeout: Option<Duration>,
+    ) -> (Self, WsReceiver) {
+        let encoding = match socket.protocol() {
+            Some(protocol) if protocol == CBOR_PROTOCOL => Encoding::Cbor,
+            _ => Encoding::Json,
+        };
         let (ws_sender, ws_receiver) = socket.split();
-        let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+        let (tx, mut rx) = outbound_queue(&id);
 
         let id_clone = id.clone();
+        let mut keepalive = idle_timeout.map(|timeout| {
+            let period = timeout / PINGS_PER_IDLE_TIMEOUT;
+            tokio::time::interval_at(Instant::now() + period, period)
+        });
 
         tokio::spawn(async move {
             let mut ws_sender = ws_sender;
-            while let Some(msg) = rx.recv().await {
+            loop {
+                let msg = tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                    _ = next_keepalive(&mut keepalive) => Message::Ping(Vec::new()),
+                };
                 if let Err(e) = ws_sender.send(msg).await {
                     warn!("Failed to send WebSocket message to {}: {}", id_clone, e);
                     break;
@@ -30,25 +228,174 @@ impl WsSession {
             trace!("WebSocket sender task for {} terminated", id_clone);
         });
 
-        (Self { id, sender: tx }, ws_receiver)
+        let receiver = WsReceiver {
+            id: id.clone(),
+            stream: ws_receiver.boxed(),
+            idle_timeout,
+            last_activity: Instant::now(),
+        };
+
+        (
+            Self {
+                id,
+                encoding,
+                sender: tx,
+            },
+            receiver,
+        )
     }
 
+    /// A session over channels instead of a socket, for transports such as
+    /// long polling: `inbox` feeds the receiver, and everything sent comes
+    /// out of the returned outbox as JSON text.
+    pub fn detached(
+        id: String,
+        inbox: mpsc::UnboundedReceiver<Message>,
+    ) -> (Self, WsReceiver, OutboundReceiver) {
+        let (tx, outbox) = outbound_queue(&id);
+        let receiver = WsReceiver {
+            id: id.clone(),
+            stream: futures::stream::unfold(inbox, |mut inbox| async move {
+                inbox.recv().await.map(|msg| (Ok(msg), inbox))
+            })
+            .boxed(),
+            idle_timeout: None,
+            last_activity: Instant::now(),
+        };
+        (
+            Self {
+                id,
+                encoding: Encoding::Json,
+                sender: tx,
+            },
+            receiver,
+            outbox,
+        )
+    }
+
+    /// Sends `msg` in the session's encoding; JSON unless CBOR was negotiated.
+    /// Fails, and disconnects the client, if too many messages are already
+    /// waiting to go out.
     pub fn send_json<T: Serialize>(&self, msg: &T) -> Result<()> {
-        let text = serde_json::to_string(msg)?;
-        self.sender
-            .send(Message::Text(text))
-            .map_err(|e| SignallingError::WebSocket(format!("Failed to queue message: {}", e)))
+        self.send(MessageClass::Normal, msg)
     }
 
-    pub fn send_text(&self, text: String) -> Result<()> {
-        self.sender
-            .send(Message::Text(text))
-            .map_err(|e| SignallingError::WebSocket(format!("Failed to queue message: {}", e)))
+    /// Like [`Self::send_json`], for messages that must arrive however far
+    /// behind the client is, such as auth results and answers.
+    pub fn send_critical<T: Serialize>(&self, msg: &T) -> Result<()> {
+        self.send(MessageClass::Critical, msg)
+    }
+
+    /// Like [`Self::send_json`], for periodic state that supersedes the
+    /// previous update: one still queued is replaced instead of piling up.
+    pub fn send_status<T: Serialize>(&self, msg: &T) -> Result<()> {
+        self.send(MessageClass::Status, msg)
+    }
+
+    /// Whether a status update is still waiting to go out, and so would be
+    /// replaced by the next [`Self::send_status`].
+    pub fn status_pending(&self) -> bool {
+        self.sender.status_pending()
+    }
+
+    fn send<T: Serialize>(&self, class: MessageClass, msg: &T) -> Result<()> {
+        let message = match self.encoding {
+            Encoding::Json => Message::Text(serde_json::to_string(msg)?),
+            Encoding::Cbor => {
+                let mut data = Vec::new();
+                ciborium::into_writer(msg, &mut data)
+                    .map_err(|e| SignallingError::InvalidMessageFormat(e.to_string()))?;
+                Message::Binary(data)
+            }
+        };
+        self.sender.push(class, message)
     }
 
     pub fn close(&self) -> Result<()> {
         self.sender
-            .send(Message::Close(None))
-            .map_err(|e| SignallingError::WebSocket(format!("Failed to send close: {}", e)))
+            .push(MessageClass::Critical, Message::Close(None))
     }
 }
+
+/// Incoming half of a [`WsSession`]. Control frames are handled here so
+/// handlers only see protocol messages.
+pub struct WsReceiver {
+    id: String,
+    stream: BoxStream<'static, std::result::Result<Message, axum::Error>>,
+    /// Longest silence, control frames included, before the peer is
+    /// considered dead.
+    idle_timeout: Option<Duration>,
+    // Kept across calls: `recv` is cancelled and restarted by `select!` loops,
+    // which must not push the deadline back.
+    last_activity: Instant,
+}
+
+impl WsReceiver {
+    /// Next protocol message as JSON, or `None` once the peer has closed the
+    /// connection. Binary frames carry either UTF-8 JSON or CBOR, which is
+    /// transcoded so handlers only deal with JSON. Yields a timeout error when
+    /// nothing arrived within the idle timeout.
+    pub async fn recv(&mut self) -> Option<Result<String>> {
+        loop {
+            let result = match self.idle_timeout {
+                Some(idle_timeout) => {
+                    let deadline = self.last_activity + idle_timeout;
+                    match tokio::time::timeout_at(deadline, self.stream.next()).await {
+                        Ok(result) => result?,
+                        Err(_) => {
+                            return Some(Err(SignallingError::Timeout(format!(
+                                "No activity from {} for {:?}",
+                                self.id, idle_timeout
+                            ))))
+                        }
+                    }
+                }
+                None => self.stream.next().await?,
+            };
+            self.last_activity = Instant::now();
+            let msg = match result {
+                Ok(msg) => msg,
+                Err(e) => return Some(Err(SignallingError::WebSocket(e.to_string()))),
+            };
+
+            match msg {
+                Message::Text(text) => return Some(Ok(text)),
+                Message::Binary(data) if data.first() == Some(&b'{') => {
+                    match String::from_utf8(data) {
+                        Ok(text) => return Some(Ok(text)),
+                        Err(_) => warn!("Ignoring non-UTF-8 binary frame from {}", self.id),
+                    }
+                }
+                Message::Binary(data) => match cbor_to_json(&data) {
+                    Ok(text) => return Some(Ok(text)),
+                    Err(e) => warn!("Ignoring malformed CBOR frame from {}: {}", self.id, e),
+                },
+                // The socket answers pings itself.
+                Message::Ping(_) => trace!("Ping from {}", self.id),
+                Message::Pong(_) => trace!("Pong from {}", self.id),
+                Message::Close(frame) => {
+                    debug!(
+                        "{} sent close frame: {:?}",
+                        self.id,
+                        frame.map(|f| (f.code, f.reason))
+                    );
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+async fn next_keepalive(keepalive: &mut Option<tokio::time::Interval>) {
+    match keepalive {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn cbor_to_json(data: &[u8]) -> std::result::Result<String, String> {
+    let value: serde_json::Value = ciborium::from_reader(data).map_err(|e| e.to_string())?;
+    serde_json::to_string(&value).map_err(|e| e.to_string())
+}
@@ -97,6 +97,15 @@ class Publisher {
 
     async handleMessage(msg) {
         switch (msg.event) {
+            case 'AUTH_REQUEST':
+                this.ws.send(JSON.stringify({
+                    event: 'AUTH',
+                    grabberAuth: { credential: 'test' }
+                }));
+                break;
+            case 'AUTH_FAILED':
+                this.logger.error('Publisher authentication failed');
+                break;
             case 'INIT_PEER':
                 await this.initPeerConnection(msg.initPeer.pcConfig);
                 break;
@@ -196,10 +205,12 @@ class Publisher {
 }
 
 class Viewer {
-    constructor(peerName, logger, onStatusChange) {
+    // Viewers sharing a syncGroup are aligned by the SFU to play in step.
+    constructor(peerName, logger, onStatusChange, syncGroup = null) {
         this.peerName = peerName;
         this.logger = logger;
         this.onStatusChange = onStatusChange;
+        this.syncGroup = syncGroup;
         this.ws = null;
         this.pc = null;
         this.authenticated = false;
@@ -207,6 +218,8 @@ class Viewer {
         this.remoteDescriptionSet = false;
         this.videoElement = null;
         this.statsInterval = null;
+        this.pcConfig = null;
+        this.offerRetries = 0;
     }
 
     async start(videoElement) {
@@ -259,7 +272,8 @@ class Viewer {
 
             case 'INIT_PEER':
                 this.authenticated = true;
-                await this.initPeerConnection(msg.initPeer.pcConfig);
+                this.pcConfig = msg.initPeer.pcConfig;
+                await this.initPeerConnection(this.pcConfig);
                 break;
 
             case 'ANSWER':
@@ -271,8 +285,7 @@ class Viewer {
                 break;
 
             case 'OFFER_FAILED':
-                this.logger.error(`Offer failed for ${this.peerName} - peer may not exist`);
-                this.updateStatus('error');
+                this.handleOfferFailed(msg.offerFailed);
                 break;
         }
     }
@@ -354,16 +367,41 @@ class Viewer {
             offer: {
                 type: offer.type,
                 sdp: offer.sdp,
-                peerName: this.peerName
+                peerName: this.peerName,
+                syncGroup: this.syncGroup
             }
         }));
 
         this.logger.log(`Offer sent for ${this.peerName}`);
     }
 
+    handleOfferFailed(info) {
+        const reason = info?.reason || 'peer may not exist';
+        if (info?.retryable && this.offerRetries < 3) {
+            this.offerRetries++;
+            const delay = info.retryAfterMs || 2000;
+            this.logger.warning(`Offer failed for ${this.peerName} (${reason}), retrying in ${delay}ms`);
+            if (this.pc) {
+                this.pc.close();
+                this.pc = null;
+            }
+            this.remoteDescriptionSet = false;
+            this.pendingIceCandidates = [];
+            setTimeout(() => {
+                if (this.ws && this.pcConfig) {
+                    this.initPeerConnection(this.pcConfig);
+                }
+            }, delay);
+            return;
+        }
+        this.logger.error(`Offer failed for ${this.peerName}: ${reason}`);
+        this.updateStatus('error');
+    }
+
     async handleAnswer(answer) {
         try {
             await this.pc.setRemoteDescription({ type: answer.type, sdp: answer.sdp });
+            this.offerRetries = 0;
             this.logger.success(`Remote description set for ${this.peerName}`);
             this.remoteDescriptionSet = true;
 
@@ -655,12 +693,20 @@ class UIController {
             }
         }
 
+        // Synchronized viewers share a group of their own, so other players'
+        // streams don't hold these back
+        const synchronized = document.getElementById('watchMode').value === 'synchronized';
+        const syncGroup = synchronized ? `judge-${Date.now().toString(36)}-${Math.random().toString(36).slice(2)}` : null;
+        if (synchronized) {
+            this.logger.log(`Synchronizing playback of ${peers.join(', ')}`);
+        }
+
         // Start viewers for new peers
         for (const peerName of peers) {
             if (!this.viewers.has(peerName)) {
                 this.addVideoCard(peerName);
                 const videoElement = document.getElementById(`video-${peerName}`);
-                const viewer = new Viewer(peerName, this.logger, (name, type, data) => this.handleViewerUpdate(name, type, data));
+                const viewer = new Viewer(peerName, this.logger, (name, type, data) => this.handleViewerUpdate(name, type, data), syncGroup);
                 this.viewers.set(peerName, viewer);
                 await viewer.start(videoElement);
             }